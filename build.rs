@@ -0,0 +1,26 @@
+// Build metadata for GET /version: the commit the binary is built from and when it was built.
+// TREND_STORY_GIT_COMMIT can be set where the checkout has no .git (e.g. a Docker build context).
+use std::process::Command;
+
+fn main() {
+    let commit = std::env::var("TREND_STORY_GIT_COMMIT").ok().filter(|c| !c.is_empty()).or_else(|| {
+        let output = Command::new("git").args(["rev-parse", "HEAD"]).output().ok()?;
+        output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    });
+    let dirty = Command::new("git")
+        .args(["status", "--porcelain", "--untracked-files=no"])
+        .output()
+        .is_ok_and(|output| output.status.success() && !output.stdout.is_empty());
+    let commit = match commit {
+        Some(commit) if dirty => format!("{}-dirty", commit),
+        Some(commit) => commit,
+        None => "unknown".to_string(),
+    };
+    println!("cargo:rustc-env=TREND_STORY_GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=TREND_STORY_BUILD_TIMESTAMP={}", chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+
+    println!("cargo:rerun-if-env-changed=TREND_STORY_GIT_COMMIT");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-changed=.git/index");
+}
@@ -0,0 +1,131 @@
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use serde_json::Value;
+use warp::Filter;
+
+use crate::config::Config;
+use crate::crypto::constant_time_eq;
+use crate::error::ApiError;
+use crate::jwt::{Claims, JwtVerifier};
+
+// Credentials accepted by the /admin/... routes: the admin token, those of the tokens file and
+// JWTs from the configured identity provider
+#[derive(Clone)]
+pub(crate) struct AdminAuth {
+    token: Option<Arc<str>>,
+    tokens_file: Option<Arc<TokensFile>>,
+    jwt: Option<Arc<JwtVerifier>>,
+}
+
+// Who made an admin request: the claims of their JWT, none for an admin token
+#[derive(Clone)]
+pub(crate) struct Caller {
+    pub(crate) claims: Option<Arc<Claims>>,
+}
+
+impl std::fmt::Display for Caller {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.claims {
+            Some(claims) => match claims.get("sub").and_then(Value::as_str) {
+                Some(subject) => write!(f, "{}", subject),
+                None => write!(f, "a JWT without subject"),
+            },
+            None => write!(f, "an admin token"),
+        }
+    }
+}
+
+// Tokens listed one per line, read again whenever the file changes so they can be rotated without
+// a restart. Blank lines and lines starting with '#' are skipped.
+struct TokensFile {
+    path: PathBuf,
+    loaded: RwLock<Option<LoadedTokens>>,
+}
+
+// The tokens file as last read, with its modification time then (None: missing)
+struct LoadedTokens {
+    modified: Option<SystemTime>,
+    tokens: Vec<Arc<str>>,
+}
+
+impl TokensFile {
+    fn tokens(&self) -> Vec<Arc<str>> {
+        let modified = std::fs::metadata(&self.path).and_then(|metadata| metadata.modified()).ok();
+        if let Some(loaded) = self.loaded.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            if loaded.modified == modified {
+                return loaded.tokens.clone();
+            }
+        }
+        let tokens: Vec<Arc<str>> = match std::fs::read_to_string(&self.path) {
+            Ok(text) => text
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(Arc::from)
+                .collect(),
+            Err(e) => {
+                eprintln!("Cannot read admin tokens from {}: {}", self.path.display(), e);
+                Vec::new()
+            }
+        };
+        *self.loaded.write().unwrap_or_else(|e| e.into_inner()) = Some(LoadedTokens { modified, tokens: tokens.clone() });
+        tokens
+    }
+}
+
+impl AdminAuth {
+    pub(crate) fn new(config: &Config) -> AdminAuth {
+        AdminAuth {
+            token: config.admin_token.as_deref().map(Arc::from),
+            tokens_file: config.admin_tokens_file.clone().map(|path| Arc::new(TokensFile { path, loaded: RwLock::new(None) })),
+            jwt: JwtVerifier::new(&config.jwt).map(Arc::new),
+        }
+    }
+
+    // Blocks when a JWT's signing keys have to be fetched
+    fn check(&self, authorization: Option<&str>) -> Result<Caller, ApiError> {
+        if self.token.is_none() && self.tokens_file.is_none() && self.jwt.is_none() {
+            return Err(ApiError::AdminDisabled);
+        }
+        let supplied = authorization
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+            .ok_or(ApiError::Unauthorized)?;
+        let file_tokens = self.tokens_file.as_ref().map(|file| file.tokens()).unwrap_or_default();
+        // Every token is compared, so timing doesn't reveal which one matched either
+        let accepted = self
+            .token
+            .iter()
+            .chain(&file_tokens)
+            .fold(false, |accepted, expected| accepted | constant_time_eq(supplied.as_bytes(), expected.as_bytes()));
+        if accepted {
+            return Ok(Caller { claims: None });
+        }
+        match &self.jwt {
+            Some(jwt) if supplied.matches('.').count() == 2 => {
+                jwt.verify(supplied).map(|claims| Caller { claims: Some(Arc::new(claims)) })
+            }
+            _ => Err(ApiError::Unauthorized),
+        }
+    }
+}
+
+// Passes only requests carrying "Authorization: Bearer <admin token or JWT>", extracting who made
+// them
+pub(crate) fn require_admin(auth: AdminAuth) -> impl Filter<Extract = (Caller,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("authorization").and_then(move |authorization: Option<String>| {
+        let auth = auth.clone();
+        async move {
+            // Checking a JWT may fetch the JWKS, so it runs off the async workers
+            let result = if auth.jwt.is_some() {
+                tokio::task::spawn_blocking(move || auth.check(authorization.as_deref()))
+                    .await
+                    .unwrap_or(Err(ApiError::Panicked))
+            } else {
+                auth.check(authorization.as_deref())
+            };
+            result.map_err(warp::Rejection::from)
+        }
+    })
+}
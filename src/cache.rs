@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::config::CACHE_MAX_AGE_SECONDS;
+
+// Routes with cached responses, named by the prefix of their cache keys
+pub(crate) const CACHED_ROUTES: [&str; 12] = [
+    "latest", "dates", "date", "month", "year", "stats", "analytics_keywords", "tag_counts", "related_tags",
+    "related_news", "similar_news", "threads",
+];
+
+// Lifetimes applied before TREND_STORY_CACHE_TTLS: the newest day and the day list are refreshed
+// now and then even between syncs, everything else lives until the next sync
+const DEFAULT_CACHE_TTLS: &str = "latest=1m,dates=10m";
+
+// How long each route's cached responses are served. Every response is still dropped by the next
+// sync or a purge; a route without a lifetime is cached until then (for at most
+// CACHE_MAX_AGE_SECONDS), one with 0 isn't cached.
+#[derive(Debug, Clone)]
+pub struct CacheTtls {
+    ttls: HashMap<&'static str, Duration>,
+}
+
+impl Default for CacheTtls {
+    fn default() -> CacheTtls {
+        let mut ttls = CacheTtls { ttls: HashMap::new() };
+        ttls.apply(DEFAULT_CACHE_TTLS);
+        ttls
+    }
+}
+
+impl CacheTtls {
+    // Override routes' lifetimes from "route=ttl,..." where ttl is "sync", 0 or a duration such
+    // as 30s, 10m, 1h or 1d. Invalid entries are skipped and returned as errors.
+    pub(crate) fn apply(&mut self, raw: &str) -> Vec<String> {
+        raw.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .filter_map(|entry| self.apply_entry(entry).err())
+            .collect()
+    }
+
+    fn apply_entry(&mut self, entry: &str) -> Result<(), String> {
+        let (route, ttl) = entry.split_once('=').ok_or_else(|| format!("'{}' is not route=ttl", entry))?;
+        let route = CACHED_ROUTES
+            .iter()
+            .find(|name| **name == route.trim())
+            .ok_or_else(|| format!("'{}' is not a cached route ({})", route.trim(), CACHED_ROUTES.join(", ")))?;
+        match ttl.trim() {
+            "sync" => {
+                self.ttls.remove(route);
+            }
+            ttl => {
+                let ttl = parse_duration(ttl).ok_or_else(|| format!("'{}' is not sync or a duration like 10m", ttl))?;
+                self.ttls.insert(route, ttl);
+            }
+        }
+        Ok(())
+    }
+
+    // Lifetime of a cache key's responses, by its route prefix; None until the next sync
+    pub(crate) fn ttl(&self, key: &str) -> Option<Duration> {
+        let route = key.split(':').next().unwrap_or(key);
+        self.ttls.get(route).copied()
+    }
+
+    // How long a cache key's responses are served: its route's lifetime, and at most
+    // CACHE_MAX_AGE_SECONDS for routes cached until the next sync
+    pub(crate) fn lifetime(&self, key: &str) -> Duration {
+        self.ttl(key).unwrap_or(Duration::from_secs(CACHE_MAX_AGE_SECONDS))
+    }
+}
+
+fn parse_duration(raw: &str) -> Option<Duration> {
+    if raw == "0" {
+        return Some(Duration::ZERO);
+    }
+    let unit = raw.chars().last()?;
+    let digits = &raw[..raw.len() - unit.len_utf8()];
+    let seconds = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        _ => return None,
+    };
+    Some(Duration::from_secs(digits.parse::<u64>().ok()?.checked_mul(seconds)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{parse_duration, CacheTtls};
+    use crate::config::CACHE_MAX_AGE_SECONDS;
+
+    #[test]
+    fn applies_valid_entries_over_the_defaults() {
+        let mut ttls = CacheTtls::default();
+        assert!(ttls.apply(" latest=30s, stats=1h ,dates=sync,").is_empty());
+
+        assert_eq!(ttls.ttl("latest"), Some(Duration::from_secs(30)));
+        assert_eq!(ttls.ttl("stats:"), Some(Duration::from_secs(60 * 60)));
+        assert_eq!(ttls.ttl("dates"), None);
+        assert_eq!(ttls.ttl("month:2025-11:tag="), None);
+        assert_eq!(ttls.lifetime("month:2025-11:tag="), Duration::from_secs(CACHE_MAX_AGE_SECONDS));
+    }
+
+    #[test]
+    fn zero_turns_a_route_off() {
+        let mut ttls = CacheTtls::default();
+        assert!(ttls.apply("threads=0").is_empty());
+        assert!(ttls.lifetime("threads:abc").is_zero());
+    }
+
+    #[test]
+    fn skips_unknown_routes_and_garbage() {
+        let mut ttls = CacheTtls::default();
+        let errors = ttls.apply("news=1m,latest,date=soon,stats=5m");
+
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+        assert!(errors[0].contains("'news' is not a cached route"), "{}", errors[0]);
+        assert!(errors[1].contains("is not route=ttl"), "{}", errors[1]);
+        assert!(errors[2].contains("'soon' is not sync or a duration"), "{}", errors[2]);
+        assert_eq!(ttls.ttl("latest"), Some(Duration::from_secs(60)));
+        assert_eq!(ttls.ttl("stats"), Some(Duration::from_secs(5 * 60)));
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("0"), Some(Duration::ZERO));
+        assert_eq!(parse_duration("0s"), Some(Duration::ZERO));
+        assert_eq!(parse_duration("45s"), Some(Duration::from_secs(45)));
+        assert_eq!(parse_duration("10m"), Some(Duration::from_secs(600)));
+        assert_eq!(parse_duration("2h"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("1d"), Some(Duration::from_secs(86_400)));
+        for garbage in ["", "m", "10", "10x", "-5m", "1.5h", "ten m", "99999999999999999999d", "5é"] {
+            assert_eq!(parse_duration(garbage), None, "{}", garbage);
+        }
+    }
+}
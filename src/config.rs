@@ -0,0 +1,1085 @@
+// Immutable Config
+// Frontend and API origins links point at unless TREND_STORY_SITE_URL / TREND_STORY_API_URL
+// say otherwise
+pub(crate) const DOMAIN: &str = "https://trending.oopus.info";
+pub(crate) const DOMAIN_API: &str = "https://trend-story-api.oopus.info";
+pub(crate) const SYNC_INTERVAL_MINUTES: u64 = 20; // User-configurable
+// Until a source's first sync succeeds, it is retried this often
+pub(crate) const SYNC_RETRY_SECONDS: u64 = 30;
+// Sync attempts kept for GET /admin/sync/log
+pub(crate) const SYNC_LOG_CAPACITY: usize = 100;
+// A source's sync failures are reported (with a Sentry DSN set) once this many fail in a row
+pub(crate) const SYNC_FAILURES_BEFORE_REPORT: usize = 3;
+// Served at /robots.txt unless a file is configured: keep crawlers out of the admin endpoints and
+// the full export
+pub(crate) const DEFAULT_ROBOTS_TXT: &str = "User-agent: *\nDisallow: /admin/\nDisallow: /export/\n";
+// Rotated log files kept when logging to a file
+pub(crate) const DEFAULT_LOG_KEEP: usize = 7;
+pub(crate) const DEFAULT_DB_PATH: &str = "trends-story/trends_data.db";
+pub(crate) const DB_BUSY_TIMEOUT_MS: u64 = 5000;
+pub(crate) const DB_BUSY_RETRY_AFTER_SECONDS: u64 = 5;
+// Read-only connections kept open per source between requests, and the statements each caches
+pub(crate) const DB_POOL_IDLE_CONNECTIONS: usize = 16;
+pub(crate) const DB_STATEMENT_CACHE_CAPACITY: usize = 64;
+pub(crate) const DEFAULT_REPO_URL: &str = "https://github.com/sudoghut/trends-story";
+// Sent with TREND_STORY_GIT_TOKEN unless TREND_STORY_GIT_USERNAME says otherwise; what GitHub
+// expects of app and fine-grained tokens
+pub(crate) const DEFAULT_GIT_USERNAME: &str = "x-access-token";
+// A sparse checkout keeps the image directories of at most this many days
+pub(crate) const MAX_SPARSE_IMAGE_DAYS: usize = 3660;
+// A synced database fails validation when it has more than this share fewer records than the
+// one served, or when more than SYNC_MAX_MISSING_IMAGES_PERCENT of its newest day's images are missing
+pub(crate) const SYNC_MAX_ROW_DROP_PERCENT: i64 = 10;
+pub(crate) const SYNC_MAX_MISSING_IMAGES_PERCENT: usize = 50;
+// With file watching, changes are taken in once the files have been quiet this long
+pub(crate) const WATCH_DEBOUNCE_MILLIS: u64 = 500;
+// Data versions kept on disk by the atomic swap, the served one included
+pub(crate) const DATA_VERSIONS_KEPT: usize = 2;
+// Longest wait for one file of a snapshot sync (the database can be large)
+pub(crate) const SNAPSHOT_TIMEOUT_SECONDS: u64 = 600;
+// Presigned image URLs stay valid this long; a proxied image fetch waits at most S3_TIMEOUT_SECONDS
+pub(crate) const S3_PRESIGN_SECONDS: u64 = 300;
+pub(crate) const S3_TIMEOUT_SECONDS: u64 = 30;
+pub(crate) const DEFAULT_S3_REGION: &str = "us-east-1";
+// Days for /latest are computed in this UTC offset, and a day only counts as "latest" once it
+// has at least LATEST_MIN_RECORDS stories (falling back to the newest day if none qualifies)
+pub(crate) const LATEST_TZ_OFFSET_MINUTES: i32 = 0; // User-configurable
+pub(crate) const LATEST_MIN_RECORDS: i64 = 10; // User-configurable
+pub(crate) const MAX_ANALYTICS_DAYS: u32 = 365;
+pub(crate) const MAX_RELATED_RECORDS: usize = 50;
+pub(crate) const MAX_PAGE_SIZE: usize = 200;
+// Page size of /recent without ?limit=
+pub(crate) const RECENT_DEFAULT_LIMIT: usize = 20;
+// Page size of /changes without ?limit=
+pub(crate) const CHANGES_DEFAULT_LIMIT: usize = 100;
+pub(crate) const TOP_TAGS_LIMIT: usize = 5;
+// Longest ?summary= in characters
+pub(crate) const MAX_SUMMARY_LENGTH: usize = 10_000;
+// Ids remembered per source by each of the keyword, category and image lookup caches
+pub(crate) const LOOKUP_CACHE_CAPACITY: usize = 4096;
+// Responses kept per source by the response cache; when it is full, the one closest to expiring
+// makes room
+pub(crate) const RESPONSE_CACHE_CAPACITY: usize = 2048;
+// Longest a cached response is served, also on routes cached until the next sync
+pub(crate) const CACHE_MAX_AGE_SECONDS: u64 = 60 * 60;
+// Images hashed or resized at once by the image processing that runs after a sync
+pub(crate) const THUMBNAIL_WORKERS: usize = 2;
+// GET /search returns this many records unless ?limit= asks otherwise; highlighted text fragments
+// are about SEARCH_SNIPPET_TOKENS words long
+pub(crate) const SEARCH_DEFAULT_LIMIT: usize = 50;
+pub(crate) const SEARCH_SNIPPET_TOKENS: usize = 24;
+pub(crate) const DEFAULT_HIGHLIGHT_START: &str = "<mark>";
+pub(crate) const DEFAULT_HIGHLIGHT_END: &str = "</mark>";
+pub(crate) const MAX_HIGHLIGHT_MARKER_LEN: usize = 32;
+// ?dedupe=true treats records as one story when their keyword terms overlap this much (Jaccard)
+// and their texts share at least DEDUPE_TEXT_SIMILARITY, or their texts alone overlap
+// DEDUPE_SAME_TEXT_SIMILARITY
+pub(crate) const DEDUPE_KEYWORD_SIMILARITY: f64 = 0.5;
+pub(crate) const DEDUPE_TEXT_SIMILARITY: f64 = 0.15;
+pub(crate) const DEDUPE_SAME_TEXT_SIMILARITY: f64 = 0.5;
+// Requests beyond these in-flight counts are shed with a 503 instead of queueing
+pub(crate) const DEFAULT_MAX_IN_FLIGHT: usize = 64;
+pub(crate) const DEFAULT_MAX_IN_FLIGHT_PER_ROUTE: usize = 16;
+pub(crate) const OVERLOAD_RETRY_AFTER_SECONDS: u64 = 1;
+// Ids of locally added rows start above this, clear of the ids upstream will hand out
+pub(crate) const LOCAL_ID_BASE: i64 = 1_000_000_000;
+// Request bodies and query strings larger than these are refused with a 413 or 414
+pub(crate) const DEFAULT_MAX_BODY_BYTES: usize = 64 * 1024;
+pub(crate) const DEFAULT_MAX_QUERY_BYTES: usize = 4096;
+// Longest wait for the translation provider per text, and records waiting for translation at
+// most; further ones are translated once a later request finds room
+#[cfg(feature = "translation")]
+pub(crate) const TRANSLATE_TIMEOUT_SECONDS: u64 = 10;
+#[cfg(feature = "translation")]
+pub(crate) const TRANSLATE_QUEUE_CAPACITY: usize = 256;
+// ?lang= values offered unless TREND_STORY_TRANSLATE_LANGUAGES lists others
+pub(crate) const DEFAULT_TRANSLATE_LANGUAGES: &str = "en";
+// Texts sent to the embeddings endpoint per request, and the longest wait for one
+#[cfg(feature = "embeddings")]
+pub(crate) const EMBEDDING_BATCH: usize = 32;
+#[cfg(feature = "embeddings")]
+pub(crate) const EMBEDDINGS_TIMEOUT_SECONDS: u64 = 60;
+// Clock skew tolerated on a JWT's exp and nbf
+pub(crate) const JWT_LEEWAY_SECONDS: i64 = 60;
+// JWKS keys are fetched again after this long, or after JWKS_RETRY_SECONDS for an unknown key id
+pub(crate) const JWKS_REFRESH_MINUTES: u64 = 60;
+pub(crate) const JWKS_RETRY_SECONDS: u64 = 30;
+pub(crate) const JWKS_TIMEOUT_SECONDS: u64 = 10;
+// API key usage is written to the usage file this often, and reported for this many days
+pub(crate) const USAGE_FLUSH_SECONDS: u64 = 10;
+pub(crate) const USAGE_HISTORY_DAYS: i64 = 30;
+// Downloads (/export/all, /admin/backup) are streamed in chunks of about this size; an export
+// keeps at most EXPORT_BUFFERED_CHUNKS ready ahead of a slow client
+pub(crate) const EXPORT_CHUNK_BYTES: usize = 64 * 1024;
+pub(crate) const EXPORT_BUFFERED_CHUNKS: usize = 4;
+// Error reports waiting for the Sentry worker; more are dropped. An event identical to one sent
+// less than REPORT_REPEAT_SECONDS ago is dropped as well
+pub(crate) const REPORT_QUEUE_CAPACITY: usize = 64;
+pub(crate) const REPORT_REPEAT_SECONDS: u64 = 60;
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, RwLock};
+use crate::cache::CacheTtls;
+use crate::credentials::GitCredentials;
+use crate::jwt::JwtSettings;
+use crate::logging::{LogFile, LogRotation};
+use crate::lookup::Lookups;
+use crate::placeholders::Placeholders;
+use crate::pool::ConnectionPool;
+use crate::provenance::{parse_committers, parse_fingerprints, TrustPolicy};
+use crate::proxy::TrustedProxies;
+use crate::s3::{ImageBucket, ImageDelivery};
+use crate::tags::TagMap;
+use crate::threads::Threads;
+use crate::embeddings::Embedder;
+use crate::translate::{parse_languages, Translator};
+use crate::thumbnails::parse_widths;
+use crate::urls::{parse_base_url, BaseUrls};
+use crate::versions::CURRENT_VERSION;
+
+// One trend dataset: a git repository holding the SQLite file and its images.
+// The default source is served at the root, additional ones under /<name>/...
+#[derive(Debug, Clone)]
+pub struct DataSource {
+    pub name: Option<String>,
+    // Remote the source syncs from (TREND_STORY_REPO_URL / --repo-url for the default source)
+    pub repo_url: String,
+    // Branch synced (TREND_STORY_BRANCH / --branch for the default source, name=repo_url#branch
+    // for others); the remote's default branch when none
+    pub branch: Option<String>,
+    // Token or SSH key syncing a private repository (TREND_STORY_GIT_TOKEN or
+    // TREND_STORY_GIT_TOKEN_FILE, TREND_STORY_GIT_SSH_KEY; default source only); none by default
+    pub git_credentials: Option<Arc<GitCredentials>>,
+    pub repo_path: PathBuf,
+    // Clone and fetch only this many newest commits (TREND_STORY_CLONE_DEPTH / --clone-depth,
+    // TREND_STORY_SHALLOW_SYNC / --shallow-sync for 1), and check out the image directories of
+    // this many recent days only (TREND_STORY_SPARSE_IMAGE_DAYS / --sparse-image-days); full
+    // clones by default
+    pub clone_depth: Option<u32>,
+    pub sparse_image_days: Option<u32>,
+    // Signing keys and committers a fetched commit must match before the checkout moves to it
+    // (TREND_STORY_TRUSTED_SIGNING_KEYS, TREND_STORY_TRUSTED_COMMITTERS); none by default
+    pub trust: Option<Arc<TrustPolicy>>,
+    // Base URL the database and images are downloaded from instead of cloning repo_url
+    // (TREND_STORY_SNAPSHOT_URL / --snapshot-url, default source only); none by default
+    pub snapshot_url: Option<String>,
+    // SQLite file to serve; relative paths resolve against the working directory
+    pub db_path: PathBuf,
+    // Copy of the last synced database that passed validation, served while a newer one fails it
+    pub known_good_path: PathBuf,
+    // Set to known_good_path while the synced database fails validation
+    pub(crate) serving_override: Arc<RwLock<Option<PathBuf>>>,
+    // Serve copies of the synced database and images published under versions_dir once a sync has
+    // completed, never the checkout itself (TREND_STORY_ATOMIC_SWAP / --atomic-swap); off by default
+    pub atomic_swap: bool,
+    pub versions_dir: PathBuf,
+    // Take in changes to db_path and images_dir as they happen, between syncs
+    // (TREND_STORY_WATCH_FILES / --watch-files); off by default
+    pub watch_files: bool,
+    // Open the file with immutable=1 (no locking). Only safe when the file is replaced rather
+    // than modified in place, which is what git does on pull.
+    pub db_immutable: bool,
+    pub images_dir: PathBuf,
+    // Bucket /images/ serves the images from instead of images_dir (TREND_STORY_S3_BUCKET /
+    // --s3-bucket, default source only); none by default
+    pub image_bucket: Option<Arc<ImageBucket>>,
+    // BlurHashes of the images by file name, computed after syncs
+    pub placeholders_path: PathBuf,
+    // Resized WebP copies of the images, as <width>/<image path>, rebuilt after every sync
+    pub thumbnails_dir: PathBuf,
+    // Widths the thumbnails are built in (TREND_STORY_THUMBNAIL_WIDTHS / --thumbnail-widths); none by default
+    pub thumbnail_widths: Vec<u32>,
+    // Local, writable SQLite file with indexes derived from db_path, rebuilt after every sync
+    pub overlay_path: PathBuf,
+    // Local, writable SQLite file with records added, edited or deleted through the admin API; queries read it
+    // together with db_path, and the sync never touches it
+    pub edits_path: PathBuf,
+    // Provider that ?lang= translates news texts and keywords with (TREND_STORY_TRANSLATE_URL /
+    // --translate-url, with the translation feature); none by default
+    pub translator: Option<Arc<Translator>>,
+    // Local, writable SQLite file with the translations made, by record id and language
+    pub translations_path: PathBuf,
+    // Language a request asked for with ?lang=, records in others being translated into it
+    pub(crate) translate_to: Option<String>,
+    // Set when a ?lang= response lacks translations still being made, so it isn't cached
+    pub(crate) translations_pending: Arc<AtomicBool>,
+    // Local, writable SQLite file with the sentiment scores of the records (with the sentiment
+    // feature), updated after every sync
+    pub sentiment_path: PathBuf,
+    // Endpoint the embeddings of /news/<id>/similar come from (TREND_STORY_EMBEDDINGS_URL /
+    // --embeddings-url, with the embeddings feature); the local model when none is set
+    pub embedder: Option<Arc<Embedder>>,
+    // Local, writable SQLite file with the embeddings of the records, updated after every sync
+    pub embeddings_path: PathBuf,
+    // Serve news texts as stored, HTML from upstream included, instead of as their plain text
+    // (TREND_STORY_KEEP_NEWS_HTML / --keep-news-html); ?raw=true asks for it per request
+    pub keep_news_html: bool,
+    // Applied to the tags of every record (TREND_STORY_TAG_MAP / --tag-map); empty by default
+    pub tag_map: Arc<TagMap>,
+    // Lifetimes of cached responses per route (TREND_STORY_CACHE_TTLS / --cache-ttls)
+    pub cache_ttls: Arc<CacheTtls>,
+    // Keyword, category and image lookups of the records served, shared by the source's requests
+    pub(crate) lookups: Arc<Lookups>,
+    // Records recurring on consecutive days, by the same keywords
+    pub(crate) threads: Arc<Threads>,
+    pub(crate) placeholders: Arc<Placeholders>,
+    // Read-only connections to the database reused across requests
+    pub(crate) connections: Arc<ConnectionPool>,
+    // Origins of the image and date links in responses (TREND_STORY_API_URL, TREND_STORY_SITE_URL), or
+    // none for relative links
+    pub base_urls: Arc<BaseUrls>,
+}
+
+impl DataSource {
+    pub fn default_source() -> DataSource {
+        DataSource {
+            name: None,
+            repo_url: DEFAULT_REPO_URL.to_string(),
+            branch: None,
+            git_credentials: None,
+            repo_path: PathBuf::from("trends-story"),
+            clone_depth: None,
+            sparse_image_days: None,
+            trust: None,
+            snapshot_url: None,
+            db_path: PathBuf::from(DEFAULT_DB_PATH),
+            known_good_path: PathBuf::from("trends-story-known-good.db"),
+            serving_override: Arc::default(),
+            atomic_swap: false,
+            versions_dir: PathBuf::from("trends-story-versions"),
+            watch_files: false,
+            db_immutable: false,
+            images_dir: PathBuf::from("trends-story/images"),
+            image_bucket: None,
+            placeholders_path: PathBuf::from("trends-story-placeholders.json"),
+            thumbnails_dir: PathBuf::from("trends-story-thumbnails"),
+            thumbnail_widths: Vec::new(),
+            overlay_path: PathBuf::from("trends-story-overlay.db"),
+            edits_path: PathBuf::from("trends-story-edits.db"),
+            translator: None,
+            translations_path: PathBuf::from("trends-story-translations.db"),
+            sentiment_path: PathBuf::from("trends-story-sentiment.db"),
+            embedder: None,
+            embeddings_path: PathBuf::from("trends-story-embeddings.db"),
+            translate_to: None,
+            translations_pending: Arc::default(),
+            keep_news_html: false,
+            tag_map: Arc::default(),
+            cache_ttls: Arc::default(),
+            lookups: Arc::default(),
+            threads: Arc::default(),
+            placeholders: Arc::default(),
+            connections: Arc::default(),
+            base_urls: Arc::default(),
+        }
+    }
+
+    // Additional sources are cloned next to the default one as ./trends-story-<name>
+    pub fn named(name: &str, repo_url: &str) -> DataSource {
+        let repo_path = PathBuf::from(format!("trends-story-{}", name));
+        DataSource {
+            name: Some(name.to_string()),
+            repo_url: repo_url.to_string(),
+            branch: None,
+            git_credentials: None,
+            clone_depth: None,
+            sparse_image_days: None,
+            trust: None,
+            snapshot_url: None,
+            db_path: repo_path.join("trends_data.db"),
+            known_good_path: PathBuf::from(format!("trends-story-{}-known-good.db", name)),
+            serving_override: Arc::default(),
+            atomic_swap: false,
+            versions_dir: PathBuf::from(format!("trends-story-{}-versions", name)),
+            watch_files: false,
+            db_immutable: false,
+            images_dir: repo_path.join("images"),
+            image_bucket: None,
+            placeholders_path: PathBuf::from(format!("trends-story-{}-placeholders.json", name)),
+            thumbnails_dir: PathBuf::from(format!("trends-story-{}-thumbnails", name)),
+            thumbnail_widths: Vec::new(),
+            overlay_path: PathBuf::from(format!("trends-story-{}-overlay.db", name)),
+            edits_path: PathBuf::from(format!("trends-story-{}-edits.db", name)),
+            translator: None,
+            translations_path: PathBuf::from(format!("trends-story-{}-translations.db", name)),
+            sentiment_path: PathBuf::from(format!("trends-story-{}-sentiment.db", name)),
+            embedder: None,
+            embeddings_path: PathBuf::from(format!("trends-story-{}-embeddings.db", name)),
+            translate_to: None,
+            translations_pending: Arc::default(),
+            keep_news_html: false,
+            tag_map: Arc::default(),
+            cache_ttls: Arc::default(),
+            lookups: Arc::default(),
+            threads: Arc::default(),
+            placeholders: Arc::default(),
+            connections: Arc::default(),
+            base_urls: Arc::default(),
+            repo_path,
+        }
+    }
+
+    // The database file requests read: db_path, or its known-good copy while it fails validation,
+    // or with the atomic swap the published one
+    pub(crate) fn serving_path(&self) -> PathBuf {
+        if self.atomic_swap {
+            return self.versions_dir.join(CURRENT_VERSION).join("trends_data.db");
+        }
+        let serving = self.serving_override.read().ok().and_then(|path| path.clone());
+        serving.unwrap_or_else(|| self.db_path.clone())
+    }
+
+    // The image directory requests read: images_dir, or with the atomic swap the published one
+    pub(crate) fn serving_images_dir(&self) -> PathBuf {
+        if self.atomic_swap {
+            return self.versions_dir.join(CURRENT_VERSION).join("images");
+        }
+        self.images_dir.clone()
+    }
+
+    // Where the source's data comes from: its snapshot URL, or else its repository
+    pub fn origin(&self) -> &str {
+        self.snapshot_url.as_deref().unwrap_or(&self.repo_url)
+    }
+
+    // origin() for responses: without a user name and password written into the URL
+    pub(crate) fn public_origin(&self) -> String {
+        let origin = self.origin();
+        let Some((scheme, rest)) = origin.split_once("://") else {
+            return origin.to_string();
+        };
+        let authority_end = rest.find('/').unwrap_or(rest.len());
+        match rest[..authority_end].rsplit_once('@') {
+            Some((_, host)) => format!("{}://{}{}", scheme, host, &rest[authority_end..]),
+            None => origin.to_string(),
+        }
+    }
+
+    // Path prefix of this source's routes ("" for the default source, "/jp" otherwise)
+    pub fn url_prefix(&self) -> String {
+        self.name.as_ref().map(|name| format!("/{}", name)).unwrap_or_default()
+    }
+}
+
+// First path segments already taken by routes, which a source name must not shadow
+pub(crate) const RESERVED_SOURCE_NAMES: [&str; 25] = [
+    "latest", "dates", "date", "health", "stats", "analytics", "tags", "news", "onthisday", "week", "images",
+    "thumbnails", "admin", "export", "search", "year", "recent", "metrics", "version", "schema",
+    "changes", "meta", "serpapi", "threads", "sync",
+];
+
+// Boolean environment variable: set to 1/true/yes to enable
+pub(crate) fn env_flag(name: &str) -> bool {
+    std::env::var(name)
+        .map(|v| matches!(v.trim().to_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+}
+
+// Git remote to sync from: a URL (https, http, ssh, git or file), an scp-like [user@]host:path
+// or an existing local directory. Nothing git could take for an option or a remote helper gets
+// through.
+pub(crate) fn parse_repo_url(raw: &str) -> Result<String, String> {
+    let url = raw.trim();
+    if url.is_empty() || url.starts_with('-') || url.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(format!("'{}' is not a repository URL", url));
+    }
+    let valid = match url.split_once("://") {
+        Some((scheme, rest)) => {
+            matches!(scheme.to_lowercase().as_str(), "https" | "http" | "ssh" | "git" | "file") && !rest.is_empty()
+        }
+        None => match url.split_once(':') {
+            // host::address would run git-remote-<host> instead
+            Some((host, path)) => !host.is_empty() && !host.contains('/') && !path.is_empty() && !path.starts_with(':'),
+            None => Path::new(url).is_dir(),
+        },
+    };
+    if valid { Ok(url.to_string()) } else { Err(format!("'{}' is not a repository URL", url)) }
+}
+
+// Branch name as `git check-ref-format --branch` accepts them, minus the rarer forms
+pub(crate) fn parse_branch(raw: &str) -> Result<String, String> {
+    let branch = raw.trim();
+    let valid = !branch.is_empty()
+        && !branch.starts_with(['-', '/', '.'])
+        && !branch.ends_with(['/', '.'])
+        && !branch.ends_with(".lock")
+        && !["..", "//", "/.", "@{"].iter().any(|bad| branch.contains(bad))
+        && !branch.chars().any(|c| c.is_whitespace() || c.is_control() || "~^:?*[\\".contains(c));
+    if valid { Ok(branch.to_string()) } else { Err(format!("'{}' is not a branch name", branch)) }
+}
+
+// Positive request count, as accepted by the in-flight limit settings
+fn parse_limit(raw: &str) -> Option<usize> {
+    raw.trim().parse::<usize>().ok().filter(|limit| *limit > 0)
+}
+
+fn env_limit(name: &str) -> Option<usize> {
+    let raw = std::env::var(name).ok()?;
+    let limit = parse_limit(&raw);
+    if limit.is_none() {
+        eprintln!("Ignoring {}: expected a positive number", name);
+    }
+    limit
+}
+
+// Runtime settings, read at startup from the environment and overridable by command-line flags
+#[derive(Debug, Clone)]
+pub struct Config {
+    // The first source is the default one, served without a prefix
+    pub sources: Vec<DataSource>,
+    // In-flight request limits, across all routes and per route (e.g. all /date/... requests)
+    pub max_in_flight: usize,
+    pub max_in_flight_per_route: usize,
+    // Largest request body (POST and PATCH /admin/news) and query string accepted
+    pub max_body_bytes: usize,
+    pub max_query_bytes: usize,
+    // Frontend build to serve at / (index.html for client-side routes); API routes take precedence
+    pub static_dir: Option<PathBuf>,
+    // Bearer token for /admin/... routes, a file with more of them, and the JWTs accepted there;
+    // admin routes are disabled when none is set
+    pub admin_token: Option<String>,
+    pub admin_tokens_file: Option<PathBuf>,
+    pub jwt: JwtSettings,
+    // API keys with their rate limits and daily quotas, whether requests need one, and the SQLite
+    // file their usage is counted in; keys aren't checked without the keys file
+    pub api_keys_file: Option<PathBuf>,
+    pub api_key_required: bool,
+    pub api_usage_path: PathBuf,
+    // Sentry project receiving server errors, panics and repeated sync failures; none when unset
+    pub sentry_dsn: Option<String>,
+    // File receiving the server's output and access lines instead of stdout; none when unset
+    pub log_file: Option<LogFile>,
+    // Contents of /robots.txt
+    pub robots_txt: String,
+    // Proxies whose forwarding headers name the client of a request
+    pub trusted_proxies: TrustedProxies,
+}
+
+impl Config {
+    pub fn load() -> Config {
+        let mut default_source = DataSource::default_source();
+        if let Ok(path) = std::env::var("TREND_STORY_DB_PATH") {
+            default_source.db_path = PathBuf::from(path);
+        }
+        let mut config = Config {
+            sources: vec![default_source],
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            max_in_flight_per_route: DEFAULT_MAX_IN_FLIGHT_PER_ROUTE,
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+            max_query_bytes: DEFAULT_MAX_QUERY_BYTES,
+            static_dir: std::env::var("TREND_STORY_STATIC_DIR").ok().map(PathBuf::from),
+            admin_token: std::env::var("TREND_STORY_ADMIN_TOKEN").ok().filter(|t| !t.is_empty()),
+            admin_tokens_file: std::env::var("TREND_STORY_ADMIN_TOKENS_FILE").ok().filter(|p| !p.is_empty()).map(PathBuf::from),
+            jwt: JwtSettings {
+                secret: std::env::var("TREND_STORY_JWT_SECRET").ok().filter(|s| !s.is_empty()),
+                public_key: std::env::var("TREND_STORY_JWT_PUBLIC_KEY").ok().filter(|p| !p.is_empty()).map(PathBuf::from),
+                jwks_url: None,
+                issuer: std::env::var("TREND_STORY_JWT_ISSUER").ok().filter(|i| !i.is_empty()),
+                audience: std::env::var("TREND_STORY_JWT_AUDIENCE").ok().filter(|a| !a.is_empty()),
+                admin_scope: std::env::var("TREND_STORY_JWT_ADMIN_SCOPE").ok().filter(|s| !s.is_empty()),
+            },
+            api_keys_file: std::env::var("TREND_STORY_API_KEYS_FILE").ok().filter(|p| !p.is_empty()).map(PathBuf::from),
+            api_key_required: env_flag("TREND_STORY_API_KEY_REQUIRED"),
+            api_usage_path: std::env::var("TREND_STORY_API_USAGE_DB")
+                .ok()
+                .filter(|p| !p.is_empty())
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("trends-story-usage.db")),
+            sentry_dsn: std::env::var("TREND_STORY_SENTRY_DSN").ok().filter(|dsn| !dsn.is_empty()),
+            log_file: None,
+            robots_txt: DEFAULT_ROBOTS_TXT.to_string(),
+            trusted_proxies: TrustedProxies::default(),
+        };
+        if let Some(limit) = env_limit("TREND_STORY_MAX_IN_FLIGHT") {
+            config.max_in_flight = limit;
+        }
+        if let Some(limit) = env_limit("TREND_STORY_MAX_IN_FLIGHT_PER_ROUTE") {
+            config.max_in_flight_per_route = limit;
+        }
+        if let Some(limit) = env_limit("TREND_STORY_MAX_BODY_BYTES") {
+            config.max_body_bytes = limit;
+        }
+        if let Some(limit) = env_limit("TREND_STORY_MAX_QUERY_BYTES") {
+            config.max_query_bytes = limit;
+        }
+        let mut shallow_sync = env_flag("TREND_STORY_SHALLOW_SYNC");
+        let mut clone_depth = env_limit("TREND_STORY_CLONE_DEPTH");
+        let mut repo_url = std::env::var("TREND_STORY_REPO_URL").ok().filter(|u| !u.is_empty());
+        let mut branch = std::env::var("TREND_STORY_BRANCH").ok().filter(|b| !b.is_empty());
+        let mut git_credentials = GitCredentials {
+            username: std::env::var("TREND_STORY_GIT_USERNAME")
+                .ok()
+                .filter(|u| !u.is_empty())
+                .unwrap_or_else(|| DEFAULT_GIT_USERNAME.to_string()),
+            token: std::env::var("TREND_STORY_GIT_TOKEN").ok().filter(|t| !t.is_empty()),
+            ssh_key: std::env::var("TREND_STORY_GIT_SSH_KEY").ok().filter(|k| !k.is_empty()).map(PathBuf::from),
+        };
+        let mut git_token_file = std::env::var("TREND_STORY_GIT_TOKEN_FILE").ok().filter(|p| !p.is_empty()).map(PathBuf::from);
+        let mut atomic_swap = env_flag("TREND_STORY_ATOMIC_SWAP");
+        let mut watch_files = env_flag("TREND_STORY_WATCH_FILES");
+        let mut sparse_image_days = env_limit("TREND_STORY_SPARSE_IMAGE_DAYS");
+        let mut trust = TrustPolicy::default();
+        if let Ok(raw) = std::env::var("TREND_STORY_TRUSTED_SIGNING_KEYS") {
+            match parse_fingerprints(&raw) {
+                Ok(keys) => trust.signing_keys = keys,
+                Err(e) => eprintln!("Ignoring TREND_STORY_TRUSTED_SIGNING_KEYS: {}", e),
+            }
+        }
+        if let Ok(raw) = std::env::var("TREND_STORY_TRUSTED_COMMITTERS") {
+            trust.committers = parse_committers(&raw);
+        }
+        let mut snapshot_url = std::env::var("TREND_STORY_SNAPSHOT_URL").ok().filter(|u| !u.is_empty());
+        let mut s3_endpoint = std::env::var("TREND_STORY_S3_ENDPOINT").ok().filter(|v| !v.is_empty());
+        let mut s3_bucket = std::env::var("TREND_STORY_S3_BUCKET").ok().filter(|v| !v.is_empty());
+        let mut s3_region = std::env::var("TREND_STORY_S3_REGION").ok().filter(|v| !v.is_empty());
+        let mut s3_prefix = std::env::var("TREND_STORY_S3_PREFIX").ok().filter(|v| !v.is_empty());
+        let mut s3_access_key = std::env::var("TREND_STORY_S3_ACCESS_KEY").ok().filter(|v| !v.is_empty());
+        let mut s3_secret_key = std::env::var("TREND_STORY_S3_SECRET_KEY").ok().filter(|v| !v.is_empty());
+        let mut s3_delivery = ImageDelivery::Redirect;
+        if let Ok(raw) = std::env::var("TREND_STORY_S3_DELIVERY") {
+            match ImageDelivery::parse(&raw) {
+                Some(delivery) => s3_delivery = delivery,
+                None => eprintln!("Ignoring TREND_STORY_S3_DELIVERY: expected redirect or proxy"),
+            }
+        }
+        let mut db_immutable = env_flag("TREND_STORY_DB_IMMUTABLE");
+        let mut keep_news_html = env_flag("TREND_STORY_KEEP_NEWS_HTML");
+        let mut translate_url = std::env::var("TREND_STORY_TRANSLATE_URL").ok().filter(|u| !u.is_empty());
+        let mut translate_key = std::env::var("TREND_STORY_TRANSLATE_KEY").ok().filter(|k| !k.is_empty());
+        let mut translate_languages = std::env::var("TREND_STORY_TRANSLATE_LANGUAGES").ok().filter(|l| !l.is_empty());
+        let mut embeddings_url = std::env::var("TREND_STORY_EMBEDDINGS_URL").ok().filter(|u| !u.is_empty());
+        let mut embeddings_model = std::env::var("TREND_STORY_EMBEDDINGS_MODEL").ok().filter(|m| !m.is_empty());
+        let mut embeddings_key = std::env::var("TREND_STORY_EMBEDDINGS_KEY").ok().filter(|k| !k.is_empty());
+        let mut jwks_url = std::env::var("TREND_STORY_JWKS_URL").ok().filter(|u| !u.is_empty());
+        let mut tag_map_path = std::env::var("TREND_STORY_TAG_MAP").ok().filter(|p| !p.is_empty()).map(PathBuf::from);
+        if let Ok(raw) = std::env::var("TREND_STORY_TRUSTED_PROXIES") {
+            match TrustedProxies::parse(&raw) {
+                Ok(proxies) => config.trusted_proxies = proxies,
+                Err(e) => eprintln!("Ignoring TREND_STORY_TRUSTED_PROXIES: {}", e),
+            }
+        }
+        let mut cache_ttls = CacheTtls::default();
+        if let Ok(raw) = std::env::var("TREND_STORY_CACHE_TTLS") {
+            for e in cache_ttls.apply(&raw) {
+                eprintln!("Ignoring TREND_STORY_CACHE_TTLS entry: {}", e);
+            }
+        }
+        let mut base_urls = BaseUrls::default();
+        for (name, url) in [("TREND_STORY_API_URL", &mut base_urls.api), ("TREND_STORY_SITE_URL", &mut base_urls.site)] {
+            if let Ok(raw) = std::env::var(name) {
+                match parse_base_url(&raw) {
+                    Ok(parsed) => *url = parsed,
+                    Err(e) => eprintln!("Ignoring {}: {}", name, e),
+                }
+            }
+        }
+        let mut urls_from_host = env_flag("TREND_STORY_URLS_FROM_HOST");
+        base_urls.relative = env_flag("TREND_STORY_RELATIVE_URLS");
+        let mut thumbnail_widths = Vec::new();
+        if let Ok(raw) = std::env::var("TREND_STORY_THUMBNAIL_WIDTHS") {
+            match parse_widths(&raw) {
+                Ok(widths) => thumbnail_widths = widths,
+                Err(e) => eprintln!("Ignoring TREND_STORY_THUMBNAIL_WIDTHS: {}", e),
+            }
+        }
+        let mut robots_path = std::env::var("TREND_STORY_ROBOTS_TXT").ok().filter(|p| !p.is_empty()).map(PathBuf::from);
+        let mut log_path = std::env::var("TREND_STORY_LOG_FILE").ok().filter(|p| !p.is_empty()).map(PathBuf::from);
+        let mut log_rotation = LogRotation::Daily;
+        if let Ok(raw) = std::env::var("TREND_STORY_LOG_ROTATE") {
+            match LogRotation::parse(&raw) {
+                Some(rotation) => log_rotation = rotation,
+                None => eprintln!("Ignoring TREND_STORY_LOG_ROTATE: expected daily or a size like 10M"),
+            }
+        }
+        let mut log_keep = env_limit("TREND_STORY_LOG_KEEP").unwrap_or(DEFAULT_LOG_KEEP);
+
+        // Extra sources as a comma-separated list of name=repo_url pairs
+        if let Ok(sources) = std::env::var("TREND_STORY_SOURCES") {
+            for spec in sources.split(',').filter(|spec| !spec.trim().is_empty()) {
+                config.add_source(spec.trim());
+            }
+        }
+
+        // Flags accept both "--flag value" and "--flag=value"
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+                None => (arg.clone(), None),
+            };
+            let mut value = || inline_value.clone().or_else(|| args.next());
+            match flag.as_str() {
+                "--db-path" => match value() {
+                    Some(path) => config.sources[0].db_path = PathBuf::from(path),
+                    None => eprintln!("Missing value for --db-path"),
+                },
+                "--source" => match value() {
+                    Some(spec) => config.add_source(&spec),
+                    None => eprintln!("Missing value for --source"),
+                },
+                "--repo-url" => match value().filter(|u| !u.is_empty()) {
+                    Some(url) => repo_url = Some(url),
+                    None => eprintln!("Missing value for --repo-url"),
+                },
+                "--branch" => match value().filter(|b| !b.is_empty()) {
+                    Some(name) => branch = Some(name),
+                    None => eprintln!("Missing value for --branch"),
+                },
+                "--git-username" => match value().filter(|u| !u.is_empty()) {
+                    Some(username) => git_credentials.username = username,
+                    None => eprintln!("Missing value for --git-username"),
+                },
+                // Not taken from the command line, which every local user can read
+                "--git-token" => {
+                    let _ = value();
+                    eprintln!("Ignoring --git-token: set TREND_STORY_GIT_TOKEN or --git-token-file instead");
+                }
+                "--git-token-file" => match value().filter(|p| !p.is_empty()) {
+                    Some(path) => git_token_file = Some(PathBuf::from(path)),
+                    None => eprintln!("Missing value for --git-token-file"),
+                },
+                "--git-ssh-key" => match value().filter(|k| !k.is_empty()) {
+                    Some(path) => git_credentials.ssh_key = Some(PathBuf::from(path)),
+                    None => eprintln!("Missing value for --git-ssh-key"),
+                },
+                "--shallow-sync" => shallow_sync = true,
+                "--clone-depth" => match value().as_deref().and_then(parse_limit) {
+                    Some(depth) => clone_depth = Some(depth),
+                    None => eprintln!("Expected a positive number for --clone-depth"),
+                },
+                "--atomic-swap" => atomic_swap = true,
+                "--watch-files" => watch_files = true,
+                "--sparse-image-days" => match value().as_deref().and_then(parse_limit) {
+                    Some(days) => sparse_image_days = Some(days),
+                    None => eprintln!("Expected a positive number for --sparse-image-days"),
+                },
+                "--trusted-signing-keys" => match value().map(|raw| parse_fingerprints(&raw)) {
+                    Some(Ok(keys)) => trust.signing_keys = keys,
+                    Some(Err(e)) => eprintln!("Ignoring --trusted-signing-keys: {}", e),
+                    None => eprintln!("Missing value for --trusted-signing-keys"),
+                },
+                "--trusted-committers" => match value() {
+                    Some(raw) => trust.committers = parse_committers(&raw),
+                    None => eprintln!("Missing value for --trusted-committers"),
+                },
+                "--snapshot-url" => match value().filter(|u| !u.is_empty()) {
+                    Some(url) => snapshot_url = Some(url),
+                    None => eprintln!("Missing value for --snapshot-url"),
+                },
+                "--s3-endpoint" => match value().filter(|u| !u.is_empty()) {
+                    Some(url) => s3_endpoint = Some(url),
+                    None => eprintln!("Missing value for --s3-endpoint"),
+                },
+                "--s3-bucket" => match value().filter(|b| !b.is_empty()) {
+                    Some(bucket) => s3_bucket = Some(bucket),
+                    None => eprintln!("Missing value for --s3-bucket"),
+                },
+                "--s3-region" => match value().filter(|r| !r.is_empty()) {
+                    Some(region) => s3_region = Some(region),
+                    None => eprintln!("Missing value for --s3-region"),
+                },
+                "--s3-prefix" => match value() {
+                    Some(prefix) => s3_prefix = Some(prefix),
+                    None => eprintln!("Missing value for --s3-prefix"),
+                },
+                "--s3-access-key" => match value().filter(|k| !k.is_empty()) {
+                    Some(key) => s3_access_key = Some(key),
+                    None => eprintln!("Missing value for --s3-access-key"),
+                },
+                "--s3-secret-key" => match value().filter(|k| !k.is_empty()) {
+                    Some(key) => s3_secret_key = Some(key),
+                    None => eprintln!("Missing value for --s3-secret-key"),
+                },
+                "--s3-delivery" => match value().as_deref().and_then(ImageDelivery::parse) {
+                    Some(delivery) => s3_delivery = delivery,
+                    None => eprintln!("Expected redirect or proxy for --s3-delivery"),
+                },
+                "--db-immutable" => db_immutable = true,
+                "--keep-news-html" => keep_news_html = true,
+                "--translate-url" => match value().filter(|u| !u.is_empty()) {
+                    Some(url) => translate_url = Some(url),
+                    None => eprintln!("Missing value for --translate-url"),
+                },
+                "--translate-key" => match value().filter(|k| !k.is_empty()) {
+                    Some(key) => translate_key = Some(key),
+                    None => eprintln!("Missing value for --translate-key"),
+                },
+                "--translate-languages" => match value().filter(|l| !l.is_empty()) {
+                    Some(languages) => translate_languages = Some(languages),
+                    None => eprintln!("Missing value for --translate-languages"),
+                },
+                "--embeddings-url" => match value().filter(|u| !u.is_empty()) {
+                    Some(url) => embeddings_url = Some(url),
+                    None => eprintln!("Missing value for --embeddings-url"),
+                },
+                "--embeddings-model" => match value().filter(|m| !m.is_empty()) {
+                    Some(model) => embeddings_model = Some(model),
+                    None => eprintln!("Missing value for --embeddings-model"),
+                },
+                "--embeddings-key" => match value().filter(|k| !k.is_empty()) {
+                    Some(key) => embeddings_key = Some(key),
+                    None => eprintln!("Missing value for --embeddings-key"),
+                },
+                "--admin-token" => match value().filter(|t| !t.is_empty()) {
+                    Some(token) => config.admin_token = Some(token),
+                    None => eprintln!("Missing value for --admin-token"),
+                },
+                "--admin-tokens-file" => match value().filter(|p| !p.is_empty()) {
+                    Some(path) => config.admin_tokens_file = Some(PathBuf::from(path)),
+                    None => eprintln!("Missing value for --admin-tokens-file"),
+                },
+                "--api-keys-file" => match value().filter(|p| !p.is_empty()) {
+                    Some(path) => config.api_keys_file = Some(PathBuf::from(path)),
+                    None => eprintln!("Missing value for --api-keys-file"),
+                },
+                "--api-key-required" => config.api_key_required = true,
+                "--api-usage-db" => match value().filter(|p| !p.is_empty()) {
+                    Some(path) => config.api_usage_path = PathBuf::from(path),
+                    None => eprintln!("Missing value for --api-usage-db"),
+                },
+                "--jwt-secret" => match value().filter(|s| !s.is_empty()) {
+                    Some(secret) => config.jwt.secret = Some(secret),
+                    None => eprintln!("Missing value for --jwt-secret"),
+                },
+                "--jwt-public-key" => match value().filter(|p| !p.is_empty()) {
+                    Some(path) => config.jwt.public_key = Some(PathBuf::from(path)),
+                    None => eprintln!("Missing value for --jwt-public-key"),
+                },
+                "--jwks-url" => match value().filter(|u| !u.is_empty()) {
+                    Some(url) => jwks_url = Some(url),
+                    None => eprintln!("Missing value for --jwks-url"),
+                },
+                "--jwt-issuer" => match value().filter(|i| !i.is_empty()) {
+                    Some(issuer) => config.jwt.issuer = Some(issuer),
+                    None => eprintln!("Missing value for --jwt-issuer"),
+                },
+                "--jwt-audience" => match value().filter(|a| !a.is_empty()) {
+                    Some(audience) => config.jwt.audience = Some(audience),
+                    None => eprintln!("Missing value for --jwt-audience"),
+                },
+                "--jwt-admin-scope" => match value().filter(|s| !s.is_empty()) {
+                    Some(scope) => config.jwt.admin_scope = Some(scope),
+                    None => eprintln!("Missing value for --jwt-admin-scope"),
+                },
+                "--tag-map" => match value() {
+                    Some(path) => tag_map_path = Some(PathBuf::from(path)),
+                    None => eprintln!("Missing value for --tag-map"),
+                },
+                "--sentry-dsn" => match value().filter(|dsn| !dsn.is_empty()) {
+                    Some(dsn) => config.sentry_dsn = Some(dsn),
+                    None => eprintln!("Missing value for --sentry-dsn"),
+                },
+                "--trusted-proxies" => match value().map(|raw| TrustedProxies::parse(&raw)) {
+                    Some(Ok(proxies)) => config.trusted_proxies = proxies,
+                    Some(Err(e)) => eprintln!("Ignoring --trusted-proxies: {}", e),
+                    None => eprintln!("Missing value for --trusted-proxies"),
+                },
+                "--cache-ttls" => match value() {
+                    Some(raw) => {
+                        for e in cache_ttls.apply(&raw) {
+                            eprintln!("Ignoring --cache-ttls entry: {}", e);
+                        }
+                    }
+                    None => eprintln!("Missing value for --cache-ttls"),
+                },
+                "--api-url" => match value().map(|raw| parse_base_url(&raw)) {
+                    Some(Ok(url)) => base_urls.api = url,
+                    Some(Err(e)) => eprintln!("Ignoring --api-url: {}", e),
+                    None => eprintln!("Missing value for --api-url"),
+                },
+                "--site-url" => match value().map(|raw| parse_base_url(&raw)) {
+                    Some(Ok(url)) => base_urls.site = url,
+                    Some(Err(e)) => eprintln!("Ignoring --site-url: {}", e),
+                    None => eprintln!("Missing value for --site-url"),
+                },
+                "--urls-from-host" => urls_from_host = true,
+                "--relative-urls" => base_urls.relative = true,
+                "--thumbnail-widths" => match value().map(|raw| parse_widths(&raw)) {
+                    Some(Ok(widths)) => thumbnail_widths = widths,
+                    Some(Err(e)) => eprintln!("Ignoring --thumbnail-widths: {}", e),
+                    None => eprintln!("Missing value for --thumbnail-widths"),
+                },
+                "--robots-txt" => match value() {
+                    Some(path) => robots_path = Some(PathBuf::from(path)),
+                    None => eprintln!("Missing value for --robots-txt"),
+                },
+                "--log-file" => match value().filter(|p| !p.is_empty()) {
+                    Some(path) => log_path = Some(PathBuf::from(path)),
+                    None => eprintln!("Missing value for --log-file"),
+                },
+                "--log-rotate" => match value().as_deref().and_then(LogRotation::parse) {
+                    Some(rotation) => log_rotation = rotation,
+                    None => eprintln!("Expected daily or a size like 10M for --log-rotate"),
+                },
+                "--log-keep" => match value().as_deref().and_then(parse_limit) {
+                    Some(keep) => log_keep = keep,
+                    None => eprintln!("Expected a positive number for --log-keep"),
+                },
+                "--static-dir" => match value() {
+                    Some(dir) => config.static_dir = Some(PathBuf::from(dir)),
+                    None => eprintln!("Missing value for --static-dir"),
+                },
+                "--max-in-flight" => match value().as_deref().and_then(parse_limit) {
+                    Some(limit) => config.max_in_flight = limit,
+                    None => eprintln!("Expected a positive number for --max-in-flight"),
+                },
+                "--max-in-flight-per-route" => match value().as_deref().and_then(parse_limit) {
+                    Some(limit) => config.max_in_flight_per_route = limit,
+                    None => eprintln!("Expected a positive number for --max-in-flight-per-route"),
+                },
+                "--max-body-bytes" => match value().as_deref().and_then(parse_limit) {
+                    Some(limit) => config.max_body_bytes = limit,
+                    None => eprintln!("Expected a positive number for --max-body-bytes"),
+                },
+                "--max-query-bytes" => match value().as_deref().and_then(parse_limit) {
+                    Some(limit) => config.max_query_bytes = limit,
+                    None => eprintln!("Expected a positive number for --max-query-bytes"),
+                },
+                _ => eprintln!("Ignoring unknown argument: {}", arg),
+            }
+        }
+
+        // A mapping that can't be read leaves tags as they are in the data
+        let tag_map = match &tag_map_path {
+            Some(path) => TagMap::load(path).unwrap_or_else(|e| {
+                eprintln!("Ignoring tag map {}: {}", path.display(), e);
+                TagMap::default()
+            }),
+            None => TagMap::default(),
+        };
+        let tag_map = Arc::new(tag_map);
+        let cache_ttls = Arc::new(cache_ttls);
+        if urls_from_host {
+            base_urls.from_host = Some(config.trusted_proxies.clone());
+        }
+        let base_urls = Arc::new(base_urls);
+        let languages = match parse_languages(translate_languages.as_deref().unwrap_or(DEFAULT_TRANSLATE_LANGUAGES)) {
+            Ok(languages) => languages,
+            Err(e) => {
+                eprintln!("Ignoring the translation languages, offering {}: {}", DEFAULT_TRANSLATE_LANGUAGES, e);
+                parse_languages(DEFAULT_TRANSLATE_LANGUAGES).unwrap_or_default()
+            }
+        };
+        let translator = match translate_url.map(|raw| parse_base_url(&raw)) {
+            Some(Ok(url)) if cfg!(feature = "translation") => Some(Arc::new(Translator::new(url, translate_key, languages))),
+            Some(Ok(_)) => {
+                eprintln!("Ignoring the translation provider: built without the translation feature");
+                None
+            }
+            Some(Err(e)) => {
+                eprintln!("Ignoring the translation provider: {}", e);
+                None
+            }
+            None => None,
+        };
+        let embedder = match embeddings_url.map(|raw| parse_base_url(&raw)) {
+            Some(Ok(url)) if cfg!(feature = "embeddings") => {
+                Some(Arc::new(Embedder { url, model: embeddings_model, api_key: embeddings_key }))
+            }
+            Some(Ok(_)) => {
+                eprintln!("Ignoring the embeddings endpoint: built without the embeddings feature");
+                None
+            }
+            Some(Err(e)) => {
+                eprintln!("Ignoring the embeddings endpoint: {}", e);
+                None
+            }
+            None => None,
+        };
+        config.sources[0].snapshot_url = match snapshot_url.map(|raw| parse_base_url(&raw)) {
+            Some(Ok(url)) => Some(url),
+            Some(Err(e)) => {
+                eprintln!("Ignoring the snapshot URL: {}", e);
+                None
+            }
+            None => None,
+        };
+        if let Some(bucket) = s3_bucket {
+            let region = s3_region.unwrap_or_else(|| DEFAULT_S3_REGION.to_string());
+            let endpoint = s3_endpoint.unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", region));
+            if s3_access_key.is_some() != s3_secret_key.is_some() {
+                eprintln!("Ignoring the S3 access key: both an access key and a secret key are needed");
+                s3_access_key = None;
+                s3_secret_key = None;
+            }
+            match parse_base_url(&endpoint) {
+                Ok(endpoint) => {
+                    config.sources[0].image_bucket = Some(Arc::new(ImageBucket {
+                        endpoint,
+                        bucket,
+                        region,
+                        prefix: s3_prefix.unwrap_or_default(),
+                        access_key: s3_access_key,
+                        secret_key: s3_secret_key,
+                        delivery: s3_delivery,
+                    }))
+                }
+                Err(e) => eprintln!("Ignoring the image bucket: {}", e),
+            }
+        }
+        config.jwt.jwks_url = match jwks_url.map(|raw| parse_base_url(&raw)) {
+            Some(Ok(url)) => Some(url),
+            Some(Err(e)) => {
+                eprintln!("Ignoring the JWKS URL: {}", e);
+                None
+            }
+            None => None,
+        };
+        if let Some(path) = &robots_path {
+            match std::fs::read_to_string(path) {
+                Ok(robots_txt) => config.robots_txt = robots_txt,
+                Err(e) => eprintln!("Ignoring robots.txt {}: {}", path.display(), e),
+            }
+        }
+        config.log_file = log_path.map(|path| LogFile { path, rotation: log_rotation, keep: log_keep });
+        match repo_url.map(|raw| parse_repo_url(&raw)) {
+            Some(Ok(url)) => config.sources[0].repo_url = url,
+            Some(Err(e)) => eprintln!("Ignoring the repository URL, syncing {}: {}", DEFAULT_REPO_URL, e),
+            None => {}
+        }
+        match branch.map(|raw| parse_branch(&raw)) {
+            Some(Ok(name)) => config.sources[0].branch = Some(name),
+            Some(Err(e)) => eprintln!("Ignoring the branch, syncing the default one: {}", e),
+            None => {}
+        }
+        if let Some(path) = &git_token_file {
+            match std::fs::read_to_string(path).map(|token| token.trim().to_string()) {
+                Ok(token) if !token.is_empty() => git_credentials.token = Some(token),
+                Ok(_) => eprintln!("Ignoring the git token file {}: it is empty", path.display()),
+                Err(e) => eprintln!("Ignoring the git token file {}: {}", path.display(), e),
+            }
+        }
+        if git_credentials.token.is_some() && config.sources[0].repo_url.to_lowercase().starts_with("http://") {
+            eprintln!("Ignoring the git token: it would be sent unencrypted to {}", config.sources[0].repo_url);
+            git_credentials.token = None;
+        }
+        if let Some(key) = git_credentials.ssh_key.take() {
+            match std::fs::metadata(&key) {
+                Ok(metadata) if metadata.is_file() => git_credentials.ssh_key = Some(key),
+                _ => eprintln!("Ignoring the git SSH key: {} is not a readable file", key.display()),
+            }
+        }
+        if !git_credentials.is_empty() {
+            config.sources[0].git_credentials = Some(Arc::new(git_credentials));
+        }
+        let clone_depth = clone_depth.or(shallow_sync.then_some(1)).map(|depth| depth.min(u32::MAX as usize) as u32);
+        let trust = (!trust.is_empty()).then(|| Arc::new(trust));
+        if trust.is_some() && config.sources[0].snapshot_url.is_some() {
+            eprintln!("The trust policy doesn't apply to the snapshot sync of the default source");
+        }
+        for source in &mut config.sources {
+            source.trust = trust.clone();
+            source.clone_depth = clone_depth;
+            source.atomic_swap = atomic_swap;
+            source.watch_files = watch_files;
+            source.sparse_image_days = sparse_image_days.map(|days| days.min(MAX_SPARSE_IMAGE_DAYS) as u32);
+            source.db_immutable = db_immutable;
+            source.keep_news_html = keep_news_html;
+            source.translator = translator.clone();
+            source.embedder = embedder.clone();
+            source.tag_map = tag_map.clone();
+            source.cache_ttls = cache_ttls.clone();
+            source.thumbnail_widths = thumbnail_widths.clone();
+            source.base_urls = base_urls.clone();
+        }
+        if let Some(dir) = &config.static_dir {
+            if !dir.join("index.html").is_file() {
+                eprintln!("Static directory {} has no index.html", dir.display());
+            }
+        }
+        config
+    }
+
+    // Register a "name=repo_url" or "name=repo_url#branch" source, skipping invalid or duplicate
+    // names, URLs and branches
+    pub(crate) fn add_source(&mut self, spec: &str) {
+        let Some((name, remote)) = spec.split_once('=') else {
+            eprintln!("Ignoring source '{}': expected name=repo_url", spec);
+            return;
+        };
+        let (repo_url, branch) = match remote.rsplit_once('#') {
+            Some((repo_url, branch)) => (repo_url, Some(branch)),
+            None => (remote, None),
+        };
+        let remote = parse_repo_url(repo_url).and_then(|url| Ok((url, branch.map(parse_branch).transpose()?)));
+        let (repo_url, branch) = match remote {
+            Ok(remote) => remote,
+            Err(e) => {
+                eprintln!("Ignoring source '{}': {}", spec, e);
+                return;
+            }
+        };
+        let name = name.trim().to_lowercase();
+        let valid_name = !name.is_empty()
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            && !RESERVED_SOURCE_NAMES.contains(&name.as_str());
+        if !valid_name || self.sources.iter().any(|s| s.name.as_deref() == Some(name.as_str())) {
+            eprintln!("Ignoring source '{}': invalid or duplicate name", spec);
+            return;
+        }
+        let mut source = DataSource::named(&name, &repo_url);
+        source.branch = branch;
+        self.sources.push(source);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_branch, parse_repo_url};
+
+    #[test]
+    fn accepts_repository_urls() {
+        let local = std::env::temp_dir();
+        for url in [
+            "https://github.com/sudoghut/trend-story-data.git",
+            " HTTP://mirror.local:8080/data ",
+            "ssh://git@github.com/sudoghut/data.git",
+            "git://example.org/data",
+            "file:///srv/git/data",
+            "git@github.com:sudoghut/data.git",
+            &*local.to_string_lossy(),
+        ] {
+            assert_eq!(parse_repo_url(url).as_deref(), Ok(url.trim()), "{}", url);
+        }
+    }
+
+    #[test]
+    fn rejects_what_git_could_read_as_an_option_or_another_transport() {
+        for url in [
+            "",
+            "   ",
+            "--upload-pack=touch /tmp/pwned",
+            "-c core.sshCommand=evil",
+            "https://github.com/a b",
+            "https://github.com/a\nb",
+            "ext::sh -c evil",
+            "fd::3",
+            "ftp://example.org/data",
+            "https://",
+            ":path",
+            "host/dir:path",
+            "host:",
+            "/no/such/directory/for/trend-story",
+        ] {
+            assert!(parse_repo_url(url).is_err(), "{:?}", url);
+        }
+    }
+
+    #[test]
+    fn accepts_branch_names() {
+        for branch in ["main", " data ", "release/2025-11", "feature/a.b_c", "v1.0", "x@y"] {
+            assert_eq!(parse_branch(branch).as_deref(), Ok(branch.trim()), "{}", branch);
+        }
+    }
+
+    #[test]
+    fn rejects_branch_names_git_refuses_or_reads_as_options() {
+        for branch in [
+            "", "-f", "--orphan", "/main", "main/", ".hidden", "main.", "main.lock", "a..b", "a//b", "a/.b", "a@{1}",
+            "a b", "a\tb", "a~1", "a^", "a:b", "a?", "a*", "a[b", "a\\b",
+        ] {
+            assert!(parse_branch(branch).is_err(), "{:?}", branch);
+        }
+    }
+}
@@ -0,0 +1,390 @@
+// What verifying JWT signatures takes (HS256 and RS256): SHA-256 and HMAC-SHA256, and RSASSA-PKCS1-v1_5
+// verification with public keys read from PEM files or JWKS members. Beyond that, SHA-256 and
+// HMAC-SHA256 only sign the presigned URLs of an image bucket; nothing here handles private keys.
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+pub(crate) fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut h: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+    ];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let choice = (e & f) ^ (!e & g);
+            let t1 = hh.wrapping_add(s1).wrapping_add(choice).wrapping_add(SHA256_K[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(majority);
+            hh = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (state, value) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0u8; 32];
+    for (bytes, word) in digest.chunks_exact_mut(4).zip(h) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+pub(crate) fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    if key.len() > 64 {
+        block[..32].copy_from_slice(&sha256(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner: Vec<u8> = block.iter().map(|b| b ^ 0x36).collect();
+    inner.extend_from_slice(message);
+    let mut outer: Vec<u8> = block.iter().map(|b| b ^ 0x5c).collect();
+    outer.extend_from_slice(&sha256(&inner));
+    sha256(&outer)
+}
+
+// Compare without returning early, so timing doesn't reveal how much matched
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+// An RSA public key: modulus and exponent as little-endian 64-bit limbs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RsaPublicKey {
+    modulus: Vec<u64>,
+    exponent: Vec<u64>,
+    // Length of the modulus in bytes, which signatures have
+    size: usize,
+}
+
+// Big-endian bytes as little-endian limbs, without leading zero limbs
+fn limbs(bytes: &[u8]) -> Vec<u64> {
+    let bytes = &bytes[bytes.iter().take_while(|b| **b == 0).count()..];
+    let mut limbs: Vec<u64> = bytes
+        .rchunks(8)
+        .map(|chunk| chunk.iter().fold(0u64, |limb, byte| (limb << 8) | u64::from(*byte)))
+        .collect();
+    while limbs.last() == Some(&0) {
+        limbs.pop();
+    }
+    limbs
+}
+
+fn greater_or_equal(a: &[u64], b: &[u64]) -> bool {
+    for (x, y) in a.iter().rev().zip(b.iter().rev()) {
+        if x != y {
+            return x > y;
+        }
+    }
+    true
+}
+
+// a - b in place, for a >= b of the same length
+fn subtract(a: &mut [u64], b: &[u64]) {
+    let mut borrow = false;
+    for (x, y) in a.iter_mut().zip(b) {
+        let (difference, borrowed) = x.overflowing_sub(*y);
+        let (difference, borrowed_again) = difference.overflowing_sub(u64::from(borrow));
+        *x = difference;
+        borrow = borrowed || borrowed_again;
+    }
+}
+
+// Arithmetic modulo an odd modulus in Montgomery form (x·R mod n, R = 2^(64·limbs))
+struct Montgomery<'a> {
+    modulus: &'a [u64],
+    // -modulus⁻¹ mod 2^64
+    inverse: u64,
+    // R² mod n, to bring numbers into Montgomery form
+    r_squared: Vec<u64>,
+}
+
+impl<'a> Montgomery<'a> {
+    fn new(modulus: &'a [u64]) -> Montgomery<'a> {
+        // Newton's iteration doubles the correct low bits each round: 6 rounds cover 64
+        let mut inverse: u64 = 1;
+        for _ in 0..6 {
+            inverse = inverse.wrapping_mul(2u64.wrapping_sub(modulus[0].wrapping_mul(inverse)));
+        }
+        // R² mod n by doubling 1 modulo n, 2·64·limbs times
+        let k = modulus.len();
+        let mut r_squared = vec![0u64; k];
+        r_squared[0] = 1;
+        for _ in 0..2 * 64 * k {
+            let carry = r_squared[k - 1] >> 63;
+            for i in (1..k).rev() {
+                r_squared[i] = (r_squared[i] << 1) | (r_squared[i - 1] >> 63);
+            }
+            r_squared[0] <<= 1;
+            if carry == 1 || greater_or_equal(&r_squared, modulus) {
+                subtract(&mut r_squared, modulus);
+            }
+        }
+        Montgomery { modulus, inverse: inverse.wrapping_neg(), r_squared }
+    }
+
+    // a·b·R⁻¹ mod n
+    fn multiply(&self, a: &[u64], b: &[u64]) -> Vec<u64> {
+        let n = self.modulus;
+        let k = n.len();
+        let mut t = vec![0u64; k + 2];
+        for &b_limb in b.iter().take(k) {
+            let mut carry = 0u128;
+            for j in 0..k {
+                let sum = u128::from(t[j]) + u128::from(a[j]) * u128::from(b_limb) + carry;
+                t[j] = sum as u64;
+                carry = sum >> 64;
+            }
+            let sum = u128::from(t[k]) + carry;
+            t[k] = sum as u64;
+            t[k + 1] = (sum >> 64) as u64;
+
+            let m = t[0].wrapping_mul(self.inverse);
+            let sum = u128::from(t[0]) + u128::from(m) * u128::from(n[0]);
+            let mut carry = sum >> 64;
+            for j in 1..k {
+                let sum = u128::from(t[j]) + u128::from(m) * u128::from(n[j]) + carry;
+                t[j - 1] = sum as u64;
+                carry = sum >> 64;
+            }
+            let sum = u128::from(t[k]) + carry;
+            t[k - 1] = sum as u64;
+            t[k] = t[k + 1] + (sum >> 64) as u64;
+            t[k + 1] = 0;
+        }
+        let overflow = t[k] != 0;
+        t.truncate(k);
+        if overflow || greater_or_equal(&t, n) {
+            subtract(&mut t, n);
+        }
+        t
+    }
+
+    // base^exponent mod n, for base < n
+    fn power(&self, base: &[u64], exponent: &[u64]) -> Vec<u64> {
+        let k = self.modulus.len();
+        let mut padded = base.to_vec();
+        padded.resize(k, 0);
+        let base = self.multiply(&padded, &self.r_squared);
+        let mut one = vec![0u64; k];
+        one[0] = 1;
+        let mut result = self.multiply(&one, &self.r_squared);
+        for limb in exponent.iter().rev() {
+            for bit in (0..64).rev() {
+                result = self.multiply(&result, &result);
+                if (limb >> bit) & 1 == 1 {
+                    result = self.multiply(&result, &base);
+                }
+            }
+        }
+        self.multiply(&result, &one)
+    }
+}
+
+// DigestInfo of a SHA-256 hash, as PKCS #1 puts it before the hash
+const SHA256_DIGEST_INFO: [u8; 19] = [
+    0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x05, 0x00, 0x04, 0x20,
+];
+
+impl RsaPublicKey {
+    // From the big-endian modulus and exponent; None for keys too weak or malformed to trust
+    pub(crate) fn new(modulus: &[u8], exponent: &[u8]) -> Option<RsaPublicKey> {
+        let modulus_limbs = limbs(modulus);
+        let exponent = limbs(exponent);
+        let size = modulus_limbs.len() * 8 - (modulus_limbs.last()?.leading_zeros() / 8) as usize;
+        let odd = modulus_limbs.first().is_some_and(|limb| limb & 1 == 1);
+        (odd && size >= 256 && !exponent.is_empty()).then_some(RsaPublicKey { modulus: modulus_limbs, exponent, size })
+    }
+
+    // RSASSA-PKCS1-v1_5 with SHA-256 (RS256)
+    pub(crate) fn verify_sha256(&self, message: &[u8], signature: &[u8]) -> bool {
+        if signature.len() != self.size {
+            return false;
+        }
+        let mut s = limbs(signature);
+        s.resize(self.modulus.len(), 0);
+        if greater_or_equal(&s, &self.modulus) {
+            return false;
+        }
+        let m = Montgomery::new(&self.modulus).power(&s, &self.exponent);
+        let mut encoded = vec![0u8; self.size];
+        for (i, byte) in encoded.iter_mut().rev().enumerate() {
+            *byte = (m.get(i / 8).copied().unwrap_or(0) >> (8 * (i % 8))) as u8;
+        }
+
+        // 00 01 FF..FF 00 DigestInfo hash
+        let mut expected = vec![0x00, 0x01];
+        expected.resize(self.size - SHA256_DIGEST_INFO.len() - 32 - 1, 0xff);
+        expected.push(0x00);
+        expected.extend_from_slice(&SHA256_DIGEST_INFO);
+        expected.extend_from_slice(&sha256(message));
+        constant_time_eq(&encoded, &expected)
+    }
+
+    // A PEM public key: "PUBLIC KEY" (SubjectPublicKeyInfo) or "RSA PUBLIC KEY" (PKCS #1)
+    pub(crate) fn from_pem(pem: &str) -> Option<RsaPublicKey> {
+        use base64::Engine;
+
+        let (label, body) = pem_body(pem)?;
+        let der = base64::engine::general_purpose::STANDARD.decode(body).ok()?;
+        match label.as_str() {
+            "PUBLIC KEY" => {
+                let (spki, _) = der_element(&der, 0x30)?;
+                let (algorithm, rest) = der_element(spki, 0x30)?;
+                let (oid, _) = der_element(algorithm, 0x06)?;
+                // rsaEncryption, 1.2.840.113549.1.1.1
+                if oid != [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01] {
+                    return None;
+                }
+                let (bits, _) = der_element(rest, 0x03)?;
+                RsaPublicKey::from_pkcs1(bits.strip_prefix(&[0])?)
+            }
+            "RSA PUBLIC KEY" => RsaPublicKey::from_pkcs1(&der),
+            _ => None,
+        }
+    }
+
+    fn from_pkcs1(der: &[u8]) -> Option<RsaPublicKey> {
+        let (key, _) = der_element(der, 0x30)?;
+        let (modulus, rest) = der_element(key, 0x02)?;
+        let (exponent, _) = der_element(rest, 0x02)?;
+        RsaPublicKey::new(modulus, exponent)
+    }
+}
+
+// The label and base64 body of the first PEM block
+fn pem_body(pem: &str) -> Option<(String, String)> {
+    let start = pem.find("-----BEGIN ")?;
+    let rest = &pem[start + 11..];
+    let label_end = rest.find("-----")?;
+    let label = rest[..label_end].to_string();
+    let body_start = &rest[label_end + 5..];
+    let end = body_start.find(&format!("-----END {}-----", label))?;
+    let body = body_start[..end].chars().filter(|c| !c.is_whitespace()).collect();
+    Some((label, body))
+}
+
+// A DER element with the expected tag at the start of `input`: its content and what follows it
+fn der_element(input: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+    let (&found, rest) = input.split_first()?;
+    if found != tag {
+        return None;
+    }
+    let (&first, rest) = rest.split_first()?;
+    let (length, rest) = if first < 0x80 {
+        (usize::from(first), rest)
+    } else {
+        let count = usize::from(first & 0x7f);
+        if count == 0 || count > 4 || rest.len() < count {
+            return None;
+        }
+        let length = rest[..count].iter().fold(0usize, |length, byte| (length << 8) | usize::from(*byte));
+        (length, &rest[count..])
+    };
+    (rest.len() >= length).then(|| rest.split_at(length))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{hmac_sha256, sha256, RsaPublicKey};
+
+    // A 2048-bit key made for these tests, and its RS256 signature of SIGNED_MESSAGE
+    pub(crate) const TEST_PUBLIC_KEY: &str = "\
+-----BEGIN PUBLIC KEY-----
+MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8AMIIBCgKCAQEA5TMaHYt9MpvNObWsNNso
+DTRHkLn3Gve1rXECvtzq4Fo7MXaK7Y706Ul2X6WcAsWJddsjSjWPf48jP1btDUvZ
+BhqOdt1/CrGWgJW12fN2gOPrK04jWkLLtxytnnGB6If/f/O1S2EpXsD6yoNL4Dk2
+R6l/W5+CAqTeDssfuQlVXqVRJ6NggtdPhLeccePUZYszemPGi56J+yDfsQ1y4HC/
+aHszl6pDmuiCetgXowcmoJMYStLtHVCm602B1AWNMsFSFuPc2Vw3jx0LBsH9JSU2
+ZE5j5nspTix2bFAyCzNAFCarvik1eyDxEkm2AH2AeI+gvTQBq1arUjcNdIPkZ9Gx
+UQIDAQAB
+-----END PUBLIC KEY-----
+";
+    const SIGNED_MESSAGE: &[u8] = b"trend-story";
+    const SIGNATURE: &str = "Y7mZUeWO6UhRqpG+deAQBt4UtYizeyxqYQ/s1U4az+7ByorCBapKEx8ee0w2N6Da3WHByhbmWNSXEeWpN18FwKrSRyyZWK8b8pkuyqUgFaHuqTGoFIi+hjXw0J5dt9vP6KzqHnGjauLqjlE28j+Kl2yN4+dIBVRCm9CefUTZJiGiiiO92XzMvyuZjfIXLX1t9J5HXigI66/vENed1D//+Nfz3hm/u59H2UDUzA3EQbreu2/fwJkFKL0vZjs7iSQV04FhwN897DuX3drDrk2rbDQqp5FekucfcegJRlFPEDrQXP5n7hWa0ftFtXwHbCcHmGZoXzSOBIFIFHfbAnHVRw==";
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn signature() -> Vec<u8> {
+        use base64::Engine;
+        base64::engine::general_purpose::STANDARD.decode(SIGNATURE).unwrap()
+    }
+
+    // FIPS 180-2, appendix B
+    #[test]
+    fn sha256_known_answers() {
+        assert_eq!(hex(&sha256(b"abc")), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(hex(&sha256(b"")), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(
+            hex(&sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    // RFC 4231, test cases 1 to 4
+    #[test]
+    fn hmac_sha256_known_answers() {
+        let key: Vec<u8> = (1..=25).collect();
+        let cases: [(&[u8], &[u8], &str); 4] = [
+            (&[0x0b; 20], b"Hi There", "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"),
+            (b"Jefe", b"what do ya want for nothing?", "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"),
+            (&[0xaa; 20], &[0xdd; 50], "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe"),
+            (&key, &[0xcd; 50], "82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b"),
+        ];
+        for (key, data, mac) in cases {
+            assert_eq!(hex(&hmac_sha256(key, data)), mac);
+        }
+    }
+
+    #[test]
+    fn verifies_an_rs256_signature() {
+        let key = RsaPublicKey::from_pem(TEST_PUBLIC_KEY).expect("test key parses");
+        assert!(key.verify_sha256(SIGNED_MESSAGE, &signature()));
+    }
+
+    #[test]
+    fn rejects_altered_rs256_signatures_and_messages() {
+        let key = RsaPublicKey::from_pem(TEST_PUBLIC_KEY).unwrap();
+        assert!(!key.verify_sha256(b"trend-storY", &signature()));
+        let mut altered = signature();
+        altered[100] ^= 1;
+        assert!(!key.verify_sha256(SIGNED_MESSAGE, &altered));
+        assert!(!key.verify_sha256(SIGNED_MESSAGE, &signature()[1..]));
+    }
+}
@@ -0,0 +1,1748 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::Path;
+use rusqlite::{Connection, Result as SqlResult};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{
+    DataSource, DB_BUSY_TIMEOUT_MS, DB_STATEMENT_CACHE_CAPACITY, LATEST_MIN_RECORDS, LATEST_TZ_OFFSET_MINUTES,
+    TOP_TAGS_LIMIT,
+};
+use crate::edits::{attach_edits, OVERRIDES_TABLE, SUPPRESSED_TABLE};
+use crate::lang::detect_language;
+use crate::list::{ListOptions, SortOrder};
+use crate::markup::strip_html;
+use crate::metrics::time_query;
+use crate::pool::PooledConnection;
+use crate::search::build_search_index;
+use crate::tags::TagMap;
+use crate::AppState;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LatestResponse {
+    pub date: Option<String>,
+    // The day's matches after filters, and how many of them come before this page
+    pub total_records: usize,
+    pub offset: usize,
+    pub records: Vec<NewsRecord>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    pub links: DayLinks,
+    // The days (yyyy-mm-dd) with records before and after this one, which the links depend on
+    #[serde(skip)]
+    pub(crate) adjacent_days: (Option<String>, Option<String>),
+}
+
+// Where to go from a day's records: the response itself, the nearest earlier and later days
+// with records (absent at either end) and the list of all days
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DayLinks {
+    #[serde(rename = "self")]
+    pub self_link: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prev_date: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_date: Option<String>,
+    pub dates_index: String,
+}
+
+impl DayLinks {
+    // Links of a response at `self_path` (/latest, /date/yyyymmdd) between the adjacent days
+    fn new(source: &DataSource, self_path: &str, (prev, next): &(Option<String>, Option<String>)) -> DayLinks {
+        let day_link = |day: &String| api_link(source, &format!("/date/{}", day.replace('-', "")));
+        DayLinks {
+            self_link: api_link(source, self_path),
+            prev_date: prev.as_ref().map(day_link),
+            next_date: next.as_ref().map(day_link),
+            dates_index: api_link(source, "/dates"),
+        }
+    }
+}
+
+// Link to one of the source's API routes
+fn api_link(source: &DataSource, path: &str) -> String {
+    format!("{}{}{}", source.base_urls.api_origin(), source.url_prefix(), path)
+}
+
+// A day's matches and how many precede `page`. Without ?limit= or ?cursor= the page is all of
+// them; otherwise the day is listed again without paging to count.
+fn page_position(
+    conn: &Connection,
+    source: &DataSource,
+    condition: &str,
+    day: &str,
+    options: &ListOptions,
+    page: &[NewsRecord],
+) -> SqlResult<(usize, usize)> {
+    if options.limit.is_none() && options.cursor.is_none() {
+        return Ok((page.len(), 0));
+    }
+    let unpaged = ListOptions { limit: None, cursor: None, ..options.clone() };
+    let all = query_filtered_records(conn, source, condition, None, day, &unpaged)?;
+    let offset = match page.first() {
+        Some(first) => all.iter().position(|record| record.id == first.id).unwrap_or(0),
+        None => all.len(),
+    };
+    Ok((all.len(), offset))
+}
+
+// Nearest days (yyyy-mm-dd) before and after `day` that have records
+fn adjacent_days(conn: &Connection, day: &str) -> SqlResult<(Option<String>, Option<String>)> {
+    conn.prepare_cached(
+        "SELECT (SELECT MAX(day) FROM news_days WHERE day < ?1), (SELECT MIN(day) FROM news_days WHERE day > ?1)",
+    )?
+    .query_row([day], |row| Ok((row.get(0)?, row.get(1)?)))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecentResponse {
+    pub records: Vec<NewsRecord>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+// Records added after a /changes marker, oldest first, and the marker to ask with next
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChangesResponse {
+    pub since: String,
+    pub next_since: String,
+    // Whether more records follow next_since already
+    pub has_more: bool,
+    pub records: Vec<NewsRecord>,
+}
+
+// Where a /changes page starts: after a record id, or after a time (UTC, as stored)
+#[derive(Debug, Clone)]
+pub(crate) enum ChangesMarker {
+    Id(i64),
+    Time(String),
+}
+
+// Stands in for the day of /recent cursors, which page across days
+pub(crate) const RECENT_CURSOR_DAY: &str = "recent";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DateResponse {
+    pub date: String,
+    pub date_with_url: String,
+    pub record_count: i64,
+    pub has_images: bool,
+}
+
+// The images of a day's records, for /images/meta
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImageListResponse {
+    pub date: String,
+    pub images: Vec<ImageEntry>,
+    // Records of the day without an image row, a file name or the file itself
+    pub missing: Vec<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImageEntry {
+    pub id: i64,
+    pub record_id: i64,
+    pub file_name: Option<String>,
+    pub url: Option<String>,
+    // Whether the file is present in the images directory
+    pub exists: bool,
+}
+
+// Bounds of the days with records (yyyymmdd, null without any), for /dates/range
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DateRangeResponse {
+    pub first: Option<String>,
+    pub last: Option<String>,
+    pub days: i64,
+    pub total_records: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DayCount {
+    pub date: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DateSpan {
+    pub first: Option<String>,
+    pub last: Option<String>,
+    pub days: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatsResponse {
+    pub total_records: i64,
+    pub records_per_day: Vec<DayCount>,
+    pub records_per_tag: Vec<TagCount>,
+    pub images_count: i64,
+    pub date_span: DateSpan,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeywordTrend {
+    pub keyword: String,
+    pub count: i64,
+    pub first_seen: String,
+    pub last_seen: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeywordAnalyticsResponse {
+    pub days: u32,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub keywords: Vec<KeywordTrend>,
+}
+
+// Appearances of one keyword in serpapi_data per day (yyyymmdd), every day from its first to its
+// last appearance, including those without any
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeywordSeriesResponse {
+    pub keyword: String,
+    pub total: i64,
+    pub first_seen: String,
+    pub last_seen: String,
+    pub days: Vec<DayCount>,
+}
+
+// Tags ranked by records over the last `days` days with data (yyyymmdd bounds), against the
+// same number of days before
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TagCountsResponse {
+    pub days: u32,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub tags: Vec<TagPopularity>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TagPopularity {
+    pub tag: String,
+    pub count: i64,
+    pub rank: usize,
+    pub previous_count: i64,
+    // Null for a tag without records in the previous window
+    pub previous_rank: Option<usize>,
+    // Places climbed since the previous window (negative when falling), null for a new tag
+    pub rank_change: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RelatedTagsResponse {
+    pub tag: String,
+    pub record_count: i64,
+    pub related: Vec<TagCount>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScoredRecord {
+    pub score: i64,
+    #[serde(flatten)]
+    pub record: NewsRecord,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RelatedNewsResponse {
+    pub id: i64,
+    pub related: Vec<ScoredRecord>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct YearRecords {
+    pub year: String,
+    pub date: String,
+    pub records: Vec<NewsRecord>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OnThisDayResponse {
+    pub month_day: String,
+    pub years: Vec<YearRecords>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DayRecords {
+    pub date: String,
+    pub count: usize,
+    pub records: Vec<NewsRecord>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MonthResponse {
+    pub month: String,
+    pub total_records: usize,
+    pub days: Vec<DayRecords>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WeekDayRecords {
+    pub date: String,
+    pub count: usize,
+    pub top_tags: Vec<TagCount>,
+    pub records: Vec<NewsRecord>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WeekResponse {
+    pub week: String,
+    pub start_date: String,
+    pub end_date: String,
+    pub total_records: usize,
+    pub top_tags: Vec<TagCount>,
+    pub days: Vec<WeekDayRecords>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct YearMonthSummary {
+    pub month: String,
+    pub total_records: i64,
+    pub top_tags: Vec<TagCount>,
+    pub days: Vec<DateResponse>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct YearResponse {
+    pub year: String,
+    pub total_records: i64,
+    pub months: Vec<YearMonthSummary>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImageInfo {
+    pub file_name: Option<String>,
+    pub url: Option<String>,
+    // Placeholder to show while the image loads, once computed after a sync
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blurhash: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NewsRecord {
+    pub id: i64,
+    pub news: Option<String>,
+    pub date: Option<String>,
+    pub serpapi_id: Option<i64>,
+    pub image_id: Option<i64>,
+    pub serpapi_data_date: Option<String>,
+    pub keywords: Option<String>,
+    pub image: Option<ImageInfo>,
+    pub tag: Vec<String>,
+    // ISO 639-1 code of the language the news is written in, null when it can't be told
+    pub lang: Option<String>,
+    // The story's thread (/threads/<id>) when its keywords recur on consecutive days
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread_id: Option<i64>,
+    // Polarity of the news from -1 to 1, once scored (English texts, with the sentiment feature)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sentiment: Option<f64>,
+}
+
+// Tables and columns the queries read. Each column lists the names it has had upstream, current
+// layout first; a table whose file uses older names or lacks columns is mapped by a temp view.
+pub(crate) type ColumnNames = (&'static str, &'static [&'static str]);
+
+pub(crate) const EXPECTED_SCHEMA: [(&str, &[ColumnNames]); 3] = [
+    ("main_news_data", &[
+        ("id", &["id"]),
+        ("news", &["news", "summary", "content"]),
+        ("date", &["date", "created_at"]),
+        ("serpapi_id", &["serpapi_id", "serpapi_data_id"]),
+        ("image_id", &["image_id", "image_data_id"]),
+    ]),
+    ("serpapi_data", &[
+        ("id", &["id"]),
+        ("query", &["query", "keyword", "keywords"]),
+        ("categories", &["categories", "tags"]),
+        ("date", &["date", "created_at"]),
+    ]),
+    ("image_data", &[
+        ("id", &["id"]),
+        ("file_name", &["file_name", "filename"]),
+    ]),
+];
+
+// How the file's layout maps onto the one the queries expect
+#[derive(Debug, Default)]
+pub(crate) struct SchemaLayout {
+    // SELECTs reading tables that don't match the expected layout under the expected columns
+    pub(crate) selects: HashMap<&'static str, String>,
+    // Columns found under an older name, e.g. "image_data.file_name <- filename"
+    pub(crate) adapted: Vec<String>,
+    // Missing tables and columns; they read as empty / NULL
+    pub(crate) problems: Vec<String>,
+}
+
+pub(crate) fn detect_schema(conn: &Connection) -> SqlResult<SchemaLayout> {
+    let mut layout = SchemaLayout::default();
+    for (table, columns) in EXPECTED_SCHEMA {
+        let mut stmt = conn.prepare(&format!("PRAGMA main.table_info({})", table))?;
+        let present = stmt.query_map([], |row| row.get::<_, String>(1))?
+            .collect::<SqlResult<Vec<String>>>()?;
+
+        if present.is_empty() {
+            layout.problems.push(format!("missing table {}", table));
+            let nulls: Vec<String> = columns.iter().map(|(name, _)| format!("NULL AS {}", name)).collect();
+            layout.selects.insert(table, format!("SELECT {} WHERE 0", nulls.join(", ")));
+            continue;
+        }
+
+        let mut select = Vec::new();
+        let mut canonical = true;
+        for (name, aliases) in columns.iter() {
+            match aliases.iter().find(|alias| present.iter().any(|p| p == *alias)) {
+                Some(alias) if alias == name => select.push(name.to_string()),
+                Some(alias) => {
+                    canonical = false;
+                    layout.adapted.push(format!("{}.{} <- {}", table, name, alias));
+                    select.push(format!("\"{}\" AS {}", alias, name));
+                }
+                None => {
+                    canonical = false;
+                    layout.problems.push(format!("missing column {}.{}", table, name));
+                    select.push(format!("NULL AS {}", name));
+                }
+            }
+        }
+        if !canonical {
+            layout.selects.insert(table, format!("SELECT {} FROM main.{}", select.join(", "), table));
+        }
+    }
+    Ok(layout)
+}
+
+// Run a query on the blocking thread pool: SQLite calls (and their busy_timeout waits) must not
+// stall the async workers that accept and route other requests. A panic in the query resurfaces
+// in the calling handler, where catch_panic answers it.
+pub(crate) async fn run_blocking<T, F>(state: &AppState, label: &'static str, query: F) -> SqlResult<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&DataSource) -> SqlResult<T> + Send + 'static,
+{
+    let source = state.source.clone();
+    match tokio::task::spawn_blocking(move || time_query(label, || query(&source))).await {
+        Ok(result) => result,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+// Read-only connection: the API never writes to the synced file, and NO_MUTEX is fine because
+// each connection is used by a single request at a time. It comes from the source's pool when one
+// was opened for the current files, and goes back there when dropped.
+pub(crate) fn open_database(source: &DataSource) -> SqlResult<PooledConnection> {
+    let db_path = source.serving_path();
+    if !db_path.exists() {
+        return Err(rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_CANTOPEN),
+            Some("Database file not found".to_string())
+        ));
+    }
+    // Taken before connecting, so a connection opened while a pull replaces the file is retired
+    let fingerprint = connection_fingerprint(source);
+    if let Some(conn) = fingerprint.as_deref().and_then(|fingerprint| source.connections.take(fingerprint)) {
+        return Ok(PooledConnection::new(conn, fingerprint, source.connections.clone()));
+    }
+    let conn = connect(source)?;
+    Ok(PooledConnection::new(conn, fingerprint, source.connections.clone()))
+}
+
+fn connect(source: &DataSource) -> SqlResult<Connection> {
+    use rusqlite::OpenFlags;
+
+    let flags = OpenFlags::SQLITE_OPEN_READ_ONLY
+        | OpenFlags::SQLITE_OPEN_NO_MUTEX
+        | OpenFlags::SQLITE_OPEN_URI;
+    let conn = Connection::open_with_flags(database_uri(&source.serving_path(), source.db_immutable), flags)?;
+
+    // Wait for a competing lock instead of failing right away. The journal mode (WAL or not)
+    // is a property of the file set by its writer; a read-only connection can't change it.
+    conn.busy_timeout(std::time::Duration::from_millis(DB_BUSY_TIMEOUT_MS))?;
+    conn.set_prepared_statement_cache_capacity(DB_STATEMENT_CACHE_CAPACITY);
+
+    // Unqualified table names resolve to temp views first, so queries keep working unchanged
+    // against an older or newer upstream layout and see locally added records
+    let layout = detect_schema(&conn)?;
+    let edited = attach_edits(&conn, source)?;
+    let views = table_views(&layout, &edited);
+    if !views.is_empty() {
+        conn.execute_batch(&views.join(";"))?;
+    }
+    attach_overlay(&conn, source)?;
+    #[cfg(feature = "sentiment")]
+    crate::sentiment::attach_sentiment(&conn, source)?;
+    Ok(conn)
+}
+
+// CREATE TEMP VIEW statements for the tables that can't be read as they are: upstream tables
+// with another layout, tables with local rows (appended to the upstream ones) and, once records
+// were edited or deleted, main_news_data with the overrides applied and without the deleted ones
+fn table_views(layout: &SchemaLayout, edited: &[&str]) -> Vec<String> {
+    EXPECTED_SCHEMA
+        .iter()
+        .filter_map(|(table, columns)| {
+            let names = columns.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(", ");
+            // The rows read so far, as a FROM term
+            let from = |select: &Option<String>| match select {
+                Some(select) => format!("({})", select),
+                None => format!("main.{}", table),
+            };
+
+            let mut select = layout.selects.get(table).cloned();
+            if edited.contains(table) {
+                select = Some(format!(
+                    "SELECT {0} FROM {1} UNION ALL SELECT {0} FROM edits.{2}", names, from(&select), table
+                ));
+            }
+            if *table == "main_news_data" && edited.contains(&OVERRIDES_TABLE) {
+                select = Some(format!(
+                    "SELECT m.id AS id, COALESCE(o.news, m.news) AS news, m.date AS date, \
+                     COALESCE(o.serpapi_id, m.serpapi_id) AS serpapi_id, m.image_id AS image_id \
+                     FROM {} AS m LEFT JOIN edits.{} AS o ON o.id = m.id",
+                    from(&select), OVERRIDES_TABLE
+                ));
+            }
+            if *table == "main_news_data" && edited.contains(&SUPPRESSED_TABLE) {
+                select = Some(format!(
+                    "SELECT {} FROM {} WHERE id NOT IN (SELECT id FROM edits.{})", names, from(&select), SUPPRESSED_TABLE
+                ));
+            }
+            select.map(|select| format!("CREATE TEMP VIEW {} AS {}", table, select))
+        })
+        .collect()
+}
+
+// Identifies one version of the database file; the overlay records the version it was built from
+pub(crate) fn db_fingerprint(db_path: &Path) -> Option<String> {
+    let metadata = std::fs::metadata(db_path).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(std::time::UNIX_EPOCH).ok()?;
+    Some(format!("{}.{:09}:{}", modified.as_secs(), modified.subsec_nanos(), metadata.len()))
+}
+
+// Identifies the data a source serves: its database file plus the local edits, if any
+pub(crate) fn source_fingerprint(source: &DataSource) -> Option<String> {
+    let fingerprint = db_fingerprint(&source.serving_path())?;
+    Some(match db_fingerprint(&source.edits_path) {
+        Some(edits) => format!("{}+{}", fingerprint, edits),
+        None => fingerprint,
+    })
+}
+
+// What the setup of a connection depends on: the source's files and the side files it attaches
+fn connection_fingerprint(source: &DataSource) -> Option<String> {
+    let mut fingerprint = source_fingerprint(source)?;
+    let mut attached = vec![&source.overlay_path];
+    if cfg!(feature = "sentiment") {
+        attached.push(&source.sentiment_path);
+    }
+    for path in attached {
+        fingerprint.push('+');
+        fingerprint.push_str(&db_fingerprint(path).unwrap_or_default());
+    }
+    Some(fingerprint)
+}
+
+// Layout of the overlay file; an overlay of another version is rebuilt (2: news_search)
+const OVERLAY_VERSION: i64 = 2;
+
+// Make news_days(id, day) and news_search available to the queries: the overlay tables when it was built
+// from the current file, otherwise a temp view computing the same rows with a full scan (and a
+// per-connection search index, see query_search)
+pub(crate) fn attach_overlay(conn: &Connection, source: &DataSource) -> SqlResult<()> {
+    let fingerprint = source_fingerprint(source);
+    let attached = fingerprint.is_some()
+        && source.overlay_path.exists()
+        && conn.execute("ATTACH DATABASE ?1 AS overlay", [database_uri(&source.overlay_path, false)]).is_ok();
+    if attached {
+        let built_from: Option<String> = conn
+            .query_row(
+                "SELECT source_fingerprint FROM overlay.overlay_meta WHERE version = ?1",
+                [OVERLAY_VERSION],
+                |row| row.get(0),
+            )
+            .unwrap_or(None);
+        if built_from == fingerprint {
+            return Ok(());
+        }
+        conn.execute_batch("DETACH DATABASE overlay")?;
+    }
+    conn.execute_batch(
+        "CREATE TEMP VIEW news_days AS \
+         SELECT id, substr(date, 1, 10) AS day FROM main_news_data WHERE date IS NOT NULL"
+    )
+}
+
+// Write the overlay for the current database file unless it is already up to date. It is built
+// in a side file and renamed into place, so readers never see a half-written index.
+pub(crate) fn build_overlay(source: &DataSource) -> SqlResult<bool> {
+    let Some(fingerprint) = source_fingerprint(source) else {
+        return Ok(false);
+    };
+    let conn = open_database(source)?;
+    let is_current: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM pragma_database_list WHERE name = 'overlay')",
+        [],
+        |row| row.get(0),
+    )?;
+    if is_current {
+        return Ok(false);
+    }
+
+    let mut stmt = conn.prepare("SELECT id, day FROM news_days")?;
+    let days = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?
+        .collect::<SqlResult<Vec<_>>>()?;
+
+    let tmp_path = source.overlay_path.with_extension("db.tmp");
+    let _ = std::fs::remove_file(&tmp_path);
+    let mut overlay = Connection::open(&tmp_path)?;
+    overlay.execute_batch(
+        "CREATE TABLE news_days (id INTEGER PRIMARY KEY, day TEXT NOT NULL); \
+         CREATE INDEX news_days_day ON news_days (day, id); \
+         CREATE TABLE overlay_meta (source_fingerprint TEXT NOT NULL, version INTEGER NOT NULL);"
+    )?;
+    let tx = overlay.transaction()?;
+    {
+        let mut insert = tx.prepare("INSERT INTO news_days (id, day) VALUES (?1, ?2)")?;
+        for (id, day) in &days {
+            insert.execute(rusqlite::params![id, day])?;
+        }
+    }
+    build_search_index(&conn, &tx, "news_search")?;
+    tx.execute(
+        "INSERT INTO overlay_meta (source_fingerprint, version) VALUES (?1, ?2)",
+        rusqlite::params![fingerprint, OVERLAY_VERSION],
+    )?;
+    tx.commit()?;
+    drop(overlay);
+
+    std::fs::rename(&tmp_path, &source.overlay_path).map_err(|e| {
+        rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_CANTOPEN),
+            Some(format!("cannot move overlay into place: {}", e)),
+        )
+    })?;
+    Ok(true)
+}
+
+// Consistent copy of the served database file, made with SQLite's online backup API so it
+// never catches a half-applied change. It is written to `dest` and returned open with `dest`
+// already removed, so the copy disappears once the handle is dropped.
+pub(crate) fn backup_database(source: &DataSource, dest: &Path) -> SqlResult<std::fs::File> {
+    let conn = open_database(source)?;
+    let mut copy = Connection::open(dest)?;
+    let copied = rusqlite::backup::Backup::new(&conn, &mut copy)
+        // All pages in one step, under a single read lock
+        .and_then(|backup| backup.run_to_completion(i32::MAX, std::time::Duration::ZERO, None));
+    drop(copy);
+    let file = copied.and_then(|()| {
+        std::fs::File::open(dest).map_err(|e| {
+            rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_CANTOPEN),
+                Some(format!("cannot read backup: {}", e)),
+            )
+        })
+    });
+    let _ = std::fs::remove_file(dest);
+    file
+}
+
+// file: URI for a database path; immutable=1 additionally skips all locking and change detection
+pub(crate) fn database_uri(db_path: &Path, immutable: bool) -> String {
+    let mut uri = String::from("file:");
+    for c in db_path.to_string_lossy().chars() {
+        match c {
+            '%' => uri.push_str("%25"),
+            '?' => uri.push_str("%3f"),
+            '#' => uri.push_str("%23"),
+            _ => uri.push(c),
+        }
+    }
+    uri.push_str("?mode=ro");
+    if immutable {
+        uri.push_str("&immutable=1");
+    }
+    uri
+}
+
+pub(crate) fn count_records(source: &DataSource) -> SqlResult<i64> {
+    let conn = open_database(source)?;
+    conn.query_row("SELECT COUNT(*) FROM main_news_data", [], |row| row.get(0))
+}
+
+pub(crate) fn query_stats(source: &DataSource) -> SqlResult<StatsResponse> {
+    let conn = open_database(source)?;
+
+    let total_records: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM main_news_data",
+        [],
+        |row| row.get(0)
+    )?;
+
+    let images_count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM image_data",
+        [],
+        |row| row.get(0)
+    )?;
+
+    // Records per day in yyyymmdd format
+    let mut day_stmt = conn.prepare_cached(
+        "SELECT REPLACE(substr(date, 1, 10), '-', '') AS day, COUNT(*) \
+         FROM main_news_data \
+         WHERE date IS NOT NULL \
+         GROUP BY day \
+         ORDER BY day ASC"
+    )?;
+    let records_per_day = day_stmt.query_map([], |row| {
+        Ok(DayCount {
+            date: row.get(0)?,
+            count: row.get(1)?,
+        })
+    })?.collect::<SqlResult<Vec<DayCount>>>()?;
+
+    // Categories are stored as a raw string, so group by it first and split afterwards
+    let mut tag_stmt = conn.prepare_cached(
+        "SELECT serpapi_data.categories, COUNT(*) \
+         FROM main_news_data \
+         JOIN serpapi_data ON main_news_data.serpapi_id = serpapi_data.id \
+         WHERE serpapi_data.categories IS NOT NULL \
+         GROUP BY serpapi_data.categories"
+    )?;
+    let category_rows = tag_stmt.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+    })?;
+
+    let mut tag_totals: HashMap<String, i64> = HashMap::new();
+    for row_result in category_rows {
+        let (categories, count) = row_result?;
+        for tag in parse_categories(&categories, &source.tag_map) {
+            *tag_totals.entry(tag).or_insert(0) += count;
+        }
+    }
+    let mut records_per_tag: Vec<TagCount> = tag_totals
+        .into_iter()
+        .map(|(tag, count)| TagCount { tag, count })
+        .collect();
+    records_per_tag.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tag.cmp(&b.tag)));
+
+    let date_span = DateSpan {
+        first: records_per_day.first().map(|d| d.date.clone()),
+        last: records_per_day.last().map(|d| d.date.clone()),
+        days: records_per_day.len() as i64,
+    };
+
+    Ok(StatsResponse {
+        total_records,
+        records_per_day,
+        records_per_tag,
+        images_count,
+        date_span,
+    })
+}
+
+pub(crate) fn query_tag_counts(source: &DataSource, days: u32) -> SqlResult<TagCountsResponse> {
+    let conn = open_database(source)?;
+
+    // Window bounds as yyyy-mm-dd, ending at the newest day with records
+    let (from, to): (Option<String>, Option<String>) = conn.query_row(
+        "SELECT date(MAX(day), ?1), MAX(day) FROM news_days",
+        [format!("-{} days", days - 1)],
+        |row| Ok((row.get(0)?, row.get(1)?))
+    )?;
+    let (from, to) = match (from, to) {
+        (Some(from), Some(to)) => (from, to),
+        _ => return Ok(TagCountsResponse { days, from: None, to: None, tags: vec![] }),
+    };
+    let (previous_from, previous_to): (String, String) = conn.query_row(
+        "SELECT date(?1, ?2), date(?1, '-1 days')",
+        [from.clone(), format!("-{} days", days)],
+        |row| Ok((row.get(0)?, row.get(1)?))
+    )?;
+
+    let ranked = |totals: HashMap<String, i64>| {
+        let mut tags: Vec<(String, i64)> = totals.into_iter().collect();
+        tags.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        tags
+    };
+    let current = ranked(tag_totals_between(&conn, source, &from, &to)?);
+    let previous: HashMap<String, (usize, i64)> = ranked(tag_totals_between(&conn, source, &previous_from, &previous_to)?)
+        .into_iter()
+        .enumerate()
+        .map(|(i, (tag, count))| (tag, (i + 1, count)))
+        .collect();
+    let tags = current
+        .into_iter()
+        .enumerate()
+        .map(|(i, (tag, count))| {
+            let rank = i + 1;
+            let before = previous.get(&tag).copied();
+            TagPopularity {
+                count,
+                rank,
+                previous_count: before.map_or(0, |(_, count)| count),
+                previous_rank: before.map(|(rank, _)| rank),
+                rank_change: before.map(|(previous_rank, _)| previous_rank as i64 - rank as i64),
+                tag,
+            }
+        })
+        .collect();
+
+    Ok(TagCountsResponse {
+        days,
+        from: Some(from.replace('-', "")),
+        to: Some(to.replace('-', "")),
+        tags,
+    })
+}
+
+// Records per tag among the records of the days from..=to (yyyy-mm-dd)
+fn tag_totals_between(conn: &Connection, source: &DataSource, from: &str, to: &str) -> SqlResult<HashMap<String, i64>> {
+    // Categories are stored as a raw string, so group by it first and split afterwards
+    let mut stmt = conn.prepare_cached(
+        "SELECT serpapi_data.categories, COUNT(*) \
+         FROM news_days \
+         JOIN main_news_data ON main_news_data.id = news_days.id \
+         JOIN serpapi_data ON main_news_data.serpapi_id = serpapi_data.id \
+         WHERE news_days.day BETWEEN ?1 AND ?2 AND serpapi_data.categories IS NOT NULL \
+         GROUP BY serpapi_data.categories"
+    )?;
+    let rows = stmt.query_map([from, to], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?;
+    let mut totals: HashMap<String, i64> = HashMap::new();
+    for row_result in rows {
+        let (categories, count) = row_result?;
+        for tag in parse_categories(&categories, &source.tag_map) {
+            *totals.entry(tag).or_insert(0) += count;
+        }
+    }
+    Ok(totals)
+}
+
+pub(crate) fn query_keyword_analytics(source: &DataSource, days: u32) -> SqlResult<KeywordAnalyticsResponse> {
+    let conn = open_database(source)?;
+
+    // Window bounds as yyyy-mm-dd, ending at the newest day present in serpapi_data
+    let (from, to): (Option<String>, Option<String>) = conn.query_row(
+        "SELECT date(MAX(substr(date, 1, 10)), ?1), MAX(substr(date, 1, 10)) FROM serpapi_data",
+        [format!("-{} days", days - 1)],
+        |row| Ok((row.get(0)?, row.get(1)?))
+    )?;
+
+    let (from, to) = match (from, to) {
+        (Some(from), Some(to)) => (from, to),
+        _ => return Ok(KeywordAnalyticsResponse {
+            days,
+            from: None,
+            to: None,
+            keywords: vec![],
+        }),
+    };
+
+    let mut stmt = conn.prepare_cached(
+        "SELECT lower(trim(query)) AS keyword, COUNT(*) AS hits, \
+         REPLACE(MIN(substr(date, 1, 10)), '-', ''), \
+         REPLACE(MAX(substr(date, 1, 10)), '-', '') \
+         FROM serpapi_data \
+         WHERE substr(date, 1, 10) BETWEEN ?1 AND ?2 \
+         AND trim(query) != '' \
+         GROUP BY keyword \
+         ORDER BY hits DESC, keyword ASC"
+    )?;
+
+    let keywords = stmt.query_map([&from, &to], |row| {
+        Ok(KeywordTrend {
+            keyword: row.get(0)?,
+            count: row.get(1)?,
+            first_seen: row.get(2)?,
+            last_seen: row.get(3)?,
+        })
+    })?.collect::<SqlResult<Vec<KeywordTrend>>>()?;
+
+    Ok(KeywordAnalyticsResponse {
+        days,
+        from: Some(from.replace('-', "")),
+        to: Some(to.replace('-', "")),
+        keywords,
+    })
+}
+
+// None when the keyword (matched like /analytics/keywords groups them, trimmed and lowercased)
+// never appears
+pub(crate) fn query_keyword_series(source: &DataSource, keyword: &str) -> SqlResult<Option<KeywordSeriesResponse>> {
+    let conn = open_database(source)?;
+
+    let keyword = keyword.trim().to_lowercase();
+    let mut stmt = conn.prepare_cached(
+        "SELECT substr(date, 1, 10) AS day, COUNT(*) \
+         FROM serpapi_data \
+         WHERE lower(trim(query)) = ?1 AND date IS NOT NULL \
+         GROUP BY day \
+         ORDER BY day ASC"
+    )?;
+    let counts = stmt
+        .query_map([&keyword], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?
+        .collect::<SqlResult<Vec<(String, i64)>>>()?;
+    let (Some((first, _)), Some((last, _))) = (counts.first(), counts.last()) else {
+        return Ok(None);
+    };
+    let (first_seen, last_seen) = (first.replace('-', ""), last.replace('-', ""));
+
+    // Fill in the days between appearances, unless a malformed date gets in the way
+    let parse = |day: &str| chrono::NaiveDate::parse_from_str(day, "%Y-%m-%d").ok();
+    let days = match (parse(first), parse(last)) {
+        (Some(first), Some(last)) => {
+            let by_day: HashMap<String, i64> = counts.iter().cloned().collect();
+            first
+                .iter_days()
+                .take_while(|day| *day <= last)
+                .map(|day| DayCount {
+                    date: day.format("%Y%m%d").to_string(),
+                    count: by_day.get(&day.format("%Y-%m-%d").to_string()).copied().unwrap_or(0),
+                })
+                .collect()
+        }
+        _ => counts.iter().map(|(day, count)| DayCount { date: day.replace('-', ""), count: *count }).collect(),
+    };
+
+    Ok(Some(KeywordSeriesResponse {
+        keyword,
+        total: counts.iter().map(|(_, count)| count).sum(),
+        first_seen,
+        last_seen,
+        days,
+    }))
+}
+
+pub(crate) fn query_related_tags(source: &DataSource, tag: &str) -> SqlResult<Option<RelatedTagsResponse>> {
+    let conn = open_database(source)?;
+
+    let mut stmt = conn.prepare_cached(
+        "SELECT serpapi_data.categories, COUNT(*) \
+         FROM main_news_data \
+         JOIN serpapi_data ON main_news_data.serpapi_id = serpapi_data.id \
+         WHERE serpapi_data.categories IS NOT NULL \
+         GROUP BY serpapi_data.categories"
+    )?;
+    let category_rows = stmt.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+    })?;
+
+    // Tag names are matched case-insensitively after the tag map, the canonical spelling comes from the data
+    let needle = source.tag_map.normalize(tag).to_lowercase();
+    let mut canonical: Option<String> = None;
+    let mut record_count = 0;
+    let mut co_occurrences: HashMap<String, i64> = HashMap::new();
+
+    for row_result in category_rows {
+        let (categories, count) = row_result?;
+        let tags = parse_categories(&categories, &source.tag_map);
+        let Some(matched) = tags.iter().find(|t| t.to_lowercase() == needle) else {
+            continue;
+        };
+        canonical.get_or_insert_with(|| matched.clone());
+        record_count += count;
+        for other in tags.iter().filter(|t| t.to_lowercase() != needle) {
+            *co_occurrences.entry(other.clone()).or_insert(0) += count;
+        }
+    }
+
+    let Some(tag) = canonical else {
+        return Ok(None);
+    };
+
+    let mut related: Vec<TagCount> = co_occurrences
+        .into_iter()
+        .map(|(tag, count)| TagCount { tag, count })
+        .collect();
+    related.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tag.cmp(&b.tag)));
+
+    Ok(Some(RelatedTagsResponse {
+        tag,
+        record_count,
+        related,
+    }))
+}
+
+// Split a keyword query into lowercase terms worth matching on (years and other bare numbers are too common)
+pub(crate) fn keyword_terms(query: &str) -> std::collections::HashSet<String> {
+    const STOP_WORDS: [&str; 6] = ["the", "and", "for", "with", "from", "news"];
+    query
+        .split(|c: char| !c.is_alphanumeric())
+        .map(|term| term.to_lowercase())
+        .filter(|term| term.chars().count() >= 3 && !STOP_WORDS.contains(&term.as_str()))
+        .filter(|term| !term.chars().all(|c| c.is_ascii_digit()))
+        .collect()
+}
+
+// A serpapi_data row as stored, every column under its own name (not the view adapting older
+// layouts); blobs come base64-encoded. None if there is no row with that id.
+pub(crate) fn query_serpapi_row(source: &DataSource, id: i64) -> SqlResult<Option<serde_json::Map<String, serde_json::Value>>> {
+    use base64::Engine;
+    use rusqlite::types::ValueRef;
+    use rusqlite::OptionalExtension;
+
+    let conn = open_database(source)?;
+    let mut stmt = conn.prepare_cached("SELECT * FROM main.serpapi_data WHERE id = ?1")?;
+    let names: Vec<String> = stmt.column_names().into_iter().map(str::to_string).collect();
+    stmt.query_row([id], |row| {
+        let mut columns = serde_json::Map::new();
+        for (i, name) in names.iter().enumerate() {
+            let value = match row.get_ref(i)? {
+                ValueRef::Null => serde_json::Value::Null,
+                ValueRef::Integer(n) => n.into(),
+                ValueRef::Real(x) => x.into(),
+                ValueRef::Text(text) => String::from_utf8_lossy(text).into_owned().into(),
+                ValueRef::Blob(blob) => base64::engine::general_purpose::STANDARD.encode(blob).into(),
+            };
+            columns.insert(name.clone(), value);
+        }
+        Ok(columns)
+    })
+    .optional()
+}
+
+pub(crate) fn query_related_news(source: &DataSource, id: i64, limit: usize) -> SqlResult<Option<RelatedNewsResponse>> {
+    let conn = open_database(source)?;
+
+    // Keyword and category of every record, used for overlap scoring
+    let mut stmt = conn.prepare_cached(
+        "SELECT main_news_data.id, serpapi_data.query, serpapi_data.categories \
+         FROM main_news_data \
+         LEFT JOIN serpapi_data ON main_news_data.serpapi_id = serpapi_data.id"
+    )?;
+    let candidates = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, Option<String>>(1)?,
+            row.get::<_, Option<String>>(2)?,
+        ))
+    })?.collect::<SqlResult<Vec<_>>>()?;
+
+    let Some((_, target_query, target_categories)) = candidates.iter().find(|(cid, _, _)| *cid == id) else {
+        return Ok(None);
+    };
+    let target_terms = keyword_terms(target_query.as_deref().unwrap_or(""));
+    let target_tags = parse_categories(target_categories.as_deref().unwrap_or(""), &source.tag_map);
+
+    // A shared keyword term is a much stronger signal than a shared broad category
+    let mut scored: Vec<(i64, i64)> = candidates
+        .iter()
+        .filter(|(cid, _, _)| *cid != id)
+        .filter_map(|(cid, query, categories)| {
+            let terms = keyword_terms(query.as_deref().unwrap_or(""));
+            let tags = parse_categories(categories.as_deref().unwrap_or(""), &source.tag_map);
+            let term_overlap = terms.intersection(&target_terms).count() as i64;
+            let tag_overlap = tags.iter().filter(|t| target_tags.contains(t)).count() as i64;
+            let score = term_overlap * 3 + tag_overlap;
+            if term_overlap > 0 || (target_terms.is_empty() && tag_overlap > 0) {
+                Some((*cid, score))
+            } else {
+                None
+            }
+        })
+        .collect();
+    scored.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| b.0.cmp(&a.0)));
+    scored.truncate(limit);
+
+    let mut related = Vec::new();
+    for (cid, score) in scored {
+        if let Some(record) = query_news_records(&conn, source, "WHERE main_news_data.id = ?1", [cid])?.pop() {
+            related.push(ScoredRecord { score, record });
+        }
+    }
+
+    Ok(Some(RelatedNewsResponse { id, related }))
+}
+
+pub(crate) fn query_on_this_day(source: &DataSource, mmdd: &str, options: &ListOptions) -> SqlResult<OnThisDayResponse> {
+    let conn = open_database(source)?;
+
+    // Match the mm-dd part of the yyyy-mm-dd prefix, newest year first
+    let month_day = format!("{}-{}", &mmdd[0..2], &mmdd[2..4]);
+    let records = query_filtered_records(
+        &conn,
+        source,
+        "substr(main_news_data.date, 6, 5) = ?1",
+        Some("substr(main_news_data.date, 1, 4) DESC"),
+        &month_day,
+        options,
+    )?;
+
+    let mut years: Vec<YearRecords> = Vec::new();
+    for record in records {
+        let year = record.date.as_deref().map(|d| d[0..4].to_string()).unwrap_or_default();
+        match years.last_mut() {
+            Some(group) if group.year == year => group.records.push(record),
+            _ => years.push(YearRecords {
+                date: format!("{}{}", year, mmdd),
+                year,
+                records: vec![record],
+            }),
+        }
+    }
+
+    Ok(OnThisDayResponse {
+        month_day: mmdd.to_string(),
+        years,
+    })
+}
+
+// The image rows of the records of a day (yyyy-mm-dd) in record order; None if it has none
+pub(crate) fn query_day_images(source: &DataSource, day: &str) -> SqlResult<Option<ImageListResponse>> {
+    let conn = open_database(source)?;
+    let mut stmt = conn.prepare_cached(
+        "SELECT main_news_data.id, main_news_data.image_id, image_data.file_name \
+         FROM news_days JOIN main_news_data ON main_news_data.id = news_days.id \
+         LEFT JOIN image_data ON image_data.id = main_news_data.image_id \
+         WHERE news_days.day = ?1 \
+         ORDER BY main_news_data.id",
+    )?;
+    let rows = stmt
+        .query_map([day], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Option<i64>>(1)?, row.get::<_, Option<String>>(2)?)))?
+        .collect::<SqlResult<Vec<_>>>()?;
+    if rows.is_empty() {
+        return Ok(None);
+    }
+
+    let mut images = Vec::new();
+    let mut missing = Vec::new();
+    for (record_id, image_id, file_name) in rows {
+        let exists = file_name
+            .as_deref()
+            .is_some_and(|fname| source.serving_images_dir().join(image_relative_path(fname)).is_file());
+        if !exists {
+            missing.push(record_id);
+        }
+        if let Some(id) = image_id {
+            let url = file_name.as_deref().map(|fname| image_url(source, fname));
+            images.push(ImageEntry { id, record_id, file_name, url, exists });
+        }
+    }
+    Ok(Some(ImageListResponse { date: day.replace('-', ""), images, missing }))
+}
+
+// The nearest day (yyyy-mm-dd) after `day`, or before it, that has records
+pub(crate) fn query_adjacent_day(source: &DataSource, day: &str, later: bool) -> SqlResult<Option<String>> {
+    let conn = open_database(source)?;
+    let (prev, next) = adjacent_days(&conn, day)?;
+    Ok(if later { next } else { prev })
+}
+
+pub(crate) fn query_date_range(source: &DataSource) -> SqlResult<DateRangeResponse> {
+    let conn = open_database(source)?;
+    conn.query_row(
+        "SELECT MIN(day), MAX(day), COUNT(DISTINCT day), (SELECT COUNT(*) FROM main_news_data) \
+         FROM (SELECT REPLACE(substr(date, 1, 10), '-', '') AS day FROM main_news_data WHERE date IS NOT NULL)",
+        [],
+        |row| {
+            Ok(DateRangeResponse {
+                first: row.get(0)?,
+                last: row.get(1)?,
+                days: row.get(2)?,
+                total_records: row.get(3)?,
+            })
+        },
+    )
+}
+
+pub(crate) fn query_all_dates(source: &DataSource) -> SqlResult<Vec<DateResponse>> {
+    let conn = open_database(source)?;
+    
+    // Query unique dates from main_news_data in yyyymmdd format with per-day density, sorted by first id
+    let mut stmt = conn.prepare_cached(
+        "SELECT REPLACE(substr(date, 1, 10), '-', '') as date_formatted, \
+         COUNT(*) as record_count, \
+         MAX(image_id IN (SELECT id FROM image_data WHERE file_name IS NOT NULL)) as has_images \
+         FROM main_news_data \
+         GROUP BY date_formatted \
+         ORDER BY MIN(id) ASC"
+    )?;
+
+    let date_rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, i64>(1)?,
+            row.get::<_, Option<bool>>(2)?.unwrap_or(false),
+        ))
+    })?;
+    
+    let mut dates = Vec::new();
+    
+    for row_result in date_rows {
+        let (date_formatted, record_count, has_images) = row_result?;
+        let date_with_url = format!(
+            "{}{}/date/{}",
+            source.base_urls.site_origin(),
+            source.url_prefix(),
+            date_formatted
+        );
+        
+        dates.push(DateResponse {
+            date: date_formatted,
+            date_with_url,
+            record_count,
+            has_images,
+        });
+    }
+    
+    Ok(dates)
+}
+
+// Month-by-month overview of a year (yyyy): the days with records and their counts, linked like
+// /dates, plus the top tags of each month
+pub(crate) fn query_year(source: &DataSource, year: &str) -> SqlResult<YearResponse> {
+    let conn = open_database(source)?;
+
+    let mut day_stmt = conn.prepare_cached(
+        "SELECT news_days.day, COUNT(*), \
+         MAX(main_news_data.image_id IN (SELECT id FROM image_data WHERE file_name IS NOT NULL)) \
+         FROM news_days JOIN main_news_data ON main_news_data.id = news_days.id \
+         WHERE news_days.day BETWEEN ?1 AND ?1 || '~' \
+         GROUP BY news_days.day \
+         ORDER BY news_days.day ASC"
+    )?;
+    let day_rows = day_stmt.query_map([year], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, i64>(1)?,
+            row.get::<_, Option<bool>>(2)?.unwrap_or(false),
+        ))
+    })?;
+
+    let mut months: Vec<YearMonthSummary> = Vec::new();
+    for row_result in day_rows {
+        let (day, record_count, has_images) = row_result?;
+        let month = day.get(..7).unwrap_or_default().to_string();
+        let date = day.replace('-', "");
+        let entry = DateResponse {
+            date_with_url: format!("{}{}/date/{}", source.base_urls.site_origin(), source.url_prefix(), date),
+            date,
+            record_count,
+            has_images,
+        };
+        match months.last_mut() {
+            Some(summary) if summary.month == month => {
+                summary.total_records += record_count;
+                summary.days.push(entry);
+            }
+            _ => months.push(YearMonthSummary {
+                month,
+                total_records: record_count,
+                top_tags: Vec::new(),
+                days: vec![entry],
+            }),
+        }
+    }
+
+    // Categories are stored as a raw string, so group by it first and split afterwards
+    let mut tag_stmt = conn.prepare_cached(
+        "SELECT substr(news_days.day, 1, 7), serpapi_data.categories, COUNT(*) \
+         FROM news_days \
+         JOIN main_news_data ON main_news_data.id = news_days.id \
+         JOIN serpapi_data ON main_news_data.serpapi_id = serpapi_data.id \
+         WHERE news_days.day BETWEEN ?1 AND ?1 || '~' AND serpapi_data.categories IS NOT NULL \
+         GROUP BY 1, 2"
+    )?;
+    let tag_rows = tag_stmt.query_map([year], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, i64>(2)?))
+    })?;
+    let mut tag_totals: HashMap<String, HashMap<String, i64>> = HashMap::new();
+    for row_result in tag_rows {
+        let (month, categories, count) = row_result?;
+        let totals = tag_totals.entry(month).or_default();
+        for tag in parse_categories(&categories, &source.tag_map) {
+            *totals.entry(tag).or_insert(0) += count;
+        }
+    }
+    for summary in &mut months {
+        let mut tags: Vec<TagCount> = tag_totals
+            .remove(&summary.month)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(tag, count)| TagCount { tag, count })
+            .collect();
+        tags.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tag.cmp(&b.tag)));
+        tags.truncate(TOP_TAGS_LIMIT);
+        summary.top_tags = tags;
+    }
+
+    Ok(YearResponse {
+        year: year.to_string(),
+        total_records: months.iter().map(|summary| summary.total_records).sum(),
+        months,
+    })
+}
+
+// The newest dated records across all days, newest first (options carry the date:DESC sort
+// and the page size set by the route)
+pub(crate) fn query_recent_news(source: &DataSource, options: &ListOptions) -> SqlResult<RecentResponse> {
+    let conn = open_database(source)?;
+    // Every date sorts after the empty string; records without one have no place in the feed
+    let mut records = query_filtered_records(&conn, source, "main_news_data.date > ?1", None, "", options)?;
+    let next_cursor = options.paginate(RECENT_CURSOR_DAY, &mut records);
+    Ok(RecentResponse { records, next_cursor })
+}
+
+pub(crate) fn query_changes(source: &DataSource, marker: &ChangesMarker, options: &ListOptions) -> SqlResult<ChangesResponse> {
+    let conn = open_database(source)?;
+    let (condition, value, since) = match marker {
+        ChangesMarker::Id(id) => ("main_news_data.id > CAST(?1 AS INTEGER)", id.to_string(), id.to_string()),
+        ChangesMarker::Time(time) => ("main_news_data.date > ?1", time.clone(), time.clone()),
+    };
+    let mut records = query_filtered_records(&conn, source, condition, None, &value, options)?;
+    let limit = options.limit.unwrap_or(records.len());
+    let has_more = records.len() > limit;
+    records.truncate(limit);
+    let next_since = records.last().map_or(since.clone(), |last| last.id.to_string());
+    Ok(ChangesResponse { since, next_since, has_more, records })
+}
+
+pub(crate) fn query_latest_news(source: &DataSource, options: &ListOptions) -> SqlResult<LatestResponse> {
+    let conn = open_database(source)?;
+    
+    // Find the latest day (yyyy-mm-dd) in the configured timezone
+    let day_expr = local_day_expr(LATEST_TZ_OFFSET_MINUTES);
+    let latest_day = find_latest_day(&conn, &day_expr, LATEST_MIN_RECORDS)?;
+
+    // A cursor keeps paging the day it was issued for, even if a newer day arrived meanwhile
+    let latest_day = match &options.cursor {
+        Some(cursor) => Some(cursor.day.clone()),
+        None => latest_day,
+    };
+
+    // If no day found, return empty response
+    let day_filter = match &latest_day {
+        Some(day) => day.clone(),
+        None => return Ok(LatestResponse {
+            date: None,
+            total_records: 0,
+            offset: 0,
+            records: vec![],
+            next_cursor: None,
+            links: DayLinks::new(source, "/latest", &(None, None)),
+            adjacent_days: (None, None),
+        }),
+    };
+
+    // Query all records from the latest day
+    let condition = format!("{} = ?1", day_expr);
+    let mut records = query_filtered_records(
+        &conn,
+        source,
+        &condition,
+        None,
+        &day_filter,
+        options,
+    )?;
+    let next_cursor = options.paginate(&day_filter, &mut records);
+    let (total_records, offset) = page_position(&conn, source, &condition, &day_filter, options, &records)?;
+    let adjacent = adjacent_days(&conn, &day_filter)?;
+
+    Ok(LatestResponse {
+        date: latest_day,
+        total_records,
+        offset,
+        records,
+        next_cursor,
+        links: DayLinks::new(source, "/latest", &adjacent),
+        adjacent_days: adjacent,
+    })
+}
+
+// SQL expression for a record's yyyy-mm-dd day, shifted into a UTC offset (stored dates are UTC)
+pub(crate) fn local_day_expr(offset_minutes: i32) -> String {
+    if offset_minutes == 0 {
+        "substr(main_news_data.date, 1, 10)".to_string()
+    } else {
+        format!("date(main_news_data.date, '{:+} minutes')", offset_minutes)
+    }
+}
+
+// Newest day with at least min_records records; the newest day overall if none has that many
+pub(crate) fn find_latest_day(conn: &Connection, day_expr: &str, min_records: i64) -> SqlResult<Option<String>> {
+    let mut stmt = conn.prepare_cached(&format!(
+        "SELECT {0} AS day, COUNT(*) FROM main_news_data \
+         WHERE main_news_data.date IS NOT NULL \
+         GROUP BY day ORDER BY day DESC",
+        day_expr
+    ))?;
+    let days = stmt.query_map([], |row| {
+        Ok((row.get::<_, Option<String>>(0)?, row.get::<_, i64>(1)?))
+    })?.collect::<SqlResult<Vec<_>>>()?;
+
+    let newest = days.first().and_then(|(day, _)| day.clone());
+    let complete = days.into_iter()
+        .find(|(day, count)| day.is_some() && *count >= min_records)
+        .and_then(|(day, _)| day);
+    Ok(complete.or(newest))
+}
+
+pub(crate) fn query_news_by_date(source: &DataSource, target_date: &str, options: &ListOptions) -> SqlResult<Option<LatestResponse>> {
+    let conn = open_database(source)?;
+    
+    // Query all records from the specified date
+    let condition = "main_news_data.id IN (SELECT id FROM news_days WHERE day = ?1)";
+    let mut records = query_filtered_records(
+        &conn,
+        source,
+        condition,
+        None,
+        target_date,
+        options,
+    )?;
+
+    // An empty result only means "no data" when the day itself has no records
+    if records.is_empty() && !has_records_with_prefix(&conn, target_date)? {
+        return Ok(None);
+    }
+    let next_cursor = options.paginate(target_date, &mut records);
+    let (total_records, offset) = page_position(&conn, source, condition, target_date, options, &records)?;
+    let adjacent = adjacent_days(&conn, target_date)?;
+
+    Ok(Some(LatestResponse {
+        date: Some(target_date.to_string()),
+        total_records,
+        offset,
+        records,
+        next_cursor,
+        links: DayLinks::new(source, &format!("/date/{}", target_date.replace('-', "")), &adjacent),
+        adjacent_days: adjacent,
+    }))
+}
+
+pub(crate) fn query_news_by_month(source: &DataSource, target_month: &str, options: &ListOptions) -> SqlResult<Option<MonthResponse>> {
+    let conn = open_database(source)?;
+
+    // Days must stay contiguous for grouping; they follow the requested order direction
+    let day_order = format!("substr(main_news_data.date, 1, 10) {}", options.sort.order.sql());
+    let records = query_filtered_records(
+        &conn,
+        source,
+        "main_news_data.id IN (SELECT id FROM news_days WHERE day BETWEEN ?1 AND ?1 || '~')",
+        Some(&day_order),
+        target_month,
+        options,
+    )?;
+
+    if records.is_empty() && !has_records_with_prefix(&conn, target_month)? {
+        return Ok(None);
+    }
+
+    let total_records = records.len();
+    Ok(Some(MonthResponse {
+        month: target_month.to_string(),
+        total_records,
+        days: group_records_by_day(records),
+    }))
+}
+
+// Run a record query whose base condition binds ?1, narrowed by the request's filters and
+// ordered by the requested sort (after any grouping order the caller needs to keep). Ungrouped
+// queries with ?limit= return at most one record more than the limit, for paginate().
+pub(crate) fn query_filtered_records(
+    conn: &Connection,
+    source: &DataSource,
+    condition: &str,
+    group_order: Option<&str>,
+    value: &str,
+    options: &ListOptions,
+) -> SqlResult<Vec<NewsRecord>> {
+    use rusqlite::types::Value;
+
+    let (mut filter_sql, filter_values) = options.filter.sql(2, &source.tag_map);
+    let mut params: Vec<Value> = std::iter::once(value.to_string())
+        .chain(filter_values)
+        .map(Value::Text)
+        .collect();
+
+    // Resume strictly after the cursor's sort key (cursors only apply to ungrouped day lists)
+    if let (Some(cursor), None) = (&options.cursor, group_order) {
+        let placeholders: Vec<String> = (0..cursor.keys.len())
+            .map(|i| format!("?{}", params.len() + i + 1))
+            .collect();
+        let comparison = match options.sort.order {
+            SortOrder::Asc => ">",
+            SortOrder::Desc => "<",
+        };
+        filter_sql.push_str(&format!(
+            " AND ({}) {} ({})",
+            options.sort.columns().join(", "),
+            comparison,
+            placeholders.join(", ")
+        ));
+        params.extend(cursor.keys.iter().map(|key| match key {
+            serde_json::Value::Number(n) => Value::Integer(n.as_i64().unwrap_or_default()),
+            other => Value::Text(other.as_str().unwrap_or_default().to_string()),
+        }));
+    }
+
+    let order_by = match group_order {
+        Some(group) => format!("{}, {}", group, options.sort.sql()),
+        None => options.sort.sql(),
+    };
+    let mut clause = format!("WHERE {}{} ORDER BY {}", condition, filter_sql, order_by);
+    // A page needs one record beyond the limit to know there is a next one. Not with ?tag=,
+    // whose exact match drops rows only after the query.
+    if let (Some(limit), None, None) = (options.limit, group_order, &options.filter.tag) {
+        clause.push_str(&format!(" LIMIT {}", limit + 1));
+    }
+    let records = query_news_records(conn, source, &clause, rusqlite::params_from_iter(params))?;
+    Ok(records.into_iter().filter(|r| options.filter.matches(r, &source.tag_map)).collect())
+}
+
+// Whether any record's date starts with the given yyyy-mm or yyyy-mm-dd prefix. Every day with
+// that prefix sorts between the prefix itself and prefix + '~', so the day index can answer it.
+pub(crate) fn has_records_with_prefix(conn: &Connection, prefix: &str) -> SqlResult<bool> {
+    conn.prepare_cached("SELECT EXISTS(SELECT 1 FROM news_days WHERE day BETWEEN ?1 AND ?1 || '~')")?
+        .query_row([prefix], |row| row.get(0))
+}
+
+pub(crate) fn query_news_by_week(
+    source: &DataSource,
+    year: i32,
+    week: u32,
+    monday: chrono::NaiveDate,
+    options: &ListOptions,
+) -> SqlResult<WeekResponse> {
+    let conn = open_database(source)?;
+
+    let start_date = monday.format("%Y-%m-%d").to_string();
+    let end_date = (monday + chrono::Duration::days(6)).format("%Y-%m-%d").to_string();
+    let records = query_filtered_records(
+        &conn,
+        source,
+        "main_news_data.id IN (SELECT id FROM news_days WHERE day BETWEEN ?1 AND date(?1, '+6 days'))",
+        Some("substr(main_news_data.date, 1, 10) ASC"),
+        &start_date,
+        options,
+    )?;
+
+    let total_records = records.len();
+    let top_tags = count_top_tags(&records, TOP_TAGS_LIMIT);
+    let days = group_records_by_day(records)
+        .into_iter()
+        .map(|day| WeekDayRecords {
+            top_tags: count_top_tags(&day.records, TOP_TAGS_LIMIT),
+            date: day.date,
+            count: day.count,
+            records: day.records,
+        })
+        .collect();
+
+    Ok(WeekResponse {
+        week: format!("{}-W{:02}", year, week),
+        start_date,
+        end_date,
+        total_records,
+        top_tags,
+        days,
+    })
+}
+
+// Most frequent tags across a set of records
+pub(crate) fn count_top_tags(records: &[NewsRecord], limit: usize) -> Vec<TagCount> {
+    let mut totals: HashMap<&str, i64> = HashMap::new();
+    for tag in records.iter().flat_map(|r| r.tag.iter()) {
+        *totals.entry(tag.as_str()).or_insert(0) += 1;
+    }
+    let mut tags: Vec<TagCount> = totals
+        .into_iter()
+        .map(|(tag, count)| TagCount { tag: tag.to_string(), count })
+        .collect();
+    tags.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tag.cmp(&b.tag)));
+    tags.truncate(limit);
+    tags
+}
+
+// Group records (already ordered by day) into per-day buckets keyed by yyyymmdd
+pub(crate) fn group_records_by_day(records: Vec<NewsRecord>) -> Vec<DayRecords> {
+    let mut days: Vec<DayRecords> = Vec::new();
+    for record in records {
+        let day = record.date.as_deref()
+            .map(|d| d.chars().take(10).filter(|c| *c != '-').collect::<String>())
+            .unwrap_or_default();
+        match days.last_mut() {
+            Some(group) if group.date == day => {
+                group.count += 1;
+                group.records.push(record);
+            }
+            _ => days.push(DayRecords {
+                date: day,
+                count: 1,
+                records: vec![record],
+            }),
+        }
+    }
+    days
+}
+
+// Columns shared by every news record query; callers append their own WHERE/ORDER BY clause
+pub(crate) const NEWS_RECORD_SELECT: &str = "SELECT main_news_data.id, main_news_data.news, main_news_data.date, \
+     main_news_data.serpapi_id, main_news_data.image_id, \
+     serpapi_data.date AS serpapi_data_date \
+     FROM main_news_data \
+     LEFT JOIN serpapi_data \
+     ON main_news_data.serpapi_id = serpapi_data.id";
+
+pub(crate) fn query_news_records<P: rusqlite::Params>(
+    conn: &Connection,
+    source: &DataSource,
+    clause: &str,
+    params: P,
+) -> SqlResult<Vec<NewsRecord>> {
+    // The records first, so their statement is timed apart from the per-record lookups
+    let news_rows = time_query("records", || {
+        let mut stmt = conn.prepare_cached(&format!("{} {}", NEWS_RECORD_SELECT, clause))?;
+        let rows = stmt.query_map(params, |row| {
+            Ok((
+                row.get::<_, i64>(0)?,      // id
+                row.get::<_, Option<String>>(1)?,  // news
+                row.get::<_, Option<String>>(2)?,  // date
+                row.get::<_, Option<i64>>(3)?,     // serpapi_id
+                row.get::<_, Option<i64>>(4)?,     // image_id
+                row.get::<_, Option<String>>(5)?,  // serpapi_data_date
+            ))
+        })?;
+        rows.collect::<SqlResult<Vec<_>>>()
+    })?;
+
+    let mut records = Vec::new();
+
+    for (id, news, date, serpapi_id, image_id, serpapi_data_date) in news_rows {
+        // Query keywords from serpapi_data if serpapi_id exists
+        let keywords = if let Some(serpapi_id) = serpapi_id {
+            source.lookups.keywords(serpapi_id, || time_query("keywords", || {
+                let mut keyword_stmt = conn.prepare_cached(
+                    "SELECT query FROM serpapi_data WHERE id = ?1"
+                )?;
+                Ok(keyword_stmt.query_row([serpapi_id], |row| {
+                    let query: Option<String> = row.get(0)?;
+                    Ok(query)
+                }).unwrap_or(None))
+            }))?
+        } else {
+            None
+        };
+
+        // Query image file_name from image_data if image_id exists
+        let image = if let Some(image_id) = image_id {
+            let file_name = source.lookups.image(image_id, || time_query("image", || {
+                let mut image_stmt = conn.prepare_cached(
+                    "SELECT file_name FROM image_data WHERE id = ?1"
+                )?;
+                Ok(image_stmt.query_row([image_id], |row| row.get(0)).unwrap_or(None))
+            }))?;
+            let url = file_name.as_ref().map(|fname| image_url(source, fname));
+            let blurhash = file_name.as_deref().and_then(|fname| source.placeholders.blurhash(fname));
+            Some(ImageInfo { file_name, url, blurhash })
+        } else {
+            None
+        };
+
+        // Query categories from serpapi_data if serpapi_id exists
+        let tag = if let Some(serpapi_id) = serpapi_id {
+            let categories = source.lookups.categories(serpapi_id, || time_query("categories", || {
+                let mut cat_stmt = conn.prepare_cached(
+                    "SELECT categories FROM serpapi_data WHERE id = ?1"
+                )?;
+                Ok(cat_stmt.query_row([serpapi_id], |row| row.get(0)).unwrap_or(None))
+            }))?;
+            categories.map(|cat_str| parse_categories(&cat_str, &source.tag_map)).unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+
+        let thread_id = source.threads.thread_of(conn, id)?;
+        let news = news.map(|news| news_text(source, news));
+        let lang = source.lookups.language(id, || Ok(news.as_deref().and_then(detect_language).map(str::to_string)))?;
+        #[cfg(feature = "sentiment")]
+        let sentiment = crate::sentiment::record_sentiment(conn, id)?;
+        #[cfg(not(feature = "sentiment"))]
+        let sentiment = None;
+
+        records.push(NewsRecord {
+            id,
+            news,
+            date,
+            serpapi_id,
+            image_id,
+            serpapi_data_date,
+            keywords,
+            image,
+            tag,
+            lang,
+            thread_id,
+            sentiment,
+        });
+    }
+
+    #[cfg(feature = "translation")]
+    if let Some(target) = &source.translate_to {
+        crate::translate::translate_records(source, &mut records, target);
+    }
+
+    Ok(records)
+}
+
+// A stored news text as served: without its markup unless the source or request keeps it
+pub(crate) fn news_text(source: &DataSource, news: String) -> String {
+    if source.keep_news_html {
+        return news;
+    }
+    match strip_html(&news) {
+        Cow::Borrowed(_) => news,
+        Cow::Owned(text) => text,
+    }
+}
+
+// Where an image file is stored under the images directory: yyyy/mm/dd taken from its name
+pub(crate) fn image_relative_path(fname: &str) -> String {
+    let tokens: Vec<&str> = fname.split('_').collect();
+    if tokens.len() > 1 {
+        let date_str = tokens[1];
+        // Convert yyyymmdd to yyyy/mm/dd
+        if date_str.len() == 8 {
+            let year = &date_str[0..4];
+            let month = &date_str[4..6];
+            let day = &date_str[6..8];
+            format!("{}/{}/{}/{}", year, month, day, fname)
+        } else {
+            // Fallback for unexpected format
+            format!("{}/{}", date_str, fname)
+        }
+    } else {
+        fname.to_string()
+    }
+}
+
+// Public URL of an image file
+pub(crate) fn image_url(source: &DataSource, fname: &str) -> String {
+    format!("{}{}/images/{}", source.base_urls.api_origin(), source.url_prefix(), image_relative_path(fname))
+}
+
+// Parse a serpapi categories string ("1-Tag|2-Other") into a de-duplicated list of tag names,
+// each translated through the tag map
+pub(crate) fn parse_categories(cat_str: &str, tag_map: &TagMap) -> Vec<String> {
+    if cat_str.trim().is_empty() {
+        return Vec::new();
+    }
+    let mut seen = std::collections::HashSet::new();
+    cat_str.split('|')
+        .filter_map(|token| {
+            let parts: Vec<&str> = token.splitn(2, '-').collect();
+            if parts.len() == 2 {
+                let val = tag_map.normalize(parts[1]);
+                if !val.is_empty() && seen.insert(val.clone()) {
+                    Some(val)
+                } else {
+                    None
+                }
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<String>>()
+}
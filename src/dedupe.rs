@@ -0,0 +1,79 @@
+use std::collections::HashSet;
+use serde_json::Value;
+
+use crate::config::{DEDUPE_KEYWORD_SIMILARITY, DEDUPE_SAME_TEXT_SIMILARITY, DEDUPE_TEXT_SIMILARITY};
+use crate::db::keyword_terms;
+
+// Share of terms two sets have in common (Jaccard index); 0 when both are empty
+fn similarity(a: &HashSet<String>, b: &HashSet<String>) -> f64 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(b).count() as f64 / union as f64
+}
+
+fn terms(record: &Value, field: &str) -> HashSet<String> {
+    keyword_terms(record.get(field).and_then(Value::as_str).unwrap_or_default())
+}
+
+// Two records tell the same story when their keywords mostly overlap and their texts share some
+// terms ("real madrid - osasuna" and "real madrid vs osasuna"), or their texts are nearly the same
+fn same_story(a: &(HashSet<String>, HashSet<String>), b: &(HashSet<String>, HashSet<String>)) -> bool {
+    let text = similarity(&a.1, &b.1);
+    text >= DEDUPE_SAME_TEXT_SIMILARITY
+        || (similarity(&a.0, &b.0) >= DEDUPE_KEYWORD_SIMILARITY && text >= DEDUPE_TEXT_SIMILARITY)
+}
+
+// Collapse one list of records: each cluster of the same story is kept as its first record in
+// list order, with the others in a "duplicates" list on it
+fn collapse(records: Vec<Value>) -> Vec<Value> {
+    let terms: Vec<_> = records.iter().map(|r| (terms(r, "keywords"), terms(r, "news"))).collect();
+
+    // Clusters are transitive: a record joins the cluster of any earlier record it matches
+    let mut cluster: Vec<usize> = (0..records.len()).collect();
+    for i in 0..records.len() {
+        for j in 0..i {
+            if same_story(&terms[i], &terms[j]) {
+                let (from, to) = (cluster[i].max(cluster[j]), cluster[i].min(cluster[j]));
+                cluster.iter_mut().filter(|c| **c == from).for_each(|c| *c = to);
+            }
+        }
+    }
+
+    let mut duplicates: Vec<Vec<Value>> = vec![Vec::new(); records.len()];
+    let mut kept: Vec<(usize, Value)> = Vec::new();
+    for (i, record) in records.into_iter().enumerate() {
+        if cluster[i] == i {
+            kept.push((i, record));
+        } else {
+            duplicates[cluster[i]].push(record);
+        }
+    }
+    kept.into_iter()
+        .map(|(i, mut record)| {
+            let nested = std::mem::take(&mut duplicates[i]);
+            if let (Value::Object(map), false) = (&mut record, nested.is_empty()) {
+                map.insert("duplicates".to_string(), Value::Array(nested));
+            }
+            record
+        })
+        .collect()
+}
+
+// Apply ?dedupe=true to every "records" array of a response. Counts elsewhere in the response
+// still include the duplicates.
+pub(crate) fn collapse_duplicates(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, child) in map.iter_mut() {
+                match child {
+                    Value::Array(records) if key == "records" => *records = collapse(std::mem::take(records)),
+                    _ => collapse_duplicates(child),
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(collapse_duplicates),
+        _ => {}
+    }
+}
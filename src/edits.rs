@@ -0,0 +1,272 @@
+use rusqlite::{params, Connection, Result as SqlResult, TransactionBehavior};
+use serde::Deserialize;
+
+use crate::config::{DataSource, DB_BUSY_TIMEOUT_MS, LOCAL_ID_BASE};
+use crate::db::{database_uri, EXPECTED_SCHEMA};
+
+// Records added, edited or deleted through the admin API live in a separate file next to the
+// clone rather than in the synced database, so a git pull neither conflicts with them nor
+// reverts them. The data tables mirror the upstream ones, restricted to the columns the queries
+// read; news_overrides replaces fields of edited records and suppressed_news lists deleted ones.
+const EDITS_SCHEMA: &str = "\
+    CREATE TABLE IF NOT EXISTS main_news_data (\
+        id INTEGER PRIMARY KEY, news TEXT, date TEXT, serpapi_id INTEGER, image_id INTEGER); \
+    CREATE TABLE IF NOT EXISTS serpapi_data (\
+        id INTEGER PRIMARY KEY, query TEXT NOT NULL, categories TEXT, date TEXT); \
+    CREATE TABLE IF NOT EXISTS image_data (id INTEGER PRIMARY KEY, file_name TEXT); \
+    CREATE TABLE IF NOT EXISTS news_overrides (\
+        id INTEGER PRIMARY KEY, news TEXT, serpapi_id INTEGER, updated_at TEXT NOT NULL); \
+    CREATE TABLE IF NOT EXISTS suppressed_news (id INTEGER PRIMARY KEY, deleted_at TEXT NOT NULL);";
+
+pub(crate) const OVERRIDES_TABLE: &str = "news_overrides";
+pub(crate) const SUPPRESSED_TABLE: &str = "suppressed_news";
+
+// Body of POST /admin/news
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct NewRecord {
+    pub(crate) news: String,
+    // yyyy-mm-dd hh:mm:ss like upstream rows, or yyyy-mm-dd for midnight
+    pub(crate) date: String,
+    #[serde(default)]
+    pub(crate) keywords: Option<String>,
+    #[serde(default)]
+    pub(crate) tag: Vec<String>,
+    // Name of a file in the images directory, e.g. "some-story_20251101_010943.png"
+    #[serde(default)]
+    pub(crate) image_file_name: Option<String>,
+}
+
+impl NewRecord {
+    // Trim the fields and bring the date into the upstream format; the error says what is wrong
+    pub(crate) fn validate(self) -> Result<NewRecord, String> {
+        let news = self.news.trim().to_string();
+        if news.is_empty() {
+            return Err("news must not be empty".to_string());
+        }
+
+        let raw_date = self.date.trim();
+        let date = chrono::NaiveDateTime::parse_from_str(raw_date, "%Y-%m-%d %H:%M:%S")
+            .or_else(|_| {
+                chrono::NaiveDate::parse_from_str(raw_date, "%Y-%m-%d")
+                    .map(|day| day.and_hms_opt(0, 0, 0).unwrap_or_default())
+            })
+            .map_err(|_| format!("date '{}' must be yyyy-mm-dd or yyyy-mm-dd hh:mm:ss", raw_date))?
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string();
+
+        let keywords = self.keywords
+            .map(|keywords| keywords.trim().to_string())
+            .filter(|keywords| !keywords.is_empty());
+
+        let tag = clean_tags(&self.tag)?;
+        // Tags are stored with the keywords row, as upstream does
+        if !tag.is_empty() && keywords.is_none() {
+            return Err("tags require keywords".to_string());
+        }
+
+        let image_file_name = self.image_file_name
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty());
+        if let Some(name) = &image_file_name {
+            if name.starts_with('.') || name.contains('/') || name.contains('\\') {
+                return Err(format!("image_file_name '{}' must be a plain file name", name));
+            }
+        }
+
+        Ok(NewRecord { news, date, keywords, tag, image_file_name })
+    }
+
+    // Day of the record as yyyymmdd (valid once validated)
+    pub(crate) fn day(&self) -> String {
+        self.date.get(..10).unwrap_or_default().replace('-', "")
+    }
+}
+
+// Body of PATCH /admin/news/<id>: the fields to replace, at least one
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RecordPatch {
+    #[serde(default)]
+    pub(crate) news: Option<String>,
+    #[serde(default)]
+    pub(crate) keywords: Option<String>,
+    // Replaces all tags of the record; an empty list removes them
+    #[serde(default)]
+    pub(crate) tag: Option<Vec<String>>,
+}
+
+impl RecordPatch {
+    pub(crate) fn validate(self) -> Result<RecordPatch, String> {
+        if self.news.is_none() && self.keywords.is_none() && self.tag.is_none() {
+            return Err("expected at least one of news, keywords and tag".to_string());
+        }
+        let news = match self.news {
+            Some(news) if news.trim().is_empty() => return Err("news must not be empty".to_string()),
+            news => news.map(|news| news.trim().to_string()),
+        };
+        let keywords = match self.keywords {
+            Some(keywords) if keywords.trim().is_empty() => return Err("keywords must not be empty".to_string()),
+            keywords => keywords.map(|keywords| keywords.trim().to_string()),
+        };
+        let tag = self.tag.as_deref().map(clean_tags).transpose()?;
+        Ok(RecordPatch { news, keywords, tag })
+    }
+}
+
+// The keywords row a record links to, as the queries currently see it
+#[derive(Debug)]
+pub(crate) struct LinkedKeywords {
+    pub(crate) id: Option<i64>,
+    pub(crate) query: Option<String>,
+    pub(crate) categories: Option<String>,
+    pub(crate) date: Option<String>,
+}
+
+fn clean_tags(tags: &[String]) -> Result<Vec<String>, String> {
+    let tags: Vec<String> = tags
+        .iter()
+        .map(|tag| tag.trim().to_string())
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    if tags.iter().any(|tag| tag.contains('|')) {
+        return Err("tags must not contain '|'".to_string());
+    }
+    Ok(tags)
+}
+
+// Upstream categories read "<category id>-<name>|..."; local tags have no category id
+fn categories(tags: &[String]) -> String {
+    tags.iter().map(|tag| format!("0-{}", tag)).collect::<Vec<_>>().join("|")
+}
+
+// Attach the edits file read-only as "edits" if it exists; returns the tables that have local
+// rows (data tables, OVERRIDES_TABLE and SUPPRESSED_TABLE once records were edited or deleted)
+pub(crate) fn attach_edits(conn: &Connection, source: &DataSource) -> SqlResult<Vec<&'static str>> {
+    if !source.edits_path.exists() {
+        return Ok(Vec::new());
+    }
+    conn.execute("ATTACH DATABASE ?1 AS edits", [database_uri(&source.edits_path, false)])?;
+
+    let mut stmt = conn.prepare("SELECT name FROM edits.sqlite_master WHERE type = 'table'")?;
+    let present = stmt.query_map([], |row| row.get::<_, String>(0))?
+        .collect::<SqlResult<Vec<String>>>()?;
+    let mut edited = Vec::new();
+    let tables = EXPECTED_SCHEMA.iter().map(|(table, _)| *table).chain([OVERRIDES_TABLE, SUPPRESSED_TABLE]);
+    for table in tables {
+        if !present.iter().any(|name| name == table) {
+            continue;
+        }
+        let has_rows: bool = conn.query_row(
+            &format!("SELECT EXISTS(SELECT 1 FROM edits.{})", table),
+            [],
+            |row| row.get(0),
+        )?;
+        if has_rows {
+            edited.push(table);
+        }
+    }
+    Ok(edited)
+}
+
+// The read-write connection of the edits, used per write and separate from the read-only
+// connections of the queries. Creates the file on first use.
+pub(crate) fn open_edits_database(source: &DataSource) -> SqlResult<Connection> {
+    let conn = Connection::open(&source.edits_path)?;
+    conn.busy_timeout(std::time::Duration::from_millis(DB_BUSY_TIMEOUT_MS))?;
+    conn.execute_batch(EDITS_SCHEMA)?;
+    Ok(conn)
+}
+
+// Next free local id of a table; local ids never collide with upstream ones
+fn next_id(conn: &Connection, table: &str) -> SqlResult<i64> {
+    conn.query_row(
+        &format!("SELECT COALESCE(MAX(id), ?1) + 1 FROM {}", table),
+        [LOCAL_ID_BASE],
+        |row| row.get(0),
+    )
+}
+
+// Store a validated record with its keywords/tags and image rows; returns the record's id
+pub(crate) fn insert_record(source: &DataSource, record: &NewRecord) -> SqlResult<i64> {
+    let mut conn = open_edits_database(source)?;
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+    let serpapi_id = match &record.keywords {
+        Some(keywords) => {
+            let id = next_id(&tx, "serpapi_data")?;
+            tx.execute(
+                "INSERT INTO serpapi_data (id, query, categories, date) VALUES (?1, ?2, ?3, ?4)",
+                params![id, keywords, categories(&record.tag), record.date],
+            )?;
+            Some(id)
+        }
+        None => None,
+    };
+
+    let image_id = match &record.image_file_name {
+        Some(file_name) => {
+            let id = next_id(&tx, "image_data")?;
+            tx.execute("INSERT INTO image_data (id, file_name) VALUES (?1, ?2)", params![id, file_name])?;
+            Some(id)
+        }
+        None => None,
+    };
+
+    let id = next_id(&tx, "main_news_data")?;
+    tx.execute(
+        "INSERT INTO main_news_data (id, news, date, serpapi_id, image_id) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![id, record.news, record.date, serpapi_id, image_id],
+    )?;
+    tx.commit()?;
+    Ok(id)
+}
+
+// Apply a validated patch to a record. New keywords or tags go to a local keywords row (a copy
+// of the linked one unless that is already local), so upstream rows shared by other records
+// stay as they are; the record is pointed at it through news_overrides.
+pub(crate) fn patch_record(source: &DataSource, id: i64, patch: &RecordPatch, linked: LinkedKeywords) -> SqlResult<()> {
+    let mut conn = open_edits_database(source)?;
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+    let mut serpapi_id = None;
+    if patch.keywords.is_some() || patch.tag.is_some() {
+        let query = patch.keywords.clone().or(linked.query).unwrap_or_default();
+        let categories = patch.tag.as_deref().map(categories).or(linked.categories);
+        match linked.id {
+            Some(local) if local > LOCAL_ID_BASE => {
+                tx.execute(
+                    "UPDATE serpapi_data SET query = ?2, categories = ?3 WHERE id = ?1",
+                    params![local, query, categories],
+                )?;
+            }
+            _ => {
+                let local = next_id(&tx, "serpapi_data")?;
+                tx.execute(
+                    "INSERT INTO serpapi_data (id, query, categories, date) VALUES (?1, ?2, ?3, ?4)",
+                    params![local, query, categories, linked.date],
+                )?;
+                serpapi_id = Some(local);
+            }
+        }
+    }
+
+    tx.execute(
+        "INSERT INTO news_overrides (id, news, serpapi_id, updated_at) VALUES (?1, ?2, ?3, ?4) \
+         ON CONFLICT (id) DO UPDATE SET news = COALESCE(excluded.news, news), \
+         serpapi_id = COALESCE(excluded.serpapi_id, serpapi_id), updated_at = excluded.updated_at",
+        params![id, patch.news, serpapi_id, chrono::Utc::now().to_rfc3339()],
+    )?;
+    tx.commit()
+}
+
+// Hide a record from every query. Rows are never removed (so ids aren't reused); the id is
+// listed in suppressed_news, which keeps an upstream record hidden after the next pull too.
+pub(crate) fn delete_record(source: &DataSource, id: i64) -> SqlResult<()> {
+    let conn = open_edits_database(source)?;
+    conn.execute(
+        "INSERT OR IGNORE INTO suppressed_news (id, deleted_at) VALUES (?1, ?2)",
+        params![id, chrono::Utc::now().to_rfc3339()],
+    )?;
+    Ok(())
+}
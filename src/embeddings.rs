@@ -0,0 +1,412 @@
+// Text embeddings of the records for GET /news/<id>/similar, computed after every sync and kept in
+// a local side file (embeddings_path) by record id. They come from the configured endpoint, which
+// speaks the OpenAI embeddings API (POST {"model", "input": [...]}, answered with
+// {"data": [{"index", "embedding"}]}) through curl, or without one from a local model hashing the
+// words and word pairs of each text into a fixed-size vector. Only built with the embeddings
+// feature; without it the route answers 501.
+use serde::{Deserialize, Serialize};
+
+use crate::db::NewsRecord;
+
+#[cfg(feature = "embeddings")]
+pub(crate) use model::{query_similar_news, spawn_embedding};
+
+#[derive(Clone)]
+pub struct Embedder {
+    pub url: String,
+    pub model: Option<String>,
+    pub api_key: Option<String>,
+}
+
+// Debug without the key, as sources are Debug
+impl std::fmt::Debug for Embedder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Embedder")
+            .field("url", &self.url)
+            .field("model", &self.model)
+            .field("api_key", &self.api_key.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SimilarRecord {
+    // Cosine similarity of the two texts' embeddings, 1 for the same meaning
+    pub similarity: f64,
+    #[serde(flatten)]
+    pub record: NewsRecord,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SimilarNewsResponse {
+    pub id: i64,
+    // The embeddings compared: the endpoint's model, or the local one
+    pub model: String,
+    pub similar: Vec<SimilarRecord>,
+}
+
+// Never called: the route answers 501 before without the feature
+#[cfg(not(feature = "embeddings"))]
+pub(crate) fn query_similar_news(
+    _source: &crate::config::DataSource,
+    _id: i64,
+    _limit: usize,
+) -> rusqlite::Result<Option<SimilarNewsResponse>> {
+    Ok(None)
+}
+
+#[cfg(feature = "embeddings")]
+mod model {
+    use std::collections::{HashMap, HashSet};
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::path::PathBuf;
+    use std::process::{Command, Stdio};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use rusqlite::{params, Connection, OpenFlags, OptionalExtension, Result as SqlResult};
+    use serde_json::json;
+
+    use super::{Embedder, SimilarNewsResponse, SimilarRecord};
+    use crate::config::{DataSource, DB_BUSY_TIMEOUT_MS, EMBEDDINGS_TIMEOUT_SECONDS, EMBEDDING_BATCH};
+    use crate::db::{open_database, query_news_records};
+    use crate::markup::strip_html;
+    use crate::AppState;
+
+    // The local model: vector size, and the name its vectors are stored under (to be changed along
+    // with the way they are computed)
+    const LOCAL_DIMENSIONS: usize = 512;
+    const LOCAL_MODEL: &str = "local-hashed-words-512";
+    // Words too common to tell stories apart, including the phrasing every upstream text shares
+    const LOCAL_STOPWORDS: &[&str] = &[
+        "the", "and", "for", "that", "this", "with", "are", "was", "were", "has", "have", "had", "its", "his",
+        "her", "their", "they", "from", "but", "not", "you", "your", "all", "can", "will", "also", "been", "being",
+        "which", "who", "what", "when", "where", "how", "why", "about", "after", "into", "over", "more", "most",
+        "such", "than", "then", "these", "those", "there", "some", "many", "other", "out", "one", "new", "just",
+        "recent", "recently", "due", "related", "keywords", "keyword", "trending", "trend", "trends", "searches",
+        "search", "people", "interest", "public", "news", "likely", "significant", "primarily", "widely",
+    ];
+    // Characters of a text sent to the endpoint; embedding models read a limited number of tokens
+    const MAX_INPUT_CHARS: usize = 4000;
+
+    // source_sha1 identifies the text a vector was computed from and model the embeddings it is
+    // one of, so an edited record, or every record after a change of model, is embedded again
+    const EMBEDDINGS_SCHEMA: &str = "\
+        CREATE TABLE IF NOT EXISTS embeddings (\
+            record_id INTEGER PRIMARY KEY, source_sha1 TEXT NOT NULL, model TEXT NOT NULL, \
+            vector BLOB NOT NULL, computed_at TEXT NOT NULL);";
+
+    fn model_name(source: &DataSource) -> String {
+        match &source.embedder {
+            Some(embedder) => embedder.model.clone().unwrap_or_else(|| embedder.url.clone()),
+            None => LOCAL_MODEL.to_string(),
+        }
+    }
+
+    // The Authorization header in a file only the server's user can read, handed to curl as
+    // -H @file: on its command line the key would show in the process list. Removed when dropped.
+    struct HeaderFile(PathBuf);
+
+    impl HeaderFile {
+        fn write(header: &str) -> std::io::Result<HeaderFile> {
+            static NEXT: AtomicU64 = AtomicU64::new(0);
+            let path = std::env::temp_dir().join(format!(
+                "trend-story-embeddings-{}-{}.header",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            let mut file = OpenOptions::new().write(true).create_new(true).mode(0o600).open(&path)?;
+            let header_file = HeaderFile(path);
+            file.write_all(header.as_bytes())?;
+            Ok(header_file)
+        }
+    }
+
+    impl Drop for HeaderFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    impl Embedder {
+        fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, String> {
+            let mut body = json!({ "input": inputs });
+            if let Some(model) = &self.model {
+                body["model"] = json!(model);
+            }
+            let body = serde_json::to_vec(&body).unwrap_or_default();
+            let mut command = Command::new("curl");
+            command
+                .args(["-sS", "--fail", "--max-time", &EMBEDDINGS_TIMEOUT_SECONDS.to_string(), "-X", "POST"])
+                .args(["-H", "Content-Type: application/json", "--data-binary", "@-"]);
+            // Kept until curl is done with it
+            let header_file = self
+                .api_key
+                .as_ref()
+                .map(|key| HeaderFile::write(&format!("Authorization: Bearer {}\n", key)))
+                .transpose()
+                .map_err(|e| e.to_string())?;
+            if let Some(header_file) = &header_file {
+                command.arg("-H").arg(format!("@{}", header_file.0.display()));
+            }
+            let child = command
+                .arg(&self.url)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn();
+            let output = child
+                .and_then(|mut child| {
+                    if let Some(mut stdin) = child.stdin.take() {
+                        stdin.write_all(&body)?;
+                    }
+                    child.wait_with_output()
+                })
+                .map_err(|e| e.to_string())?;
+            if !output.status.success() {
+                return Err(format!("curl {}: {}", output.status, String::from_utf8_lossy(&output.stderr).trim()));
+            }
+            let reply: serde_json::Value = serde_json::from_slice(&output.stdout).map_err(|e| e.to_string())?;
+            let mut data: Vec<(u64, Vec<f32>)> = reply["data"]
+                .as_array()
+                .ok_or("no data in the reply")?
+                .iter()
+                .map(|item| {
+                    let vector: Option<Vec<f32>> =
+                        item["embedding"].as_array()?.iter().map(|x| x.as_f64().map(|x| x as f32)).collect();
+                    Some((item["index"].as_u64()?, vector?))
+                })
+                .collect::<Option<_>>()
+                .ok_or("malformed embedding in the reply")?;
+            if data.len() != inputs.len() {
+                return Err(format!("{} embeddings for {} inputs", data.len(), inputs.len()));
+            }
+            data.sort_by_key(|(index, _)| *index);
+            Ok(data.into_iter().map(|(_, vector)| vector).collect())
+        }
+    }
+
+    // FNV-1a, stable across builds so stored local vectors stay comparable
+    fn fnv1a(text: &str) -> u64 {
+        text.bytes().fold(0xcbf29ce484222325, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3))
+    }
+
+    // The local model: every word of three or more letters but the stopwords, and every pair of
+    // adjacent ones, adds or subtracts (by a bit of its hash) to one of LOCAL_DIMENSIONS components
+    fn local_embedding(text: &str) -> Vec<f32> {
+        let words: Vec<String> = text
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| word.chars().count() >= 3)
+            .map(str::to_lowercase)
+            .filter(|word| !LOCAL_STOPWORDS.contains(&word.as_str()))
+            .collect();
+        let mut vector = vec![0f32; LOCAL_DIMENSIONS];
+        let mut add = |feature: &str| {
+            let hash = fnv1a(feature);
+            let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+            vector[(hash % LOCAL_DIMENSIONS as u64) as usize] += sign;
+        };
+        for word in &words {
+            add(word);
+        }
+        for pair in words.windows(2) {
+            add(&format!("{} {}", pair[0], pair[1]));
+        }
+        // Dampen words repeated throughout a text
+        vector.iter_mut().for_each(|x| *x = x.signum() * x.abs().sqrt());
+        vector
+    }
+
+    fn cosine(a: &[f32], b: &[f32]) -> f64 {
+        let (mut dot, mut norm_a, mut norm_b) = (0f64, 0f64, 0f64);
+        for (x, y) in a.iter().zip(b) {
+            dot += f64::from(*x) * f64::from(*y);
+            norm_a += f64::from(*x) * f64::from(*x);
+            norm_b += f64::from(*y) * f64::from(*y);
+        }
+        if norm_a == 0.0 || norm_b == 0.0 {
+            return 0.0;
+        }
+        dot / (norm_a.sqrt() * norm_b.sqrt())
+    }
+
+    fn to_blob(vector: &[f32]) -> Vec<u8> {
+        vector.iter().flat_map(|x| x.to_le_bytes()).collect()
+    }
+
+    fn from_blob(blob: &[u8]) -> Vec<f32> {
+        blob.chunks_exact(4).map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])).collect()
+    }
+
+    fn text_sha1(text: &str) -> String {
+        use sha1::{Digest, Sha1};
+
+        Sha1::digest(text.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn open_embeddings_database(source: &DataSource) -> SqlResult<Connection> {
+        let conn = Connection::open(&source.embeddings_path)?;
+        conn.busy_timeout(std::time::Duration::from_millis(DB_BUSY_TIMEOUT_MS))?;
+        conn.execute_batch(EMBEDDINGS_SCHEMA)?;
+        Ok(conn)
+    }
+
+    // Once a sync is done, embed the source's new and changed records and forget the deleted ones,
+    // on a background thread. A run still going when the next sync ends is left alone.
+    pub(crate) fn spawn_embedding(state: &AppState) {
+        if state.computing_embeddings.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let state = state.clone();
+        std::thread::spawn(move || {
+            let started = std::time::Instant::now();
+            match embed_records(&state.source) {
+                Ok(0) => {}
+                Ok(changed) => {
+                    println!("Updated {} embeddings in {}ms", changed, started.elapsed().as_millis());
+                    // Similar records computed meanwhile miss the new ones
+                    state.clear_cache();
+                }
+                Err(e) => eprintln!("Failed to embed records in {}: {}", state.source.embeddings_path.display(), e),
+            }
+            state.computing_embeddings.store(false, Ordering::Release);
+        });
+    }
+
+    // Returns how many embeddings were added, replaced or dropped. Texts go to the endpoint
+    // EMBEDDING_BATCH at a time; after a failed batch the rest waits for the next sync.
+    fn embed_records(source: &DataSource) -> SqlResult<usize> {
+        let conn = open_database(source)?;
+        let mut stmt = conn.prepare(
+            "SELECT main_news_data.id, serpapi_data.query, main_news_data.news \
+             FROM main_news_data \
+             LEFT JOIN serpapi_data ON main_news_data.serpapi_id = serpapi_data.id"
+        )?;
+        let records = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, Option<String>>(1)?, row.get::<_, Option<String>>(2)?))
+            })?
+            .collect::<SqlResult<Vec<_>>>()?;
+
+        let model = model_name(source);
+        let mut out = open_embeddings_database(source)?;
+        let known: HashMap<i64, (String, String)> = out
+            .prepare("SELECT record_id, source_sha1, model FROM embeddings")?
+            .query_map([], |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?))))?
+            .collect::<SqlResult<_>>()?;
+
+        // The keywords and the plain news text, of the records without a current vector
+        let mut present = HashSet::with_capacity(records.len());
+        let mut pending = Vec::new();
+        for (id, keywords, news) in records {
+            present.insert(id);
+            let news = news.as_deref().map(strip_html).unwrap_or_default();
+            let text = format!("{}\n\n{}", keywords.unwrap_or_default().trim(), news.trim());
+            if text.trim().is_empty() {
+                continue;
+            }
+            let sha1 = text_sha1(&text);
+            if known.get(&id).is_some_and(|(known_sha1, known_model)| *known_sha1 == sha1 && *known_model == model) {
+                continue;
+            }
+            pending.push((id, sha1, text));
+        }
+
+        let tx = out.transaction()?;
+        let mut changed = 0;
+        {
+            let mut delete = tx.prepare("DELETE FROM embeddings WHERE record_id = ?1")?;
+            for id in known.keys().filter(|id| !present.contains(id)) {
+                delete.execute([id])?;
+                changed += 1;
+            }
+            let mut upsert = tx.prepare(
+                "INSERT OR REPLACE INTO embeddings (record_id, source_sha1, model, vector, computed_at) \
+                 VALUES (?1, ?2, ?3, ?4, ?5)"
+            )?;
+            let computed_at = chrono::Utc::now().to_rfc3339();
+            for batch in pending.chunks(EMBEDDING_BATCH) {
+                let vectors = match &source.embedder {
+                    Some(embedder) => {
+                        let inputs: Vec<String> =
+                            batch.iter().map(|(_, _, text)| text.chars().take(MAX_INPUT_CHARS).collect()).collect();
+                        match embedder.embed(&inputs) {
+                            Ok(vectors) => vectors,
+                            Err(e) => {
+                                eprintln!("Failed to embed {} records with {}: {}", batch.len(), embedder.url, e);
+                                break;
+                            }
+                        }
+                    }
+                    None => batch.iter().map(|(_, _, text)| local_embedding(text)).collect(),
+                };
+                for ((id, sha1, _), vector) in batch.iter().zip(vectors) {
+                    upsert.execute(params![id, sha1, model, to_blob(&vector), computed_at])?;
+                    changed += 1;
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(changed)
+    }
+
+    // The records whose embeddings are closest to a record's, most similar first. None when the
+    // record doesn't exist; no records while it has no embedding yet (or no text).
+    pub(crate) fn query_similar_news(source: &DataSource, id: i64, limit: usize) -> SqlResult<Option<SimilarNewsResponse>> {
+        let conn = open_database(source)?;
+        if query_news_records(&conn, source, "WHERE main_news_data.id = ?1", [id])?.is_empty() {
+            return Ok(None);
+        }
+        let model = model_name(source);
+        let mut response = SimilarNewsResponse { id, model, similar: Vec::new() };
+        if !source.embeddings_path.exists() {
+            return Ok(Some(response));
+        }
+        let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+        let vectors_conn = Connection::open_with_flags(&source.embeddings_path, flags)?;
+        vectors_conn.busy_timeout(std::time::Duration::from_millis(DB_BUSY_TIMEOUT_MS))?;
+        let target: Option<Vec<u8>> = vectors_conn
+            .query_row("SELECT vector FROM embeddings WHERE record_id = ?1 AND model = ?2", params![id, response.model], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        let Some(target) = target.map(|blob| from_blob(&blob)) else {
+            return Ok(Some(response));
+        };
+
+        let mut stmt = vectors_conn.prepare("SELECT record_id, vector FROM embeddings WHERE model = ?1 AND record_id != ?2")?;
+        let rows = stmt.query_map(params![response.model, id], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?)))?;
+        let mut scored: Vec<(i64, f64)> = Vec::new();
+        for row in rows {
+            let (cid, blob) = row?;
+            let vector = from_blob(&blob);
+            if vector.len() == target.len() {
+                scored.push((cid, cosine(&target, &vector)));
+            }
+        }
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| b.0.cmp(&a.0)));
+        // A few spare in case records were deleted since they were embedded
+        scored.truncate(limit * 2);
+
+        // Ids come from the side file, never from the request
+        let ids: Vec<String> = scored.iter().map(|(cid, _)| cid.to_string()).collect();
+        let mut records: HashMap<i64, _> = query_news_records(
+            &conn,
+            source,
+            &format!("WHERE main_news_data.id IN ({})", ids.join(",")),
+            [],
+        )?
+        .into_iter()
+        .map(|record| (record.id, record))
+        .collect();
+        response.similar = scored
+            .into_iter()
+            .filter_map(|(cid, similarity)| {
+                let record = records.remove(&cid)?;
+                Some(SimilarRecord { similarity: (similarity * 10000.0).round() / 10000.0, record })
+            })
+            .take(limit)
+            .collect();
+        Ok(Some(response))
+    }
+}
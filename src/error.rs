@@ -0,0 +1,394 @@
+use serde::Serialize;
+use warp::http::StatusCode;
+
+use crate::config::{DB_BUSY_RETRY_AFTER_SECONDS, OVERLOAD_RETRY_AFTER_SECONDS, SYNC_RETRY_SECONDS};
+use crate::report::report;
+
+// Every way a request can fail, with enough context to tell which input or query it was about.
+// The Display text is the message sent to clients; status() picks the HTTP status.
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum ApiError {
+    #[error("Invalid date format '{0}'. Expected yyyymmdd, yyyy-mm-dd, yyyymm or yyyy-mm")]
+    InvalidDate(String),
+    #[error("Invalid date '{0}'. No such day or month in the calendar")]
+    NonexistentDate(String),
+    #[error("Invalid date format '{0}'. Expected 4 digits (mmdd)")]
+    InvalidMonthDay(String),
+    #[error("Invalid year format '{0}'. Expected 4 digits (yyyy)")]
+    InvalidYear(String),
+    #[error("Invalid week format '{0}'. Expected an ISO week (yyyyWww)")]
+    InvalidWeek(String),
+    #[error("Invalid or expired cursor")]
+    InvalidCursor,
+    #[error("Invalid value for query parameter '{0}'")]
+    InvalidQueryParameter(&'static str),
+    #[error("Invalid record: {0}")]
+    InvalidRecord(String),
+    #[error("Request body is too large; at most {0} bytes are accepted")]
+    BodyTooLarge(usize),
+    #[error("Query string is too long; at most {0} bytes are accepted")]
+    QueryTooLong(usize),
+    #[error("No data found for {0}")]
+    NoDataFound(String),
+    #[error("No records found for tag '{0}'")]
+    TagNotFound(String),
+    #[error("No appearances found for keyword '{0}'")]
+    KeywordNotFound(String),
+    #[error("No record found with id {0}")]
+    RecordNotFound(i64),
+    #[error("No image found at {0}")]
+    ImageNotFound(String),
+    #[error("Invalid image path {0}")]
+    InvalidImagePath(String),
+    #[error("The image storage failed to deliver {0}")]
+    ImageUpstream(String),
+    #[error("Missing or invalid admin token")]
+    Unauthorized,
+    #[error("The token lacks the scope required for admin endpoints")]
+    InsufficientScope,
+    #[error("Admin endpoints are disabled; set an admin token to enable them")]
+    AdminDisabled,
+    #[error("Invalid API key")]
+    InvalidApiKey,
+    #[error("An API key is required; send it as X-API-Key")]
+    ApiKeyRequired,
+    #[error("No API key with id '{0}'")]
+    ApiKeyNotFound(String),
+    // Seconds until the next minute
+    #[error("Rate limit of this API key exceeded; retry in {0} seconds")]
+    RateLimited(u64),
+    // Seconds until the next UTC day
+    #[error("Daily quota of this API key used up; retry in {0} seconds")]
+    QuotaExceeded(u64),
+    #[error("A sync of this source is already running")]
+    SyncInProgress,
+    #[error("Translation is not available on this server")]
+    TranslationUnavailable,
+    #[error("Translation into '{0}' is not offered on this server")]
+    LanguageNotOffered(String),
+    #[error("Sentiment scores are not available on this server")]
+    SentimentUnavailable,
+    #[error("Similar stories are not available on this server")]
+    EmbeddingsUnavailable,
+    #[error("Not Acceptable; supported types are application/json, application/x-ndjson, text/csv, application/xml, text/html and application/vnd.api+json")]
+    NotAcceptable,
+    #[error("Method Not Allowed")]
+    MethodNotAllowed { allow: &'static str },
+    #[error("Too many requests in flight for {0}, please retry")]
+    Overloaded(String),
+    #[error("Data is not available yet, the first sync is still pending")]
+    NotReady,
+    #[error("Internal Server Error")]
+    Panicked,
+    #[error("Database is busy, please retry")]
+    DatabaseBusy {
+        query: String,
+        #[source]
+        source: rusqlite::Error,
+    },
+    #[error("Database Error")]
+    Database {
+        query: String,
+        #[source]
+        source: rusqlite::Error,
+    },
+}
+
+impl ApiError {
+    // Wrap a failed query: lock contention (e.g. while a pull replaces the file) becomes a
+    // retryable 503, anything else a 500
+    pub(crate) fn database(query: impl Into<String>, source: rusqlite::Error) -> ApiError {
+        let query = query.into();
+        match source.sqlite_error_code() {
+            Some(rusqlite::ErrorCode::DatabaseBusy) | Some(rusqlite::ErrorCode::DatabaseLocked) => {
+                ApiError::DatabaseBusy { query, source }
+            }
+            _ => ApiError::Database { query, source },
+        }
+    }
+
+    // Stable identifier for clients to branch on; the message may change wording
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            ApiError::InvalidDate(_) => "INVALID_DATE",
+            ApiError::NonexistentDate(_) => "NONEXISTENT_DATE",
+            ApiError::InvalidMonthDay(_) => "INVALID_MONTH_DAY",
+            ApiError::InvalidYear(_) => "INVALID_YEAR",
+            ApiError::InvalidWeek(_) => "INVALID_WEEK",
+            ApiError::InvalidCursor => "INVALID_CURSOR",
+            ApiError::InvalidQueryParameter(_) => "INVALID_QUERY_PARAMETER",
+            ApiError::InvalidRecord(_) => "INVALID_RECORD",
+            ApiError::BodyTooLarge(_) => "PAYLOAD_TOO_LARGE",
+            ApiError::QueryTooLong(_) => "URI_TOO_LONG",
+            ApiError::NoDataFound(_) => "NO_DATA",
+            ApiError::TagNotFound(_) => "TAG_NOT_FOUND",
+            ApiError::KeywordNotFound(_) => "KEYWORD_NOT_FOUND",
+            ApiError::RecordNotFound(_) => "RECORD_NOT_FOUND",
+            ApiError::ImageNotFound(_) => "IMAGE_NOT_FOUND",
+            ApiError::ImageUpstream(_) => "BAD_GATEWAY",
+            ApiError::InvalidImagePath(_) => "INVALID_IMAGE_PATH",
+            ApiError::Unauthorized => "UNAUTHORIZED",
+            ApiError::InsufficientScope => "INSUFFICIENT_SCOPE",
+            ApiError::AdminDisabled => "ADMIN_DISABLED",
+            ApiError::InvalidApiKey => "INVALID_API_KEY",
+            ApiError::ApiKeyRequired => "API_KEY_REQUIRED",
+            ApiError::ApiKeyNotFound(_) => "API_KEY_NOT_FOUND",
+            ApiError::RateLimited(_) => "RATE_LIMITED",
+            ApiError::QuotaExceeded(_) => "QUOTA_EXCEEDED",
+            ApiError::SyncInProgress => "SYNC_IN_PROGRESS",
+            ApiError::TranslationUnavailable => "TRANSLATION_UNAVAILABLE",
+            ApiError::LanguageNotOffered(_) => "LANGUAGE_NOT_OFFERED",
+            ApiError::SentimentUnavailable => "SENTIMENT_UNAVAILABLE",
+            ApiError::EmbeddingsUnavailable => "EMBEDDINGS_UNAVAILABLE",
+            ApiError::NotAcceptable => "NOT_ACCEPTABLE",
+            ApiError::MethodNotAllowed { .. } => "METHOD_NOT_ALLOWED",
+            ApiError::Overloaded(_) => "OVERLOADED",
+            ApiError::NotReady => "NOT_READY",
+            ApiError::Panicked => "INTERNAL_ERROR",
+            ApiError::DatabaseBusy { .. } => "DB_UNAVAILABLE",
+            ApiError::Database { .. } => "DB_ERROR",
+        }
+    }
+
+    pub(crate) fn status(&self) -> StatusCode {
+        match self {
+            ApiError::InvalidDate(_)
+            | ApiError::NonexistentDate(_)
+            | ApiError::InvalidMonthDay(_)
+            | ApiError::InvalidYear(_)
+            | ApiError::InvalidWeek(_)
+            | ApiError::InvalidCursor
+            | ApiError::InvalidQueryParameter(_)
+            | ApiError::InvalidRecord(_)
+            | ApiError::InvalidImagePath(_)
+            | ApiError::LanguageNotOffered(_) => StatusCode::BAD_REQUEST,
+            ApiError::BodyTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::QueryTooLong(_) => StatusCode::URI_TOO_LONG,
+            ApiError::NoDataFound(_)
+            | ApiError::TagNotFound(_)
+            | ApiError::KeywordNotFound(_)
+            | ApiError::RecordNotFound(_)
+            | ApiError::ImageNotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::InsufficientScope => StatusCode::FORBIDDEN,
+            ApiError::AdminDisabled => StatusCode::FORBIDDEN,
+            ApiError::InvalidApiKey | ApiError::ApiKeyRequired => StatusCode::UNAUTHORIZED,
+            ApiError::ApiKeyNotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::RateLimited(_) | ApiError::QuotaExceeded(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::SyncInProgress => StatusCode::CONFLICT,
+            ApiError::TranslationUnavailable => StatusCode::NOT_IMPLEMENTED,
+            ApiError::SentimentUnavailable => StatusCode::NOT_IMPLEMENTED,
+            ApiError::EmbeddingsUnavailable => StatusCode::NOT_IMPLEMENTED,
+            ApiError::NotAcceptable => StatusCode::NOT_ACCEPTABLE,
+            ApiError::MethodNotAllowed { .. } => StatusCode::METHOD_NOT_ALLOWED,
+            ApiError::Overloaded(_) | ApiError::NotReady | ApiError::DatabaseBusy { .. } => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            ApiError::ImageUpstream(_) => StatusCode::BAD_GATEWAY,
+            ApiError::Panicked | ApiError::Database { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+// Log panics with their location and a backtrace (regardless of RUST_BACKTRACE), so a handler
+// panic that catch_panic turns into a 500 still leaves a trace in the logs, and report them
+pub fn log_panics() {
+    std::panic::set_hook(Box::new(|info| {
+        let message = format!("panic: {}\n{}", info, std::backtrace::Backtrace::force_capture());
+        eprintln!("{}", message);
+        report("fatal", &message, &[("kind", "panic")], None);
+    }));
+}
+
+// JSON body of every error response. handle_rejection also stores it in the response
+// extensions so the outermost filter can fill in the request's path and id.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct ErrorBody {
+    pub(crate) error: String,
+    pub(crate) code: &'static str,
+    pub(crate) status: u16,
+    pub(crate) path: Option<String>,
+    pub(crate) timestamp: String,
+    pub(crate) request_id: Option<String>,
+    // What went wrong on the server side (5xx), for the error report but not the client
+    #[serde(skip)]
+    pub(crate) detail: Option<String>,
+}
+
+// warp converts any Reject into a Rejection, so handlers can return ApiError::...into()
+impl warp::reject::Reject for ApiError {}
+
+// warp's own rejections of malformed requests: the client's mistake, answered with a 4xx like
+// ApiError's and not reported
+fn warp_client_error(err: &warp::Rejection) -> Option<(StatusCode, &'static str, String)> {
+    use warp::reject::{InvalidHeader, InvalidQuery, MethodNotAllowed, MissingHeader, PayloadTooLarge, UnsupportedMediaType};
+
+    if let Some(rejection) = err.find::<MethodNotAllowed>() {
+        Some((StatusCode::METHOD_NOT_ALLOWED, "METHOD_NOT_ALLOWED", rejection.to_string()))
+    } else if let Some(rejection) = err.find::<InvalidQuery>() {
+        Some((StatusCode::BAD_REQUEST, "INVALID_QUERY", rejection.to_string()))
+    } else if let Some(rejection) = err.find::<InvalidHeader>() {
+        Some((StatusCode::BAD_REQUEST, "INVALID_HEADER", rejection.to_string()))
+    } else if let Some(rejection) = err.find::<MissingHeader>() {
+        Some((StatusCode::BAD_REQUEST, "MISSING_HEADER", rejection.to_string()))
+    } else if let Some(rejection) = err.find::<UnsupportedMediaType>() {
+        Some((StatusCode::UNSUPPORTED_MEDIA_TYPE, "UNSUPPORTED_MEDIA_TYPE", rejection.to_string()))
+    } else {
+        err.find::<PayloadTooLarge>()
+            .map(|rejection| (StatusCode::PAYLOAD_TOO_LARGE, "PAYLOAD_TOO_LARGE", rejection.to_string()))
+    }
+}
+
+pub(crate) async fn handle_rejection(err: warp::Rejection) -> Result<impl warp::Reply, std::convert::Infallible> {
+    use warp::Reply;
+
+    let code;
+    let error_code;
+    let message;
+    let mut allow = None;
+    let mut retry_after = None;
+    let mut bearer_challenge = false;
+    let mut detail = None;
+
+    if let Some(error) = err.find::<ApiError>() {
+        code = error.status();
+        error_code = error.code();
+        message = error.to_string();
+        match error {
+            ApiError::MethodNotAllowed { allow: methods } => allow = Some(*methods),
+            ApiError::Unauthorized => bearer_challenge = true,
+            ApiError::Overloaded(_) => retry_after = Some(OVERLOAD_RETRY_AFTER_SECONDS),
+            ApiError::RateLimited(seconds) | ApiError::QuotaExceeded(seconds) => retry_after = Some(*seconds),
+            ApiError::NotReady => retry_after = Some(SYNC_RETRY_SECONDS),
+            ApiError::DatabaseBusy { query, source } => {
+                eprintln!("Database busy in {}: {}", query, source);
+                retry_after = Some(DB_BUSY_RETRY_AFTER_SECONDS);
+            }
+            ApiError::Database { query, source } => {
+                eprintln!("Database error in {}: {}", query, source);
+                detail = Some(format!("Database error in {}: {}", query, source));
+            }
+            _ => {}
+        }
+    } else if err.find::<warp::reject::LengthRequired>().is_some() {
+        code = StatusCode::LENGTH_REQUIRED;
+        error_code = "LENGTH_REQUIRED";
+        message = "Request body needs a Content-Length header".to_string();
+    } else if let Some(forbidden) = err.find::<warp::cors::CorsForbidden>() {
+        // A cross-origin request or preflight for a method or header the CORS policy doesn't
+        // allow: the client's doing, not the server's
+        code = StatusCode::FORBIDDEN;
+        error_code = "CORS_FORBIDDEN";
+        message = forbidden.to_string();
+    } else if err.is_not_found() {
+        code = StatusCode::NOT_FOUND;
+        error_code = "NOT_FOUND";
+        message = "Not Found".to_string();
+    } else if let Some((status, client_code, client_message)) = warp_client_error(&err) {
+        code = status;
+        error_code = client_code;
+        message = client_message;
+    } else {
+        eprintln!("unhandled rejection: {:?}", err);
+        detail = Some(format!("unhandled rejection: {:?}", err));
+        code = StatusCode::INTERNAL_SERVER_ERROR;
+        error_code = "INTERNAL_ERROR";
+        message = "Internal Server Error".to_string();
+    }
+
+    let body = ErrorBody {
+        error: message,
+        code: error_code,
+        status: code.as_u16(),
+        path: None,
+        timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        request_id: None,
+        detail,
+    };
+    let mut response = warp::reply::with_status(warp::reply::json(&body), code).into_response();
+    response.extensions_mut().insert(body);
+    if let Some(allow) = allow {
+        response.headers_mut().insert(
+            warp::http::header::ALLOW,
+            warp::http::HeaderValue::from_static(allow),
+        );
+    }
+    if bearer_challenge {
+        response.headers_mut().insert(
+            warp::http::header::WWW_AUTHENTICATE,
+            warp::http::HeaderValue::from_static("Bearer"),
+        );
+    }
+    if let Some(seconds) = retry_after {
+        response.headers_mut().insert(
+            warp::http::header::RETRY_AFTER,
+            warp::http::HeaderValue::from(seconds),
+        );
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use warp::Filter;
+    use warp::Reply;
+
+    use super::{handle_rejection, ErrorBody};
+
+    // Status and code of the answer to a rejection, and whether it would be reported
+    async fn answer(rejection: Option<warp::Rejection>) -> (u16, &'static str, bool) {
+        let response = handle_rejection(rejection.expect("the request is rejected")).await.unwrap().into_response();
+        let body = response.extensions().get::<ErrorBody>().unwrap().clone();
+        (response.status().as_u16(), body.code, body.detail.is_some())
+    }
+
+    #[derive(serde::Deserialize)]
+    struct Limit {
+        #[allow(dead_code)]
+        limit: u32,
+    }
+
+    #[tokio::test]
+    async fn malformed_requests_are_client_errors() {
+        let query = warp::query::<Limit>();
+        let rejection = warp::test::request().path("/?limit=abc").filter(&query).await.err();
+        assert_eq!(answer(rejection).await, (400, "INVALID_QUERY", false));
+
+        let get = warp::get();
+        let rejection = warp::test::request().method("DELETE").path("/").filter(&get).await.err();
+        assert_eq!(answer(rejection).await, (405, "METHOD_NOT_ALLOWED", false));
+
+        let header = warp::header::<u32>("x-count");
+        let rejection = warp::test::request().path("/").filter(&header).await.err();
+        assert_eq!(answer(rejection).await, (400, "MISSING_HEADER", false));
+        let rejection = warp::test::request().path("/").header("x-count", "many").filter(&header).await.err();
+        assert_eq!(answer(rejection).await, (400, "INVALID_HEADER", false));
+
+        let form = warp::body::form::<HashMap<String, String>>();
+        let rejection = warp::test::request()
+            .method("POST")
+            .path("/")
+            .header("content-type", "text/plain")
+            .body("a=1")
+            .filter(&form)
+            .await
+            .err();
+        assert_eq!(answer(rejection).await, (415, "UNSUPPORTED_MEDIA_TYPE", false));
+
+        let limited = warp::body::content_length_limit(4);
+        let rejection = warp::test::request().method("POST").path("/").body("too long").filter(&limited).await.err();
+        assert_eq!(answer(rejection).await, (413, "PAYLOAD_TOO_LARGE", false));
+    }
+
+    #[tokio::test]
+    async fn unknown_rejections_are_internal_errors() {
+        #[derive(Debug)]
+        struct Unexpected;
+        impl warp::reject::Reject for Unexpected {}
+
+        let failing = warp::any().and_then(|| async { Err::<String, _>(warp::reject::custom(Unexpected)) });
+        let rejection = warp::test::request().path("/").filter(&failing).await.err();
+        assert_eq!(answer(rejection).await, (500, "INTERNAL_ERROR", true));
+    }
+}
@@ -0,0 +1,190 @@
+use rusqlite::Result as SqlResult;
+
+use crate::config::{DataSource, EXPORT_CHUNK_BYTES};
+use crate::db::{image_url, news_text, open_database, parse_categories, ImageInfo, NewsRecord};
+use crate::lang::detect_language;
+
+// Output of GET /export/all
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum ExportFormat {
+    // One JSON array of records shaped like the other endpoints' records
+    Json,
+    // One row per record, tags joined with '|'
+    Csv,
+}
+
+const CSV_HEADER: &str =
+    "id,news,date,serpapi_id,image_id,serpapi_data_date,keywords,tag,image_file_name,image_url,thread_id,lang,sentiment\r\n";
+
+impl ExportFormat {
+    pub(crate) fn from_param(format: Option<&str>) -> Option<ExportFormat> {
+        match format {
+            None | Some("json") => Some(ExportFormat::Json),
+            Some("csv") => Some(ExportFormat::Csv),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Json => "application/json",
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+        }
+    }
+
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Csv => "csv",
+        }
+    }
+}
+
+// Quote a CSV field when it holds a separator, quote or line break (RFC 4180). Text a spreadsheet
+// would take for a formula (starting with =, +, -, @, a tab or a carriage return) gets a leading
+// ', so opening an export never runs one; numbers such as negative scores stay as they are.
+pub(crate) fn csv_field(out: &mut Vec<u8>, value: &str) {
+    let formula = value.starts_with(['=', '+', '-', '@', '\t', '\r']) && value.parse::<f64>().is_err();
+    let value = if formula { format!("'{}", value) } else { value.to_string() };
+    if value.contains([',', '"', '\n', '\r']) {
+        out.push(b'"');
+        out.extend_from_slice(value.replace('"', "\"\"").as_bytes());
+        out.push(b'"');
+    } else {
+        out.extend_from_slice(value.as_bytes());
+    }
+}
+
+fn csv_row(out: &mut Vec<u8>, record: &NewsRecord) {
+    let image = record.image.as_ref();
+    let fields = [
+        Some(record.id.to_string()),
+        record.news.clone(),
+        record.date.clone(),
+        record.serpapi_id.map(|id| id.to_string()),
+        record.image_id.map(|id| id.to_string()),
+        record.serpapi_data_date.clone(),
+        record.keywords.clone(),
+        Some(record.tag.join("|")),
+        image.and_then(|image| image.file_name.clone()),
+        image.and_then(|image| image.url.clone()),
+        record.thread_id.map(|id| id.to_string()),
+        record.lang.clone(),
+        record.sentiment.map(|score| score.to_string()),
+    ];
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            out.push(b',');
+        }
+        csv_field(out, field.as_deref().unwrap_or_default());
+    }
+    out.extend_from_slice(b"\r\n");
+}
+
+// Write every record of the source, oldest first, to `send` in chunks of about
+// EXPORT_CHUNK_BYTES, so the whole dataset is never held in memory. Stops early once `send`
+// returns false (the client went away).
+pub(crate) fn export_records(
+    source: &DataSource,
+    format: ExportFormat,
+    mut send: impl FnMut(Vec<u8>) -> bool,
+) -> SqlResult<()> {
+    let conn = open_database(source)?;
+    // One joined pass instead of the per-record keyword and image lookups of the list endpoints
+    let mut stmt = conn.prepare(
+        "SELECT main_news_data.id, main_news_data.news, main_news_data.date, \
+         main_news_data.serpapi_id, main_news_data.image_id, \
+         serpapi_data.date, serpapi_data.query, serpapi_data.categories, image_data.file_name \
+         FROM main_news_data \
+         LEFT JOIN serpapi_data ON main_news_data.serpapi_id = serpapi_data.id \
+         LEFT JOIN image_data ON main_news_data.image_id = image_data.id \
+         ORDER BY main_news_data.date, main_news_data.id"
+    )?;
+    let mut rows = stmt.query([])?;
+
+    let mut out = Vec::with_capacity(EXPORT_CHUNK_BYTES);
+    match format {
+        ExportFormat::Json => out.push(b'['),
+        ExportFormat::Csv => out.extend_from_slice(CSV_HEADER.as_bytes()),
+    }
+    let mut first = true;
+    while let Some(row) = rows.next()? {
+        let image_id: Option<i64> = row.get(4)?;
+        let categories: Option<String> = row.get(7)?;
+        let file_name: Option<String> = row.get(8)?;
+        let id: i64 = row.get(0)?;
+        let news = row.get::<_, Option<String>>(1)?.map(|news| news_text(source, news));
+        let lang = news.as_deref().and_then(detect_language).map(str::to_string);
+        let record = NewsRecord {
+            id,
+            news,
+            date: row.get(2)?,
+            serpapi_id: row.get(3)?,
+            image_id,
+            serpapi_data_date: row.get(5)?,
+            keywords: row.get(6)?,
+            image: image_id.map(|_| ImageInfo {
+                url: file_name.as_deref().map(|fname| image_url(source, fname)),
+                blurhash: file_name.as_deref().and_then(|fname| source.placeholders.blurhash(fname)),
+                file_name,
+            }),
+            tag: categories.map(|cat_str| parse_categories(&cat_str, &source.tag_map)).unwrap_or_default(),
+            lang,
+            thread_id: source.threads.thread_of(&conn, id)?,
+            #[cfg(feature = "sentiment")]
+            sentiment: crate::sentiment::record_sentiment(&conn, id)?,
+            #[cfg(not(feature = "sentiment"))]
+            sentiment: None,
+        };
+
+        match format {
+            ExportFormat::Json => {
+                if !first {
+                    out.push(b',');
+                }
+                out.extend_from_slice(&serde_json::to_vec(&record).unwrap_or_default());
+            }
+            ExportFormat::Csv => csv_row(&mut out, &record),
+        }
+        first = false;
+
+        if out.len() >= EXPORT_CHUNK_BYTES && !send(std::mem::replace(&mut out, Vec::with_capacity(EXPORT_CHUNK_BYTES))) {
+            return Ok(());
+        }
+    }
+    if let ExportFormat::Json = format {
+        out.push(b']');
+    }
+    send(out);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::csv_field;
+
+    fn field(value: &str) -> String {
+        let mut out = Vec::new();
+        csv_field(&mut out, value);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn quotes_separators_and_quotes() {
+        assert_eq!(field("plain"), "plain");
+        assert_eq!(field("a,b"), "\"a,b\"");
+        assert_eq!(field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn neutralises_formulas() {
+        assert_eq!(field("=HYPERLINK(\"http://evil\")"), "\"'=HYPERLINK(\"\"http://evil\"\")\"");
+        assert_eq!(field("+1 day"), "'+1 day");
+        assert_eq!(field("-cmd"), "'-cmd");
+        assert_eq!(field("@SUM(A1)"), "'@SUM(A1)");
+        assert_eq!(field("\t=1"), "'\t=1");
+        assert_eq!(field("-0.25"), "-0.25");
+        assert_eq!(field("a = b"), "a = b");
+    }
+}
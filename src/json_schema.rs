@@ -0,0 +1,154 @@
+use serde_json::{json, Value};
+
+use crate::config::DataSource;
+
+// Response types described at /schema/<name>.json, each document carrying the definitions it
+// refers to. Written by hand after the serialized types: a field added to LatestResponse,
+// NewsRecord, ImageInfo, DayLinks, DateResponse or ErrorBody belongs here too.
+
+// JSON Schema (draft 2020-12) of a response type by name, its $id on the source's API origin;
+// None for other names
+pub(crate) fn json_schema(source: &DataSource, name: &str) -> Option<Value> {
+    let (title, root, defs): (&str, Value, Vec<&str>) = match name {
+        "latest" => ("LatestResponse", latest_response(), vec!["NewsRecord", "ImageInfo", "DayLinks"]),
+        "news_record" => ("NewsRecord", news_record(), vec!["NewsRecord", "ImageInfo"]),
+        "date" => ("DateResponse", date_response(), vec![]),
+        "error" => ("ErrorBody", error_body(), vec![]),
+        _ => return None,
+    };
+    let mut schema = json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$id": format!("{}{}/schema/{}.json", source.base_urls.api_origin(), source.url_prefix(), name),
+        "title": title,
+    });
+    if let (Value::Object(schema), Value::Object(root)) = (&mut schema, root) {
+        schema.extend(root);
+    }
+    if !defs.is_empty() {
+        let defs: serde_json::Map<String, Value> = defs.into_iter().map(|def| (def.to_string(), definition(def))).collect();
+        schema["$defs"] = Value::Object(defs);
+    }
+    Some(schema)
+}
+
+fn definition(name: &str) -> Value {
+    match name {
+        "NewsRecord" => news_record(),
+        "ImageInfo" => image_info(),
+        "DayLinks" => day_links(),
+        _ => Value::Null,
+    }
+}
+
+fn nullable(kind: &str) -> Value {
+    json!({ "type": [kind, "null"] })
+}
+
+// The day's records as /latest and /date/<day> answer them (?api_version=1)
+fn latest_response() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "date": { "type": ["string", "null"], "description": "The day (yyyy-mm-dd), null without records" },
+            "total_records": { "type": "integer", "minimum": 0, "description": "The day's matches after filters" },
+            "offset": { "type": "integer", "minimum": 0, "description": "Matches before this page" },
+            "records": { "type": "array", "items": { "$ref": "#/$defs/NewsRecord" } },
+            "next_cursor": { "type": "string", "description": "?cursor= of the next page, absent on the last" },
+            "links": { "$ref": "#/$defs/DayLinks" },
+        },
+        "required": ["date", "total_records", "offset", "records", "links"],
+    })
+}
+
+// A record of a list response; ?dedupe=true adds "duplicates", /search "highlights"
+fn news_record() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "id": { "type": "integer" },
+            "news": nullable("string"),
+            "date": { "type": ["string", "null"], "description": "When the story was written (yyyy-mm-dd hh:mm:ss)" },
+            "serpapi_id": nullable("integer"),
+            "image_id": nullable("integer"),
+            "serpapi_data_date": { "type": ["string", "null"], "description": "When the trend was fetched" },
+            "keywords": nullable("string"),
+            "image": { "anyOf": [{ "$ref": "#/$defs/ImageInfo" }, { "type": "null" }] },
+            "tag": { "type": "array", "items": { "type": "string" } },
+            "lang": { "type": ["string", "null"], "description": "ISO 639-1 code of the text's language, null when unsure" },
+            "thread_id": {
+                "type": "integer",
+                "description": "The story's thread at /threads/<id>, absent unless its keywords recur on consecutive days",
+            },
+            "sentiment": {
+                "type": "number",
+                "description": "Polarity of the text from -1 to 1, absent until scored or when it isn't English",
+            },
+            "duplicates": {
+                "type": "array",
+                "items": { "$ref": "#/$defs/NewsRecord" },
+                "description": "With ?dedupe=true, the records telling the same story",
+            },
+            "highlights": {
+                "type": "object",
+                "properties": { "news": { "type": "string" }, "keywords": { "type": "string" } },
+                "description": "On /search, the matched terms within markers",
+            },
+        },
+        "required": ["id", "news", "date", "serpapi_id", "image_id", "serpapi_data_date", "keywords", "image", "tag", "lang"],
+    })
+}
+
+fn image_info() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "file_name": nullable("string"),
+            "url": nullable("string"),
+            "blurhash": { "type": "string", "description": "BlurHash placeholder, once computed after a sync" },
+        },
+        "required": ["file_name", "url"],
+    })
+}
+
+fn day_links() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "self": { "type": "string" },
+            "prev_date": { "type": "string", "description": "The nearest earlier day with records, absent on the first" },
+            "next_date": { "type": "string", "description": "The nearest later day with records, absent on the last" },
+            "dates_index": { "type": "string" },
+        },
+        "required": ["self", "dates_index"],
+    })
+}
+
+// An entry of /dates
+fn date_response() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "date": { "type": "string", "pattern": "^[0-9]{8}$" },
+            "date_with_url": { "type": "string", "description": "The day's page on the site" },
+            "record_count": { "type": "integer", "minimum": 0 },
+            "has_images": { "type": "boolean" },
+        },
+        "required": ["date", "date_with_url", "record_count", "has_images"],
+    })
+}
+
+// The body of every error response
+fn error_body() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "error": { "type": "string", "description": "What went wrong, for people" },
+            "code": { "type": "string", "description": "What went wrong, for programs (INVALID_QUERY_PARAMETER, ...)" },
+            "status": { "type": "integer", "minimum": 400, "maximum": 599 },
+            "path": { "type": ["string", "null"], "description": "The request's path" },
+            "timestamp": { "type": "string", "format": "date-time" },
+            "request_id": { "type": ["string", "null"], "description": "Also sent as X-Request-Id" },
+        },
+        "required": ["error", "code", "status", "path", "timestamp", "request_id"],
+    })
+}
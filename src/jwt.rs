@@ -0,0 +1,378 @@
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use serde_json::{Map, Value};
+
+use crate::config::{JWKS_REFRESH_MINUTES, JWKS_RETRY_SECONDS, JWKS_TIMEOUT_SECONDS, JWT_LEEWAY_SECONDS};
+use crate::crypto::{constant_time_eq, hmac_sha256, RsaPublicKey};
+use crate::error::ApiError;
+
+// JWTs accepted by the /admin/... routes besides the admin tokens, issued by an identity provider:
+// HS256 with a shared secret, RS256 with a PEM public key or the keys published at a JWKS URL.
+// Any of them enables JWTs; issuer, audience and scope narrow down the tokens accepted.
+#[derive(Clone, Default)]
+pub struct JwtSettings {
+    pub secret: Option<String>,
+    pub public_key: Option<PathBuf>,
+    pub jwks_url: Option<String>,
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+    // Required in the token's scope (space-separated "scope", or "scp" or "roles" lists)
+    pub admin_scope: Option<String>,
+}
+
+// Debug without the secret, as the configuration is Debug
+impl std::fmt::Debug for JwtSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JwtSettings")
+            .field("secret", &self.secret.as_ref().map(|_| "<redacted>"))
+            .field("public_key", &self.public_key)
+            .field("jwks_url", &self.jwks_url)
+            .field("issuer", &self.issuer)
+            .field("audience", &self.audience)
+            .field("admin_scope", &self.admin_scope)
+            .finish()
+    }
+}
+
+pub(crate) type Claims = Map<String, Value>;
+
+pub(crate) struct JwtVerifier {
+    secret: Option<Vec<u8>>,
+    public_key: Option<RsaPublicKey>,
+    jwks: Option<Jwks>,
+    issuer: Option<String>,
+    audience: Option<String>,
+    admin_scope: Option<String>,
+}
+
+// The signing keys of a JWKS URL, fetched on first use and again every JWKS_REFRESH_MINUTES, or
+// sooner for a token signed with a key not seen yet (at most every JWKS_RETRY_SECONDS)
+struct Jwks {
+    url: String,
+    state: RwLock<JwksState>,
+}
+
+#[derive(Default)]
+struct JwksState {
+    // Key id ("kid") and key
+    keys: Vec<(Option<String>, RsaPublicKey)>,
+    fetched_at: Option<Instant>,
+    attempted_at: Option<Instant>,
+}
+
+impl Jwks {
+    // The keys a token's key id designates (all of them without one). Blocks while fetching.
+    fn keys(&self, kid: Option<&str>) -> Vec<RsaPublicKey> {
+        let matching = |state: &JwksState| -> Vec<RsaPublicKey> {
+            state
+                .keys
+                .iter()
+                .filter(|(id, _)| kid.is_none() || id.as_deref() == kid)
+                .map(|(_, key)| key.clone())
+                .collect()
+        };
+        {
+            let state = self.state.read().unwrap_or_else(|e| e.into_inner());
+            let keys = matching(&state);
+            let stale = state.fetched_at.is_none_or(|at| at.elapsed() >= Duration::from_secs(JWKS_REFRESH_MINUTES * 60));
+            let may_retry = state.attempted_at.is_none_or(|at| at.elapsed() >= Duration::from_secs(JWKS_RETRY_SECONDS));
+            if !((stale || keys.is_empty()) && may_retry) {
+                return keys;
+            }
+        }
+        let mut state = self.state.write().unwrap_or_else(|e| e.into_inner());
+        state.attempted_at = Some(Instant::now());
+        // Keys fetched before stay in use while the URL can't be read
+        match fetch_jwks(&self.url) {
+            Ok(keys) => {
+                state.keys = keys;
+                state.fetched_at = Some(Instant::now());
+            }
+            Err(e) => eprintln!("Failed to fetch signing keys from {}: {}", self.url, e),
+        }
+        matching(&state)
+    }
+}
+
+fn base64url(raw: &str) -> Option<Vec<u8>> {
+    use base64::Engine;
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(raw.trim_end_matches('=')).ok()
+}
+
+// The RS256 signing keys of a JWK Set; keys of other types or uses are skipped
+fn fetch_jwks(url: &str) -> Result<Vec<(Option<String>, RsaPublicKey)>, String> {
+    let output = std::process::Command::new("curl")
+        .args(["-sS", "--fail", "--max-time", &JWKS_TIMEOUT_SECONDS.to_string()])
+        .arg(url)
+        .output()
+        .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(format!("curl {}: {}", output.status, String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    let set: Value = serde_json::from_slice(&output.stdout).map_err(|e| e.to_string())?;
+    let keys = set["keys"].as_array().ok_or("no keys in the JWK Set")?;
+    Ok(keys
+        .iter()
+        .filter(|key| key["kty"] == "RSA")
+        .filter(|key| key["alg"].is_null() || key["alg"] == "RS256")
+        .filter(|key| key["use"].is_null() || key["use"] == "sig")
+        .filter_map(|key| {
+            let modulus = base64url(key["n"].as_str()?)?;
+            let exponent = base64url(key["e"].as_str()?)?;
+            Some((key["kid"].as_str().map(str::to_string), RsaPublicKey::new(&modulus, &exponent)?))
+        })
+        .collect())
+}
+
+// Whether a claim names `wanted`: the claim itself, or one of its values for a list
+fn claim_contains(claim: Option<&Value>, wanted: &str, space_separated: bool) -> bool {
+    match claim {
+        Some(Value::String(value)) if space_separated => value.split_whitespace().any(|item| item == wanted),
+        Some(Value::String(value)) => value == wanted,
+        Some(Value::Array(items)) => items.iter().any(|item| item.as_str() == Some(wanted)),
+        _ => false,
+    }
+}
+
+impl JwtVerifier {
+    // None unless a secret, public key or JWKS URL is set. A public key that can't be read is
+    // reported and left out.
+    pub(crate) fn new(settings: &JwtSettings) -> Option<JwtVerifier> {
+        if settings.secret.is_none() && settings.public_key.is_none() && settings.jwks_url.is_none() {
+            return None;
+        }
+        let public_key = settings.public_key.as_ref().and_then(|path| {
+            let key = std::fs::read_to_string(path)
+                .map_err(|e| e.to_string())
+                .and_then(|pem| RsaPublicKey::from_pem(&pem).ok_or_else(|| "not an RSA public key".to_string()));
+            key.map_err(|e| eprintln!("Ignoring JWT public key {}: {}", path.display(), e)).ok()
+        });
+        if settings.issuer.is_none() && settings.audience.is_none() && settings.admin_scope.is_none() {
+            eprintln!(
+                "Any unexpired JWT signed with the configured keys is accepted for the admin endpoints; \
+                 set an issuer, audience or admin scope to narrow them down"
+            );
+        }
+        Some(JwtVerifier {
+            secret: settings.secret.as_ref().map(|secret| secret.as_bytes().to_vec()),
+            public_key,
+            jwks: settings.jwks_url.clone().map(|url| Jwks { url, state: RwLock::default() }),
+            issuer: settings.issuer.clone(),
+            audience: settings.audience.clone(),
+            admin_scope: settings.admin_scope.clone(),
+        })
+    }
+
+    // The claims of a token signed with a configured key and valid now, from the configured issuer
+    // for the configured audience; Unauthorized otherwise, InsufficientScope without the admin scope.
+    // Blocks when the JWKS has to be fetched.
+    pub(crate) fn verify(&self, token: &str) -> Result<Claims, ApiError> {
+        let mut parts = token.split('.');
+        let (Some(header), Some(payload), Some(signature), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+            return Err(ApiError::Unauthorized);
+        };
+        let decode_json = |raw: &str| -> Option<Claims> { serde_json::from_slice(&base64url(raw)?).ok() };
+        let header = decode_json(header).ok_or(ApiError::Unauthorized)?;
+        let claims = decode_json(payload).ok_or(ApiError::Unauthorized)?;
+        let signature = base64url(signature).ok_or(ApiError::Unauthorized)?;
+        // The signature covers "<header>.<payload>"
+        let signed = &token[..token.rfind('.').unwrap_or(0)];
+
+        // The algorithm comes from the token, so only those with a configured key are honored
+        let valid = match header.get("alg").and_then(Value::as_str) {
+            Some("HS256") => self
+                .secret
+                .as_ref()
+                .is_some_and(|secret| constant_time_eq(&hmac_sha256(secret, signed.as_bytes()), &signature)),
+            Some("RS256") => {
+                let kid = header.get("kid").and_then(Value::as_str);
+                let jwks_keys = self.jwks.as_ref().map(|jwks| jwks.keys(kid)).unwrap_or_default();
+                self.public_key.iter().chain(&jwks_keys).any(|key| key.verify_sha256(signed.as_bytes(), &signature))
+            }
+            _ => false,
+        };
+        if !valid {
+            return Err(ApiError::Unauthorized);
+        }
+
+        let now = chrono::Utc::now().timestamp();
+        let time = |name: &str| claims.get(name).and_then(Value::as_f64).map(|seconds| seconds as i64);
+        let expired = time("exp").is_none_or(|exp| now >= exp + JWT_LEEWAY_SECONDS);
+        let early = time("nbf").is_some_and(|nbf| now + JWT_LEEWAY_SECONDS < nbf);
+        let wrong_issuer = self.issuer.as_deref().is_some_and(|issuer| !claim_contains(claims.get("iss"), issuer, false));
+        let wrong_audience =
+            self.audience.as_deref().is_some_and(|audience| !claim_contains(claims.get("aud"), audience, false));
+        if expired || early || wrong_issuer || wrong_audience {
+            return Err(ApiError::Unauthorized);
+        }
+        if let Some(scope) = &self.admin_scope {
+            let granted = claim_contains(claims.get("scope"), scope, true)
+                || claim_contains(claims.get("scp"), scope, true)
+                || claim_contains(claims.get("roles"), scope, false);
+            if !granted {
+                return Err(ApiError::InsufficientScope);
+            }
+        }
+        Ok(claims)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::{base64url, JwtSettings, JwtVerifier};
+    use crate::config::JWT_LEEWAY_SECONDS;
+    use crate::crypto::tests::TEST_PUBLIC_KEY;
+    use crate::crypto::{hmac_sha256, RsaPublicKey};
+    use crate::error::ApiError;
+
+    const SECRET: &str = "a shared secret for the tests";
+    // RS256 with the test key of crate::crypto: sub "ops", iss "https://id.example", aud
+    // "trend-story", scope "read admin", exp in 2100
+    const RS256_TOKEN: &str = "\
+        eyJhbGciOiJSUzI1NiIsInR5cCI6IkpXVCJ9.eyJzdWIiOiJvcHMiLCJleHAiOjQxMDI0NDQ4MDAsImlzcyI6Imh0dHBzOi8v\
+        aWQuZXhhbXBsZSIsImF1ZCI6InRyZW5kLXN0b3J5Iiwic2NvcGUiOiJyZWFkIGFkbWluIn0.iwdtty-7rje8XH9JK8v23ZAo7Q5DO\
+        9MJslF7m0acTApq1x8-tqcPqQ6TvOoiWPgw8kgedqbVgkH_W24EGXLlge4dfuWbuEEDfwOyTw78gMMxz1DYAdDRNwoMLNYqPqqQ4yY\
+        nga9Ve4QmOBREJmjrqi6GcYtHWUjC_BHQw0SljJBCx5eyxWE5loV36DyWHkMo1cMjec4lPvo2xMgWdzZ4wjkH8oE1jrShcMsLrHSv\
+        KdjPxtERcV62vK2EBoRmHn5W8eqBaj9L1gFIyFpAqcCMptXqVn5TaTnVwnVj6nJSRTeZodE08CPQCFzpS5f1tjV4EF_hlg432GWeb\
+        ixhzzpFfA";
+
+    fn encode(raw: &[u8]) -> String {
+        use base64::Engine;
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(raw)
+    }
+
+    // A token with the given header and claims, signed with HMAC-SHA256 under `key`
+    fn hs256_token(key: &[u8], header: Value, claims: Value) -> String {
+        let signed = format!("{}.{}", encode(header.to_string().as_bytes()), encode(claims.to_string().as_bytes()));
+        format!("{}.{}", signed, encode(&hmac_sha256(key, signed.as_bytes())))
+    }
+
+    fn hs256(claims: Value) -> String {
+        hs256_token(SECRET.as_bytes(), json!({"alg": "HS256", "typ": "JWT"}), claims)
+    }
+
+    fn now() -> i64 {
+        chrono::Utc::now().timestamp()
+    }
+
+    fn hs256_verifier() -> JwtVerifier {
+        JwtVerifier {
+            secret: Some(SECRET.as_bytes().to_vec()),
+            public_key: None,
+            jwks: None,
+            issuer: None,
+            audience: None,
+            admin_scope: None,
+        }
+    }
+
+    fn rs256_verifier() -> JwtVerifier {
+        JwtVerifier {
+            secret: None,
+            public_key: RsaPublicKey::from_pem(TEST_PUBLIC_KEY),
+            jwks: None,
+            issuer: Some("https://id.example".to_string()),
+            audience: Some("trend-story".to_string()),
+            admin_scope: Some("admin".to_string()),
+        }
+    }
+
+    #[test]
+    fn accepts_valid_tokens() {
+        assert!(hs256_verifier().verify(&hs256(json!({"exp": now() + 600}))).is_ok());
+        let claims = rs256_verifier().verify(RS256_TOKEN).expect("RS256 token accepted");
+        assert_eq!(claims["sub"], "ops");
+    }
+
+    #[test]
+    fn rejects_a_tampered_rs256_token() {
+        let (signed, signature) = RS256_TOKEN.rsplit_once('.').unwrap();
+        let (header, _) = signed.split_once('.').unwrap();
+        let claims = encode(br#"{"sub":"root","exp":4102444800,"iss":"https://id.example","aud":"trend-story","scope":"admin"}"#);
+        let tampered = format!("{}.{}.{}", header, claims, signature);
+        assert!(matches!(rs256_verifier().verify(&tampered), Err(ApiError::Unauthorized)));
+    }
+
+    // HS256 signed with the RS256 public key as the secret, as if the server would take the key
+    // it has for an HMAC secret
+    #[test]
+    fn rejects_hs256_against_an_rs256_key() {
+        let claims = json!({"exp": now() + 600, "iss": "https://id.example", "aud": "trend-story", "scope": "admin"});
+        let token = hs256_token(TEST_PUBLIC_KEY.as_bytes(), json!({"alg": "HS256"}), claims);
+        assert!(matches!(rs256_verifier().verify(&token), Err(ApiError::Unauthorized)));
+    }
+
+    #[test]
+    fn rejects_alg_none() {
+        let claims = json!({"exp": now() + 600, "iss": "https://id.example", "aud": "trend-story", "scope": "admin"});
+        for alg in ["none", "None", "NONE"] {
+            let header = encode(json!({"alg": alg}).to_string().as_bytes());
+            let token = format!("{}.{}.", header, encode(claims.to_string().as_bytes()));
+            assert!(matches!(rs256_verifier().verify(&token), Err(ApiError::Unauthorized)));
+            assert!(matches!(hs256_verifier().verify(&token), Err(ApiError::Unauthorized)));
+        }
+    }
+
+    #[test]
+    fn checks_expiry_with_leeway() {
+        let verifier = hs256_verifier();
+        let leeway = JWT_LEEWAY_SECONDS;
+        assert!(verifier.verify(&hs256(json!({"exp": now() - leeway / 2}))).is_ok());
+        assert!(matches!(verifier.verify(&hs256(json!({"exp": now() - leeway - 10}))), Err(ApiError::Unauthorized)));
+        // A token without exp never expires, so it isn't taken
+        assert!(matches!(verifier.verify(&hs256(json!({"sub": "ops"}))), Err(ApiError::Unauthorized)));
+    }
+
+    #[test]
+    fn checks_not_before_with_leeway() {
+        let verifier = hs256_verifier();
+        let leeway = JWT_LEEWAY_SECONDS;
+        assert!(verifier.verify(&hs256(json!({"exp": now() + 600, "nbf": now() + leeway / 2}))).is_ok());
+        let early = hs256(json!({"exp": now() + 600, "nbf": now() + leeway + 10}));
+        assert!(matches!(verifier.verify(&early), Err(ApiError::Unauthorized)));
+    }
+
+    #[test]
+    fn checks_issuer_and_audience() {
+        let verifier = JwtVerifier {
+            issuer: Some("https://id.example".to_string()),
+            audience: Some("trend-story".to_string()),
+            ..hs256_verifier()
+        };
+        let exp = now() + 600;
+        let valid = hs256(json!({"exp": exp, "iss": "https://id.example", "aud": ["other", "trend-story"]}));
+        assert!(verifier.verify(&valid).is_ok());
+        let wrong_issuer = hs256(json!({"exp": exp, "iss": "https://evil.example", "aud": "trend-story"}));
+        assert!(matches!(verifier.verify(&wrong_issuer), Err(ApiError::Unauthorized)));
+        let wrong_audience = hs256(json!({"exp": exp, "iss": "https://id.example", "aud": "other"}));
+        assert!(matches!(verifier.verify(&wrong_audience), Err(ApiError::Unauthorized)));
+        let no_audience = hs256(json!({"exp": exp, "iss": "https://id.example"}));
+        assert!(matches!(verifier.verify(&no_audience), Err(ApiError::Unauthorized)));
+    }
+
+    #[test]
+    fn requires_the_admin_scope() {
+        let verifier = JwtVerifier { admin_scope: Some("admin".to_string()), ..hs256_verifier() };
+        let exp = now() + 600;
+        assert!(verifier.verify(&hs256(json!({"exp": exp, "scope": "read admin"}))).is_ok());
+        assert!(verifier.verify(&hs256(json!({"exp": exp, "scp": ["admin"]}))).is_ok());
+        assert!(verifier.verify(&hs256(json!({"exp": exp, "roles": ["admin"]}))).is_ok());
+        let missing = hs256(json!({"exp": exp, "scope": "read administrator"}));
+        assert!(matches!(verifier.verify(&missing), Err(ApiError::InsufficientScope)));
+        assert!(matches!(verifier.verify(&hs256(json!({"exp": exp}))), Err(ApiError::InsufficientScope)));
+    }
+
+    #[test]
+    fn debug_leaves_out_the_secret() {
+        let settings = JwtSettings { secret: Some(SECRET.to_string()), ..JwtSettings::default() };
+        assert!(!format!("{:?}", settings).contains(SECRET));
+    }
+
+    #[test]
+    fn decodes_padded_base64url() {
+        assert_eq!(base64url("YWI="), Some(b"ab".to_vec()));
+    }
+}
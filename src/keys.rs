@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use chrono::Timelike;
+use rusqlite::{params, Connection, Result as SqlResult};
+use serde::Serialize;
+use warp::Filter;
+
+use crate::config::{Config, DB_BUSY_TIMEOUT_MS, USAGE_FLUSH_SECONDS, USAGE_HISTORY_DAYS};
+use crate::crypto::constant_time_eq;
+use crate::error::ApiError;
+use crate::routes::strip_source_prefix;
+
+// API keys for partners, sent as "X-API-Key: <key>", each with its own tier: a limit of requests
+// per minute and a quota of requests per UTC day. The keys file lists one per line as
+// "<id> <key> <per minute> <per day>", "-" for no limit, and is read again whenever it changes.
+// Requests are counted per key and day in memory and added to the usage file every
+// USAGE_FLUSH_SECONDS, so a restart forgets at most that much.
+#[derive(Clone)]
+pub(crate) struct ApiKeys {
+    registry: Option<Arc<KeyRegistry>>,
+}
+
+struct KeyRegistry {
+    path: PathBuf,
+    loaded: RwLock<Option<LoadedKeys>>,
+    // Requests without a key are refused rather than served without limits
+    required: bool,
+    usage_path: PathBuf,
+    usage: Mutex<Usage>,
+    source_names: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+struct ApiKey {
+    id: String,
+    key: String,
+    per_minute: Option<u64>,
+    per_day: Option<u64>,
+}
+
+// The keys file as last read, with its modification time then (None: missing)
+struct LoadedKeys {
+    modified: Option<SystemTime>,
+    keys: Arc<Vec<ApiKey>>,
+}
+
+#[derive(Default)]
+struct Usage {
+    // The UTC day (yyyymmdd) `today` counts
+    day: String,
+    // By key id: the minute (since the epoch) counted and its requests
+    minutes: HashMap<String, (i64, u64)>,
+    // By key id: requests served today, written or not
+    today: HashMap<String, u64>,
+    // By key id and day: requests served and refused not written yet
+    pending: HashMap<(String, String), (u64, u64)>,
+}
+
+const USAGE_SCHEMA: &str = "\
+    CREATE TABLE IF NOT EXISTS api_key_usage (\
+        key_id TEXT NOT NULL, day TEXT NOT NULL, requests INTEGER NOT NULL, rejected INTEGER NOT NULL, \
+        PRIMARY KEY (key_id, day));";
+
+fn today() -> String {
+    chrono::Utc::now().format("%Y%m%d").to_string()
+}
+
+// "-" for no limit
+fn parse_limit(raw: &str) -> Option<Option<u64>> {
+    if raw == "-" {
+        return Some(None);
+    }
+    raw.parse().ok().map(Some)
+}
+
+fn parse_keys(text: &str, path: &std::path::Path) -> Vec<ApiKey> {
+    let mut keys: Vec<ApiKey> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let key = match fields.as_slice() {
+            [id, key, per_minute, per_day] => parse_limit(per_minute).zip(parse_limit(per_day)).map(|(per_minute, per_day)| {
+                ApiKey { id: id.to_string(), key: key.to_string(), per_minute, per_day }
+            }),
+            _ => None,
+        };
+        match key {
+            Some(key) if !keys.iter().any(|known| known.id == key.id) => keys.push(key),
+            Some(key) => eprintln!("Ignoring API key '{}' in {}: duplicate id", key.id, path.display()),
+            None => eprintln!(
+                "Ignoring line {} of {}: expected <id> <key> <per minute> <per day>",
+                number + 1,
+                path.display()
+            ),
+        }
+    }
+    keys
+}
+
+impl KeyRegistry {
+    fn keys(&self) -> Arc<Vec<ApiKey>> {
+        let modified = std::fs::metadata(&self.path).and_then(|metadata| metadata.modified()).ok();
+        if let Some(loaded) = self.loaded.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            if loaded.modified == modified {
+                return loaded.keys.clone();
+            }
+        }
+        let keys = match std::fs::read_to_string(&self.path) {
+            Ok(text) => Arc::new(parse_keys(&text, &self.path)),
+            Err(e) => {
+                eprintln!("Cannot read API keys from {}: {}", self.path.display(), e);
+                Arc::default()
+            }
+        };
+        *self.loaded.write().unwrap_or_else(|e| e.into_inner()) = Some(LoadedKeys { modified, keys: keys.clone() });
+        keys
+    }
+
+    fn open_usage_database(&self) -> SqlResult<Connection> {
+        let conn = Connection::open(&self.usage_path)?;
+        conn.busy_timeout(Duration::from_millis(DB_BUSY_TIMEOUT_MS))?;
+        conn.execute_batch(USAGE_SCHEMA)?;
+        Ok(conn)
+    }
+
+    // Count a request against its key, or refuse it when the key's limit or quota is used up
+    fn admit(&self, path: &str, supplied: Option<&str>) -> Result<(), ApiError> {
+        let route = strip_source_prefix(path, &self.source_names);
+        let route = route.trim_start_matches('/').split('/').next().unwrap_or_default();
+        // Admin requests carry their own credentials
+        if matches!(route, "admin" | "metrics") {
+            return Ok(());
+        }
+        let Some(supplied) = supplied else {
+            return if self.required { Err(ApiError::ApiKeyRequired) } else { Ok(()) };
+        };
+        let keys = self.keys();
+        // Every key is compared, so timing doesn't reveal which one matched
+        let key = keys.iter().fold(None, |found, key| {
+            let matches = constant_time_eq(supplied.as_bytes(), key.key.as_bytes());
+            if matches { Some(key) } else { found }
+        });
+        let key = key.ok_or(ApiError::InvalidApiKey)?;
+
+        let now = chrono::Utc::now();
+        let day = now.format("%Y%m%d").to_string();
+        let minute = now.timestamp().div_euclid(60);
+        let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        if usage.day != day {
+            usage.day = day.clone();
+            usage.today.clear();
+        }
+        let in_minute = match usage.minutes.get(&key.id) {
+            Some((counted, requests)) if *counted == minute => *requests,
+            _ => 0,
+        };
+        let served_today = usage.today.get(&key.id).copied().unwrap_or_default();
+        let refusal = if key.per_minute.is_some_and(|limit| in_minute >= limit) {
+            Some(ApiError::RateLimited(60 - u64::from(now.second())))
+        } else if key.per_day.is_some_and(|quota| served_today >= quota) {
+            let seconds_into_day = u64::from(now.num_seconds_from_midnight());
+            Some(ApiError::QuotaExceeded(24 * 60 * 60 - seconds_into_day))
+        } else {
+            None
+        };
+        let pending = usage.pending.entry((key.id.clone(), day)).or_default();
+        if let Some(refusal) = refusal {
+            pending.1 += 1;
+            return Err(refusal);
+        }
+        pending.0 += 1;
+        usage.minutes.insert(key.id.clone(), (minute, in_minute + 1));
+        usage.today.insert(key.id.clone(), served_today + 1);
+        Ok(())
+    }
+
+    // Add the counts not written yet to the usage file; on failure they are kept for the next try
+    fn flush(&self) -> SqlResult<()> {
+        let pending = std::mem::take(&mut self.usage.lock().unwrap_or_else(|e| e.into_inner()).pending);
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let written = self.open_usage_database().and_then(|mut conn| {
+            let tx = conn.transaction()?;
+            {
+                let mut upsert = tx.prepare(
+                    "INSERT INTO api_key_usage (key_id, day, requests, rejected) VALUES (?1, ?2, ?3, ?4) \
+                     ON CONFLICT (key_id, day) DO UPDATE SET \
+                     requests = requests + excluded.requests, rejected = rejected + excluded.rejected"
+                )?;
+                for ((id, day), (requests, rejected)) in &pending {
+                    upsert.execute(params![id, day, *requests as i64, *rejected as i64])?;
+                }
+            }
+            tx.commit()
+        });
+        if written.is_err() {
+            let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+            for (entry, (requests, rejected)) in pending {
+                let counts = usage.pending.entry(entry).or_default();
+                counts.0 += requests;
+                counts.1 += rejected;
+            }
+        }
+        written
+    }
+}
+
+impl ApiKeys {
+    // Keys are only checked with a keys file. Today's counts are read back from the usage file, and
+    // a thread starts writing new ones to it.
+    pub(crate) fn new(config: &Config) -> ApiKeys {
+        let Some(path) = config.api_keys_file.clone() else {
+            return ApiKeys { registry: None };
+        };
+        let registry = Arc::new(KeyRegistry {
+            path,
+            loaded: RwLock::new(None),
+            required: config.api_key_required,
+            usage_path: config.api_usage_path.clone(),
+            usage: Mutex::default(),
+            source_names: config.sources.iter().filter_map(|s| s.name.clone()).collect(),
+        });
+        let day = today();
+        let counted = registry.open_usage_database().and_then(|conn| {
+            conn.prepare("SELECT key_id, requests FROM api_key_usage WHERE day = ?1")?
+                .query_map([&day], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)? as u64)))?
+                .collect::<SqlResult<HashMap<_, _>>>()
+        });
+        match counted {
+            Ok(today) => *registry.usage.lock().unwrap_or_else(|e| e.into_inner()) = Usage { day, today, ..Usage::default() },
+            Err(e) => eprintln!("Failed to read API key usage from {}: {}", registry.usage_path.display(), e),
+        }
+        let flushing = registry.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(Duration::from_secs(USAGE_FLUSH_SECONDS));
+            if let Err(e) = flushing.flush() {
+                eprintln!("Failed to write API key usage to {}: {}", flushing.usage_path.display(), e);
+            }
+        });
+        ApiKeys { registry: Some(registry) }
+    }
+
+    // The key's tier and its usage over the last USAGE_HISTORY_DAYS days, newest first; None for an
+    // unknown id. Blocks on the usage file.
+    pub(crate) fn usage(&self, id: &str) -> SqlResult<Option<KeyUsageResponse>> {
+        let Some(registry) = &self.registry else {
+            return Ok(None);
+        };
+        let keys = registry.keys();
+        let Some(key) = keys.iter().find(|key| key.id == id) else {
+            return Ok(None);
+        };
+        registry.flush()?;
+        let first_day = (chrono::Utc::now().date_naive() - chrono::Duration::days(USAGE_HISTORY_DAYS - 1))
+            .format("%Y%m%d")
+            .to_string();
+        let conn = registry.open_usage_database()?;
+        let days = conn
+            .prepare(
+                "SELECT day, requests, rejected FROM api_key_usage WHERE key_id = ?1 AND day >= ?2 ORDER BY day DESC"
+            )?
+            .query_map(params![id, first_day], |row| {
+                Ok(DayUsage { date: row.get(0)?, requests: row.get(1)?, rejected: row.get(2)? })
+            })?
+            .collect::<SqlResult<Vec<_>>>()?;
+        let day = today();
+        let served_today = days.iter().find(|usage| usage.date == day).map(|usage| usage.requests).unwrap_or_default();
+        Ok(Some(KeyUsageResponse {
+            id: key.id.clone(),
+            requests_per_minute: key.per_minute,
+            daily_quota: key.per_day,
+            remaining_today: key.per_day.map(|quota| quota.saturating_sub(served_today.max(0) as u64)),
+            days,
+        }))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct DayUsage {
+    pub(crate) date: String,
+    pub(crate) requests: i64,
+    pub(crate) rejected: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct KeyUsageResponse {
+    pub(crate) id: String,
+    // None: no limit
+    pub(crate) requests_per_minute: Option<u64>,
+    pub(crate) daily_quota: Option<u64>,
+    pub(crate) remaining_today: Option<u64>,
+    pub(crate) days: Vec<DayUsage>,
+}
+
+// Rejects requests with an unknown key (401), without one when keys are required (401), and over
+// their key's limit or quota (429)
+pub(crate) fn meter(keys: ApiKeys) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::path::full()
+        .and(warp::header::optional::<String>("x-api-key"))
+        .and_then(move |path: warp::path::FullPath, supplied: Option<String>| {
+            let result = match &keys.registry {
+                Some(registry) => registry.admit(path.as_str(), supplied.as_deref().map(str::trim)),
+                None => Ok(()),
+            };
+            async move { result.map_err(warp::Rejection::from) }
+        })
+        .untuple_one()
+}
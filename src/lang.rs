@@ -0,0 +1,114 @@
+// Language of a news text as an ISO 639-1 code, guessed from its script and, for texts in Latin
+// script, from its most common words. Only the start of the text is read; None when it is too
+// short or no language stands out.
+
+// Letters read from the start of a text, and the fewest a guess is made from
+const SAMPLE_LETTERS: usize = 1000;
+const MIN_LETTERS: usize = 20;
+
+// Frequent short words of the Latin-script languages told apart; a word on two lists counts for both
+const STOPWORDS: [(&str, &[&str]); 11] = [
+    ("en", &["the", "and", "of", "to", "is", "in", "that", "for", "with", "was", "are", "has", "this", "its"]),
+    ("es", &["el", "los", "las", "del", "que", "y", "por", "una", "con", "para", "es", "su", "al", "se"]),
+    ("pt", &["o", "os", "do", "da", "dos", "das", "não", "uma", "em", "com", "para", "é", "ao", "foi"]),
+    ("fr", &["le", "les", "des", "et", "est", "une", "dans", "pour", "qui", "sur", "au", "du", "pas", "ce"]),
+    ("de", &["der", "die", "und", "das", "ist", "nicht", "ein", "eine", "mit", "den", "von", "zu", "auf", "sich"]),
+    ("it", &["il", "di", "che", "è", "gli", "della", "delle", "per", "non", "sono", "nel", "alla", "ha", "lo"]),
+    ("nl", &["de", "het", "een", "en", "van", "niet", "op", "dat", "zijn", "voor", "met", "ook", "wordt", "bij"]),
+    ("tr", &["ve", "bir", "bu", "için", "ile", "olarak", "da", "de", "çok", "gibi", "daha", "olan", "sonra", "ise"]),
+    ("id", &["yang", "dan", "di", "ini", "itu", "dengan", "untuk", "dari", "dalam", "tidak", "akan", "pada", "adalah", "juga"]),
+    ("pl", &["i", "w", "na", "nie", "się", "jest", "że", "z", "do", "to", "jak", "oraz", "przez", "po"]),
+    ("sv", &["och", "att", "är", "som", "för", "på", "med", "av", "till", "inte", "den", "ett", "har", "om"]),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Script {
+    Latin,
+    Cyrillic,
+    Greek,
+    Arabic,
+    Hebrew,
+    Devanagari,
+    Thai,
+    Hangul,
+    Kana,
+    Han,
+}
+
+fn script(c: char) -> Option<Script> {
+    let script = match c as u32 {
+        0x41..=0x5A | 0x61..=0x7A | 0xC0..=0x24F => Script::Latin,
+        0x370..=0x3FF => Script::Greek,
+        0x400..=0x52F => Script::Cyrillic,
+        0x590..=0x5FF => Script::Hebrew,
+        0x600..=0x6FF | 0x750..=0x77F => Script::Arabic,
+        0x900..=0x97F => Script::Devanagari,
+        0xE00..=0xE7F => Script::Thai,
+        0x1100..=0x11FF | 0xAC00..=0xD7AF => Script::Hangul,
+        0x3040..=0x30FF => Script::Kana,
+        0x4E00..=0x9FFF | 0x3400..=0x4DBF => Script::Han,
+        _ => return None,
+    };
+    Some(script)
+}
+
+pub(crate) fn detect_language(text: &str) -> Option<&'static str> {
+    let letters: Vec<(char, Script)> = text
+        .chars()
+        .filter_map(|c| script(c).map(|script| (c, script)))
+        .take(SAMPLE_LETTERS)
+        .collect();
+    if letters.len() < MIN_LETTERS {
+        return None;
+    }
+    let count = |wanted: Script| letters.iter().filter(|(_, script)| *script == wanted).count();
+    let has = |chars: &[char]| letters.iter().any(|(c, _)| chars.contains(c));
+
+    // Japanese mixes kana into its kanji; any noticeable share of kana decides it
+    let kana = count(Script::Kana);
+    if kana * 20 >= letters.len() {
+        return Some("ja");
+    }
+    let scripts = [
+        Script::Latin, Script::Cyrillic, Script::Greek, Script::Arabic, Script::Hebrew,
+        Script::Devanagari, Script::Thai, Script::Hangul, Script::Han,
+    ];
+    let dominant = scripts.into_iter().max_by_key(|script| count(*script))?;
+    match dominant {
+        Script::Latin => latin_language(text),
+        Script::Cyrillic if has(&['ї', 'є', 'ґ', 'і']) => Some("uk"),
+        Script::Cyrillic => Some("ru"),
+        Script::Greek => Some("el"),
+        Script::Arabic if has(&['پ', 'چ', 'ژ', 'گ']) => Some("fa"),
+        Script::Arabic => Some("ar"),
+        Script::Hebrew => Some("he"),
+        Script::Devanagari => Some("hi"),
+        Script::Thai => Some("th"),
+        Script::Hangul => Some("ko"),
+        Script::Kana => Some("ja"),
+        Script::Han => Some("zh"),
+    }
+}
+
+// The language whose common words the text uses most, if it clearly leads
+fn latin_language(text: &str) -> Option<&'static str> {
+    let mut scores = [0usize; STOPWORDS.len()];
+    let words = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .take(SAMPLE_LETTERS / 4);
+    for word in words {
+        let word = word.to_lowercase();
+        for (score, (_, stopwords)) in scores.iter_mut().zip(STOPWORDS) {
+            if stopwords.contains(&word.as_str()) {
+                *score += 1;
+            }
+        }
+    }
+    let mut ranked: Vec<(usize, &str)> = scores.iter().zip(STOPWORDS).map(|(score, (lang, _))| (*score, lang)).collect();
+    ranked.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+    let (best, lang) = ranked[0];
+    let second = ranked[1].0;
+    // At least a few hits, and a third more than the runner-up
+    (best >= 3 && best * 3 > second * 4).then_some(lang)
+}
@@ -0,0 +1,400 @@
+// Trend Story API: serves the trends-story SQLite dataset (news records, dates, tags and images)
+// over HTTP. The binary in main.rs only wires a Config to build_routes and the sync task.
+mod auth;
+mod cache;
+mod config;
+mod credentials;
+mod crypto;
+mod db;
+mod dedupe;
+mod edits;
+mod embeddings;
+mod error;
+mod export;
+mod json_schema;
+mod jwt;
+mod keys;
+mod lang;
+mod limit;
+mod list;
+mod logging;
+mod lookup;
+mod markup;
+mod metrics;
+pub mod models;
+mod negotiate;
+mod placeholders;
+mod pool;
+mod provenance;
+mod proxy;
+mod report;
+mod routes;
+mod s3;
+mod search;
+mod sentiment;
+mod snapshot;
+mod summary;
+mod sync;
+mod tags;
+mod threads;
+mod thumbnails;
+mod translate;
+mod urls;
+mod validate;
+mod versions;
+mod watch;
+
+pub use cache::CacheTtls;
+pub use config::{Config, DataSource};
+pub use error::log_panics;
+pub use logging::{init_logging, LogFile, LogRotation};
+pub use proxy::TrustedProxies;
+pub use report::init_reporting;
+pub use routes::build_routes;
+pub use sync::{spawn_sync, sync_once};
+pub use tags::TagMap;
+pub use urls::BaseUrls;
+pub use watch::spawn_watcher;
+
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use serde::Serialize;
+use config::{RESPONSE_CACHE_CAPACITY, SYNC_LOG_CAPACITY};
+use db::{build_overlay, detect_schema, open_database, LatestResponse, SchemaLayout};
+use metrics::time_query;
+use sync::SyncRecord;
+use validate::ValidationStatus;
+
+// Outcome of the last schema check against the tables and columns the queries rely on
+#[derive(Debug, Clone, Default, Serialize)]
+pub(crate) struct SchemaStatus {
+    pub(crate) ok: bool,
+    pub(crate) checked_at: Option<String>,
+    pub(crate) adapted: Vec<String>,
+    pub(crate) problems: Vec<String>,
+}
+
+// A computed response and the days (yyyymmdd, inclusive) it was computed from; None means all days
+#[derive(Debug, Clone)]
+pub(crate) struct CacheEntry {
+    pub(crate) value: serde_json::Value,
+    pub(crate) days: Option<(String, String)>,
+    // End of the route's cache lifetime, at most CACHE_MAX_AGE_SECONDS away
+    pub(crate) expires: std::time::Instant,
+}
+
+// A response serialized ahead of requests, with its ETag
+#[derive(Debug, Clone)]
+pub(crate) struct PreparedBody {
+    pub(crate) body: warp::hyper::body::Bytes,
+    pub(crate) etag: String,
+    // End of the route's cache lifetime, at most CACHE_MAX_AGE_SECONDS away
+    pub(crate) expires: std::time::Instant,
+}
+
+// Shared state of one data source. The cache holds computed responses and is cleared after every sync.
+#[derive(Clone)]
+pub struct AppState {
+    pub(crate) source: Arc<DataSource>,
+    pub(crate) cache: Arc<RwLock<HashMap<String, CacheEntry>>>,
+    // GET /latest without options, serialized after every sync as the hottest response
+    pub(crate) latest: Arc<RwLock<Option<PreparedBody>>>,
+    pub(crate) schema: Arc<RwLock<SchemaStatus>>,
+    // Outcome of the last validation of a synced database
+    pub(crate) validation: Arc<RwLock<ValidationStatus>>,
+    // Fingerprint of the database file the known-good copy was made from
+    pub(crate) known_good_fingerprint: Arc<Mutex<Option<String>>>,
+    // False until the database could be opened after a sync; data routes answer 503 meanwhile
+    pub(crate) ready: Arc<AtomicBool>,
+    // Most recent sync attempts, oldest first
+    pub(crate) sync_log: Arc<RwLock<VecDeque<SyncRecord>>>,
+    // True while a sync of the source runs, so a triggered one and the scheduled one don't overlap
+    pub(crate) syncing: Arc<AtomicBool>,
+    // True while placeholders and thumbnails are being built, so a sync doesn't start a second run
+    pub(crate) processing_images: Arc<AtomicBool>,
+    // True while sentiment scores are being computed, likewise
+    #[cfg(feature = "sentiment")]
+    pub(crate) analyzing_sentiment: Arc<AtomicBool>,
+    // True while embeddings are being computed, likewise
+    #[cfg(feature = "embeddings")]
+    pub(crate) computing_embeddings: Arc<AtomicBool>,
+    // SHA-1 (hex) of the database file as of the last sync that could open it, for /meta
+    pub(crate) database_sha1: Arc<RwLock<Option<String>>>,
+    // Set per request by ?case=camel: JSON responses with camelCase field names
+    pub(crate) camel_case: bool,
+}
+
+impl AppState {
+    pub fn new(source: DataSource) -> AppState {
+        AppState {
+            source: Arc::new(source),
+            cache: Arc::new(RwLock::new(HashMap::new())),
+            latest: Arc::new(RwLock::new(None)),
+            schema: Arc::new(RwLock::new(SchemaStatus::default())),
+            validation: Arc::new(RwLock::new(ValidationStatus::default())),
+            known_good_fingerprint: Arc::new(Mutex::new(None)),
+            ready: Arc::new(AtomicBool::new(false)),
+            sync_log: Arc::new(RwLock::new(VecDeque::with_capacity(SYNC_LOG_CAPACITY))),
+            syncing: Arc::new(AtomicBool::new(false)),
+            processing_images: Arc::new(AtomicBool::new(false)),
+            #[cfg(feature = "sentiment")]
+            analyzing_sentiment: Arc::new(AtomicBool::new(false)),
+            #[cfg(feature = "embeddings")]
+            computing_embeddings: Arc::new(AtomicBool::new(false)),
+            database_sha1: Arc::new(RwLock::new(None)),
+            camel_case: false,
+        }
+    }
+
+    // The state of a request whose links follow its host: the same caches, with the source's
+    // links built from `urls`
+    pub(crate) fn with_base_urls(&self, urls: BaseUrls) -> AppState {
+        let mut source = (*self.source).clone();
+        source.base_urls = Arc::new(urls);
+        AppState { source: Arc::new(source), ..self.clone() }
+    }
+
+    // The state for a ?lang= request: news texts and keywords translated into `lang`, as far as
+    // translations were made
+    pub(crate) fn with_translation(&self, lang: String) -> AppState {
+        let mut source = (*self.source).clone();
+        source.translate_to = Some(lang);
+        source.translations_pending = Arc::default();
+        AppState { source: Arc::new(source), ..self.clone() }
+    }
+
+    // The state for a ?raw=true request: news texts as stored, markup included
+    pub(crate) fn with_news_html(&self) -> AppState {
+        let mut source = (*self.source).clone();
+        source.keep_news_html = true;
+        AppState { source: Arc::new(source), ..self.clone() }
+    }
+
+    // Cached responses embed links, so they are kept per link origin (the request's host, or
+    // none for relative links), and apart with ?raw=true or translated news texts
+    fn cache_key(&self, key: &str) -> String {
+        let markup = if self.source.keep_news_html { "+html" } else { "" };
+        let lang = self.source.translate_to.as_deref().map(|lang| format!("+{}", lang)).unwrap_or_default();
+        format!("{}@{}{}{}", key, self.source.base_urls.api_origin(), markup, lang)
+    }
+
+    // Re-check the schema, log any problems and keep the result for /health
+    pub fn validate_schema(&self) {
+        let layout = time_query("schema_check", || open_database(&self.source).and_then(|conn| detect_schema(&conn)))
+            .unwrap_or_else(|e| SchemaLayout {
+                problems: vec![format!("cannot read {}: {}", self.db_path().display(), e)],
+                ..SchemaLayout::default()
+            });
+        for mapping in &layout.adapted {
+            println!("Schema of {} adapted: {}", self.db_path().display(), mapping);
+        }
+        for problem in &layout.problems {
+            eprintln!("Schema problem in {}: {}", self.db_path().display(), problem);
+        }
+        let status = SchemaStatus {
+            ok: layout.problems.is_empty(),
+            checked_at: Some(chrono::Utc::now().to_rfc3339()),
+            adapted: layout.adapted,
+            problems: layout.problems,
+        };
+        if let Ok(mut schema) = self.schema.write() {
+            *schema = status;
+        }
+    }
+
+    // Rebuild the day index overlay if the database changed since it was last built
+    pub fn refresh_overlay(&self) {
+        match time_query("build_overlay", || build_overlay(&self.source)) {
+            Ok(true) => println!("Rebuilt day index in {}", self.source.overlay_path.display()),
+            Ok(false) => {}
+            Err(e) => eprintln!("Failed to build day index in {}: {}", self.source.overlay_path.display(), e),
+        }
+    }
+
+    // Serialize a GET /latest response without options, kept (unless the route isn't cached, or
+    // its links depend on the request) so requests only copy it
+    pub(crate) fn prepare_latest(&self, response: &LatestResponse) -> PreparedBody {
+        use std::hash::{Hash, Hasher};
+
+        let body = serde_json::to_vec(response).unwrap_or_default();
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        body.hash(&mut hasher);
+        let lifetime = self.source.cache_ttls.lifetime("latest");
+        let prepared = PreparedBody {
+            etag: format!("\"{:016x}\"", hasher.finish()),
+            body: body.into(),
+            expires: std::time::Instant::now() + lifetime,
+        };
+        if !lifetime.is_zero() && self.source.base_urls.from_host.is_none() {
+            if let Ok(mut latest) = self.latest.write() {
+                *latest = Some(prepared.clone());
+            }
+        }
+        prepared
+    }
+
+    // The prepared GET /latest while within the route's cache lifetime
+    pub(crate) fn prepared_latest(&self) -> Option<PreparedBody> {
+        let latest = self.latest.read().ok()?;
+        latest.clone().filter(|prepared| prepared.expires > std::time::Instant::now())
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_sync(&self, record: SyncRecord) {
+        if let Ok(mut log) = self.sync_log.write() {
+            if log.len() == SYNC_LOG_CAPACITY {
+                log.pop_front();
+            }
+            log.push_back(record);
+        }
+    }
+
+    // Number of sync attempts that failed since the last one that succeeded
+    pub(crate) fn consecutive_sync_failures(&self) -> usize {
+        self.sync_log
+            .read()
+            .map(|log| log.iter().rev().take_while(|record| record.error.is_some()).count())
+            .unwrap_or_default()
+    }
+
+    // Commit of the data repository as of the last sync that found one
+    pub(crate) fn data_commit(&self) -> Option<String> {
+        self.sync_log
+            .read()
+            .ok()
+            .and_then(|log| log.iter().rev().find_map(|record| record.commit_after.clone()))
+    }
+
+    // Start (RFC 3339) of the last sync that succeeded
+    pub(crate) fn last_sync_at(&self) -> Option<String> {
+        self.sync_log
+            .read()
+            .ok()
+            .and_then(|log| log.iter().rev().find(|record| record.error.is_none()).map(|record| record.started_at.clone()))
+    }
+
+    // Hash the database file again after a sync; None when it can't be read
+    pub(crate) fn refresh_database_sha1(&self) {
+        use sha1::{Digest, Sha1};
+
+        let db_path = self.db_path();
+        let hashed = std::fs::File::open(&db_path).and_then(|mut file| {
+            let mut hasher = Sha1::new();
+            std::io::copy(&mut file, &mut hasher)?;
+            Ok(hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect::<String>())
+        });
+        let sha1 = hashed
+            .map_err(|e| eprintln!("Failed to hash {}: {}", db_path.display(), e))
+            .ok();
+        if let Ok(mut current) = self.database_sha1.write() {
+            *current = sha1;
+        }
+    }
+
+    pub(crate) fn database_sha1(&self) -> Option<String> {
+        self.database_sha1.read().ok().and_then(|sha1| sha1.clone())
+    }
+
+    // Sync attempts, newest first
+    pub(crate) fn sync_history(&self) -> Vec<SyncRecord> {
+        self.sync_log.read().map(|log| log.iter().rev().cloned().collect()).unwrap_or_default()
+    }
+
+    pub(crate) fn schema_status(&self) -> SchemaStatus {
+        self.schema.read().map(|s| s.clone()).unwrap_or_default()
+    }
+
+    pub(crate) fn validation_status(&self) -> ValidationStatus {
+        self.validation.read().map(|v| v.clone()).unwrap_or_default()
+    }
+
+    // The database file requests read, see DataSource::serving_path
+    pub(crate) fn db_path(&self) -> PathBuf {
+        self.source.serving_path()
+    }
+
+    // A cached response that is still within its route's lifetime
+    pub(crate) fn cached(&self, key: &str) -> Option<serde_json::Value> {
+        let cache = self.cache.read().ok()?;
+        let entry = cache.get(&self.cache_key(key))?;
+        if entry.expires <= std::time::Instant::now() {
+            return None;
+        }
+        Some(entry.value.clone())
+    }
+
+    // Cache a response that depends on every day of data
+    pub(crate) fn store(&self, key: &str, value: serde_json::Value) {
+        self.store_entry(key, value, None);
+    }
+
+    // Cache a response computed from the days between first and last (yyyymmdd)
+    pub(crate) fn store_for_days(&self, key: &str, value: serde_json::Value, first: String, last: String) {
+        self.store_entry(key, value, Some((first, last)));
+    }
+
+    fn store_entry(&self, key: &str, value: serde_json::Value, days: Option<(String, String)>) {
+        let lifetime = self.source.cache_ttls.lifetime(key);
+        // A response missing translations would keep them out once they are made
+        if lifetime.is_zero() || self.source.translations_pending.load(Ordering::Relaxed) {
+            return;
+        }
+        let now = std::time::Instant::now();
+        let key = self.cache_key(key);
+        if let Ok(mut cache) = self.cache.write() {
+            // Expired entries would only be replaced on a hit of the same key
+            cache.retain(|_, entry| entry.expires > now);
+            if cache.len() >= RESPONSE_CACHE_CAPACITY && !cache.contains_key(&key) {
+                let soonest = cache.iter().min_by_key(|(_, entry)| entry.expires).map(|(key, _)| key.clone());
+                if let Some(soonest) = soonest {
+                    cache.remove(&soonest);
+                }
+            }
+            cache.insert(key, CacheEntry { value, days, expires: now + lifetime });
+        }
+    }
+
+    // Drop cached responses computed from any day in first..=last (yyyymmdd), or all of them;
+    // returns how many were dropped
+    pub(crate) fn purge_cache(&self, days: Option<(&str, &str)>) -> usize {
+        let Ok(mut cache) = self.cache.write() else {
+            return 0;
+        };
+        let before = cache.len();
+        self.source.lookups.clear();
+        self.source.threads.clear();
+        self.drop_latest();
+        match days {
+            None => cache.clear(),
+            Some((first, last)) => cache.retain(|_, entry| match &entry.days {
+                Some((from, to)) => to.as_str() < first || from.as_str() > last,
+                None => false,
+            }),
+        }
+        before - cache.len()
+    }
+
+    fn drop_latest(&self) {
+        if let Ok(mut latest) = self.latest.write() {
+            *latest = None;
+        }
+    }
+
+    pub(crate) fn clear_cache(&self) {
+        self.source.lookups.clear();
+        self.source.threads.clear();
+        self.drop_latest();
+        if let Ok(mut cache) = self.cache.write() {
+            cache.clear();
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use warp::hyper::body::HttpBody;
+use warp::hyper::Body;
+use warp::Filter;
+
+use crate::config::{Config, RESERVED_SOURCE_NAMES};
+use crate::error::ApiError;
+use crate::routes::strip_source_prefix;
+
+// In-flight request budgets: one shared by every request and one per route, keyed by the
+// route's first path segment (so /date/20251101 and /jp/date/20251102 share "date")
+#[derive(Clone)]
+pub(crate) struct ConcurrencyLimits {
+    global: Arc<Semaphore>,
+    routes: Arc<HashMap<&'static str, Arc<Semaphore>>>,
+    source_names: Arc<Vec<String>>,
+}
+
+// Held for the lifetime of a request; dropping it frees the slots
+pub(crate) struct Admission {
+    _global: OwnedSemaphorePermit,
+    _route: Option<OwnedSemaphorePermit>,
+}
+
+impl Admission {
+    // Hold the slots until a streamed body (/export/all, /admin/backup) has sent its last chunk,
+    // not just until the handler returned it; bodies already in full free them right away
+    pub(crate) fn hold_until_sent(self, response: warp::reply::Response) -> warp::reply::Response {
+        if response.body().size_hint().exact().is_some() {
+            return response;
+        }
+        let (parts, mut body) = response.into_parts();
+        let (mut sender, held) = Body::channel();
+        tokio::spawn(async move {
+            let _admission = self;
+            while let Some(chunk) = body.data().await {
+                match chunk {
+                    Ok(chunk) => {
+                        if sender.send_data(chunk).await.is_err() {
+                            return;
+                        }
+                    }
+                    Err(_) => {
+                        sender.abort();
+                        return;
+                    }
+                }
+            }
+        });
+        warp::reply::Response::from_parts(parts, held)
+    }
+}
+
+impl ConcurrencyLimits {
+    pub(crate) fn new(config: &Config) -> ConcurrencyLimits {
+        let routes = RESERVED_SOURCE_NAMES
+            .iter()
+            .map(|name| (*name, Arc::new(Semaphore::new(config.max_in_flight_per_route))))
+            .collect();
+        ConcurrencyLimits {
+            global: Arc::new(Semaphore::new(config.max_in_flight)),
+            routes: Arc::new(routes),
+            source_names: Arc::new(config.sources.iter().filter_map(|s| s.name.clone()).collect()),
+        }
+    }
+
+    // Take a slot without waiting; a full budget is reported instead of queueing the request
+    fn admit(&self, path: &str) -> Result<Admission, ApiError> {
+        let path = strip_source_prefix(path, &self.source_names);
+        let route = path.trim_start_matches('/').split('/').next().unwrap_or_default();
+
+        let global = self.global.clone()
+            .try_acquire_owned()
+            .map_err(|_| ApiError::Overloaded("the server".to_string()))?;
+        let route = match self.routes.get(route) {
+            Some(semaphore) => Some(
+                semaphore.clone()
+                    .try_acquire_owned()
+                    .map_err(|_| ApiError::Overloaded(format!("/{}", route)))?,
+            ),
+            None => None,
+        };
+        Ok(Admission { _global: global, _route: route })
+    }
+}
+
+// Rejects requests whose query string is longer than `max` bytes (414) before anything parses it
+pub(crate) fn query_limit(max: usize) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::query::raw()
+        .or(warp::any().map(String::new))
+        .unify()
+        .and_then(move |query: String| async move {
+            if query.len() > max {
+                Err(warp::Rejection::from(ApiError::QueryTooLong(max)))
+            } else {
+                Ok(())
+            }
+        })
+        .untuple_one()
+}
+
+// Bodies need a Content-Length of at most `max` bytes; larger ones are refused (413) unread
+pub(crate) fn body_limit(max: usize) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::body::content_length_limit(max as u64).or_else(move |rejection: warp::Rejection| async move {
+        if rejection.find::<warp::reject::PayloadTooLarge>().is_some() {
+            Err(ApiError::BodyTooLarge(max).into())
+        } else {
+            Err(rejection)
+        }
+    })
+}
+
+// Extracts an Admission for the request, or rejects with a 503 when a budget is exhausted
+pub(crate) fn admit(limits: ConcurrencyLimits) -> impl Filter<Extract = (Admission,), Error = warp::Rejection> + Clone {
+    warp::path::full().and_then(move |path: warp::path::FullPath| {
+        let admission = limits.admit(path.as_str()).map_err(warp::Rejection::from);
+        async move { admission }
+    })
+}
@@ -0,0 +1,488 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::config::MAX_PAGE_SIZE;
+use crate::db::NewsRecord;
+use crate::dedupe::collapse_duplicates;
+use crate::sentiment::Polarity;
+use crate::summary::{summarize_records, Summary};
+use crate::error::ApiError;
+use crate::negotiate::ResponseFormat;
+use crate::routes::{camel_case_keys, json_response};
+use crate::tags::TagMap;
+use crate::AppState;
+
+// Optional ?tag=, ?keyword=, ?has_image= and ?sentiment= filters shared by the record list endpoints
+#[derive(Debug, Default, Clone)]
+pub(crate) struct RecordFilter {
+    pub(crate) tag: Option<String>,
+    pub(crate) keyword: Option<String>,
+    pub(crate) has_image: Option<bool>,
+    pub(crate) sentiment: Option<Polarity>,
+}
+
+// LIKE pattern (with ESCAPE '\') matching values containing `text`, whose % and _ match only
+// themselves
+fn contains_pattern(text: &str) -> String {
+    let mut pattern = String::with_capacity(text.len() + 2);
+    pattern.push('%');
+    for c in text.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+impl RecordFilter {
+    pub(crate) fn from_params(params: &HashMap<String, String>) -> Result<Self, warp::Rejection> {
+        let non_empty = |key: &str| {
+            params.get(key)
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+        let has_image = match params.get("has_image").map(|v| v.as_str()) {
+            None => None,
+            Some("true") | Some("1") => Some(true),
+            Some("false") | Some("0") => Some(false),
+            Some(_) => return Err(ApiError::InvalidQueryParameter("has_image").into()),
+        };
+        let sentiment = match params.get("sentiment") {
+            None => None,
+            Some(raw) => Some(Polarity::from_param(raw).ok_or(ApiError::InvalidQueryParameter("sentiment"))?),
+        };
+        if sentiment.is_some() && !cfg!(feature = "sentiment") {
+            return Err(ApiError::SentimentUnavailable.into());
+        }
+        Ok(RecordFilter {
+            tag: non_empty("tag"),
+            keyword: non_empty("keyword"),
+            has_image,
+            sentiment,
+        })
+    }
+
+    // SQL conditions (each prefixed with AND) plus their bound values, numbered from first_param
+    pub(crate) fn sql(&self, first_param: usize, tag_map: &TagMap) -> (String, Vec<String>) {
+        let mut clause = String::new();
+        let mut values = Vec::new();
+        if let Some(tag) = &self.tag {
+            // Coarse match on the raw categories string, for any spelling the tag map folds into
+            // the tag; exact matching happens in matches()
+            let mut likes = Vec::new();
+            for spelling in tag_map.spellings(tag) {
+                values.push(contains_pattern(&spelling));
+                likes.push(format!("serpapi_data.categories LIKE ?{} ESCAPE '\\'", first_param + values.len() - 1));
+            }
+            clause.push_str(&format!(" AND ({})", likes.join(" OR ")));
+        }
+        if let Some(keyword) = &self.keyword {
+            values.push(contains_pattern(keyword));
+            clause.push_str(&format!(" AND serpapi_data.query LIKE ?{} ESCAPE '\\'", first_param + values.len() - 1));
+        }
+        match self.has_image {
+            Some(true) => clause.push_str(
+                " AND main_news_data.image_id IN (SELECT id FROM image_data WHERE file_name IS NOT NULL)"
+            ),
+            Some(false) => clause.push_str(
+                " AND (main_news_data.image_id IS NULL \
+                 OR main_news_data.image_id NOT IN (SELECT id FROM image_data WHERE file_name IS NOT NULL))"
+            ),
+            None => {}
+        }
+        if let Some(polarity) = self.sentiment {
+            clause.push_str(&polarity.sql());
+        }
+        (clause, values)
+    }
+
+    // Record tags are already translated by the tag map, so the filter tag is translated too
+    pub(crate) fn matches(&self, record: &NewsRecord, tag_map: &TagMap) -> bool {
+        match &self.tag {
+            Some(tag) => {
+                let tag = tag_map.normalize(tag);
+                record.tag.iter().any(|t| t.eq_ignore_ascii_case(&tag))
+            }
+            None => true,
+        }
+    }
+}
+
+// Field names accepted by ?fields=, in NewsRecord serialization order
+pub(crate) const NEWS_RECORD_FIELDS: [&str; 12] = [
+    "id", "news", "date", "serpapi_id", "image_id", "serpapi_data_date", "keywords", "image", "tag", "lang",
+    "thread_id", "sentiment",
+];
+
+// ?sort= column for record lists, mapped to a fixed ORDER BY expression so input never reaches SQL
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) enum SortField {
+    #[default]
+    Id,
+    Date,
+    Keywords,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl SortOrder {
+    pub(crate) fn sql(self) -> &'static str {
+        match self {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct RecordSort {
+    pub(crate) field: SortField,
+    pub(crate) order: SortOrder,
+}
+
+impl RecordSort {
+    pub(crate) fn from_params(params: &HashMap<String, String>) -> Result<Self, warp::Rejection> {
+        let field = match params.get("sort").map(|v| v.as_str()) {
+            None | Some("id") => SortField::Id,
+            Some("date") => SortField::Date,
+            Some("keywords") => SortField::Keywords,
+            Some(_) => return Err(ApiError::InvalidQueryParameter("sort").into()),
+        };
+        let order = match params.get("order").map(|v| v.as_str()) {
+            None | Some("asc") => SortOrder::Asc,
+            Some("desc") => SortOrder::Desc,
+            Some(_) => return Err(ApiError::InvalidQueryParameter("order").into()),
+        };
+        Ok(RecordSort { field, order })
+    }
+
+    // Sort key expressions, ending with the record id as a stable tie-breaker
+    pub(crate) fn columns(&self) -> &'static [&'static str] {
+        match self.field {
+            SortField::Id => &["main_news_data.id"],
+            SortField::Date => &["COALESCE(main_news_data.date, '')", "main_news_data.id"],
+            SortField::Keywords => &["COALESCE(serpapi_data.query, '') COLLATE NOCASE", "main_news_data.id"],
+        }
+    }
+
+    // ORDER BY terms (without the keyword)
+    pub(crate) fn sql(&self) -> String {
+        let dir = self.order.sql();
+        self.columns()
+            .iter()
+            .map(|column| format!("{} {}", column, dir))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+
+    // Values of the sort key columns for a record, used to resume after it
+    pub(crate) fn key_values(&self, record: &NewsRecord) -> Vec<serde_json::Value> {
+        let id = serde_json::Value::from(record.id);
+        match self.field {
+            SortField::Id => vec![id],
+            SortField::Date => vec![record.date.clone().unwrap_or_default().into(), id],
+            SortField::Keywords => vec![record.keywords.clone().unwrap_or_default().into(), id],
+        }
+    }
+
+    pub(crate) fn name(&self) -> String {
+        let field = match self.field {
+            SortField::Id => "id",
+            SortField::Date => "date",
+            SortField::Keywords => "keywords",
+        };
+        format!("{}:{}", field, self.order.sql())
+    }
+}
+
+// Opaque ?cursor= token: the day being paged, the sort in effect and the sort key of the last record.
+// Paging by key instead of offset keeps pages stable when a sync adds records mid-way.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Cursor {
+    pub(crate) day: String,
+    pub(crate) sort: String,
+    pub(crate) keys: Vec<serde_json::Value>,
+}
+
+impl Cursor {
+    pub(crate) fn encode(&self) -> String {
+        use base64::Engine;
+        let json = serde_json::to_vec(self).unwrap_or_default();
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(json)
+    }
+
+    pub(crate) fn decode(token: &str) -> Option<Cursor> {
+        use base64::Engine;
+        let json = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(token).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+}
+
+// Shape of JSON list responses, from ?api_version=: 1 as each route documents it; 2 wraps the
+// records in the envelope every list route shares ({data, meta, links})
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ApiVersion {
+    #[default]
+    V1,
+    V2,
+}
+
+// Per-request options for endpoints returning lists of news records
+#[derive(Debug, Default, Clone)]
+pub(crate) struct ListOptions {
+    pub(crate) filter: RecordFilter,
+    pub(crate) sort: RecordSort,
+    pub(crate) fields: Option<Vec<String>>,
+    pub(crate) limit: Option<usize>,
+    pub(crate) cursor: Option<Cursor>,
+    // ?dedupe=true: collapse records telling the same story into one with its "duplicates"
+    pub(crate) dedupe: bool,
+    // ?summary=N or ?summary=paragraph: shortened news texts, with their full length as news_length
+    pub(crate) summary: Option<Summary>,
+    pub(crate) version: ApiVersion,
+    // Set by the route from ?format= and Accept
+    pub(crate) format: ResponseFormat,
+}
+
+impl ListOptions {
+    pub(crate) fn from_params(params: &HashMap<String, String>) -> Result<Self, warp::Rejection> {
+        let fields = match params.get("fields") {
+            None => None,
+            Some(raw) => {
+                let fields: Vec<String> = raw.split(',')
+                    .map(|f| f.trim().to_string())
+                    .filter(|f| !f.is_empty())
+                    .collect();
+                if fields.is_empty() || fields.iter().any(|f| !NEWS_RECORD_FIELDS.contains(&f.as_str())) {
+                    return Err(ApiError::InvalidQueryParameter("fields").into());
+                }
+                Some(fields)
+            }
+        };
+        let limit = match params.get("limit") {
+            None => None,
+            Some(value) => match value.parse::<usize>() {
+                Ok(limit) if (1..=MAX_PAGE_SIZE).contains(&limit) => Some(limit),
+                _ => return Err(ApiError::InvalidQueryParameter("limit").into()),
+            },
+        };
+        let sort = RecordSort::from_params(params)?;
+        let cursor = match params.get("cursor") {
+            None => None,
+            Some(token) => match Cursor::decode(token) {
+                // A cursor is only meaningful for the sort it was issued under
+                Some(cursor) if cursor.sort == sort.name() && cursor.keys.len() == sort.columns().len() => Some(cursor),
+                _ => return Err(ApiError::InvalidCursor.into()),
+            },
+        };
+        let dedupe = match params.get("dedupe").map(|v| v.as_str()) {
+            None | Some("false") | Some("0") => false,
+            Some("true") | Some("1") => true,
+            Some(_) => return Err(ApiError::InvalidQueryParameter("dedupe").into()),
+        };
+        let summary = match params.get("summary") {
+            None => None,
+            Some(raw) => Some(Summary::from_param(raw).ok_or(ApiError::InvalidQueryParameter("summary"))?),
+        };
+        let version = match params.get("api_version").map(|v| v.as_str()) {
+            None | Some("1") => ApiVersion::V1,
+            Some("2") => ApiVersion::V2,
+            Some(_) => return Err(ApiError::InvalidQueryParameter("api_version").into()),
+        };
+        Ok(ListOptions {
+            filter: RecordFilter::from_params(params)?,
+            sort,
+            fields,
+            limit,
+            cursor,
+            dedupe,
+            summary,
+            version,
+            format: ResponseFormat::Json,
+        })
+    }
+
+    // Trim a single day's records to the page size, returning the cursor for the next page
+    pub(crate) fn paginate(&self, day: &str, records: &mut Vec<NewsRecord>) -> Option<String> {
+        let limit = self.limit?;
+        if records.len() <= limit {
+            return None;
+        }
+        records.truncate(limit);
+        records.last().map(|last| Cursor {
+            day: day.to_string(),
+            sort: self.sort.name(),
+            keys: self.sort.key_values(last),
+        }.encode())
+    }
+
+    // Serialize a response in the negotiated format, with duplicates collapsed and texts shortened
+    // if asked, only the requested fields of every entry in a "records" array, in the asked API version and,
+    // for JSON, with the asked field name case
+    pub(crate) fn reply<T: Serialize>(&self, state: &AppState, response: &T) -> warp::reply::Response {
+        let enveloped = self.version == ApiVersion::V2 && self.format == ResponseFormat::Json;
+        if self.fields.is_none() && !self.dedupe && self.summary.is_none() && !enveloped && self.format == ResponseFormat::Json {
+            let mut reply = json_response(state, response);
+            reply.headers_mut().insert(warp::http::header::VARY, warp::http::HeaderValue::from_static("Accept"));
+            return reply;
+        }
+        let mut value = serde_json::to_value(response).unwrap_or_default();
+        if self.dedupe {
+            collapse_duplicates(&mut value);
+        }
+        if let Some(summary) = self.summary {
+            summarize_records(&mut value, summary);
+        }
+        if let Some(fields) = &self.fields {
+            prune_record_fields(&mut value, fields);
+        }
+        if enveloped {
+            value = envelope(value, self.limit);
+        }
+        if state.camel_case && self.format == ResponseFormat::Json {
+            camel_case_keys(&mut value);
+        }
+        self.format.render(state, &value)
+    }
+}
+
+// The version 2 shape of a response with top-level records: the records as "data", the page as
+// "meta" (total matches, page size, matches before the page, plus the response's other values
+// such as date and next_cursor) and its navigation as "links". Other responses are unchanged.
+fn envelope(value: serde_json::Value, limit: Option<usize>) -> serde_json::Value {
+    use serde_json::{json, Value};
+
+    let Value::Object(mut map) = value else {
+        return value;
+    };
+    let Some(data) = map.remove("records").filter(Value::is_array) else {
+        return Value::Object(map);
+    };
+    let links = map.remove("links").unwrap_or_else(|| json!({}));
+    let mut meta = serde_json::Map::new();
+    meta.insert("total".to_string(), map.remove("total_records").unwrap_or(Value::Null));
+    meta.insert("limit".to_string(), json!(limit));
+    meta.insert("offset".to_string(), map.remove("offset").unwrap_or_else(|| json!(0)));
+    meta.extend(map);
+    json!({ "data": data, "meta": meta, "links": links })
+}
+
+// Keep the requested fields of a record and of the duplicates nested in it by ?dedupe=true
+// (search results keep their highlights, shortened news their news_length)
+fn prune_record(record: &mut serde_json::Value, fields: &[String]) {
+    if let serde_json::Value::Object(record) = record {
+        let keep_length = fields.iter().any(|f| f == "news");
+        record.retain(|k, _| {
+            k == "duplicates" || k == "highlights" || (k == "news_length" && keep_length) || fields.iter().any(|f| f == k)
+        });
+        if let Some(serde_json::Value::Array(duplicates)) = record.get_mut("duplicates") {
+            duplicates.iter_mut().for_each(|duplicate| prune_record(duplicate, fields));
+        }
+    }
+}
+
+pub(crate) fn prune_record_fields(value: &mut serde_json::Value, fields: &[String]) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, child) in map.iter_mut() {
+                match child {
+                    serde_json::Value::Array(records) if key == "records" => {
+                        records.iter_mut().for_each(|record| prune_record(record, fields));
+                    }
+                    _ => prune_record_fields(child, fields),
+                }
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for item in items.iter_mut() {
+                prune_record_fields(item, fields);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{contains_pattern, Cursor, ListOptions};
+    use crate::error::ApiError;
+
+    fn like(text: &str, filter: &str) -> bool {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        db.query_row("SELECT ?1 LIKE ?2 ESCAPE '\\'", [text, &contains_pattern(filter)], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn like_wildcards_in_filters_match_only_themselves() {
+        assert_eq!(contains_pattern("50%_off\\"), "%50\\%\\_off\\\\%");
+        assert!(like("Election results", "result"));
+        assert!(!like("Election results", "%"));
+        assert!(!like("Election results", "_"));
+        assert!(like("50% off", "50%"));
+        assert!(like("snake_case", "e_c"));
+        assert!(!like("snakeXcase", "e_c"));
+        assert!(like("back\\slash", "k\\s"));
+    }
+
+    fn with_cursor(token: &str, sort: Option<&str>) -> Result<ListOptions, warp::Rejection> {
+        let mut params = HashMap::from([("cursor".to_string(), token.to_string())]);
+        if let Some(sort) = sort {
+            params.insert("sort".to_string(), sort.to_string());
+        }
+        ListOptions::from_params(&params)
+    }
+
+    fn rejects_cursor(token: &str, sort: Option<&str>) -> bool {
+        let rejection = with_cursor(token, sort).unwrap_err();
+        let error = rejection.find::<ApiError>().expect("an ApiError");
+        matches!(error, ApiError::InvalidCursor) && error.status() == 400
+    }
+
+    #[test]
+    fn cursors_survive_a_round_trip() {
+        let cursor = Cursor {
+            day: "2025-11-01".to_string(),
+            sort: "date:ASC".to_string(),
+            keys: vec!["2025-11-01 08:00".into(), 42.into()],
+        };
+        let token = cursor.encode();
+        assert!(token.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'), "{}", token);
+
+        let decoded = Cursor::decode(&token).unwrap();
+        assert_eq!((decoded.day.as_str(), decoded.sort.as_str()), ("2025-11-01", "date:ASC"));
+        assert_eq!(decoded.keys, cursor.keys);
+        let options = with_cursor(&token, Some("date")).unwrap_or_else(|_| panic!("{} rejected", token));
+        assert_eq!(options.cursor.unwrap().keys, cursor.keys);
+    }
+
+    #[test]
+    fn malformed_or_forged_cursors_are_rejected() {
+        use base64::Engine;
+        let forge = |json: &str| base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(json);
+        let issued = Cursor { day: "2025-11-01".to_string(), sort: "id:ASC".to_string(), keys: vec![7.into()] }.encode();
+
+        assert!(with_cursor(&issued, None).is_ok());
+        // Not base64, padded, or base64 of something other than a cursor
+        for token in ["", "!!!", "abc$", &format!("{}==", issued), &forge("not json"), &forge("[1,2]"), &forge("{}")] {
+            assert!(rejects_cursor(token, None), "{}", token);
+        }
+        // Truncated or with a flipped character
+        assert!(rejects_cursor(&issued[..issued.len() - 3], None));
+        let mut flipped = issued.clone().into_bytes();
+        flipped[2] ^= 0x01;
+        assert!(rejects_cursor(&String::from_utf8(flipped).unwrap(), None));
+        // Well-formed, but for another sort or with the wrong number of keys
+        assert!(rejects_cursor(&issued, Some("date")));
+        assert!(rejects_cursor(&forge(r#"{"day":"2025-11-01","sort":"id:ASC","keys":[]}"#), None));
+        assert!(rejects_cursor(&forge(r#"{"day":"2025-11-01","sort":"id:ASC","keys":[1,2]}"#), None));
+    }
+}
@@ -0,0 +1,194 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use chrono::{DateTime, NaiveDate, Utc};
+
+use crate::config::Config;
+
+// When the current log file is moved aside for a new one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogRotation {
+    // At the first line of a new day (UTC), named after the day the file covers
+    Daily,
+    // Before a line would take the file past this many bytes, named after the rotation time
+    Size(u64),
+}
+
+impl LogRotation {
+    // "daily", or a size in bytes with an optional K, M or G suffix ("10M")
+    pub(crate) fn parse(raw: &str) -> Option<LogRotation> {
+        let raw = raw.trim().to_uppercase();
+        if raw == "DAILY" {
+            return Some(LogRotation::Daily);
+        }
+        let digits = raw.trim_end_matches('B');
+        let (digits, unit) = match digits.chars().last()? {
+            'K' => (&digits[..digits.len() - 1], 1 << 10),
+            'M' => (&digits[..digits.len() - 1], 1 << 20),
+            'G' => (&digits[..digits.len() - 1], 1 << 30),
+            _ => (digits, 1),
+        };
+        let size = digits.parse::<u64>().ok()?.checked_mul(unit)?;
+        (size > 0).then_some(LogRotation::Size(size))
+    }
+}
+
+// Where the server writes its output when not logging to stdout and stderr
+#[derive(Debug, Clone)]
+pub struct LogFile {
+    pub path: PathBuf,
+    pub rotation: LogRotation,
+    // Rotated files kept next to the current one; older ones are deleted
+    pub keep: usize,
+}
+
+// Access lines are only written with a log file, as journald deployments log requests at the proxy
+static ACCESS_LOG: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn access_log_enabled() -> bool {
+    ACCESS_LOG.load(Ordering::Relaxed)
+}
+
+// Send stdout and stderr (startup output, warnings, access lines) to the configured log file,
+// each line prefixed with its time. Output stays on the terminal when the file can't be opened.
+pub fn init_logging(config: &Config) {
+    let Some(settings) = &config.log_file else {
+        return;
+    };
+    match redirect_output(settings.clone()) {
+        Ok(()) => ACCESS_LOG.store(true, Ordering::Relaxed),
+        Err(e) => eprintln!("Logging to stdout: can't write {}: {}", settings.path.display(), e),
+    }
+}
+
+// Point stdout and stderr at a pipe and copy what comes through it into the file on a thread,
+// so existing println!/eprintln! output is logged without going through a logger
+fn redirect_output(settings: LogFile) -> std::io::Result<()> {
+    let mut writer = LogWriter::open(settings)?;
+    let (reader, pipe) = std::io::pipe()?;
+    // The terminal's stderr, for when the log file itself can't be written
+    let fallback = unsafe {
+        let fd = libc::dup(libc::STDERR_FILENO);
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        File::from_raw_fd(fd)
+    };
+    for target in [libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        if unsafe { libc::dup2(pipe.as_raw_fd(), target) } < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    drop(pipe);
+
+    std::thread::spawn(move || {
+        let mut fallback = fallback;
+        let mut reader = BufReader::new(reader);
+        let mut line = Vec::new();
+        while matches!(reader.read_until(b'\n', &mut line), Ok(n) if n > 0) {
+            if let Err(e) = writer.write_line(&line) {
+                let _ = writeln!(fallback, "Can't write log file {}: {}", writer.settings.path.display(), e);
+                let _ = fallback.write_all(&line);
+            }
+            line.clear();
+        }
+    });
+    Ok(())
+}
+
+struct LogWriter {
+    settings: LogFile,
+    file: File,
+    // Size of the current file and the day its first line was written
+    written: u64,
+    day: NaiveDate,
+}
+
+impl LogWriter {
+    fn open(settings: LogFile) -> std::io::Result<LogWriter> {
+        let file = OpenOptions::new().create(true).append(true).open(&settings.path)?;
+        let metadata = file.metadata()?;
+        // A file left from an earlier day is rotated by its first new line
+        let day = match metadata.modified() {
+            Ok(modified) if metadata.len() > 0 => DateTime::<Utc>::from(modified).date_naive(),
+            _ => Utc::now().date_naive(),
+        };
+        Ok(LogWriter { settings, file, written: metadata.len(), day })
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> std::io::Result<()> {
+        let now = Utc::now();
+        let mut entry = format!("{} ", now.format("%Y-%m-%dT%H:%M:%S%.3fZ")).into_bytes();
+        entry.extend_from_slice(line);
+        if !entry.ends_with(b"\n") {
+            entry.push(b'\n');
+        }
+
+        let rotate = match self.settings.rotation {
+            LogRotation::Daily => now.date_naive() != self.day,
+            LogRotation::Size(max) => self.written > 0 && self.written + entry.len() as u64 > max,
+        };
+        if rotate {
+            let suffix = match self.settings.rotation {
+                LogRotation::Daily => self.day.format("%Y%m%d").to_string(),
+                LogRotation::Size(_) => now.format("%Y%m%d-%H%M%S").to_string(),
+            };
+            self.rotate(&suffix)?;
+            self.day = now.date_naive();
+        }
+
+        self.file.write_all(&entry)?;
+        self.written += entry.len() as u64;
+        Ok(())
+    }
+
+    // Move the current file to <path>.<suffix>, start a new one and prune old rotated files
+    fn rotate(&mut self, suffix: &str) -> std::io::Result<()> {
+        let path = &self.settings.path;
+        let mut rotated = suffixed(path, suffix);
+        let mut n = 1;
+        while rotated.exists() {
+            rotated = suffixed(path, &format!("{}.{}", suffix, n));
+            n += 1;
+        }
+        std::fs::rename(path, &rotated)?;
+        self.file = OpenOptions::new().create(true).append(true).open(path)?;
+        self.written = 0;
+        self.prune();
+        Ok(())
+    }
+
+    // Rotated files sort oldest first by their timestamp suffix
+    fn prune(&self) {
+        let path = &self.settings.path;
+        let (Some(dir), Some(name)) = (path.parent(), path.file_name().and_then(|n| n.to_str())) else {
+            return;
+        };
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        let prefix = format!("{}.", name);
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+        let mut rotated: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_str().is_some_and(|n| n.starts_with(&prefix)))
+            .map(|entry| entry.path())
+            .collect();
+        rotated.sort();
+        let excess = rotated.len().saturating_sub(self.settings.keep);
+        for old in &rotated[..excess] {
+            if let Err(e) = std::fs::remove_file(old) {
+                eprintln!("Can't remove old log file {}: {}", old.display(), e);
+            }
+        }
+    }
+}
+
+fn suffixed(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
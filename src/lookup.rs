@@ -0,0 +1,106 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use rusqlite::Result as SqlResult;
+
+use crate::config::LOOKUP_CACHE_CAPACITY;
+
+// Least-recently-used map from row id to a looked-up value, holding at most `capacity` ids
+#[derive(Debug)]
+struct Lru {
+    capacity: usize,
+    // Incremented on every use; an entry's tick orders it in `by_use`
+    tick: u64,
+    entries: HashMap<i64, (Option<String>, u64)>,
+    by_use: BTreeMap<u64, i64>,
+}
+
+impl Lru {
+    fn new(capacity: usize) -> Lru {
+        Lru { capacity, tick: 0, entries: HashMap::new(), by_use: BTreeMap::new() }
+    }
+
+    fn get(&mut self, id: i64) -> Option<Option<String>> {
+        self.tick += 1;
+        let (value, used) = self.entries.get_mut(&id)?;
+        self.by_use.remove(used);
+        *used = self.tick;
+        self.by_use.insert(self.tick, id);
+        Some(value.clone())
+    }
+
+    fn insert(&mut self, id: i64, value: Option<String>) {
+        self.tick += 1;
+        if let Some((_, used)) = self.entries.insert(id, (value, self.tick)) {
+            self.by_use.remove(&used);
+        }
+        self.by_use.insert(self.tick, id);
+        while self.entries.len() > self.capacity {
+            let Some((_, oldest)) = self.by_use.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.by_use.clear();
+    }
+}
+
+// Per-source caches of the per-record lookups (keywords and categories by serpapi_data id, image
+// file names by image_data id, detected languages by record id), so rendering the same records
+// again doesn't query SQLite or read the texts again for each.
+// Cleared with the response cache: after every sync, purge and local edit.
+#[derive(Debug)]
+pub(crate) struct Lookups {
+    keywords: Mutex<Lru>,
+    categories: Mutex<Lru>,
+    images: Mutex<Lru>,
+    languages: Mutex<Lru>,
+}
+
+impl Default for Lookups {
+    fn default() -> Lookups {
+        Lookups {
+            keywords: Mutex::new(Lru::new(LOOKUP_CACHE_CAPACITY)),
+            categories: Mutex::new(Lru::new(LOOKUP_CACHE_CAPACITY)),
+            images: Mutex::new(Lru::new(LOOKUP_CACHE_CAPACITY)),
+            languages: Mutex::new(Lru::new(LOOKUP_CACHE_CAPACITY)),
+        }
+    }
+}
+
+// The cached value of `id`, or `load` it and cache it; failed loads aren't cached
+fn lookup(lru: &Mutex<Lru>, id: i64, load: impl FnOnce() -> SqlResult<Option<String>>) -> SqlResult<Option<String>> {
+    if let Some(value) = lru.lock().unwrap_or_else(|e| e.into_inner()).get(id) {
+        return Ok(value);
+    }
+    let value = load()?;
+    lru.lock().unwrap_or_else(|e| e.into_inner()).insert(id, value.clone());
+    Ok(value)
+}
+
+impl Lookups {
+    pub(crate) fn keywords(&self, serpapi_id: i64, load: impl FnOnce() -> SqlResult<Option<String>>) -> SqlResult<Option<String>> {
+        lookup(&self.keywords, serpapi_id, load)
+    }
+
+    pub(crate) fn categories(&self, serpapi_id: i64, load: impl FnOnce() -> SqlResult<Option<String>>) -> SqlResult<Option<String>> {
+        lookup(&self.categories, serpapi_id, load)
+    }
+
+    pub(crate) fn image(&self, image_id: i64, load: impl FnOnce() -> SqlResult<Option<String>>) -> SqlResult<Option<String>> {
+        lookup(&self.images, image_id, load)
+    }
+
+    pub(crate) fn language(&self, record_id: i64, load: impl FnOnce() -> SqlResult<Option<String>>) -> SqlResult<Option<String>> {
+        lookup(&self.languages, record_id, load)
+    }
+
+    pub(crate) fn clear(&self) {
+        for lru in [&self.keywords, &self.categories, &self.images, &self.languages] {
+            lru.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        }
+    }
+}
@@ -1,534 +1,91 @@
-// Immutable Config
-const DOMAIN: &str = "https://trending.oopus.info";
-const DOMAIN_API: &str = "https://trend-story-api.oopus.info";
-const SYNC_INTERVAL_MINUTES: u64 = 20; // User-configurable
-
-use std::path::Path;
-use rusqlite::{Connection, Result as SqlResult};
-use serde::{Deserialize, Serialize};
-use warp::Filter;
-
-#[derive(Debug, Serialize, Deserialize)]
-struct LatestResponse {
-    date: Option<String>,
-    records: Vec<NewsRecord>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct DateResponse {
-    date: String,
-    date_with_url: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct ImageInfo {
-    file_name: Option<String>,
-    url: Option<String>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct NewsRecord {
-    id: i64,
-    news: Option<String>,
-    date: Option<String>,
-    serpapi_id: Option<i64>,
-    image_id: Option<i64>,
-    serpapi_data_date: Option<String>,
-    keywords: Option<String>,
-    image: Option<ImageInfo>,
-    tag: Vec<String>,
-}
-
-async fn get_latest() -> Result<impl warp::Reply, warp::Rejection> {
-    match query_latest_news() {
-        Ok(response) => Ok(warp::reply::json(&response)),
-        Err(e) => {
-            eprintln!("Database error: {}", e);
-            Err(warp::reject::custom(DatabaseError))
-        }
-    }
-}
-
-async fn get_date(date_param: String) -> Result<impl warp::Reply, warp::Rejection> {
-    // Validate date format (must be 8 digits)
-    if date_param.len() != 8 || !date_param.chars().all(|c| c.is_numeric()) {
-        return Err(warp::reject::custom(InvalidDateFormat));
-    }
-    
-    // Convert yyyymmdd to yyyy-mm-dd
-    let formatted_date = format!(
-        "{}-{}-{}",
-        &date_param[0..4],
-        &date_param[4..6],
-        &date_param[6..8]
-    );
-    
-    match query_news_by_date(&formatted_date) {
-        Ok(response) => {
-            if response.records.is_empty() {
-                Err(warp::reject::custom(NoDataFound))
-            } else {
-                Ok(warp::reply::json(&response))
-            }
-        }
-        Err(e) => {
-            eprintln!("Database error: {}", e);
-            Err(warp::reject::custom(DatabaseError))
-        }
-    }
-}
-
-async fn get_dates() -> Result<impl warp::Reply, warp::Rejection> {
-    match query_all_dates() {
-        Ok(dates) => Ok(warp::reply::json(&dates)),
-        Err(e) => {
-            eprintln!("Database error: {}", e);
-            Err(warp::reject::custom(DatabaseError))
-        }
-    }
-}
-
-fn query_all_dates() -> SqlResult<Vec<DateResponse>> {
-    let db_path = "trends-story/trends_data.db";
-    
-    if !Path::new(db_path).exists() {
-        return Err(rusqlite::Error::SqliteFailure(
-            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_CANTOPEN),
-            Some("Database file not found".to_string())
-        ));
-    }
-
-    let conn = Connection::open(db_path)?;
-    
-    // Query unique dates from main_news_data, extract yyyymmdd format, and sort by id
-    let mut stmt = conn.prepare(
-        "SELECT DISTINCT REPLACE(substr(date, 1, 10), '-', '') as date_formatted \
-         FROM main_news_data \
-         ORDER BY id ASC"
-    )?;
-
-    let date_rows = stmt.query_map([], |row| {
-        let date: String = row.get(0)?;
-        Ok(date)
-    })?;
-    
-    let mut dates = Vec::new();
-    let mut seen = std::collections::HashSet::new();
-    
-    for row_result in date_rows {
-        let date_formatted = row_result?;
-        
-        // Only add unique dates
-        if seen.insert(date_formatted.clone()) {
-            let date_with_url = format!(
-                "{}/date/{}",
-                DOMAIN,
-                date_formatted
-            );
-            
-            dates.push(DateResponse {
-                date: date_formatted,
-                date_with_url,
-            });
-        }
-    }
-    
-    Ok(dates)
-}
-
-fn query_latest_news() -> SqlResult<LatestResponse> {
-    let db_path = "trends-story/trends_data.db";
-    
-    if !Path::new(db_path).exists() {
-        return Err(rusqlite::Error::SqliteFailure(
-            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_CANTOPEN),
-            Some("Database file not found".to_string())
-        ));
-    }
-
-    let conn = Connection::open(db_path)?;
-    
-    // Find the latest day (yyyy-mm-dd) from the date column
-    let latest_day: Option<String> = conn.query_row(
-        "SELECT substr(date, 1, 10) as day FROM main_news_data ORDER BY date DESC LIMIT 1",
-        [],
-        |row| row.get(0)
-    ).ok();
-
-    // If no day found, return empty response
-    let day_filter = match &latest_day {
-        Some(day) => day.clone(),
-        None => return Ok(LatestResponse {
-            date: None,
-            records: vec![],
-        }),
-    };
-
-    // Query all records from the latest day
-    let mut stmt = conn.prepare(
-        "SELECT main_news_data.id, main_news_data.news, main_news_data.date, \
-         main_news_data.serpapi_id, main_news_data.image_id, \
-         serpapi_data.date AS serpapi_data_date \
-         FROM main_news_data \
-         LEFT JOIN serpapi_data \
-         ON main_news_data.serpapi_id = serpapi_data.id \
-         WHERE substr(main_news_data.date, 1, 10) = ?1 \
-         ORDER BY main_news_data.id ASC"
-    )?;
-
-    let news_rows = stmt.query_map([&day_filter], |row| {
-        Ok((
-            row.get::<_, i64>(0)?,      // id
-            row.get::<_, Option<String>>(1)?,  // news
-            row.get::<_, Option<String>>(2)?,  // date
-            row.get::<_, Option<i64>>(3)?,     // serpapi_id
-            row.get::<_, Option<i64>>(4)?,     // image_id
-            row.get::<_, Option<String>>(5)?,  // serpapi_data_date
-        ))
-    })?;
-    
-    let mut records = Vec::new();
-    
-    for row_result in news_rows {
-        let (id, news, date, serpapi_id, image_id, serpapi_data_date) = row_result?;
-
-        // Query keywords from serpapi_data if serpapi_id exists
-        let keywords = if let Some(serpapi_id) = serpapi_id {
-            let mut keyword_stmt = conn.prepare(
-                "SELECT query FROM serpapi_data WHERE id = ?1"
-            )?;
-            keyword_stmt.query_row([serpapi_id], |row| {
-                let query: Option<String> = row.get(0)?;
-                Ok(query)
-            }).unwrap_or(None)
-        } else {
-            None
-        };
-
-        // Query image file_name from image_data if image_id exists
-        let image = if let Some(image_id) = image_id {
-            let mut image_stmt = conn.prepare(
-                "SELECT file_name FROM image_data WHERE id = ?1"
-            )?;
-            let file_name: Option<String> = image_stmt.query_row([image_id], |row| row.get(0)).unwrap_or(None);
-            let url = file_name.as_ref().map(|fname| {
-                let tokens: Vec<&str> = fname.split('_').collect();
-                if tokens.len() > 1 {
-                    let date_str = tokens[1];
-                    // Convert yyyymmdd to yyyy/mm/dd
-                    if date_str.len() == 8 {
-                        let year = &date_str[0..4];
-                        let month = &date_str[4..6];
-                        let day = &date_str[6..8];
-                        format!("{}/images/{}/{}/{}/{}", DOMAIN_API, year, month, day, fname)
-                    } else {
-                        // Fallback for unexpected format
-                        format!("{}/images/{}/{}", DOMAIN_API, date_str, fname)
-                    }
-                } else {
-                    format!("{}/images/{}", DOMAIN_API, fname)
-                }
-            });
-            Some(ImageInfo { file_name, url })
-        } else {
-            None
-        };
-
-        // Query categories from serpapi_data if serpapi_id exists
-        let tag = if let Some(serpapi_id) = serpapi_id {
-            let mut cat_stmt = conn.prepare(
-                "SELECT categories FROM serpapi_data WHERE id = ?1"
-            )?;
-            let categories: Option<String> = cat_stmt.query_row([serpapi_id], |row| row.get(0)).unwrap_or(None);
-            if let Some(cat_str) = categories {
-                if cat_str.trim().is_empty() {
-                    Vec::new()
-                } else {
-                    let mut seen = std::collections::HashSet::new();
-                    cat_str.split('|')
-                        .filter_map(|token| {
-                            let parts: Vec<&str> = token.splitn(2, '-').collect();
-                            if parts.len() == 2 {
-                                let val = parts[1].trim();
-                                if !val.is_empty() && seen.insert(val.to_string()) {
-                                    Some(val.to_string())
-                                } else {
-                                    None
-                                }
-                            } else {
-                                None
-                            }
-                        })
-                        .collect::<Vec<String>>()
-                }
-            } else {
-                Vec::new()
-            }
-        } else {
-            Vec::new()
-        };
-
-        records.push(NewsRecord {
-            id,
-            news,
-            date,
-            serpapi_id,
-            image_id,
-            serpapi_data_date,
-            keywords,
-            image,
-            tag,
-        });
-    }
-    
-    Ok(LatestResponse {
-        date: latest_day,
-        records,
-    })
-}
-
-fn query_news_by_date(target_date: &str) -> SqlResult<LatestResponse> {
-    let db_path = "trends-story/trends_data.db";
-    
-    if !Path::new(db_path).exists() {
-        return Err(rusqlite::Error::SqliteFailure(
-            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_CANTOPEN),
-            Some("Database file not found".to_string())
-        ));
-    }
-
-    let conn = Connection::open(db_path)?;
-    
-    // Query all records from the specified date
-let mut stmt = conn.prepare(
-    "SELECT main_news_data.id, main_news_data.news, main_news_data.date, \
-     main_news_data.serpapi_id, main_news_data.image_id, \
-     serpapi_data.date AS serpapi_data_date \
-     FROM main_news_data \
-     LEFT JOIN serpapi_data \
-     ON main_news_data.serpapi_id = serpapi_data.id \
-     WHERE substr(main_news_data.date, 1, 10) = ?1 \
-     ORDER BY main_news_data.id ASC"
-)?;
-
-    let news_rows = stmt.query_map([target_date], |row| {
-        Ok((
-            row.get::<_, i64>(0)?,      // id
-            row.get::<_, Option<String>>(1)?,  // news
-            row.get::<_, Option<String>>(2)?,  // date
-            row.get::<_, Option<i64>>(3)?,     // serpapi_id
-            row.get::<_, Option<i64>>(4)?,     // image_id
-            row.get::<_, Option<String>>(5)?,  // serpapi_data_date
-        ))
-    })?;
-    
-    let mut records = Vec::new();
-    
-    for row_result in news_rows {
-        let (id, news, date, serpapi_id, image_id, serpapi_data_date) = row_result?;
-
-        // Query keywords from serpapi_data if serpapi_id exists
-        let keywords = if let Some(serpapi_id) = serpapi_id {
-            let mut keyword_stmt = conn.prepare(
-                "SELECT query FROM serpapi_data WHERE id = ?1"
-            )?;
-            keyword_stmt.query_row([serpapi_id], |row| {
-                let query: Option<String> = row.get(0)?;
-                Ok(query)
-            }).unwrap_or(None)
-        } else {
-            None
-        };
-
-        // Query image file_name from image_data if image_id exists
-        let image = if let Some(image_id) = image_id {
-            let mut image_stmt = conn.prepare(
-                "SELECT file_name FROM image_data WHERE id = ?1"
-            )?;
-            let file_name: Option<String> = image_stmt.query_row([image_id], |row| row.get(0)).unwrap_or(None);
-            let url = file_name.as_ref().map(|fname| {
-                let tokens: Vec<&str> = fname.split('_').collect();
-                if tokens.len() > 1 {
-                    let date_str = tokens[1];
-                    // Convert yyyymmdd to yyyy/mm/dd
-                    if date_str.len() == 8 {
-                        let year = &date_str[0..4];
-                        let month = &date_str[4..6];
-                        let day = &date_str[6..8];
-                        format!("{}/images/{}/{}/{}/{}", DOMAIN_API, year, month, day, fname)
-                    } else {
-                        // Fallback for unexpected format
-                        format!("{}/images/{}/{}", DOMAIN_API, date_str, fname)
-                    }
-                } else {
-                    format!("{}/images/{}", DOMAIN_API, fname)
-                }
-            });
-            Some(ImageInfo { file_name, url })
-        } else {
-            None
-        };
-
-        // Query categories from serpapi_data if serpapi_id exists
-        let tag = if let Some(serpapi_id) = serpapi_id {
-            let mut cat_stmt = conn.prepare(
-                "SELECT categories FROM serpapi_data WHERE id = ?1"
-            )?;
-            let categories: Option<String> = cat_stmt.query_row([serpapi_id], |row| row.get(0)).unwrap_or(None);
-            if let Some(cat_str) = categories {
-                if cat_str.trim().is_empty() {
-                    Vec::new()
-                } else {
-                    let mut seen = std::collections::HashSet::new();
-                    cat_str.split('|')
-                        .filter_map(|token| {
-                            let parts: Vec<&str> = token.splitn(2, '-').collect();
-                            if parts.len() == 2 {
-                                let val = parts[1].trim();
-                                if !val.is_empty() && seen.insert(val.to_string()) {
-                                    Some(val.to_string())
-                                } else {
-                                    None
-                                }
-                            } else {
-                                None
-                            }
-                        })
-                        .collect::<Vec<String>>()
-                }
-            } else {
-                Vec::new()
-            }
-        } else {
-            Vec::new()
-        };
-
-        records.push(NewsRecord {
-            id,
-            news,
-            date,
-            serpapi_id,
-            image_id,
-            serpapi_data_date,
-            keywords,
-            image,
-            tag,
-        });
-    }
-    
-    Ok(LatestResponse {
-        date: Some(target_date.to_string()),
-        records,
-    })
-}
-
-#[derive(Debug)]
-struct DatabaseError;
-
-impl warp::reject::Reject for DatabaseError {}
-
-#[derive(Debug)]
-struct InvalidDateFormat;
-
-impl warp::reject::Reject for InvalidDateFormat {}
-
-#[derive(Debug)]
-struct NoDataFound;
-
-impl warp::reject::Reject for NoDataFound {}
+use trend_story_api::{build_routes, init_logging, init_reporting, log_panics, spawn_sync, spawn_watcher, sync_once, AppState, Config};
 
 #[tokio::main]
 async fn main() {
-    // Start periodic git sync task
-    tokio::spawn(async move {
-        use std::process::Command;
-        use std::time::Duration;
-        loop {
-            // If repo doesn't exist, clone; else, pull
-            let repo_path = "./trends-story";
-            if !std::path::Path::new(repo_path).exists() {
-                let _ = Command::new("git")
-                    .args(["clone", "https://github.com/sudoghut/trends-story"])
-                    .status();
-            } else {
-                let _ = Command::new("git")
-                    .args(["-C", repo_path, "pull"])
-                    .status();
-            }
-            tokio::time::sleep(Duration::from_secs(SYNC_INTERVAL_MINUTES * 60)).await;
+    log_panics();
+    let config = Config::load();
+    init_logging(&config);
+    init_reporting(&config);
+    let states: Vec<AppState> = config.sources
+        .iter()
+        .map(|source| AppState::new(source.clone()))
+        .collect();
+
+    // Sync every source before accepting traffic, then keep syncing in the background. A source
+    // whose first sync fails is served as not ready (503) until a retry succeeds. With file watching,
+    // changes between syncs are taken in as well.
+    for (state, source) in states.iter().zip(&config.sources) {
+        let initial = state.clone();
+        let ready = tokio::task::spawn_blocking(move || sync_once(&initial)).await.unwrap_or(false);
+        if !ready {
+            eprintln!("Starting without data from {}; retrying in the background", source.origin());
         }
-    });
-    // CORS filter
-    let cors = warp::cors()
-        .allow_any_origin()
-        .allow_headers(vec!["content-type"])
-        .allow_methods(vec!["GET", "POST", "DELETE"]);
-
-    // Routes
-    let latest = warp::path("latest")
-        .and(warp::get())
-        .and_then(get_latest);
-
-    let dates = warp::path("dates")
-        .and(warp::get())
-        .and_then(get_dates);
-
-    let date = warp::path("date")
-        .and(warp::path::param::<String>())
-        .and(warp::get())
-        .and_then(get_date);
-
-    // Serve images from ./trends-story/images via /images route
-    let images = warp::path("images")
-        .and(warp::fs::dir("trends-story/images"));
+        spawn_sync(state.clone());
+        spawn_watcher(state.clone());
+    }
 
-    let routes = latest
-        .or(dates)
-        .or(date)
-        .or(images)
-        .with(cors)
-        .recover(handle_rejection);
+    let routes = build_routes(&config, &states);
 
     const PORT: u16 = 3003;
-    
+
     println!("Starting Trend Story API server on http://localhost:{}", PORT);
+    for source in &config.sources {
+        println!("Serving data from {} at /{}", source.db_path.display(), source.name.as_deref().unwrap_or(""));
+    }
+    if let Some(dir) = &config.static_dir {
+        println!("Serving frontend from {} at /", dir.display());
+    }
     println!("Available endpoints:");
     println!("  GET /latest - Get all news records from the latest date with keywords");
+    println!("  GET /changes?since=<id|time> - Get records added after a record id or time, with next_since for the next call");
+    println!("  GET /recent?limit=N - Get the newest N records across days, with ?cursor= for the next page");
     println!("  GET /dates - Get all available dates in yyyymmdd format");
-    println!("  GET /date/<yyyymmdd> - Get all news records from a specific date");
+    println!("  GET /dates/range - Get the first and last available dates with day and record counts");
+    println!("  GET /date/<yyyymmdd|yyyy-mm-dd> - Get all news records from a specific date");
+    println!("  GET /date/<yyyymm|yyyy-mm> - Get all news records from a month grouped by day");
+    println!("  GET /date/<yyyymmdd>/next, /prev - Redirect to the nearest later or earlier date with records");
+    println!("  GET /year/<yyyy> - Get a month-by-month overview of a year with per-day counts and top tags");
+    println!("  GET /week/<yyyyWww> - Get records of an ISO week grouped by day with top tags");
+    println!("    (list endpoints accept ?tag=, ?keyword= and ?has_image=true filters)");
+    println!("    (list endpoints accept ?fields=id,news,... to return only selected record fields)");
+    println!("    (list endpoints accept ?sort=id|date|keywords&order=asc|desc)");
+    println!("    (list endpoints accept ?dedupe=true to nest near-duplicate stories under one record)");
+    println!("    (list endpoints answer as JSON, NDJSON, CSV, XML, HTML or JSON:API by Accept header or ?format=)");
+    println!("    (/latest and /date/<yyyymmdd> accept ?limit=N and ?cursor=<next_cursor> for paging)");
+    println!("  GET /search?q=<words> - Search record texts and keywords, with matched terms highlighted");
+    println!("  GET /health - Get readiness, schema and sync validation status");
+    println!("  GET /meta - Get the data commit, last sync time and database checksum (also sent as X-Data-Version)");
+    println!("  GET /sync/status - Get the configured remote, branch and depth of the sync and how syncs are going");
+    println!("  GET /version - Get the build version and commit and the commit of the served data");
+    println!("  GET /metrics - Get SQL query timing histograms in the Prometheus text format");
+    println!("  GET /stats - Get aggregate statistics about the dataset");
+    println!("  GET /analytics/keywords?days=N - Get keywords ranked by frequency over the last N days");
+    println!("  GET /analytics/keyword/<term> - Get the per-day appearances of a keyword over the whole dataset");
+    println!("  GET /tags/mapping - Get the active tag normalization mapping");
+    println!("  GET /schema/<latest|news_record|date|error>.json - Get the JSON Schema of a response type");
+    println!("  GET /tags/counts?days=N - Get tags ranked by records over the last N days with their rank change");
+    println!("  GET /tags/<tag>/related - Get tags that most often appear together with a tag");
+    println!("  GET /threads/<id> - Get the appearances over consecutive days of a story by its thread_id");
+    println!("  GET /news/<id>/related - Get records from any date sharing keywords or tags with a record");
+    println!("  GET /news/<id>/similar - Get the records closest in meaning to a record (with the embeddings feature)");
+    println!("  GET /serpapi/<id> - Get the raw serpapi_data row behind records' serpapi_id");
+    println!("  GET /onthisday/<mmdd> - Get records from the same month and day across all years");
+    println!("  GET /export/all[?format=json|csv] - Download every record as a streamed file");
     println!("  GET /images/* - Serve images from trends-story/images");
+    println!("  GET /images/meta?date=<yyyymmdd> - List the image rows of a day's records and the records missing one");
+    println!("  GET /thumbnails/<width>/* - Serve prebuilt WebP thumbnails of the images");
+    println!("  GET /robots.txt, /favicon.ico - Serve the crawler rules and the site icon");
+    println!("  POST /admin/cache/purge[?date=yyyymmdd] - Drop cached responses (requires the admin token)");
+    println!("  POST /admin/sync - Pull the data repository now and report the sync (requires the admin token)");
+    println!("  GET /admin/sync/log - List recent sync attempts (requires the admin token)");
+    println!("  GET /admin/backup - Download a snapshot of the SQLite file (requires the admin token)");
+    println!("  POST /admin/news - Add a record from a JSON body (requires the admin token)");
+    println!("  PATCH /admin/news/<id> - Replace the text, keywords or tags of a record (requires the admin token)");
+    println!("  DELETE /admin/news/<id> - Hide a record from all endpoints (requires the admin token)");
+    println!("  GET /admin/keys/<id>/usage - Get an API key's limits and daily requests (requires the admin token)");
+    println!("  (every endpoint is also available under /<source>/... for each extra source)");
 
     warp::serve(routes)
         .run(([127, 0, 0, 1], PORT))
         .await;
 }
-
-async fn handle_rejection(err: warp::Rejection) -> Result<impl warp::Reply, std::convert::Infallible> {
-    let code;
-    let message;
-
-    if err.is_not_found() {
-        code = warp::http::StatusCode::NOT_FOUND;
-        message = "Not Found";
-    } else if let Some(_) = err.find::<InvalidDateFormat>() {
-        code = warp::http::StatusCode::BAD_REQUEST;
-        message = "Invalid date format. Expected 8 digits (yyyymmdd)";
-    } else if let Some(_) = err.find::<NoDataFound>() {
-        code = warp::http::StatusCode::NOT_FOUND;
-        message = "No data found for the requested date";
-    } else if let Some(_) = err.find::<DatabaseError>() {
-        code = warp::http::StatusCode::INTERNAL_SERVER_ERROR;
-        message = "Database Error";
-    } else {
-        eprintln!("unhandled rejection: {:?}", err);
-        code = warp::http::StatusCode::INTERNAL_SERVER_ERROR;
-        message = "Internal Server Error";
-    }
-
-    let json = warp::reply::json(&serde_json::json!({
-        "error": message,
-        "code": code.as_u16()
-    }));
-
-    Ok(warp::reply::with_status(json, code))
-}
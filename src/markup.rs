@@ -0,0 +1,126 @@
+use std::borrow::Cow;
+
+// Elements whose content isn't text at all
+const DROPPED_ELEMENTS: [&str; 2] = ["script", "style"];
+// Elements that end a line or paragraph, so the text keeps its breaks without them
+const BREAKING_ELEMENTS: [&str; 12] = ["br", "p", "div", "li", "tr", "h1", "h2", "h3", "h4", "h5", "h6", "blockquote"];
+
+// The text of news that came with HTML from upstream: tags and comments removed (block elements
+// leaving a line break, script and style with their content) and entities decoded. Text without
+// markup is returned as is, and a '<' that doesn't start a tag ("a < b") stays.
+pub(crate) fn strip_html(text: &str) -> Cow<'_, str> {
+    if !text.contains(['<', '&']) {
+        return Cow::Borrowed(text);
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(['<', '&']) {
+        let (before, from) = rest.split_at(start);
+        out.push_str(before);
+        if let Some(after) = from.strip_prefix('&') {
+            match decode_entity(from) {
+                Some((decoded, length)) => {
+                    out.push(decoded);
+                    rest = &from[length..];
+                }
+                None => {
+                    out.push('&');
+                    rest = after;
+                }
+            }
+            continue;
+        }
+        match tag(from) {
+            Some((name, closing, length)) => {
+                rest = &from[length..];
+                if !closing && DROPPED_ELEMENTS.contains(&name.as_str()) {
+                    // Up to the closing tag, or everything when there is none
+                    let end = format!("</{}", name);
+                    rest = match rest.to_ascii_lowercase().find(&end) {
+                        Some(at) => rest[at..].find('>').map_or("", |close| &rest[at + close + 1..]),
+                        None => "",
+                    };
+                } else if BREAKING_ELEMENTS.contains(&name.as_str()) && !out.is_empty() && !out.ends_with("\n\n") {
+                    out.push('\n');
+                }
+            }
+            None => {
+                out.push('<');
+                rest = &from[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    Cow::Owned(out.trim().to_string())
+}
+
+// A tag or comment at the start of `from`: its lowercased element name (empty for comments and
+// declarations), whether it closes an element, and its length in bytes
+fn tag(from: &str) -> Option<(String, bool, usize)> {
+    if let Some(comment) = from.strip_prefix("<!--") {
+        let end = comment.find("-->")?;
+        return Some((String::new(), false, 4 + end + 3));
+    }
+    let inner = &from[1..];
+    let first = inner.chars().next()?;
+    if !(first.is_ascii_alphabetic() || matches!(first, '/' | '!' | '?')) {
+        return None;
+    }
+    // A tag ends at the first '>' outside quoted attribute values; a new '<' first means it wasn't one
+    let mut quote = None;
+    let mut end = None;
+    for (i, c) in inner.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') if i > 0 => quote = Some(c),
+            (None, '>') => {
+                end = Some(i);
+                break;
+            }
+            (None, '<') => return None,
+            _ => {}
+        }
+    }
+    let end = end?;
+    let closing = first == '/';
+    let name: String = inner[usize::from(closing)..end]
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    if name.is_empty() && matches!(first, '/') {
+        return None;
+    }
+    Some((name, closing, 1 + end + 1))
+}
+
+// A character reference at the start of `from` (&amp;, &#39;, &#x27;) and its length in bytes
+fn decode_entity(from: &str) -> Option<(char, usize)> {
+    let end = from[1..].find(';').filter(|end| *end <= 10)? + 1;
+    let name = &from[1..end];
+    let decoded = match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => ' ',
+        "ndash" => '–',
+        "mdash" => '—',
+        "hellip" => '…',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        _ => {
+            let code = name.strip_prefix('#')?;
+            let value = match code.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => code.parse::<u32>().ok()?,
+            };
+            char::from_u32(value)?
+        }
+    };
+    Some((decoded, end + 1))
+}
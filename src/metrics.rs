@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Upper bounds in seconds of the query duration buckets, from index lookups to full scans
+const QUERY_BUCKETS: [f64; 12] = [0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+
+#[derive(Debug, Default)]
+struct Histogram {
+    // Observations per bucket (not cumulative), the last one for those above every bound
+    buckets: [u64; QUERY_BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        let bucket = QUERY_BUCKETS.iter().position(|bound| seconds <= *bound).unwrap_or(QUERY_BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+// Durations of every query by label, shared by all sources: per endpoint ("latest", "by_date",
+// ...) around the whole database work, and per lookup inside it ("keywords", "image", ...)
+static QUERY_DURATIONS: Mutex<BTreeMap<&'static str, Histogram>> = Mutex::new(BTreeMap::new());
+
+pub(crate) fn observe_query(label: &'static str, elapsed: Duration) {
+    let mut durations = QUERY_DURATIONS.lock().unwrap_or_else(|e| e.into_inner());
+    durations.entry(label).or_default().observe(elapsed.as_secs_f64());
+}
+
+// Run a query (prepare and execute) and record how long it took under `label`
+pub(crate) fn time_query<T>(label: &'static str, query: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = query();
+    observe_query(label, started.elapsed());
+    result
+}
+
+// Sync counters of one source since the server started
+#[derive(Debug, Default)]
+struct SyncStats {
+    attempts: u64,
+    failures: u64,
+    // Unix time of the last sync without an error
+    last_success: Option<f64>,
+    bytes_fetched: u64,
+    rows_added: u64,
+    // Fetched commits the trust policy refused
+    unverified_commits: u64,
+}
+
+// By source name ("default" for the unnamed source)
+static SYNC_STATS: Mutex<BTreeMap<String, SyncStats>> = Mutex::new(BTreeMap::new());
+
+pub(crate) fn observe_sync(source: &str, succeeded: bool, bytes_fetched: u64, rows_added: u64) {
+    let mut stats = SYNC_STATS.lock().unwrap_or_else(|e| e.into_inner());
+    let stats = stats.entry(source.to_string()).or_default();
+    stats.attempts += 1;
+    if succeeded {
+        stats.last_success = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()
+            .map(|since| since.as_secs_f64());
+    } else {
+        stats.failures += 1;
+    }
+    stats.bytes_fetched += bytes_fetched;
+    stats.rows_added += rows_added;
+}
+
+pub(crate) fn observe_unverified_commit(source: &str) {
+    let mut stats = SYNC_STATS.lock().unwrap_or_else(|e| e.into_inner());
+    stats.entry(source.to_string()).or_default().unverified_commits += 1;
+}
+
+// One sync metric for every source; sources without a value (no success yet) are left out
+fn write_sync_metric(
+    out: &mut String,
+    stats: &BTreeMap<String, SyncStats>,
+    (name, kind, help): (&str, &str, &str),
+    value: impl Fn(&SyncStats) -> Option<f64>,
+) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    for (source, stats) in stats {
+        if let Some(value) = value(stats) {
+            let _ = writeln!(out, "{}{{source=\"{}\"}} {}", name, source, value);
+        }
+    }
+}
+
+fn render_sync_metrics(out: &mut String) {
+    let stats = SYNC_STATS.lock().unwrap_or_else(|e| e.into_inner());
+    write_sync_metric(out, &stats, ("trend_story_sync_attempts_total", "counter", "Sync attempts (clone or pull)."), |s| {
+        Some(s.attempts as f64)
+    });
+    write_sync_metric(
+        out,
+        &stats,
+        ("trend_story_sync_failures_total", "counter", "Sync attempts that ended with an error."),
+        |s| Some(s.failures as f64),
+    );
+    write_sync_metric(
+        out,
+        &stats,
+        ("trend_story_sync_last_success_timestamp_seconds", "gauge", "Unix time of the last sync without an error."),
+        |s| s.last_success,
+    );
+    write_sync_metric(
+        out,
+        &stats,
+        ("trend_story_sync_fetched_bytes_total", "counter", "Growth of the git object store from syncs."),
+        |s| Some(s.bytes_fetched as f64),
+    );
+    write_sync_metric(
+        out,
+        &stats,
+        ("trend_story_sync_new_rows_total", "counter", "Records that syncs added to the database."),
+        |s| Some(s.rows_added as f64),
+    );
+    write_sync_metric(
+        out,
+        &stats,
+        ("trend_story_sync_unverified_commits_total", "counter", "Fetched commits refused by the trust policy."),
+        |s| Some(s.unverified_commits as f64),
+    );
+}
+
+// The metrics in the Prometheus text format, for GET /metrics
+pub(crate) fn render_metrics() -> String {
+    let mut out = String::new();
+    render_sync_metrics(&mut out);
+    out.push_str("# HELP trend_story_db_query_duration_seconds Time spent preparing and running SQL queries.\n");
+    out.push_str("# TYPE trend_story_db_query_duration_seconds histogram\n");
+    let durations = QUERY_DURATIONS.lock().unwrap_or_else(|e| e.into_inner());
+    for (label, histogram) in durations.iter() {
+        let mut cumulative = 0;
+        for (bound, count) in QUERY_BUCKETS.iter().zip(&histogram.buckets) {
+            cumulative += count;
+            let _ = writeln!(
+                out, "trend_story_db_query_duration_seconds_bucket{{query=\"{}\",le=\"{}\"}} {}", label, bound, cumulative
+            );
+        }
+        let _ = writeln!(
+            out, "trend_story_db_query_duration_seconds_bucket{{query=\"{}\",le=\"+Inf\"}} {}", label, histogram.count
+        );
+        let _ = writeln!(out, "trend_story_db_query_duration_seconds_sum{{query=\"{}\"}} {}", label, histogram.sum);
+        let _ = writeln!(out, "trend_story_db_query_duration_seconds_count{{query=\"{}\"}} {}", label, histogram.count);
+    }
+    out
+}
@@ -0,0 +1,11 @@
+// The types responses are serialized from, public so Rust clients can deserialize the API's JSON
+// (the default shape: ?api_version=1, ?case=snake) without copying their definitions
+pub use crate::db::{
+    ChangesResponse, DateRangeResponse, DateResponse, DateSpan, DayCount, DayLinks, DayRecords, ImageEntry, ImageInfo,
+    ImageListResponse, KeywordAnalyticsResponse, KeywordSeriesResponse, KeywordTrend, LatestResponse, MonthResponse,
+    NewsRecord, OnThisDayResponse, RecentResponse, RelatedNewsResponse, RelatedTagsResponse, ScoredRecord, StatsResponse,
+    TagCount, TagCountsResponse, TagPopularity, WeekDayRecords, WeekResponse, YearMonthSummary, YearRecords, YearResponse,
+};
+pub use crate::embeddings::{SimilarNewsResponse, SimilarRecord};
+pub use crate::search::{Highlights, SearchResponse, SearchResult};
+pub use crate::threads::ThreadResponse;
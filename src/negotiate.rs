@@ -0,0 +1,438 @@
+use std::collections::HashMap;
+use serde_json::Value;
+use warp::Filter;
+
+use crate::error::ApiError;
+use crate::export::csv_field;
+use crate::list::NEWS_RECORD_FIELDS;
+use crate::routes::body_response;
+use crate::AppState;
+
+// Representation of a record list response, picked by ?format= or else the Accept header.
+// JSON is the response itself; the other formats carry the records found in its "records"
+// arrays (XML renders the whole response, JSON:API keeps its other values as meta).
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) enum ResponseFormat {
+    #[default]
+    Json,
+    Ndjson,
+    Csv,
+    Xml,
+    Html,
+    JsonApi,
+}
+
+// Accept media types and ?format= names, in the order ties are broken
+const MEDIA_TYPES: [(&str, &str, ResponseFormat); 8] = [
+    ("application/json", "json", ResponseFormat::Json),
+    ("application/vnd.api+json", "jsonapi", ResponseFormat::JsonApi),
+    ("application/x-ndjson", "ndjson", ResponseFormat::Ndjson),
+    ("application/ndjson", "ndjson", ResponseFormat::Ndjson),
+    ("text/csv", "csv", ResponseFormat::Csv),
+    ("application/xml", "xml", ResponseFormat::Xml),
+    ("text/xml", "xml", ResponseFormat::Xml),
+    ("text/html", "html", ResponseFormat::Html),
+];
+
+impl ResponseFormat {
+    fn from_name(name: &str) -> Option<ResponseFormat> {
+        MEDIA_TYPES.iter().find(|(_, n, _)| *n == name).map(|(_, _, format)| *format)
+    }
+
+    // Best supported type of an Accept header by q-value; None when it accepts none of them
+    fn from_accept(accept: &str) -> Option<ResponseFormat> {
+        let mut best: Option<(f32, ResponseFormat)> = None;
+        for range in accept.split(',') {
+            let mut parts = range.split(';');
+            let media_type = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+            let quality = parts
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            let format = match media_type.as_str() {
+                "*/*" | "application/*" => Some(ResponseFormat::Json),
+                _ => MEDIA_TYPES.iter().find(|(t, _, _)| *t == media_type).map(|(_, _, format)| *format),
+            };
+            if let Some(format) = format {
+                if quality > 0.0 && best.is_none_or(|(q, _)| quality > q) {
+                    best = Some((quality, format));
+                }
+            }
+        }
+        best.map(|(_, format)| format)
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            ResponseFormat::Json => "application/json",
+            ResponseFormat::Ndjson => "application/x-ndjson",
+            ResponseFormat::Csv => "text/csv; charset=utf-8",
+            ResponseFormat::Xml => "application/xml; charset=utf-8",
+            ResponseFormat::Html => "text/html; charset=utf-8",
+            ResponseFormat::JsonApi => "application/vnd.api+json",
+        }
+    }
+
+    // Serialize a response in this format
+    pub(crate) fn render(self, state: &AppState, value: &Value) -> warp::reply::Response {
+        let body = match self {
+            ResponseFormat::Json => serde_json::to_vec(value).unwrap_or_default(),
+            ResponseFormat::Ndjson => ndjson(value),
+            ResponseFormat::Csv => csv(value),
+            ResponseFormat::Xml => xml(value),
+            ResponseFormat::Html => html(value),
+            ResponseFormat::JsonApi => serde_json::to_vec(&json_api(value)).unwrap_or_default(),
+        };
+        let mut response = body_response(state, self.content_type(), body);
+        response.headers_mut().insert(
+            warp::http::header::VARY,
+            warp::http::HeaderValue::from_static("Accept"),
+        );
+        response
+    }
+}
+
+// Extracts the requested format: ?format= wins over Accept, a missing or empty Accept means JSON
+pub(crate) fn negotiate() -> impl Filter<Extract = (ResponseFormat,), Error = warp::Rejection> + Clone {
+    warp::query::<HashMap<String, String>>()
+        .and(warp::header::optional::<String>("accept"))
+        .and_then(|params: HashMap<String, String>, accept: Option<String>| async move {
+            let format = match (params.get("format"), accept.as_deref().map(str::trim)) {
+                (Some(name), _) => ResponseFormat::from_name(name).ok_or(ApiError::InvalidQueryParameter("format")),
+                (None, None) | (None, Some("")) => Ok(ResponseFormat::Json),
+                (None, Some(accept)) => ResponseFormat::from_accept(accept).ok_or(ApiError::NotAcceptable),
+            };
+            format.map_err(warp::Rejection::from)
+        })
+}
+
+// Entries of every "records" array in the response, in order
+fn records(value: &Value) -> Vec<&Value> {
+    fn collect<'a>(value: &'a Value, out: &mut Vec<&'a Value>) {
+        match value {
+            Value::Object(map) => {
+                for (key, child) in map {
+                    match child {
+                        Value::Array(records) if key == "records" => out.extend(records),
+                        _ => collect(child, out),
+                    }
+                }
+            }
+            Value::Array(items) => items.iter().for_each(|item| collect(item, out)),
+            _ => {}
+        }
+    }
+    let mut out = Vec::new();
+    collect(value, &mut out);
+    out
+}
+
+// Keys of an object, record fields in NewsRecord order (serde_json maps are sorted by key)
+fn ordered_keys(map: &serde_json::Map<String, Value>) -> Vec<&String> {
+    let mut keys: Vec<&String> = map.keys().collect();
+    keys.sort_by_key(|key| NEWS_RECORD_FIELDS.iter().position(|field| field == key).unwrap_or(usize::MAX));
+    keys
+}
+
+fn scalar_text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+// A record as flat (column, text) pairs: nested objects become prefix_key columns (image_url)
+// and lists are joined with '|' (tag), as in the CSV export; lists of records (duplicates)
+// become their ids
+fn flatten(record: &Value) -> Vec<(String, String)> {
+    fn walk(prefix: &str, value: &Value, out: &mut Vec<(String, String)>) {
+        match value {
+            Value::Object(map) => {
+                for key in ordered_keys(map) {
+                    let column = if prefix.is_empty() { key.clone() } else { format!("{}_{}", prefix, key) };
+                    walk(&column, &map[key], out);
+                }
+            }
+            Value::Array(items) => {
+                let texts: Vec<String> = items
+                    .iter()
+                    .map(|item| scalar_text(item.get("id").unwrap_or(item)))
+                    .collect();
+                out.push((prefix.to_string(), texts.join("|")));
+            }
+            scalar => out.push((prefix.to_string(), scalar_text(scalar))),
+        }
+    }
+    let mut out = Vec::new();
+    walk("", record, &mut out);
+    out
+}
+
+// Column names in record field order, and each record's values in that order
+fn table(value: &Value) -> (Vec<String>, Vec<Vec<String>>) {
+    let flat: Vec<Vec<(String, String)>> = records(value).into_iter().map(flatten).collect();
+    let mut columns: Vec<String> = Vec::new();
+    for (column, _) in flat.iter().flatten() {
+        if !columns.contains(column) {
+            columns.push(column.clone());
+        }
+    }
+    // A record without image leaves a bare, empty "image" column next to the image_... ones
+    let is_empty = |column: &String| flat.iter().flatten().all(|(c, v)| c != column || v.is_empty());
+    let nested: Vec<String> = columns
+        .iter()
+        .filter(|column| is_empty(column) && columns.iter().any(|other| other.starts_with(&format!("{}_", column))))
+        .cloned()
+        .collect();
+    columns.retain(|column| !nested.contains(column));
+    columns.sort_by_key(|column| {
+        NEWS_RECORD_FIELDS
+            .iter()
+            .position(|field| column == field || column.starts_with(&format!("{}_", field)))
+            .unwrap_or(usize::MAX)
+    });
+
+    let rows = flat
+        .into_iter()
+        .map(|fields| {
+            columns
+                .iter()
+                .map(|column| fields.iter().find(|(c, _)| c == column).map(|(_, v)| v.clone()).unwrap_or_default())
+                .collect()
+        })
+        .collect();
+    (columns, rows)
+}
+
+// A record as a JSON:API "news" resource. Its image and serpapi data become related resources,
+// added to `included` once each, as do the records it stands for with ?dedupe=true; search
+// highlights go to the resource's meta.
+fn news_resource(record: &Value, included: &mut Vec<Value>) -> Value {
+    use serde_json::{json, Map};
+
+    let Value::Object(fields) = record else {
+        return Value::Null;
+    };
+    let include = |resource: Value, included: &mut Vec<Value>| {
+        let key = |r: &Value| (r["type"].clone(), r["id"].clone());
+        if !included.iter().any(|existing| key(existing) == key(&resource)) {
+            included.push(resource);
+        }
+    };
+    let id_text = |id: &Value| match id {
+        Value::Null => Value::Null,
+        id => Value::String(scalar_text(id)),
+    };
+    let mut attributes = Map::new();
+    let mut relationships = Map::new();
+    let mut meta = Map::new();
+    let mut serpapi = Map::new();
+    for (key, value) in fields {
+        match key.as_str() {
+            "id" | "image" | "image_id" | "serpapi_id" => {}
+            "keywords" => {
+                serpapi.insert("query".to_string(), value.clone());
+            }
+            "serpapi_data_date" => {
+                serpapi.insert("date".to_string(), value.clone());
+            }
+            "tag" => {
+                serpapi.insert("categories".to_string(), value.clone());
+            }
+            "highlights" => {
+                meta.insert(key.clone(), value.clone());
+            }
+            "duplicates" => {
+                let duplicates: Vec<Value> = value.as_array().into_iter().flatten().map(|duplicate| {
+                    let resource = news_resource(duplicate, included);
+                    let identifier = json!({ "type": "news", "id": resource["id"] });
+                    include(resource, included);
+                    identifier
+                }).collect();
+                relationships.insert(key.clone(), json!({ "data": duplicates }));
+            }
+            _ => {
+                attributes.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    if let Some(image_id) = fields.get("image_id") {
+        let id = id_text(image_id);
+        if !id.is_null() {
+            let image = fields.get("image").cloned().unwrap_or_else(|| json!({}));
+            include(json!({ "type": "images", "id": id, "attributes": image }), included);
+            relationships.insert("image".to_string(), json!({ "data": { "type": "images", "id": id } }));
+        } else {
+            relationships.insert("image".to_string(), json!({ "data": null }));
+        }
+    }
+    if let Some(serpapi_id) = fields.get("serpapi_id") {
+        let id = id_text(serpapi_id);
+        if !id.is_null() {
+            include(json!({ "type": "serpapi_data", "id": id, "attributes": serpapi }), included);
+            relationships.insert("serpapi_data".to_string(), json!({ "data": { "type": "serpapi_data", "id": id } }));
+        } else {
+            relationships.insert("serpapi_data".to_string(), json!({ "data": null }));
+        }
+    } else {
+        // Without serpapi_id (?fields=) its values stay on the record
+        attributes.extend(serpapi);
+    }
+
+    let mut resource = json!({
+        "type": "news",
+        "id": id_text(fields.get("id").unwrap_or(&Value::Null)),
+        "attributes": attributes,
+    });
+    if !relationships.is_empty() {
+        resource["relationships"] = Value::Object(relationships);
+    }
+    if !meta.is_empty() {
+        resource["meta"] = Value::Object(meta);
+    }
+    resource
+}
+
+// The response as a JSON:API document: every record as primary data, the response's top-level
+// values (date, total_records, next_cursor, ...) as meta and its navigation as links
+fn json_api(value: &Value) -> Value {
+    use serde_json::{json, Map};
+
+    let mut included = Vec::new();
+    let data: Vec<Value> = records(value).into_iter().map(|record| news_resource(record, &mut included)).collect();
+    let mut document = json!({ "jsonapi": { "version": "1.1" }, "data": data });
+    if !included.is_empty() {
+        document["included"] = Value::Array(included);
+    }
+    if let Value::Object(map) = value {
+        let meta: Map<String, Value> = map
+            .iter()
+            .filter(|(key, child)| !child.is_array() && !child.is_object() && !key.is_empty())
+            .map(|(key, child)| (key.clone(), child.clone()))
+            .collect();
+        if !meta.is_empty() {
+            document["meta"] = Value::Object(meta);
+        }
+        if let Some(links) = map.get("links").filter(|links| links.is_object()) {
+            document["links"] = links.clone();
+        }
+    }
+    document
+}
+
+fn ndjson(value: &Value) -> Vec<u8> {
+    let mut out = Vec::new();
+    for record in records(value) {
+        out.extend_from_slice(&serde_json::to_vec(record).unwrap_or_default());
+        out.push(b'\n');
+    }
+    out
+}
+
+fn csv(value: &Value) -> Vec<u8> {
+    let (columns, rows) = table(value);
+    let mut out = Vec::new();
+    for row in std::iter::once(&columns).chain(&rows) {
+        for (i, field) in row.iter().enumerate() {
+            if i > 0 {
+                out.push(b',');
+            }
+            csv_field(&mut out, field);
+        }
+        out.extend_from_slice(b"\r\n");
+    }
+    out
+}
+
+// Escape text for XML and HTML element content and attribute values
+fn escape_markup(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+// Element name for a JSON key, with characters XML names can't hold replaced
+fn element_name(key: &str) -> String {
+    let name: String = key.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' }).collect();
+    if name.is_empty() || !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+fn xml_element(out: &mut String, name: &str, value: &Value) {
+    match value {
+        Value::Null => out.push_str(&format!("<{}/>", name)),
+        Value::Object(map) => {
+            out.push_str(&format!("<{}>", name));
+            for key in ordered_keys(map) {
+                xml_element(out, &element_name(key), &map[key]);
+            }
+            out.push_str(&format!("</{}>", name));
+        }
+        // Items are named after the singular of their list, e.g. <records><record>
+        Value::Array(items) => {
+            let item = match name.strip_suffix('s') {
+                Some(singular) if !singular.is_empty() => singular,
+                _ => "item",
+            };
+            out.push_str(&format!("<{}>", name));
+            for child in items {
+                xml_element(out, item, child);
+            }
+            out.push_str(&format!("</{}>", name));
+        }
+        scalar => out.push_str(&format!("<{0}>{1}</{0}>", name, escape_markup(&scalar_text(scalar)))),
+    }
+}
+
+fn xml(value: &Value) -> Vec<u8> {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml_element(&mut out, "response", value);
+    out.push('\n');
+    out.into_bytes()
+}
+
+// A plain page for browsing: the response's top-level values, then a table of its records
+fn html(value: &Value) -> Vec<u8> {
+    let mut out = String::from(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Trend Story records</title></head><body>\n"
+    );
+    if let Value::Object(map) = value {
+        out.push_str("<dl>");
+        for (key, child) in map {
+            if !child.is_array() && !child.is_object() {
+                out.push_str(&format!(
+                    "<dt>{}</dt><dd>{}</dd>", escape_markup(key), escape_markup(&scalar_text(child))
+                ));
+            }
+        }
+        out.push_str("</dl>\n");
+    }
+    let (columns, rows) = table(value);
+    out.push_str("<table>\n<thead><tr>");
+    for column in &columns {
+        out.push_str(&format!("<th>{}</th>", escape_markup(column)));
+    }
+    out.push_str("</tr></thead>\n<tbody>\n");
+    for row in &rows {
+        out.push_str("<tr>");
+        for field in row {
+            out.push_str(&format!("<td>{}</td>", escape_markup(field)));
+        }
+        out.push_str("</tr>\n");
+    }
+    out.push_str("</tbody>\n</table>\n</body></html>\n");
+    out.into_bytes()
+}
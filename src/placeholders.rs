@@ -0,0 +1,220 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::process::Command;
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+
+// Images are sampled at this many pixels square (the hash doesn't keep the aspect ratio anyway)
+// and hashed with PLACEHOLDER_COMPONENTS horizontal and vertical components
+const SAMPLE_SIZE: usize = 32;
+const PLACEHOLDER_COMPONENTS: (usize, usize) = (4, 3);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Placeholder {
+    // Modification time (Unix seconds) of the image the hash was computed from
+    modified: u64,
+    blurhash: String,
+}
+
+// BlurHashes of a source's images by file name, computed after syncs and kept in
+// placeholders_path so a restart doesn't recompute them. None until that file has been read.
+#[derive(Debug, Default)]
+pub(crate) struct Placeholders {
+    entries: RwLock<Option<HashMap<String, Placeholder>>>,
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+impl Placeholders {
+    pub(crate) fn blurhash(&self, file_name: &str) -> Option<String> {
+        let entries = self.entries.read().ok()?;
+        entries.as_ref()?.get(file_name).map(|entry| entry.blurhash.clone())
+    }
+
+    // Read the saved hashes on first use; a missing or unreadable file starts empty. True if
+    // this loaded any.
+    fn load(&self, path: &Path) -> bool {
+        let Ok(mut entries) = self.entries.write() else {
+            return false;
+        };
+        if entries.is_some() {
+            return false;
+        }
+        let loaded = match std::fs::read(path) {
+            Ok(raw) => serde_json::from_slice(&raw).unwrap_or_else(|e| {
+                eprintln!("Ignoring placeholders in {}: {}", path.display(), e);
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        let any = !loaded.is_empty();
+        *entries = Some(loaded);
+        any
+    }
+
+    fn save(&self, path: &Path) {
+        let Ok(entries) = self.entries.read() else {
+            return;
+        };
+        let sorted: BTreeMap<&String, &Placeholder> = entries.iter().flatten().collect();
+        let written = serde_json::to_vec(&sorted)
+            .map_err(std::io::Error::other)
+            .and_then(|body| std::fs::write(path, body));
+        if let Err(e) = written {
+            eprintln!("Failed to save placeholders to {}: {}", path.display(), e);
+        }
+    }
+}
+
+// Hash the images that are new or changed since their hash and forget those that are gone; true
+// if anything changed, so responses are recomputed with the new hashes
+pub(crate) fn update_placeholders(
+    placeholders: &Placeholders,
+    path: &Path,
+    convert: &str,
+    images_dir: &Path,
+    originals: &[(std::path::PathBuf, SystemTime)],
+) -> bool {
+    let loaded = placeholders.load(path);
+    let mut stale = Vec::new();
+    let mut removed = false;
+    if let Ok(mut entries) = placeholders.entries.write() {
+        let entries = entries.get_or_insert_with(HashMap::new);
+        let mut present = std::collections::HashSet::new();
+        for (relative, modified) in originals {
+            let Some(name) = relative.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            present.insert(name.to_string());
+            if entries.get(name).is_none_or(|entry| entry.modified != unix_seconds(*modified)) {
+                stale.push((name.to_string(), images_dir.join(relative), unix_seconds(*modified)));
+            }
+        }
+        let before = entries.len();
+        entries.retain(|name, _| present.contains(name));
+        removed = entries.len() != before;
+    }
+    if stale.is_empty() && !removed {
+        return loaded;
+    }
+
+    let started = std::time::Instant::now();
+    let total = stale.len();
+    let hashed = std::sync::Mutex::new(Vec::new());
+    crate::thumbnails::run_workers(stale, |(name, original, modified)| match sample(convert, &original) {
+        Ok(pixels) => {
+            let blurhash = encode_blurhash(&pixels, SAMPLE_SIZE, SAMPLE_SIZE, PLACEHOLDER_COMPONENTS);
+            hashed.lock().unwrap_or_else(|e| e.into_inner()).push((name, Placeholder { modified, blurhash }));
+        }
+        Err(e) => eprintln!("Failed to compute placeholder of {}: {}", original.display(), e),
+    });
+    let hashed = hashed.into_inner().unwrap_or_else(|e| e.into_inner());
+    if total > 0 {
+        println!(
+            "Computed {} placeholders in {}ms ({} failed)",
+            hashed.len(),
+            started.elapsed().as_millis(),
+            total - hashed.len()
+        );
+    }
+    if let Ok(mut entries) = placeholders.entries.write() {
+        entries.get_or_insert_with(HashMap::new).extend(hashed);
+    }
+    placeholders.save(path);
+    true
+}
+
+// The image's first frame scaled to SAMPLE_SIZE square, as 8-bit RGB
+fn sample(convert: &str, original: &Path) -> std::io::Result<Vec<u8>> {
+    let output = Command::new(convert)
+        .arg(format!("{}[0]", original.display()))
+        .args(["-auto-orient", "-resize"])
+        .arg(format!("{}x{}!", SAMPLE_SIZE, SAMPLE_SIZE))
+        .args(["-depth", "8", "rgb:-"])
+        .output()?;
+    if !output.status.success() || output.stdout.len() != SAMPLE_SIZE * SAMPLE_SIZE * 3 {
+        return Err(std::io::Error::other(format!(
+            "{} failed ({}): {}",
+            convert,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output.stdout)
+}
+
+const BASE83: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+fn base83(value: u32, length: u32, out: &mut String) {
+    for i in 1..=length {
+        let digit = (value / 83u32.pow(length - i)) % 83;
+        out.push(BASE83[digit as usize] as char);
+    }
+}
+
+fn srgb_to_linear(value: u8) -> f64 {
+    let v = value as f64 / 255.0;
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f64) -> u32 {
+    let v = value.clamp(0.0, 1.0);
+    let srgb = if v <= 0.0031308 { v * 12.92 } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 };
+    (srgb * 255.0 + 0.5) as u32
+}
+
+fn sign_pow(value: f64, exp: f64) -> f64 {
+    value.abs().powf(exp).copysign(value)
+}
+
+// BlurHash (https://blurha.sh) of width x height RGB pixels with (x, y) components
+fn encode_blurhash(pixels: &[u8], width: usize, height: usize, (cx, cy): (usize, usize)) -> String {
+    use std::f64::consts::PI;
+
+    let mut factors = Vec::with_capacity(cx * cy);
+    for j in 0..cy {
+        for i in 0..cx {
+            let norm = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+            let mut sum = [0.0f64; 3];
+            for y in 0..height {
+                for x in 0..width {
+                    let basis = (PI * i as f64 * x as f64 / width as f64).cos()
+                        * (PI * j as f64 * y as f64 / height as f64).cos();
+                    let pixel = &pixels[(y * width + x) * 3..][..3];
+                    for (channel, value) in sum.iter_mut().zip(pixel) {
+                        *channel += basis * srgb_to_linear(*value);
+                    }
+                }
+            }
+            let scale = norm / (width * height) as f64;
+            factors.push(sum.map(|channel| channel * scale));
+        }
+    }
+
+    let mut hash = String::new();
+    base83(((cx - 1) + (cy - 1) * 9) as u32, 1, &mut hash);
+    let (dc, ac) = factors.split_first().expect("at least one component");
+    let max_value = if ac.is_empty() {
+        base83(0, 1, &mut hash);
+        1.0
+    } else {
+        let actual_max = ac.iter().flatten().fold(0.0f64, |max, v| max.max(v.abs()));
+        let quantised = (actual_max * 166.0 - 0.5).floor().clamp(0.0, 82.0) as u32;
+        base83(quantised, 1, &mut hash);
+        (quantised + 1) as f64 / 166.0
+    };
+    let dc_value = (linear_to_srgb(dc[0]) << 16) + (linear_to_srgb(dc[1]) << 8) + linear_to_srgb(dc[2]);
+    base83(dc_value, 4, &mut hash);
+    for component in ac {
+        let quant = |v: f64| (sign_pow(v / max_value, 0.5) * 9.0 + 9.5).floor().clamp(0.0, 18.0) as u32;
+        base83(quant(component[0]) * 19 * 19 + quant(component[1]) * 19 + quant(component[2]), 2, &mut hash);
+    }
+    hash
+}
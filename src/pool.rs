@@ -0,0 +1,77 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+use rusqlite::Connection;
+
+use crate::config::DB_POOL_IDLE_CONNECTIONS;
+
+// Read-only connections of a source kept between requests, so their setup (schema detection,
+// attached files, temp views) and their cached statements are reused. Each is tagged with the
+// fingerprint of the files it was opened for and handed out only while they still match it, so a
+// pull, an edit or a rebuilt overlay retires every connection opened before.
+#[derive(Default)]
+pub(crate) struct ConnectionPool {
+    idle: Mutex<Vec<(String, Connection)>>,
+}
+
+impl std::fmt::Debug for ConnectionPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let idle = self.idle.lock().map(|idle| idle.len()).unwrap_or_default();
+        f.debug_struct("ConnectionPool").field("idle", &idle).finish()
+    }
+}
+
+impl ConnectionPool {
+    // An idle connection opened for `fingerprint`; those opened for another one are closed
+    pub(crate) fn take(&self, fingerprint: &str) -> Option<Connection> {
+        let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        idle.retain(|(opened_for, _)| opened_for == fingerprint);
+        idle.pop().map(|(_, conn)| conn)
+    }
+
+    fn put_back(&self, fingerprint: String, conn: Connection) {
+        let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        if idle.len() < DB_POOL_IDLE_CONNECTIONS {
+            idle.push((fingerprint, conn));
+        }
+    }
+}
+
+// A connection checked out of a source's pool, returned to it when dropped. Connections of files
+// without a fingerprint (missing or unreadable metadata) aren't kept.
+pub(crate) struct PooledConnection {
+    conn: Option<Connection>,
+    fingerprint: Option<String>,
+    pool: Arc<ConnectionPool>,
+}
+
+impl PooledConnection {
+    pub(crate) fn new(conn: Connection, fingerprint: Option<String>, pool: Arc<ConnectionPool>) -> PooledConnection {
+        PooledConnection { conn: Some(conn), fingerprint, pool }
+    }
+}
+
+impl Deref for PooledConnection {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn.as_ref().expect("connection is present until dropped")
+    }
+}
+
+impl DerefMut for PooledConnection {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.conn.as_mut().expect("connection is present until dropped")
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        // A connection left inside a transaction (after an error) isn't reused
+        let (Some(conn), Some(fingerprint)) = (self.conn.take(), self.fingerprint.take()) else {
+            return;
+        };
+        if conn.is_autocommit() {
+            self.pool.put_back(fingerprint, conn);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+// Provenance of the data: commits fetched by a sync are checked against the signing keys and
+// committers trusted for the dataset before the checkout moves to them. Signatures are verified
+// by git (`git verify-commit`, with the keys in the server user's GnuPG keyring), and the
+// fingerprint it reports must be one of the trusted ones.
+
+use std::path::Path;
+use std::process::Command;
+
+#[derive(Debug, Clone, Default)]
+pub struct TrustPolicy {
+    // GnuPG fingerprints (or long key ids) a commit must be signed by, any of them; uppercase hex
+    pub signing_keys: Vec<String>,
+    // Committer emails a commit must come from, any of them; lowercase
+    pub committers: Vec<String>,
+}
+
+// Comma-separated fingerprints, spaces within one allowed ("ABCD 1234 ...")
+pub(crate) fn parse_fingerprints(raw: &str) -> Result<Vec<String>, String> {
+    raw.split(',')
+        .map(|entry| entry.split_whitespace().collect::<String>().to_uppercase())
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let valid = entry.len() >= 16 && entry.bytes().all(|b| b.is_ascii_hexdigit());
+            if valid { Ok(entry) } else { Err(format!("'{}' is not a key fingerprint", entry)) }
+        })
+        .collect()
+}
+
+pub(crate) fn parse_committers(raw: &str) -> Vec<String> {
+    raw.split(',').map(|email| email.trim().to_lowercase()).filter(|email| !email.is_empty()).collect()
+}
+
+fn git_output(repo_path: &Path, args: &[&str]) -> Result<std::process::Output, String> {
+    Command::new("git")
+        .arg("-C")
+        .arg(repo_path)
+        .args(args)
+        .output()
+        .map_err(|e| format!("cannot run git {}: {}", args[0], e))
+}
+
+impl TrustPolicy {
+    pub fn is_empty(&self) -> bool {
+        self.signing_keys.is_empty() && self.committers.is_empty()
+    }
+
+    // Check the commit `rev` names in a checkout; the reason it can't be trusted otherwise
+    pub(crate) fn verify(&self, repo_path: &Path, rev: &str) -> Result<(), String> {
+        let output = git_output(repo_path, &["rev-parse", "--verify", &format!("{}^{{commit}}", rev)])?;
+        if !output.status.success() {
+            return Err(format!("cannot resolve {}: {}", rev, String::from_utf8_lossy(&output.stderr).trim()));
+        }
+        let commit = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+        if !self.signing_keys.is_empty() {
+            // Status lines go to stderr; VALIDSIG carries the signing key's fingerprint and, last,
+            // its primary key's
+            let output = git_output(repo_path, &["verify-commit", "--raw", &commit])?;
+            let status = String::from_utf8_lossy(&output.stderr);
+            let trusted = output.status.success()
+                && status
+                    .lines()
+                    .filter_map(|line| line.strip_prefix("[GNUPG:] VALIDSIG "))
+                    .flat_map(|fields| {
+                        let fields: Vec<&str> = fields.split_whitespace().collect();
+                        [fields.first().copied(), fields.last().copied()]
+                    })
+                    .flatten()
+                    .any(|fingerprint| {
+                        let fingerprint = fingerprint.to_uppercase();
+                        self.signing_keys.iter().any(|key| fingerprint.ends_with(key.as_str()))
+                    });
+            if !trusted {
+                return Err(format!("commit {} is not signed by a trusted key", commit));
+            }
+        }
+
+        if !self.committers.is_empty() {
+            let output = git_output(repo_path, &["log", "-1", "--format=%ce", &commit])?;
+            let committer = String::from_utf8_lossy(&output.stdout).trim().to_lowercase();
+            if !output.status.success() || !self.committers.contains(&committer) {
+                return Err(format!("commit {} was committed by '{}', not a trusted committer", commit, committer));
+            }
+        }
+        Ok(())
+    }
+}
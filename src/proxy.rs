@@ -0,0 +1,124 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+use warp::http::HeaderMap;
+
+// Addresses of reverse proxies whose X-Forwarded-For / Forwarded headers are believed, as single
+// addresses or CIDR ranges. Defaults to loopback, as the server only listens on localhost.
+#[derive(Debug, Clone)]
+pub struct TrustedProxies {
+    ranges: Arc<Vec<(IpAddr, u8)>>,
+}
+
+impl Default for TrustedProxies {
+    fn default() -> TrustedProxies {
+        TrustedProxies::parse("127.0.0.1/8,::1").unwrap_or_else(|_| TrustedProxies::none())
+    }
+}
+
+impl TrustedProxies {
+    pub fn none() -> TrustedProxies {
+        TrustedProxies { ranges: Arc::new(Vec::new()) }
+    }
+
+    // Comma-separated addresses and ranges ("127.0.0.1, 10.0.0.0/8, fd00::/8"), or "none"
+    pub(crate) fn parse(raw: &str) -> Result<TrustedProxies, String> {
+        if raw.trim().eq_ignore_ascii_case("none") {
+            return Ok(TrustedProxies::none());
+        }
+        let mut ranges = Vec::new();
+        for entry in raw.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let (addr, prefix) = match entry.split_once('/') {
+                Some((addr, prefix)) => (addr, Some(prefix)),
+                None => (entry, None),
+            };
+            let addr: IpAddr = addr.parse().map_err(|_| format!("'{}' is not an IP address or range", entry))?;
+            let max = if addr.is_ipv4() { 32 } else { 128 };
+            let prefix = match prefix {
+                Some(prefix) => prefix.parse::<u8>().ok().filter(|p| *p <= max)
+                    .ok_or_else(|| format!("'{}' has an invalid prefix length", entry))?,
+                None => max,
+            };
+            ranges.push((addr, prefix));
+        }
+        Ok(TrustedProxies { ranges: Arc::new(ranges) })
+    }
+
+    pub(crate) fn trusts(&self, ip: IpAddr) -> bool {
+        let ip = canonical(ip);
+        self.ranges.iter().any(|(range, prefix)| match (canonical(*range), ip) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - *prefix as u32).unwrap_or(0);
+                u32::from(range) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(range), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - *prefix as u32).unwrap_or(0);
+                u128::from(range) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        })
+    }
+
+    // The client a request came from: the peer, or when the peer is a trusted proxy, the nearest
+    // untrusted hop of the forwarding chain. Forwarded is preferred over X-Forwarded-For. Hops
+    // that aren't addresses ("unknown", obfuscated names) end the walk at the proxy that added them.
+    pub(crate) fn client_ip(&self, peer: Option<IpAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        let mut client = peer?;
+        if !self.trusts(client) {
+            return Some(client);
+        }
+        let hops: Vec<&str> = if headers.contains_key("forwarded") {
+            header_values(headers, "forwarded")
+                .filter_map(|element| {
+                    element.split(';').find_map(|pair| {
+                        let (key, value) = pair.split_once('=')?;
+                        key.trim().eq_ignore_ascii_case("for").then(|| value.trim())
+                    })
+                })
+                .collect()
+        } else {
+            header_values(headers, "x-forwarded-for").collect()
+        };
+        for hop in hops.into_iter().rev() {
+            match parse_hop(hop) {
+                Some(ip) => {
+                    client = ip;
+                    if !self.trusts(ip) {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+        Some(client)
+    }
+}
+
+// Comma-separated entries of every occurrence of a header, in order
+fn header_values<'a>(headers: &'a HeaderMap, name: &str) -> impl Iterator<Item = &'a str> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+}
+
+// "1.2.3.4", "1.2.3.4:80", "2001:db8::1" or "[2001:db8::1]:80", quoted or not
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    let hop = hop.trim_matches('"');
+    if let Ok(ip) = hop.parse() {
+        return Some(ip);
+    }
+    if let Some(rest) = hop.strip_prefix('[') {
+        return rest.split_once(']')?.0.parse().ok();
+    }
+    hop.rsplit_once(':')?.0.parse().ok()
+}
+
+// IPv4-mapped IPv6 addresses (::ffff:1.2.3.4) compare as IPv4
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        ip => ip,
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use serde_json::json;
+
+use crate::config::{Config, REPORT_QUEUE_CAPACITY, REPORT_REPEAT_SECONDS};
+
+// Where error reports go: a Sentry project, from a DSN like https://<key>@o1.ingest.sentry.io/42.
+// Events are posted with curl by one background thread (as the sync shells out to git), fed
+// through a bounded queue, so a slow or unreachable Sentry never holds up a request and a burst
+// of errors can't start a process each.
+#[derive(Debug)]
+struct Sentry {
+    store_url: String,
+    auth: String,
+}
+
+// The worker's queue, and when each event (by level, message and tags) was last queued
+struct Reporter {
+    queue: SyncSender<Vec<u8>>,
+    recent: Mutex<HashMap<String, Instant>>,
+}
+
+static REPORTER: OnceLock<Reporter> = OnceLock::new();
+
+impl Sentry {
+    fn from_dsn(dsn: &str) -> Option<Sentry> {
+        let (scheme, rest) = dsn.trim().split_once("://")?;
+        let (key, rest) = rest.split_once('@')?;
+        let key = key.split(':').next().unwrap_or_default();
+        let (host, path) = rest.split_once('/')?;
+        let (prefix, project) = match path.trim_end_matches('/').rsplit_once('/') {
+            Some((prefix, project)) => (format!("/{}", prefix), project),
+            None => (String::new(), path.trim_end_matches('/')),
+        };
+        if key.is_empty() || host.is_empty() || project.is_empty() || !matches!(scheme, "http" | "https") {
+            return None;
+        }
+        Some(Sentry {
+            store_url: format!("{}://{}{}/api/{}/store/", scheme, host, prefix, project),
+            auth: format!(
+                "Sentry sentry_version=7, sentry_key={}, sentry_client=trend-story-api/{}",
+                key,
+                env!("CARGO_PKG_VERSION")
+            ),
+        })
+    }
+}
+
+// Enable reporting when the config has a Sentry DSN; reports are dropped otherwise
+pub fn init_reporting(config: &Config) {
+    let Some(dsn) = &config.sentry_dsn else {
+        return;
+    };
+    let Some(sentry) = Sentry::from_dsn(dsn) else {
+        eprintln!("Ignoring Sentry DSN: expected <scheme>://<key>@<host>/<project id>");
+        return;
+    };
+    let (queue, events) = sync_channel::<Vec<u8>>(REPORT_QUEUE_CAPACITY);
+    if REPORTER.set(Reporter { queue, recent: Mutex::new(HashMap::new()) }).is_err() {
+        return;
+    }
+    std::thread::spawn(move || {
+        while let Ok(body) = events.recv() {
+            sentry.post(&body);
+        }
+    });
+}
+
+impl Sentry {
+    fn post(&self, body: &[u8]) {
+        let child = std::process::Command::new("curl")
+            .args(["-sS", "-o", "/dev/null", "--max-time", "10", "-X", "POST"])
+            .args(["-H", "Content-Type: application/json", "-H"])
+            .arg(format!("X-Sentry-Auth: {}", self.auth))
+            .args(["--data-binary", "@-"])
+            .arg(&self.store_url)
+            .stdin(std::process::Stdio::piped())
+            .spawn();
+        let sent = child.and_then(|mut child| {
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(body)?;
+            }
+            child.wait()
+        });
+        match sent {
+            Ok(status) if status.success() => {}
+            Ok(status) => eprintln!("Sending error report to Sentry failed: curl {}", status),
+            Err(e) => eprintln!("Sending error report to Sentry failed: {}", e),
+        }
+    }
+}
+
+impl Reporter {
+    // Whether an event with this key went out less than REPORT_REPEAT_SECONDS ago; records it
+    // as sent now otherwise
+    fn is_repeat(&self, key: String) -> bool {
+        let window = Duration::from_secs(REPORT_REPEAT_SECONDS);
+        let now = Instant::now();
+        let Ok(mut recent) = self.recent.lock() else {
+            return false;
+        };
+        if recent.get(&key).is_some_and(|sent| now.duration_since(*sent) < window) {
+            return true;
+        }
+        recent.retain(|_, sent| now.duration_since(*sent) < window);
+        recent.insert(key, now);
+        false
+    }
+}
+
+// 32 hex digits, random enough to keep event ids apart
+fn event_id() -> String {
+    use std::hash::{BuildHasher, Hasher};
+    let half = || {
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        hasher.write_u128(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos());
+        hasher.finish()
+    };
+    format!("{:016x}{:016x}", half(), half())
+}
+
+// Request an error happened in, for the event's request context
+pub(crate) struct ReportRequest<'a> {
+    pub(crate) id: &'a str,
+    pub(crate) method: &'a str,
+    pub(crate) path: &'a str,
+    pub(crate) client: Option<std::net::IpAddr>,
+}
+
+// Send an event at `level` ("error", "fatal", ...) with its tags; a no-op without a DSN. Repeats
+// of a recent event, and events finding the queue full, are dropped
+pub(crate) fn report(level: &str, message: &str, tags: &[(&str, &str)], request: Option<ReportRequest>) {
+    let Some(reporter) = REPORTER.get() else {
+        return;
+    };
+    if reporter.is_repeat(format!("{}\n{}\n{:?}", level, message, tags)) {
+        return;
+    }
+    let mut tag_map: serde_json::Map<String, serde_json::Value> =
+        tags.iter().map(|(key, value)| (key.to_string(), json!(value))).collect();
+    let mut event = json!({
+        "event_id": event_id(),
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+        "platform": "other",
+        "level": level,
+        "logger": "trend-story-api",
+        "release": format!("trend-story-api@{}", env!("CARGO_PKG_VERSION")),
+        "message": { "formatted": message },
+    });
+    if let Some(request) = request {
+        tag_map.insert("request_id".to_string(), json!(request.id));
+        event["request"] = json!({ "method": request.method, "url": request.path });
+        if let Some(client) = request.client {
+            event["user"] = json!({ "ip_address": client.to_string() });
+        }
+    }
+    event["tags"] = serde_json::Value::Object(tag_map);
+
+    let body = serde_json::to_vec(&event).unwrap_or_default();
+    if let Err(TrySendError::Full(_)) = reporter.queue.try_send(body) {
+        eprintln!("Dropping an error report: {} are already waiting to be sent", REPORT_QUEUE_CAPACITY);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::mpsc::sync_channel;
+    use std::sync::Mutex;
+
+    use super::Reporter;
+
+    #[test]
+    fn repeats_of_a_recent_event_are_dropped() {
+        let (queue, _events) = sync_channel(1);
+        let reporter = Reporter { queue, recent: Mutex::new(HashMap::new()) };
+        assert!(!reporter.is_repeat("error\ndatabase locked".to_string()));
+        assert!(reporter.is_repeat("error\ndatabase locked".to_string()));
+        assert!(!reporter.is_repeat("fatal\ndatabase locked".to_string()));
+    }
+}
@@ -0,0 +1,2305 @@
+use std::collections::HashMap;
+use std::path::Path;
+use rusqlite::OptionalExtension;
+use serde::Serialize;
+use warp::Filter;
+
+use crate::auth::{require_admin, AdminAuth, Caller};
+use crate::keys::{meter, ApiKeys};
+use crate::config::{
+    Config, DataSource, CHANGES_DEFAULT_LIMIT, DEFAULT_HIGHLIGHT_END, DEFAULT_HIGHLIGHT_START, EXPORT_BUFFERED_CHUNKS,
+    EXPORT_CHUNK_BYTES, MAX_ANALYTICS_DAYS, MAX_HIGHLIGHT_MARKER_LEN, MAX_RELATED_RECORDS,
+    RECENT_DEFAULT_LIMIT, SYNC_INTERVAL_MINUTES,
+};
+use crate::db::{
+    backup_database, build_overlay, open_database, query_adjacent_day, query_all_dates, query_changes, query_date_range,
+    query_day_images, query_keyword_analytics, query_keyword_series, query_latest_news, query_news_by_date, query_news_by_month,
+    query_news_by_week, query_news_records, query_on_this_day, query_recent_news, query_related_news, query_related_tags,
+    query_serpapi_row, query_stats, query_tag_counts, query_year, run_blocking, ChangesMarker, RECENT_CURSOR_DAY,
+};
+use crate::edits::{delete_record, insert_record, patch_record, LinkedKeywords, NewRecord, RecordPatch};
+use crate::embeddings::query_similar_news;
+use crate::error::{handle_rejection, ApiError, ErrorBody};
+use crate::export::{export_records, ExportFormat};
+use crate::limit::{admit, body_limit, query_limit, Admission, ConcurrencyLimits};
+use crate::list::{Cursor, ListOptions};
+use crate::logging::access_log_enabled;
+use crate::report::{report, ReportRequest};
+use crate::metrics::{render_metrics, time_query};
+use crate::search::{match_expression, query_search, Markers};
+use crate::threads::query_thread;
+use crate::translate::parse_language;
+use crate::json_schema::json_schema;
+use crate::negotiate::{negotiate, ResponseFormat};
+use crate::proxy::TrustedProxies;
+use crate::sync::{sync_unless_running, SyncRecord};
+use crate::{AppState, SchemaStatus};
+
+// The source's state; for a request whose links differ from the source's (following its host,
+// or ?urls=relative|absolute), a copy building them its way. ?case=camel|snake picks the
+// field names of its JSON.
+pub(crate) fn with_state(state: AppState) -> impl Filter<Extract = (AppState,), Error = warp::Rejection> + Clone {
+    // A header that isn't text counts as missing
+    let header = |name: &'static str| warp::header::optional::<String>(name).or(warp::any().map(|| None)).unify();
+    let query = warp::query::raw().or(warp::any().map(String::new)).unify();
+    warp::addr::remote()
+        .and(header("host"))
+        .and(header("x-forwarded-host"))
+        .and(header("x-forwarded-proto"))
+        .and(query)
+        .and_then(move |remote: Option<std::net::SocketAddr>,
+                        host: Option<String>,
+                        forwarded_host: Option<String>,
+                        forwarded_proto: Option<String>,
+                        query: String| {
+            let state = state.clone();
+            async move {
+                let base_urls = &state.source.base_urls;
+                let mut urls = base_urls.for_request(
+                    remote.map(|addr| addr.ip()),
+                    host.as_deref(),
+                    forwarded_host.as_deref(),
+                    forwarded_proto.as_deref(),
+                );
+                let relative = match query.split('&').find_map(|pair| pair.strip_prefix("urls=")) {
+                    None => base_urls.relative,
+                    Some("relative") => true,
+                    Some("absolute") => false,
+                    Some(_) => return Err(warp::Rejection::from(ApiError::InvalidQueryParameter("urls"))),
+                };
+                let camel_case = match query.split('&').find_map(|pair| pair.strip_prefix("case=")) {
+                    None | Some("snake") => false,
+                    Some("camel") => true,
+                    Some(_) => return Err(warp::Rejection::from(ApiError::InvalidQueryParameter("case"))),
+                };
+                let raw = match query.split('&').find_map(|pair| pair.strip_prefix("raw=")) {
+                    None | Some("false") | Some("0") => false,
+                    Some("true") | Some("1") => true,
+                    Some(_) => return Err(warp::Rejection::from(ApiError::InvalidQueryParameter("raw"))),
+                };
+                let lang = match query.split('&').find_map(|pair| pair.strip_prefix("lang=")) {
+                    None => None,
+                    Some(raw) => match (parse_language(raw), &state.source.translator) {
+                        (None, _) => return Err(warp::Rejection::from(ApiError::InvalidQueryParameter("lang"))),
+                        (Some(_), None) => return Err(warp::Rejection::from(ApiError::TranslationUnavailable)),
+                        (Some(lang), Some(translator)) if !translator.offers(&lang) => {
+                            return Err(warp::Rejection::from(ApiError::LanguageNotOffered(lang)))
+                        }
+                        (Some(lang), Some(_)) => Some(lang),
+                    },
+                };
+                if relative != base_urls.relative {
+                    urls.get_or_insert_with(|| (**base_urls).clone()).relative = relative;
+                }
+                let state = match urls {
+                    Some(urls) => state.with_base_urls(urls),
+                    None => state,
+                };
+                let state = if raw && !state.source.keep_news_html { state.with_news_html() } else { state };
+                let state = match lang {
+                    Some(lang) => state.with_translation(lang),
+                    None => state,
+                };
+                Ok(AppState { camel_case, ..state })
+            }
+        })
+}
+
+// JSON reply carrying validators (ETag from the body, Last-Modified from the database file),
+// so HEAD requests and caches can probe the data without downloading it
+pub(crate) fn json_response<T: Serialize>(state: &AppState, value: &T) -> warp::reply::Response {
+    let body = if state.camel_case {
+        let mut value = serde_json::to_value(value).unwrap_or_default();
+        camel_case_keys(&mut value);
+        serde_json::to_vec(&value)
+    } else {
+        serde_json::to_vec(value)
+    };
+    body_response(state, "application/json", body.unwrap_or_default())
+}
+
+// A cached response as plain JSON, with ?case=camel field names if asked
+fn cased_json(state: &AppState, mut value: serde_json::Value) -> warp::reply::Json {
+    if state.camel_case {
+        camel_case_keys(&mut value);
+    }
+    warp::reply::json(&value)
+}
+
+// Rename snake_case field names (serpapi_id, date_with_url) to camelCase throughout a response.
+// Keys that aren't lowercase identifiers are data, such as tags or keywords, and are kept.
+pub(crate) fn camel_case_keys(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            let fields = std::mem::take(map);
+            for (key, mut child) in fields {
+                camel_case_keys(&mut child);
+                let identifier = key.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_');
+                let key = if identifier && key.contains('_') {
+                    let mut words = key.split('_').filter(|word| !word.is_empty());
+                    let mut camel = words.next().unwrap_or_default().to_string();
+                    for word in words {
+                        let mut chars = word.chars();
+                        camel.extend(chars.next().map(|c| c.to_ascii_uppercase()));
+                        camel.push_str(chars.as_str());
+                    }
+                    camel
+                } else {
+                    key
+                };
+                map.insert(key, child);
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(camel_case_keys),
+        _ => {}
+    }
+}
+
+// A serialized body with the same validators as json_response
+pub(crate) fn body_response(state: &AppState, content_type: &'static str, body: Vec<u8>) -> warp::reply::Response {
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    body.hash(&mut hasher);
+    let etag = format!("\"{:016x}\"", hasher.finish());
+    prepared_response(state, content_type, body.into(), &etag)
+}
+
+// A body serialized (and hashed into its ETag) ahead of time, as body_response would send it
+pub(crate) fn prepared_response(
+    state: &AppState,
+    content_type: &'static str,
+    body: warp::hyper::body::Bytes,
+    etag: &str,
+) -> warp::reply::Response {
+    let mut response = warp::reply::Response::new(body.into());
+    let headers = response.headers_mut();
+    headers.insert(
+        warp::http::header::CONTENT_TYPE,
+        warp::http::HeaderValue::from_static(content_type),
+    );
+    if let Ok(value) = warp::http::HeaderValue::from_str(etag) {
+        headers.insert(warp::http::header::ETAG, value);
+    }
+    if let Some(modified) = data_last_modified(&state.source) {
+        if let Ok(value) = warp::http::HeaderValue::from_str(&http_date(modified)) {
+            headers.insert(warp::http::header::LAST_MODIFIED, value);
+        }
+    }
+    response
+}
+
+// Data freshness: the sync only rewrites the database file when upstream data changed, and the
+// edits file changes with every local edit
+pub(crate) fn data_last_modified(source: &DataSource) -> Option<std::time::SystemTime> {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|meta| meta.modified()).ok();
+    let db = modified(&source.serving_path())?;
+    Some(modified(&source.edits_path).map_or(db, |edits| edits.max(db)))
+}
+
+// Answers conditional GET/HEAD requests on JSON routes with 304 when the data hasn't changed
+// since If-Modified-Since; otherwise rejects so the request reaches its real route
+pub(crate) fn not_modified(state: AppState) -> impl Filter<Extract = (warp::reply::Response,), Error = warp::Rejection> + Clone {
+    warp::path::full()
+        .and(warp::method())
+        .and(warp::header::optional::<String>("if-modified-since"))
+        .and(with_state(state))
+        .and_then(|path: warp::path::FullPath, method: warp::http::Method, since: Option<String>, state: AppState| async move {
+            let path = path.as_str()
+                .strip_prefix(state.source.url_prefix().as_str())
+                .unwrap_or(path.as_str());
+            let is_json_route = allowed_methods(path).is_some()
+                && !path.starts_with("/images/")
+                && !path.starts_with("/thumbnails/")
+                && !path.starts_with("/admin/");
+            let is_read = method == warp::http::Method::GET || method == warp::http::Method::HEAD;
+            let since = since.and_then(|raw| chrono::DateTime::parse_from_rfc2822(&raw).ok());
+            let modified = data_last_modified(&state.source).map(chrono::DateTime::<chrono::Utc>::from);
+
+            match (is_json_route && is_read, since, modified) {
+                // HTTP dates have second precision
+                (true, Some(since), Some(modified)) if modified.timestamp() <= since.timestamp() => {
+                    let mut response = warp::reply::Response::new(warp::hyper::Body::empty());
+                    *response.status_mut() = warp::http::StatusCode::NOT_MODIFIED;
+                    if let Ok(value) = warp::http::HeaderValue::from_str(&http_date(modified.into())) {
+                        response.headers_mut().insert(warp::http::header::LAST_MODIFIED, value);
+                    }
+                    Ok(response)
+                }
+                _ => Err(warp::reject::not_found()),
+            }
+        })
+}
+
+// Format a timestamp as an HTTP date (RFC 7231 IMF-fixdate)
+pub(crate) fn http_date(time: std::time::SystemTime) -> String {
+    let time: chrono::DateTime<chrono::Utc> = time.into();
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+// Cache key of a list response: its route and argument and the parsed options that choose the
+// records, so unknown or differently spelled parameters share an entry (format, fields, dedupe,
+// summary and case are applied to the cached response)
+pub(crate) fn list_cache_key(route: &str, argument: &str, options: &ListOptions) -> String {
+    let filter = &options.filter;
+    format!(
+        "{}:{}:tag={}&keyword={}&has_image={:?}&sentiment={:?}&sort={}&limit={:?}&cursor={}",
+        route,
+        argument,
+        filter.tag.as_deref().unwrap_or_default(),
+        filter.keyword.as_deref().unwrap_or_default(),
+        filter.has_image,
+        filter.sentiment,
+        options.sort.name(),
+        options.limit,
+        options.cursor.as_ref().map(Cursor::encode).unwrap_or_default(),
+    )
+}
+
+pub(crate) async fn get_latest(params: HashMap<String, String>, format: ResponseFormat, state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    let options = ListOptions { format, ..ListOptions::from_params(&params)? };
+    // Without options the response is prepared after the sync, only copied here
+    if params.is_empty() && options.format == ResponseFormat::Json && state.source.base_urls.from_host.is_none() {
+        let prepared = match state.prepared_latest() {
+            Some(prepared) => prepared,
+            None => match run_blocking(&state, "latest", |source| query_latest_news(source, &ListOptions::default())).await {
+                Ok(response) => state.prepare_latest(&response),
+                Err(e) => return Err(ApiError::database("latest news", e).into()),
+            },
+        };
+        let mut reply = prepared_response(&state, "application/json", prepared.body, &prepared.etag);
+        reply.headers_mut().insert(warp::http::header::VARY, warp::http::HeaderValue::from_static("Accept"));
+        return Ok(reply);
+    }
+    let cache_key = list_cache_key("latest", "", &options);
+    if let Some(cached) = state.cached(&cache_key) {
+        return Ok(options.reply(&state, &cached));
+    }
+    let query_options = options.clone();
+    match run_blocking(&state, "latest", move |source| query_latest_news(source, &query_options)).await {
+        Ok(response) => {
+            // Which day is latest depends on every day
+            let value = serde_json::to_value(&response).unwrap_or_default();
+            state.store(&cache_key, value.clone());
+            Ok(options.reply(&state, &value))
+        }
+        Err(e) => Err(ApiError::database("latest news", e).into()),
+    }
+}
+
+// The newest records regardless of day, for feeds: ?limit= (default RECENT_DEFAULT_LIMIT) per
+// page and ?cursor=<next_cursor> for the next one, always newest first
+pub(crate) async fn get_recent(params: HashMap<String, String>, format: ResponseFormat, state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    for fixed in ["sort", "order"] {
+        if params.contains_key(fixed) {
+            return Err(ApiError::InvalidQueryParameter(fixed).into());
+        }
+    }
+    let mut params = params;
+    params.insert("sort".to_string(), "date".to_string());
+    params.insert("order".to_string(), "desc".to_string());
+    let options = ListOptions::from_params(&params)?;
+    let options = ListOptions {
+        format,
+        limit: Some(options.limit.unwrap_or(RECENT_DEFAULT_LIMIT)),
+        ..options
+    };
+    if options.cursor.as_ref().is_some_and(|cursor| cursor.day != RECENT_CURSOR_DAY) {
+        return Err(ApiError::InvalidCursor.into());
+    }
+
+    let query_options = options.clone();
+    match run_blocking(&state, "recent", move |source| query_recent_news(source, &query_options)).await {
+        Ok(response) => Ok(options.reply(&state, &response)),
+        Err(e) => Err(ApiError::database("recent news", e).into()),
+    }
+}
+
+// Records added since a marker, for mirrors syncing incrementally: ?since= is a record id (the
+// next_since of the previous page) or a time, yyyy-mm-dd, yyyy-mm-dd hh:mm:ss or RFC 3339, after
+// which records were written. Pages follow record ids with the list filters and ?limit= applied.
+pub(crate) async fn get_changes(params: HashMap<String, String>, format: ResponseFormat, state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    for fixed in ["sort", "order", "cursor"] {
+        if params.contains_key(fixed) {
+            return Err(ApiError::InvalidQueryParameter(fixed).into());
+        }
+    }
+    let marker = params
+        .get("since")
+        .and_then(|raw| parse_changes_marker(raw.trim()))
+        .ok_or(ApiError::InvalidQueryParameter("since"))?;
+    let options = ListOptions::from_params(&params)?;
+    let options = ListOptions {
+        format,
+        limit: Some(options.limit.unwrap_or(CHANGES_DEFAULT_LIMIT)),
+        ..options
+    };
+
+    let query_options = options.clone();
+    match run_blocking(&state, "changes", move |source| query_changes(source, &marker, &query_options)).await {
+        Ok(response) => Ok(options.reply(&state, &response)),
+        Err(e) => Err(ApiError::database("changes", e).into()),
+    }
+}
+
+fn parse_changes_marker(raw: &str) -> Option<ChangesMarker> {
+    if !raw.is_empty() && raw.chars().all(|c| c.is_ascii_digit()) {
+        return raw.parse().ok().map(ChangesMarker::Id);
+    }
+    let time = chrono::DateTime::parse_from_rfc3339(raw)
+        .map(|time| time.naive_utc())
+        .or_else(|_| chrono::NaiveDateTime::parse_from_str(raw, "%Y-%m-%d %H:%M:%S"))
+        .or_else(|_| chrono::NaiveDate::parse_from_str(raw, "%Y-%m-%d").map(|day| day.and_time(chrono::NaiveTime::MIN)))
+        .ok()?;
+    Some(ChangesMarker::Time(time.format("%Y-%m-%d %H:%M:%S").to_string()))
+}
+
+// A /date path parameter, normalized to the canonical ISO form used in queries and responses
+#[derive(Debug, PartialEq)]
+pub(crate) enum DateParam {
+    Day(String),   // yyyy-mm-dd
+    Month(String), // yyyy-mm
+}
+
+// Accept both compact (yyyymmdd, yyyymm) and ISO 8601 (yyyy-mm-dd, yyyy-mm) forms, ignoring
+// surrounding whitespace. A well-formed parameter naming no calendar day or month (20250230,
+// 2025-13) is told apart from a malformed one.
+pub(crate) fn parse_date_param(raw: &str) -> Result<DateParam, ApiError> {
+    let raw = raw.trim();
+    let malformed = || ApiError::InvalidDate(raw.to_string());
+    let digits: String = raw.chars().filter(|c| *c != '-').collect();
+    if !digits.chars().all(|c| c.is_ascii_digit()) || !matches!(digits.len(), 6 | 8) {
+        return Err(malformed());
+    }
+
+    // Dashes are only allowed in their ISO positions
+    let iso_shape = match raw.len() {
+        10 => raw.as_bytes()[4] == b'-' && raw.as_bytes()[7] == b'-',
+        7 => raw.as_bytes()[4] == b'-',
+        _ => false,
+    };
+    if raw.contains('-') && !iso_shape {
+        return Err(malformed());
+    }
+
+    let number = |range: std::ops::Range<usize>| digits[range].parse::<u32>().unwrap_or_default();
+    let year = number(0..4) as i32;
+    let nonexistent = || ApiError::NonexistentDate(raw.to_string());
+    if digits.len() == 8 {
+        let day = chrono::NaiveDate::from_ymd_opt(year, number(4..6), number(6..8)).ok_or_else(nonexistent)?;
+        Ok(DateParam::Day(day.format("%Y-%m-%d").to_string()))
+    } else {
+        let month = chrono::NaiveDate::from_ymd_opt(year, number(4..6), 1).ok_or_else(nonexistent)?;
+        Ok(DateParam::Month(month.format("%Y-%m").to_string()))
+    }
+}
+
+// /date/<day>/next and /prev: a redirect to the nearest later or earlier day with records, which
+// the day itself needn't have, keeping the query string
+pub(crate) async fn get_adjacent_date(date_param: String, direction: String, query: String, state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    let later = match direction.as_str() {
+        "next" => true,
+        "prev" => false,
+        _ => return Err(warp::reject::not_found()),
+    };
+    let date_param = percent_encoding::percent_decode_str(&date_param).decode_utf8_lossy();
+    let day = match parse_date_param(&date_param)? {
+        DateParam::Day(day) => day,
+        DateParam::Month(_) => return Err(ApiError::InvalidDate(date_param.trim().to_string()).into()),
+    };
+    let query_day = day.clone();
+    let target = match run_blocking(&state, "adjacent_day", move |source| query_adjacent_day(source, &query_day, later)).await {
+        Ok(Some(target)) => target,
+        Ok(None) => {
+            let side = if later { "after" } else { "before" };
+            return Err(ApiError::NoDataFound(format!("a day {} {}", side, day)).into());
+        }
+        Err(e) => return Err(ApiError::database(format!("day {} {}", direction, day), e).into()),
+    };
+    let mut location = format!("{}/date/{}", state.source.url_prefix(), target.replace('-', ""));
+    if !query.is_empty() {
+        location = format!("{}?{}", location, query);
+    }
+    let mut response = warp::reply::Response::new(warp::hyper::Body::empty());
+    *response.status_mut() = warp::http::StatusCode::TEMPORARY_REDIRECT;
+    if let Ok(location) = warp::http::HeaderValue::from_str(&location) {
+        response.headers_mut().insert(warp::http::header::LOCATION, location);
+    }
+    Ok(response)
+}
+
+pub(crate) async fn get_date(date_param: String, params: HashMap<String, String>, format: ResponseFormat, state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    let options = ListOptions { format, ..ListOptions::from_params(&params)? };
+
+    // Path segments arrive percent-encoded ("%2020251101" for a leading space)
+    let date_param = percent_encoding::percent_decode_str(&date_param).decode_utf8_lossy();
+    let formatted_date = match parse_date_param(&date_param)? {
+        DateParam::Day(day) => day,
+        DateParam::Month(month) => return get_month(&state, month, &options).await,
+    };
+
+    if options.cursor.as_ref().is_some_and(|cursor| cursor.day != formatted_date) {
+        return Err(ApiError::InvalidCursor.into());
+    }
+
+    let cache_key = list_cache_key("date", &formatted_date, &options);
+    if let Some(cached) = state.cached(&cache_key) {
+        return Ok(options.reply(&state, &cached));
+    }
+    let (query_date, query_options) = (formatted_date.clone(), options.clone());
+    match run_blocking(&state, "by_date", move |source| query_news_by_date(source, &query_date, &query_options)).await {
+        Ok(Some(response)) => {
+            let value = serde_json::to_value(&response).unwrap_or_default();
+            // The prev/next links change when a day appears between this one and its neighbours
+            let (prev, next) = &response.adjacent_days;
+            let first = prev.as_deref().map_or("00000000".to_string(), |day| day.replace('-', ""));
+            let last = next.as_deref().map_or("99999999".to_string(), |day| day.replace('-', ""));
+            state.store_for_days(&cache_key, value.clone(), first, last);
+            Ok(options.reply(&state, &value))
+        }
+        Ok(None) => Err(ApiError::NoDataFound(format!("date {}", formatted_date)).into()),
+        Err(e) => Err(ApiError::database(format!("news for date {}", formatted_date), e).into()),
+    }
+}
+
+pub(crate) async fn get_month(
+    state: &AppState,
+    formatted_month: String,
+    options: &ListOptions,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let cache_key = list_cache_key("month", &formatted_month, options);
+    if let Some(cached) = state.cached(&cache_key) {
+        return Ok(options.reply(state, &cached));
+    }
+    let (query_month, query_options) = (formatted_month.clone(), options.clone());
+    match run_blocking(state, "by_month", move |source| query_news_by_month(source, &query_month, &query_options)).await {
+        Ok(Some(response)) => {
+            let value = serde_json::to_value(&response).unwrap_or_default();
+            let month = formatted_month.replace('-', "");
+            state.store_for_days(&cache_key, value.clone(), format!("{}01", month), format!("{}31", month));
+            Ok(options.reply(state, &value))
+        }
+        Ok(None) => Err(ApiError::NoDataFound(format!("month {}", formatted_month)).into()),
+        Err(e) => Err(ApiError::database(format!("news for month {}", formatted_month), e).into()),
+    }
+}
+
+// Parse an ISO week such as 2025W44 or 2025-W44 into its Monday
+pub(crate) fn parse_iso_week(raw: &str) -> Option<(i32, u32, chrono::NaiveDate)> {
+    let upper = raw.to_ascii_uppercase();
+    let (year, week) = upper.split_once('W')?;
+    let year = year.strip_suffix('-').unwrap_or(year);
+    if year.len() != 4 || week.len() != 2 {
+        return None;
+    }
+    let year: i32 = year.parse().ok()?;
+    let week: u32 = week.parse().ok()?;
+    let monday = chrono::NaiveDate::from_isoywd_opt(year, week, chrono::Weekday::Mon)?;
+    Some((year, week, monday))
+}
+
+pub(crate) async fn get_week(week_param: String, params: HashMap<String, String>, format: ResponseFormat, state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    let options = ListOptions { format, ..ListOptions::from_params(&params)? };
+    let Some((year, week, monday)) = parse_iso_week(&week_param) else {
+        return Err(ApiError::InvalidWeek(week_param).into());
+    };
+
+    let query_options = options.clone();
+    match run_blocking(&state, "by_week", move |source| query_news_by_week(source, year, week, monday, &query_options)).await {
+        Ok(response) => {
+            if response.days.is_empty() {
+                Err(ApiError::NoDataFound(format!("week {}-W{:02}", year, week)).into())
+            } else {
+                Ok(options.reply(&state, &response))
+            }
+        }
+        Err(e) => Err(ApiError::database(format!("news for week {}-W{:02}", year, week), e).into()),
+    }
+}
+
+pub(crate) async fn get_year(year: String, state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    if year.len() != 4 || !year.chars().all(|c| c.is_ascii_digit()) {
+        return Err(ApiError::InvalidYear(year).into());
+    }
+
+    let cache_key = format!("year:{}", year);
+    if let Some(cached) = state.cached(&cache_key) {
+        return Ok(cased_json(&state, cached));
+    }
+    let query_year_param = year.clone();
+    match run_blocking(&state, "year", move |source| query_year(source, &query_year_param)).await {
+        Ok(response) if response.months.is_empty() => Err(ApiError::NoDataFound(format!("year {}", year)).into()),
+        Ok(response) => {
+            let value = serde_json::to_value(&response).unwrap_or_default();
+            state.store_for_days(&cache_key, value.clone(), format!("{}0101", year), format!("{}1231", year));
+            Ok(cased_json(&state, value))
+        }
+        Err(e) => Err(ApiError::database(format!("archive for year {}", year), e).into()),
+    }
+}
+
+pub(crate) async fn get_dates(state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    if let Some(cached) = state.cached("dates") {
+        return Ok(json_response(&state, &cached));
+    }
+    match run_blocking(&state, "dates", query_all_dates).await {
+        Ok(dates) => {
+            let value = serde_json::to_value(&dates).unwrap_or_default();
+            state.store("dates", value.clone());
+            Ok(json_response(&state, &value))
+        }
+        Err(e) => Err(ApiError::database("all dates", e).into()),
+    }
+}
+
+// First and last day with records and how many there are, without listing them
+pub(crate) async fn get_date_range(state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    if let Some(cached) = state.cached("dates:range") {
+        return Ok(json_response(&state, &cached));
+    }
+    match run_blocking(&state, "date_range", query_date_range).await {
+        Ok(range) => {
+            let value = serde_json::to_value(&range).unwrap_or_default();
+            state.store("dates:range", value.clone());
+            Ok(json_response(&state, &value))
+        }
+        Err(e) => Err(ApiError::database("date range", e).into()),
+    }
+}
+
+// Unauthenticated, so only statuses: no file paths, and no validation problems, which can name
+// them (those are in the admin sync log)
+#[derive(Debug, Serialize)]
+pub(crate) struct HealthResponse {
+    pub(crate) status: &'static str,
+    pub(crate) ready: bool,
+    pub(crate) database_exists: bool,
+    pub(crate) schema: SchemaStatus,
+    pub(crate) validation: HealthValidation,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct HealthValidation {
+    pub(crate) ok: bool,
+    pub(crate) checked_at: Option<String>,
+    pub(crate) serving_known_good: bool,
+}
+
+pub(crate) async fn get_health(state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    let schema = state.schema_status();
+    let validation = state.validation_status();
+    let ready = state.is_ready();
+    // A synced database that failed validation is reported, but the data served is still sound
+    let (status, code) = if !ready {
+        ("not_ready", warp::http::StatusCode::SERVICE_UNAVAILABLE)
+    } else if !schema.ok {
+        ("degraded", warp::http::StatusCode::SERVICE_UNAVAILABLE)
+    } else if validation.checked_at.is_some() && !validation.ok {
+        ("stale", warp::http::StatusCode::OK)
+    } else {
+        ("ok", warp::http::StatusCode::OK)
+    };
+    let response = HealthResponse {
+        status,
+        ready,
+        database_exists: state.db_path().exists(),
+        schema,
+        validation: HealthValidation {
+            ok: validation.ok,
+            checked_at: validation.checked_at,
+            serving_known_good: validation.serving_known_good,
+        },
+    };
+    Ok(warp::reply::with_status(warp::reply::json(&response), code))
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct VersionResponse {
+    pub(crate) version: &'static str,
+    // Commit the binary was built from ("-dirty" with uncommitted changes, "unknown" without git)
+    pub(crate) commit: &'static str,
+    pub(crate) build_timestamp: &'static str,
+    pub(crate) data_repository: String,
+    // Commit of the served data, None until a sync found a checkout
+    pub(crate) data_commit: Option<String>,
+}
+
+// Which build and which dataset this deployment serves
+pub(crate) async fn get_version(state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(&VersionResponse {
+        version: env!("CARGO_PKG_VERSION"),
+        commit: env!("TREND_STORY_GIT_COMMIT"),
+        build_timestamp: env!("TREND_STORY_BUILD_TIMESTAMP"),
+        data_repository: state.source.public_origin(),
+        data_commit: state.data_commit(),
+    }))
+}
+
+// What the served data is, for clients checking whether it changed: the data repository's
+// commit and the database file's hash as of the last sync, and when that sync started
+#[derive(Debug, Serialize)]
+pub(crate) struct MetaResponse {
+    pub(crate) data_commit: Option<String>,
+    pub(crate) last_sync_at: Option<String>,
+    pub(crate) database_sha1: Option<String>,
+}
+
+pub(crate) async fn get_meta(state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(&MetaResponse {
+        data_commit: state.data_commit(),
+        last_sync_at: state.last_sync_at(),
+        database_sha1: state.database_sha1(),
+    }))
+}
+
+// Tag a source's response with the commit of the data it was computed from
+fn with_data_version(state: &AppState, mut response: warp::reply::Response) -> warp::reply::Response {
+    if let Some(commit) = state.data_commit() {
+        if let Ok(value) = warp::http::HeaderValue::from_str(&commit) {
+            response.headers_mut().insert("x-data-version", value);
+        }
+    }
+    response
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct PurgeResponse {
+    pub(crate) purged: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) date: Option<String>,
+}
+
+// Drop cached responses, all of them or (with ?date=yyyymmdd or yyyymm) those computed from that day or month
+pub(crate) async fn post_cache_purge(params: HashMap<String, String>, caller: Caller, state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    let days = match params.get("date") {
+        None => None,
+        Some(raw) => match parse_date_param(raw)? {
+            DateParam::Day(day) => Some((day.replace('-', ""), day.replace('-', ""))),
+            DateParam::Month(month) => {
+                let month = month.replace('-', "");
+                Some((format!("{}01", month), format!("{}31", month)))
+            }
+        },
+    };
+    let purged = state.purge_cache(days.as_ref().map(|(first, last)| (first.as_str(), last.as_str())));
+    println!("Purged {} cached responses for {} (by {})", purged, state.db_path().display(), caller);
+    Ok(warp::reply::json(&PurgeResponse {
+        purged,
+        date: params.get("date").cloned(),
+    }))
+}
+
+// Add a record to the source's local edits; from then on it is served like an upstream record
+pub(crate) async fn post_news(body: warp::hyper::body::Bytes, caller: Caller, state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    let record = serde_json::from_slice::<NewRecord>(&body)
+        .map_err(|e| e.to_string())
+        .and_then(NewRecord::validate)
+        .map_err(|message| warp::Rejection::from(ApiError::InvalidRecord(message)))?;
+    let day = record.day();
+
+    let result = run_blocking(&state, "add_news", move |source| {
+        let id = insert_record(source, &record)?;
+        if let Err(e) = build_overlay(source) {
+            eprintln!("Failed to build day index in {}: {}", source.overlay_path.display(), e);
+        }
+        let conn = open_database(source)?;
+        query_news_records(&conn, source, "WHERE main_news_data.id = ?1", [id])?
+            .pop()
+            .ok_or(rusqlite::Error::QueryReturnedNoRows)
+    })
+    .await;
+    match result {
+        Ok(record) => {
+            println!("Added record {} for {} to {} (by {})", record.id, day, state.source.edits_path.display(), caller);
+            state.purge_cache(Some((&day, &day)));
+            Ok(warp::reply::with_status(warp::reply::json(&record), warp::http::StatusCode::CREATED))
+        }
+        Err(e) => Err(ApiError::database("insert news", e).into()),
+    }
+}
+
+// Replace the text, keywords or tags of a record; answers with the record as patched
+pub(crate) async fn patch_news(id: i64, body: warp::hyper::body::Bytes, caller: Caller, state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    let patch = serde_json::from_slice::<RecordPatch>(&body)
+        .map_err(|e| e.to_string())
+        .and_then(RecordPatch::validate)
+        .map_err(|message| warp::Rejection::from(ApiError::InvalidRecord(message)))?;
+
+    let result = run_blocking(&state, "edit_news", move |source| {
+        let conn = open_database(source)?;
+        let current = conn
+            .query_row(
+                "SELECT main_news_data.date, serpapi_data.id, serpapi_data.query, serpapi_data.categories, \
+                 serpapi_data.date \
+                 FROM main_news_data LEFT JOIN serpapi_data ON serpapi_data.id = main_news_data.serpapi_id \
+                 WHERE main_news_data.id = ?1",
+                [id],
+                |row| {
+                    let date: Option<String> = row.get(0)?;
+                    let linked = LinkedKeywords {
+                        id: row.get(1)?,
+                        query: row.get(2)?,
+                        categories: row.get(3)?,
+                        date: row.get(4)?,
+                    };
+                    Ok((date, linked))
+                },
+            )
+            .optional()?;
+        drop(conn);
+        let Some((date, linked)) = current else {
+            return Ok(Err(ApiError::RecordNotFound(id)));
+        };
+        if patch.tag.is_some() && patch.keywords.is_none() && linked.query.is_none() {
+            return Ok(Err(ApiError::InvalidRecord("tags require keywords".to_string())));
+        }
+
+        patch_record(source, id, &patch, linked)?;
+        if let Err(e) = build_overlay(source) {
+            eprintln!("Failed to build day index in {}: {}", source.overlay_path.display(), e);
+        }
+        let conn = open_database(source)?;
+        let record = query_news_records(&conn, source, "WHERE main_news_data.id = ?1", [id])?
+            .pop()
+            .ok_or(rusqlite::Error::QueryReturnedNoRows)?;
+        Ok(Ok((date, record)))
+    })
+    .await;
+    match result {
+        Ok(Ok((date, record))) => {
+            println!("Patched record {} in {} (by {})", id, state.source.edits_path.display(), caller);
+            let day = date.as_deref().and_then(|date| date.get(..10)).map(|day| day.replace('-', ""));
+            state.purge_cache(day.as_deref().map(|day| (day, day)));
+            Ok(warp::reply::json(&record))
+        }
+        Ok(Err(e)) => Err(e.into()),
+        Err(e) => Err(ApiError::database("patch news", e).into()),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct DeletedResponse {
+    pub(crate) id: i64,
+    pub(crate) deleted: bool,
+}
+
+// Hide a record from all responses, now and after future syncs
+pub(crate) async fn delete_news(id: i64, caller: Caller, state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    let result = run_blocking(&state, "delete_news", move |source| {
+        let conn = open_database(source)?;
+        let date: Option<Option<String>> = conn
+            .query_row("SELECT date FROM main_news_data WHERE id = ?1", [id], |row| row.get(0))
+            .optional()?;
+        drop(conn);
+        if date.is_some() {
+            delete_record(source, id)?;
+            if let Err(e) = build_overlay(source) {
+                eprintln!("Failed to build day index in {}: {}", source.overlay_path.display(), e);
+            }
+        }
+        Ok(date)
+    })
+    .await;
+    match result {
+        Ok(Some(date)) => {
+            println!("Deleted record {} in {} (by {})", id, state.source.edits_path.display(), caller);
+            let day = date.as_deref().and_then(|date| date.get(..10)).map(|day| day.replace('-', ""));
+            state.purge_cache(day.as_deref().map(|day| (day, day)));
+            Ok(warp::reply::json(&DeletedResponse { id, deleted: true }))
+        }
+        Ok(None) => Err(ApiError::RecordNotFound(id).into()),
+        Err(e) => Err(ApiError::database("delete news", e).into()),
+    }
+}
+
+// Download a snapshot of the served SQLite file
+pub(crate) async fn get_backup(caller: Caller, state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    use tokio::io::AsyncReadExt;
+
+    let dest = std::env::temp_dir().join(format!(
+        "trends-story-backup-{}-{}.db",
+        std::process::id(),
+        chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+    ));
+    let file = match run_blocking(&state, "backup", move |source| backup_database(source, &dest)).await {
+        Ok(file) => file,
+        Err(e) => return Err(ApiError::database("backup", e).into()),
+    };
+    let length = file.metadata().map(|meta| meta.len()).ok();
+    let mut file = tokio::fs::File::from_std(file);
+
+    let (mut sender, body) = warp::hyper::Body::channel();
+    let db_path = state.db_path();
+    tokio::spawn(async move {
+        let mut buffer = vec![0; EXPORT_CHUNK_BYTES];
+        loop {
+            match file.read(&mut buffer).await {
+                Ok(0) => return,
+                Ok(read) => {
+                    let chunk = warp::hyper::body::Bytes::copy_from_slice(&buffer[..read]);
+                    if sender.send_data(chunk).await.is_err() {
+                        return;
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Backup of {} failed: {}", db_path.display(), e);
+                    sender.abort();
+                    return;
+                }
+            }
+        }
+    });
+
+    let file_name = format!(
+        "{}-{}.db",
+        state.db_path().file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default(),
+        chrono::Utc::now().format("%Y%m%d%H%M%S")
+    );
+    println!("Sending backup of {} as {} (to {})", state.db_path().display(), file_name, caller);
+    let mut response = warp::reply::Response::new(body);
+    let headers = response.headers_mut();
+    headers.insert(
+        warp::http::header::CONTENT_TYPE,
+        warp::http::HeaderValue::from_static("application/vnd.sqlite3"),
+    );
+    if let Ok(value) = warp::http::HeaderValue::from_str(&format!("attachment; filename=\"{}\"", file_name)) {
+        headers.insert(warp::http::header::CONTENT_DISPOSITION, value);
+    }
+    if let Some(length) = length {
+        headers.insert(warp::http::header::CONTENT_LENGTH, warp::http::HeaderValue::from(length));
+    }
+    Ok(response)
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct SyncLogResponse {
+    pub(crate) entries: Vec<SyncRecord>,
+}
+
+// Pull the source's repository now instead of at the next scheduled sync, answering once it is
+// done with its entry of the sync log
+pub(crate) async fn post_sync(caller: Caller, state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    println!("Sync of {} triggered by {}", state.source.origin(), caller);
+    let sync_state = state.clone();
+    match tokio::task::spawn_blocking(move || sync_unless_running(&sync_state)).await {
+        Ok(Some(record)) => Ok(warp::reply::json(&record)),
+        Ok(None) => Err(ApiError::SyncInProgress.into()),
+        Err(e) => {
+            eprintln!("Sync of {} triggered through the admin API failed: {}", state.source.origin(), e);
+            Err(ApiError::Panicked.into())
+        }
+    }
+}
+
+pub(crate) async fn get_sync_log(state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(&SyncLogResponse {
+        entries: state.sync_history(),
+    }))
+}
+
+// How the source syncs, as configured, and how its syncs are going
+#[derive(Debug, Serialize)]
+pub(crate) struct SyncStatusResponse {
+    // Repository, or the snapshot URL the data is downloaded from instead
+    pub(crate) remote: String,
+    pub(crate) mode: &'static str,
+    // None for the remote's default branch
+    pub(crate) branch: Option<String>,
+    // None for full clones
+    pub(crate) depth: Option<u32>,
+    pub(crate) sparse_image_days: Option<u32>,
+    pub(crate) interval_minutes: u64,
+    pub(crate) syncing: bool,
+    pub(crate) ready: bool,
+    pub(crate) data_commit: Option<String>,
+    pub(crate) last_sync_at: Option<String>,
+    pub(crate) consecutive_failures: usize,
+}
+
+pub(crate) async fn get_sync_status(state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    let source = &state.source;
+    Ok(warp::reply::json(&SyncStatusResponse {
+        remote: source.public_origin(),
+        mode: if source.snapshot_url.is_some() { "snapshot" } else { "git" },
+        branch: source.branch.clone(),
+        depth: source.clone_depth,
+        sparse_image_days: source.sparse_image_days,
+        interval_minutes: SYNC_INTERVAL_MINUTES,
+        syncing: state.syncing.load(std::sync::atomic::Ordering::Acquire),
+        ready: state.is_ready(),
+        data_commit: state.data_commit(),
+        last_sync_at: state.last_sync_at(),
+        consecutive_failures: state.consecutive_sync_failures(),
+    }))
+}
+
+// Stream every record as a JSON array or CSV download. Rows are read on a blocking thread and
+// handed over in chunks; a query that fails before the first chunk is answered with an error,
+// a later failure cuts the download short.
+pub(crate) async fn get_export(params: HashMap<String, String>, state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    let format = ExportFormat::from_param(params.get("format").map(String::as_str))
+        .ok_or(ApiError::InvalidQueryParameter("format"))?;
+
+    let (chunks, mut received) = tokio::sync::mpsc::channel::<rusqlite::Result<Vec<u8>>>(EXPORT_BUFFERED_CHUNKS);
+    let source = state.source.clone();
+    tokio::task::spawn_blocking(move || {
+        let result = time_query("export", || {
+            export_records(&source, format, |chunk| chunks.blocking_send(Ok(chunk)).is_ok())
+        });
+        if let Err(e) = result {
+            let _ = chunks.blocking_send(Err(e));
+        }
+    });
+    let first = match received.recv().await {
+        Some(Ok(chunk)) => chunk,
+        Some(Err(e)) => return Err(ApiError::database("export", e).into()),
+        None => return Err(ApiError::Panicked.into()),
+    };
+
+    let (mut sender, body) = warp::hyper::Body::channel();
+    let db_path = state.db_path();
+    tokio::spawn(async move {
+        if sender.send_data(first.into()).await.is_err() {
+            return;
+        }
+        while let Some(chunk) = received.recv().await {
+            match chunk {
+                Ok(chunk) => {
+                    if sender.send_data(chunk.into()).await.is_err() {
+                        return;
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Export of {} failed: {}", db_path.display(), e);
+                    sender.abort();
+                    return;
+                }
+            }
+        }
+    });
+
+    let file_name = format!(
+        "trends-story{}-export.{}",
+        state.source.name.as_ref().map(|name| format!("-{}", name)).unwrap_or_default(),
+        format.extension()
+    );
+    let mut response = warp::reply::Response::new(body);
+    let headers = response.headers_mut();
+    headers.insert(
+        warp::http::header::CONTENT_TYPE,
+        warp::http::HeaderValue::from_static(format.content_type()),
+    );
+    if let Ok(value) = warp::http::HeaderValue::from_str(&format!("attachment; filename=\"{}\"", file_name)) {
+        headers.insert(warp::http::header::CONTENT_DISPOSITION, value);
+    }
+    if let Some(modified) = data_last_modified(&state.source) {
+        if let Ok(value) = warp::http::HeaderValue::from_str(&http_date(modified)) {
+            headers.insert(warp::http::header::LAST_MODIFIED, value);
+        }
+    }
+    Ok(response)
+}
+
+pub(crate) async fn get_stats(state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    if let Some(cached) = state.cached("stats") {
+        return Ok(cased_json(&state, cached));
+    }
+    match run_blocking(&state, "stats", query_stats).await {
+        Ok(stats) => {
+            let value = serde_json::to_value(&stats).unwrap_or_default();
+            state.store("stats", value.clone());
+            Ok(cased_json(&state, value))
+        }
+        Err(e) => Err(ApiError::database("stats", e).into()),
+    }
+}
+
+pub(crate) async fn get_keyword_analytics(
+    params: HashMap<String, String>,
+    state: AppState,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    // Window size in days, counted back from the newest trend data
+    let days = match params.get("days") {
+        Some(value) => match value.parse::<u32>() {
+            Ok(days) if (1..=MAX_ANALYTICS_DAYS).contains(&days) => days,
+            _ => return Err(ApiError::InvalidQueryParameter("days").into()),
+        },
+        None => 7,
+    };
+
+    let cache_key = format!("analytics_keywords:{}", days);
+    if let Some(cached) = state.cached(&cache_key) {
+        return Ok(cased_json(&state, cached));
+    }
+    match run_blocking(&state, "keyword_analytics", move |source| query_keyword_analytics(source, days)).await {
+        Ok(response) => {
+            let value = serde_json::to_value(&response).unwrap_or_default();
+            match (response.from, response.to) {
+                (Some(from), Some(to)) => state.store_for_days(&cache_key, value.clone(), from, to),
+                _ => state.store(&cache_key, value.clone()),
+            }
+            Ok(cased_json(&state, value))
+        }
+        Err(e) => Err(ApiError::database(format!("keyword analytics over {} days", days), e).into()),
+    }
+}
+
+// A story's records over the consecutive days its keywords recurred, by the thread_id of its records
+pub(crate) async fn get_thread(id: i64, state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    let cache_key = format!("threads:{}", id);
+    if let Some(cached) = state.cached(&cache_key) {
+        return Ok(cased_json(&state, cached));
+    }
+    match run_blocking(&state, "thread", move |source| query_thread(source, id)).await {
+        Ok(Some(response)) => {
+            let value = serde_json::to_value(&response).unwrap_or_default();
+            state.store_for_days(&cache_key, value.clone(), response.first_seen, response.last_seen);
+            Ok(cased_json(&state, value))
+        }
+        Ok(None) => Err(ApiError::NoDataFound(format!("thread {}", id)).into()),
+        Err(e) => Err(ApiError::database(format!("thread {}", id), e).into()),
+    }
+}
+
+// Per-day appearances of one keyword over the whole dataset
+pub(crate) async fn get_keyword_series(term: String, state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    // Path segments arrive percent-encoded ("real%20madrid")
+    let keyword = percent_encoding::percent_decode_str(&term)
+        .decode_utf8_lossy()
+        .trim()
+        .to_lowercase();
+    if keyword.is_empty() {
+        return Err(ApiError::KeywordNotFound(keyword).into());
+    }
+
+    // Under analytics_keywords to share its lifetime
+    let cache_key = format!("analytics_keywords:term:{}", keyword);
+    if let Some(cached) = state.cached(&cache_key) {
+        return Ok(cased_json(&state, cached));
+    }
+    let query_keyword = keyword.clone();
+    match run_blocking(&state, "keyword_series", move |source| query_keyword_series(source, &query_keyword)).await {
+        Ok(Some(response)) => {
+            let value = serde_json::to_value(&response).unwrap_or_default();
+            state.store(&cache_key, value.clone());
+            Ok(cased_json(&state, value))
+        }
+        Ok(None) => Err(ApiError::KeywordNotFound(keyword).into()),
+        Err(e) => Err(ApiError::database(format!("appearances of keyword '{}'", keyword), e).into()),
+    }
+}
+
+// Tags by records over the last ?days= days with data (default 7), with their rank change against
+// the days before
+pub(crate) async fn get_tag_counts(params: HashMap<String, String>, state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    let days = match params.get("days") {
+        Some(value) => match value.parse::<u32>() {
+            Ok(days) if (1..=MAX_ANALYTICS_DAYS).contains(&days) => days,
+            _ => return Err(ApiError::InvalidQueryParameter("days").into()),
+        },
+        None => 7,
+    };
+
+    let cache_key = format!("tag_counts:{}", days);
+    if let Some(cached) = state.cached(&cache_key) {
+        return Ok(cased_json(&state, cached));
+    }
+    match run_blocking(&state, "tag_counts", move |source| query_tag_counts(source, days)).await {
+        Ok(response) => {
+            let value = serde_json::to_value(&response).unwrap_or_default();
+            // Computed from the previous window as well, so an edit in either drops it
+            let first = response.from.as_deref().and_then(|from| chrono::NaiveDate::parse_from_str(from, "%Y%m%d").ok());
+            match (first, response.to) {
+                (Some(first), Some(to)) => {
+                    let first = first - chrono::Duration::days(days as i64);
+                    state.store_for_days(&cache_key, value.clone(), first.format("%Y%m%d").to_string(), to)
+                }
+                _ => state.store(&cache_key, value.clone()),
+            }
+            Ok(cased_json(&state, value))
+        }
+        Err(e) => Err(ApiError::database(format!("tag counts over {} days", days), e).into()),
+    }
+}
+
+pub(crate) async fn get_related_tags(tag_param: String, state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    // Path segments arrive percent-encoded ("Law%20and%20Government")
+    let tag = percent_encoding::percent_decode_str(&tag_param)
+        .decode_utf8_lossy()
+        .trim()
+        .to_string();
+    if tag.is_empty() {
+        return Err(ApiError::TagNotFound(tag).into());
+    }
+
+    let cache_key = format!("related_tags:{}", tag.to_lowercase());
+    if let Some(cached) = state.cached(&cache_key) {
+        return Ok(cased_json(&state, cached));
+    }
+    let query_tag = tag.clone();
+    match run_blocking(&state, "related_tags", move |source| query_related_tags(source, &query_tag)).await {
+        Ok(Some(response)) => {
+            let value = serde_json::to_value(&response).unwrap_or_default();
+            state.store(&cache_key, value.clone());
+            Ok(cased_json(&state, value))
+        }
+        Ok(None) => Err(ApiError::TagNotFound(tag).into()),
+        Err(e) => Err(ApiError::database(format!("tags related to '{}'", tag), e).into()),
+    }
+}
+
+// JSON Schema of a response type, from /schema/<name>.json
+pub(crate) async fn get_schema(file: String, state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    let schema = file.strip_suffix(".json").and_then(|name| json_schema(&state.source, name));
+    match schema {
+        Some(schema) => Ok(warp::reply::with_header(
+            warp::reply::json(&schema),
+            warp::http::header::CONTENT_TYPE,
+            "application/schema+json",
+        )),
+        None => Err(warp::reject::not_found()),
+    }
+}
+
+// The tag map in effect: the file it was read from (null when none is configured) and its
+// alias -> tag entries
+pub(crate) async fn get_tag_mapping(state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(&*state.source.tag_map))
+}
+
+pub(crate) async fn get_related_news(
+    id: i64,
+    params: HashMap<String, String>,
+    state: AppState,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let limit = match params.get("limit") {
+        Some(value) => match value.parse::<usize>() {
+            Ok(limit) if (1..=MAX_RELATED_RECORDS).contains(&limit) => limit,
+            _ => return Err(ApiError::InvalidQueryParameter("limit").into()),
+        },
+        None => 10,
+    };
+
+    let cache_key = format!("related_news:{}:{}", id, limit);
+    if let Some(cached) = state.cached(&cache_key) {
+        return Ok(cased_json(&state, cached));
+    }
+    match run_blocking(&state, "related_news", move |source| query_related_news(source, id, limit)).await {
+        Ok(Some(response)) => {
+            let value = serde_json::to_value(&response).unwrap_or_default();
+            state.store(&cache_key, value.clone());
+            Ok(cased_json(&state, value))
+        }
+        Ok(None) => Err(ApiError::RecordNotFound(id).into()),
+        Err(e) => Err(ApiError::database(format!("news related to record {}", id), e).into()),
+    }
+}
+
+// The records whose text is closest in meaning to a record's, by the embeddings computed after
+// every sync
+pub(crate) async fn get_similar_news(
+    id: i64,
+    params: HashMap<String, String>,
+    state: AppState,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let limit = match params.get("limit") {
+        Some(value) => match value.parse::<usize>() {
+            Ok(limit) if (1..=MAX_RELATED_RECORDS).contains(&limit) => limit,
+            _ => return Err(ApiError::InvalidQueryParameter("limit").into()),
+        },
+        None => 10,
+    };
+    if !cfg!(feature = "embeddings") {
+        return Err(ApiError::EmbeddingsUnavailable.into());
+    }
+
+    let cache_key = format!("similar_news:{}:{}", id, limit);
+    if let Some(cached) = state.cached(&cache_key) {
+        return Ok(cased_json(&state, cached));
+    }
+    match run_blocking(&state, "similar_news", move |source| query_similar_news(source, id, limit)).await {
+        Ok(Some(response)) => {
+            let value = serde_json::to_value(&response).unwrap_or_default();
+            state.store(&cache_key, value.clone());
+            Ok(cased_json(&state, value))
+        }
+        Ok(None) => Err(ApiError::RecordNotFound(id).into()),
+        Err(e) => Err(ApiError::database(format!("news similar to record {}", id), e).into()),
+    }
+}
+
+// The images of a day's records, with the records whose image is missing, from ?date=yyyymmdd
+pub(crate) async fn get_images_meta(params: HashMap<String, String>, state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    let raw = params.get("date").ok_or(ApiError::InvalidQueryParameter("date"))?;
+    let day = match parse_date_param(raw)? {
+        DateParam::Day(day) => day,
+        DateParam::Month(_) => return Err(ApiError::InvalidDate(raw.trim().to_string()).into()),
+    };
+    let query_day = day.clone();
+    match run_blocking(&state, "images_meta", move |source| query_day_images(source, &query_day)).await {
+        Ok(Some(response)) => Ok(json_response(&state, &response)),
+        Ok(None) => Err(ApiError::NoDataFound(format!("date {}", day)).into()),
+        Err(e) => Err(ApiError::database(format!("images of {}", day), e).into()),
+    }
+}
+
+// The trend data behind a record's serpapi_id, unprocessed
+pub(crate) async fn get_serpapi(id: i64, state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    match run_blocking(&state, "serpapi", move |source| query_serpapi_row(source, id)).await {
+        Ok(Some(row)) => Ok(json_response(&state, &row)),
+        Ok(None) => Err(ApiError::NoDataFound(format!("serpapi record {}", id)).into()),
+        Err(e) => Err(ApiError::database(format!("serpapi record {}", id), e).into()),
+    }
+}
+
+// Full-text search over record texts and keywords: ?q= words must all match. Records come best
+// match first (or in ?sort= order) with the list filters, ?limit= and ?fields= applied, and
+// carry their matched terms in "highlights", wrapped in ?highlight_start= and ?highlight_end=
+pub(crate) async fn get_search(params: HashMap<String, String>, format: ResponseFormat, state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    let options = ListOptions { format, ..ListOptions::from_params(&params)? };
+    // Results are ranked over all records at once, so there is no next page to resume
+    if params.contains_key("cursor") {
+        return Err(ApiError::InvalidQueryParameter("cursor").into());
+    }
+    let q = params.get("q").map(|q| q.trim().to_string()).unwrap_or_default();
+    let Some(expression) = match_expression(&q) else {
+        return Err(ApiError::InvalidQueryParameter("q").into());
+    };
+    let marker = |key: &'static str, default: &str| match params.get(key) {
+        None => Ok(default.to_string()),
+        Some(marker) if !marker.is_empty() && marker.len() <= MAX_HIGHLIGHT_MARKER_LEN => Ok(marker.clone()),
+        Some(_) => Err(warp::Rejection::from(ApiError::InvalidQueryParameter(key))),
+    };
+    let markers = Markers {
+        start: marker("highlight_start", DEFAULT_HIGHLIGHT_START)?,
+        end: marker("highlight_end", DEFAULT_HIGHLIGHT_END)?,
+    };
+    let by_rank = !params.contains_key("sort");
+
+    let (query_q, query_options) = (q.clone(), options.clone());
+    match run_blocking(&state, "search", move |source| {
+        query_search(source, &query_q, &expression, &markers, by_rank, &query_options)
+    }).await {
+        Ok(response) => Ok(options.reply(&state, &response)),
+        Err(e) => Err(ApiError::database(format!("search for '{}'", q), e).into()),
+    }
+}
+
+pub(crate) async fn get_on_this_day(mmdd: String, params: HashMap<String, String>, format: ResponseFormat, state: AppState) -> Result<impl warp::Reply, warp::Rejection> {
+    let options = ListOptions { format, ..ListOptions::from_params(&params)? };
+
+    // Validate format (must be 4 digits forming a plausible month and day)
+    if mmdd.len() != 4 || !mmdd.chars().all(|c| c.is_ascii_digit()) {
+        return Err(ApiError::InvalidMonthDay(mmdd).into());
+    }
+    let month: u32 = mmdd[0..2].parse().unwrap_or(0);
+    let day: u32 = mmdd[2..4].parse().unwrap_or(0);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(ApiError::InvalidMonthDay(mmdd).into());
+    }
+
+    let (query_mmdd, query_options) = (mmdd.clone(), options.clone());
+    match run_blocking(&state, "on_this_day", move |source| query_on_this_day(source, &query_mmdd, &query_options)).await {
+        Ok(response) => {
+            if response.years.is_empty() {
+                Err(ApiError::NoDataFound(format!("month and day {}", mmdd)).into())
+            } else {
+                Ok(options.reply(&state, &response))
+            }
+        }
+        Err(e) => Err(ApiError::database(format!("news on this day {}", mmdd), e).into()),
+    }
+}
+
+// Run a handler as its own task so a panic inside it becomes a JSON 500 instead of a dropped
+// connection; the panic hook has already logged it with a backtrace
+pub(crate) async fn catch_panic<R: warp::Reply + 'static>(
+    handler: impl std::future::Future<Output = Result<R, warp::Rejection>> + Send + 'static,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    use warp::Reply;
+
+    match tokio::spawn(handler).await {
+        Ok(result) => result.map(Reply::into_response),
+        Err(_) => Err(ApiError::Panicked.into()),
+    }
+}
+
+// All data routes of one source, relative to its prefix
+pub(crate) fn source_routes(
+    state: AppState,
+    admin: AdminAuth,
+    max_body_bytes: usize,
+) -> warp::filters::BoxedFilter<(warp::reply::Response,)> {
+    use warp::Reply;
+
+    // GET or HEAD; hyper drops the body of HEAD responses but keeps their headers
+    let get_or_head = || warp::get().or(warp::head()).unify();
+
+    let latest = warp::path("latest")
+        .and(get_or_head())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(negotiate())
+        .and(with_state(state.clone()))
+        .and_then(|params, format, state| catch_panic(get_latest(params, format, state)));
+
+    let changes = warp::path("changes")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(negotiate())
+        .and(with_state(state.clone()))
+        .and_then(|params, format, state| catch_panic(get_changes(params, format, state)));
+
+    let recent = warp::path("recent")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(negotiate())
+        .and(with_state(state.clone()))
+        .and_then(|params, format, state| catch_panic(get_recent(params, format, state)));
+
+    let date_range = warp::path!("dates" / "range")
+        .and(get_or_head())
+        .and(with_state(state.clone()))
+        .and_then(|state| catch_panic(get_date_range(state)));
+
+    let dates = warp::path("dates")
+        .and(get_or_head())
+        .and(with_state(state.clone()))
+        .and_then(|state| catch_panic(get_dates(state)));
+
+    let health = warp::path("health")
+        .and(warp::get())
+        .and(with_state(state.clone()))
+        .and_then(|state| catch_panic(get_health(state)));
+
+    let meta = warp::path("meta")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(with_state(state.clone()))
+        .and_then(|state| catch_panic(get_meta(state)));
+
+    let version = warp::path("version")
+        .and(warp::get())
+        .and(with_state(state.clone()))
+        .and_then(|state| catch_panic(get_version(state)));
+
+    let stats = warp::path("stats")
+        .and(warp::get())
+        .and(with_state(state.clone()))
+        .and_then(|state| catch_panic(get_stats(state)));
+
+    let keyword_analytics = warp::path!("analytics" / "keywords")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_state(state.clone()))
+        .and_then(|params, state| catch_panic(get_keyword_analytics(params, state)));
+
+    let tag_mapping = warp::path!("tags" / "mapping")
+        .and(warp::get())
+        .and(with_state(state.clone()))
+        .and_then(|state| catch_panic(get_tag_mapping(state)));
+
+    let schema = warp::path!("schema" / String)
+        .and(warp::get())
+        .and(with_state(state.clone()))
+        .and_then(|file, state| catch_panic(get_schema(file, state)));
+
+    let keyword_series = warp::path!("analytics" / "keyword" / String)
+        .and(warp::get())
+        .and(with_state(state.clone()))
+        .and_then(|term, state| catch_panic(get_keyword_series(term, state)));
+
+    let tag_counts = warp::path!("tags" / "counts")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_state(state.clone()))
+        .and_then(|params, state| catch_panic(get_tag_counts(params, state)));
+
+    let related_tags = warp::path!("tags" / String / "related")
+        .and(warp::get())
+        .and(with_state(state.clone()))
+        .and_then(|tag, state| catch_panic(get_related_tags(tag, state)));
+
+    let thread = warp::path!("threads" / i64)
+        .and(warp::get())
+        .and(with_state(state.clone()))
+        .and_then(|id, state| catch_panic(get_thread(id, state)));
+
+    let related_news = warp::path!("news" / i64 / "related")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_state(state.clone()))
+        .and_then(|id, params, state| catch_panic(get_related_news(id, params, state)));
+
+    let similar_news = warp::path!("news" / i64 / "similar")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_state(state.clone()))
+        .and_then(|id, params, state| catch_panic(get_similar_news(id, params, state)));
+
+    let serpapi = warp::path!("serpapi" / i64)
+        .and(warp::get())
+        .and(with_state(state.clone()))
+        .and_then(|id, state| catch_panic(get_serpapi(id, state)));
+
+    let on_this_day = warp::path!("onthisday" / String)
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(negotiate())
+        .and(with_state(state.clone()))
+        .and_then(|mmdd, params, format, state| catch_panic(get_on_this_day(mmdd, params, format, state)));
+
+    let year = warp::path!("year" / String)
+        .and(warp::get())
+        .and(with_state(state.clone()))
+        .and_then(|year, state| catch_panic(get_year(year, state)));
+
+    let week = warp::path!("week" / String)
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(negotiate())
+        .and(with_state(state.clone()))
+        .and_then(|week, params, format, state| catch_panic(get_week(week, params, format, state)));
+
+    let adjacent_date = warp::path!("date" / String / String)
+        .and(warp::get())
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .and(with_state(state.clone()))
+        .and_then(|date, direction, query, state| catch_panic(get_adjacent_date(date, direction, query, state)));
+
+    let date = warp::path("date")
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(get_or_head())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(negotiate())
+        .and(with_state(state.clone()))
+        .and_then(|date, params, format, state| catch_panic(get_date(date, params, format, state)));
+
+    let search = warp::path("search")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(negotiate())
+        .and(with_state(state.clone()))
+        .and_then(|params, format, state| catch_panic(get_search(params, format, state)));
+
+    let export = warp::path!("export" / "all")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_state(state.clone()))
+        .and_then(|params, state| catch_panic(get_export(params, state)));
+
+    // Serve images from the source's images directory (the published one, with the atomic swap) via
+    // /images route; a file that isn't there gets the JSON error like every other route
+    let image_files = warp::fs::dir(state.source.serving_images_dir());
+    let missing_image = get_or_head()
+        .and(warp::path::full())
+        .and_then(|path: warp::path::FullPath| async move {
+            eprintln!("Image not found: {}", path.as_str());
+            Err::<warp::fs::File, _>(warp::Rejection::from(ApiError::ImageNotFound(path.as_str().to_string())))
+        });
+    let images_meta = warp::path!("images" / "meta")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_state(state.clone()))
+        .and_then(|params, state| catch_panic(get_images_meta(params, state)));
+
+    // /images/meta is the listing above, whose errors shouldn't turn into a missing image
+    let not_meta = warp::path::peek()
+        .and_then(|rest: warp::path::Peek| async move {
+            if rest.as_str() == "meta" {
+                Err(warp::reject::not_found())
+            } else {
+                Ok(())
+            }
+        })
+        .untuple_one();
+    let image_path = warp::path("images").and(not_meta).and(image_path_guard(state.source.serving_images_dir()));
+    // With an image bucket, objects are redirected to or fetched instead of local files
+    let images = match state.source.image_bucket.clone() {
+        Some(bucket) => image_path
+            .and(get_or_head())
+            .and(warp::path::tail())
+            .and(warp::path::full())
+            .and_then(move |tail: warp::path::Tail, full: warp::path::FullPath| {
+                let bucket = bucket.clone();
+                async move { bucket.respond(tail.as_str(), full.as_str()).await }
+            })
+            .boxed(),
+        None => image_path.and(image_files.or(missing_image).unify()).then(image_response).boxed(),
+    };
+
+    // Prebuilt thumbnails via /thumbnails/<width>/<image path>; an image without one (not built
+    // yet, or a width that isn't configured) is redirected to the original
+    let thumbnail_files = warp::fs::dir(state.source.thumbnails_dir.clone()).then(image_response);
+    let images_prefix = format!("{}/images", state.source.url_prefix());
+    let original_image = get_or_head()
+        .and(warp::path::param::<u32>())
+        .and(warp::path::tail())
+        .map(move |_width: u32, tail: warp::path::Tail| {
+            let mut response = warp::reply::Response::new(warp::hyper::Body::empty());
+            *response.status_mut() = warp::http::StatusCode::TEMPORARY_REDIRECT;
+            if let Ok(location) = warp::http::HeaderValue::from_str(&format!("{}/{}", images_prefix, tail.as_str())) {
+                response.headers_mut().insert(warp::http::header::LOCATION, location);
+            }
+            response
+        });
+    let thumbnails = warp::path("thumbnails")
+        .and(image_path_guard(state.source.thumbnails_dir.clone()))
+        .and(thumbnail_files.or(original_image).unify());
+
+    let purge_cache = warp::path!("admin" / "cache" / "purge")
+        .and(warp::post())
+        .and(require_admin(admin.clone()))
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_state(state.clone()))
+        .and_then(|caller, params, state| catch_panic(post_cache_purge(params, caller, state)));
+
+    let trigger_sync = warp::path!("admin" / "sync")
+        .and(warp::post())
+        .and(require_admin(admin.clone()))
+        .and(with_state(state.clone()))
+        .and_then(|caller, state| catch_panic(post_sync(caller, state)));
+
+    let sync_status = warp::path!("sync" / "status")
+        .and(warp::get())
+        .and(with_state(state.clone()))
+        .and_then(|state| catch_panic(get_sync_status(state)));
+
+    let sync_log = warp::path!("admin" / "sync" / "log")
+        .and(warp::get())
+        .and(require_admin(admin.clone()))
+        .and(with_state(state.clone()))
+        .and_then(|_caller, state| catch_panic(get_sync_log(state)));
+
+    let backup = warp::path!("admin" / "backup")
+        .and(warp::get())
+        .and(require_admin(admin.clone()))
+        .and(ready(state.clone()))
+        .and(with_state(state.clone()))
+        .and_then(|caller, state| catch_panic(get_backup(caller, state)));
+
+    let add_news = warp::path!("admin" / "news")
+        .and(warp::post())
+        .and(require_admin(admin.clone()))
+        .and(ready(state.clone()))
+        .and(body_limit(max_body_bytes))
+        .and(warp::body::bytes())
+        .and(with_state(state.clone()))
+        .and_then(|caller, body, state| catch_panic(post_news(body, caller, state)));
+
+    let remove_news = warp::path!("admin" / "news" / i64)
+        .and(warp::delete())
+        .and(require_admin(admin.clone()))
+        .and(ready(state.clone()))
+        .and(with_state(state.clone()))
+        .and_then(|id, caller, state| catch_panic(delete_news(id, caller, state)));
+
+    let edit_news = warp::path!("admin" / "news" / i64)
+        .and(warp::patch())
+        .and(require_admin(admin.clone()))
+        .and(ready(state.clone()))
+        .and(body_limit(max_body_bytes))
+        .and(warp::body::bytes())
+        .and(with_state(state.clone()))
+        .and_then(|id, caller, body, state| catch_panic(patch_news(id, body, caller, state)));
+
+    let data_routes = not_modified(state.clone())
+        .or(latest)
+        .or(recent)
+        .or(changes)
+        .or(date_range)
+        .or(dates)
+        .or(stats)
+        .or(keyword_analytics)
+        .or(keyword_series)
+        .or(tag_counts)
+        .or(related_tags)
+        .or(related_news)
+        .or(similar_news)
+        .or(thread)
+        .or(serpapi)
+        .or(on_this_day)
+        .or(week)
+        .or(year)
+        .or(adjacent_date)
+        .or(date)
+        .or(search)
+        .or(export)
+        .or(images_meta)
+        .or(images)
+        .or(thumbnails)
+        .map(Reply::into_response);
+
+    // Everything but /health, /meta, /version, /tags/mapping, /schema, /sync/status and admin routes waits for the source's first successful sync
+    let version_state = state.clone();
+    health
+        .map(Reply::into_response)
+        .or(meta.map(Reply::into_response))
+        .unify()
+        .or(version.map(Reply::into_response))
+        .unify()
+        .or(tag_mapping.map(Reply::into_response))
+        .unify()
+        .or(schema.map(Reply::into_response))
+        .unify()
+        .or(sync_status.map(Reply::into_response))
+        .unify()
+        .or(purge_cache)
+        .unify()
+        .or(trigger_sync)
+        .unify()
+        .or(sync_log)
+        .unify()
+        .or(backup)
+        .unify()
+        .or(add_news)
+        .unify()
+        .or(remove_news)
+        .unify()
+        .or(edit_news)
+        .unify()
+        .or(ready(state).and(data_routes))
+        .unify()
+        .map(move |response| with_data_version(&version_state, response))
+        .boxed()
+}
+
+// Passes only once the source has data, rejecting with a retryable 503 before that
+pub(crate) fn ready(state: AppState) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::any()
+        .and_then(move || {
+            let ready = state.is_ready();
+            async move {
+                if ready {
+                    Ok(())
+                } else {
+                    Err(warp::Rejection::from(ApiError::NotReady))
+                }
+            }
+        })
+        .untuple_one()
+}
+
+// Methods served by each known path, used to answer wrong verbs with 405 and an Allow header
+pub(crate) fn allowed_methods(path: &str) -> Option<&'static str> {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match segments.as_slice() {
+        ["latest"] | ["dates"] | ["dates", "range"] | ["date", _] => Some("GET, HEAD"),
+        ["images", "meta"] => Some("GET"),
+        ["images", ..] | ["thumbnails", ..] => Some("GET, HEAD"),
+        ["robots.txt"] | ["favicon.ico"] => Some("GET, HEAD"),
+        ["health"]
+        | ["version"]
+        | ["meta"]
+        | ["stats"]
+        | ["analytics", "keywords"]
+        | ["analytics", "keyword", _]
+        | ["tags", "mapping"]
+        | ["tags", "counts"]
+        | ["schema", _]
+        | ["tags", _, "related"]
+        | ["news", _, "related"]
+        | ["news", _, "similar"]
+        | ["threads", _]
+        | ["serpapi", _]
+        | ["onthisday", _]
+        | ["week", _]
+        | ["year", _]
+        | ["date", _, "next" | "prev"]
+        | ["search"]
+        | ["recent"]
+        | ["changes"]
+        | ["sync", "status"]
+        | ["metrics"]
+        | ["export", "all"] => Some("GET"),
+        ["admin", "cache", "purge"] | ["admin", "sync"] => Some("POST"),
+        ["admin", "sync", "log"] | ["admin", "backup"] => Some("GET"),
+        ["admin", "news"] => Some("POST"),
+        ["admin", "news", _] => Some("PATCH, DELETE"),
+        ["admin", "keys", _, "usage"] => Some("GET"),
+        _ => None,
+    }
+}
+
+// Last route: reached only when nothing else matched, turns known paths into a 405
+pub(crate) fn method_fallback(source_names: Vec<String>) -> impl Filter<Extract = (warp::reply::Response,), Error = warp::Rejection> + Clone {
+    warp::path::full()
+        .and(warp::method())
+        .and_then(move |path: warp::path::FullPath, method: warp::http::Method| {
+            let path = strip_source_prefix(path.as_str(), &source_names).to_string();
+            async move {
+                match allowed_methods(&path) {
+                    Some(allow) if !allow.split(", ").any(|m| m == method.as_str()) => {
+                        Err(ApiError::MethodNotAllowed { allow }.into())
+                    }
+                    _ => Err(warp::reject::not_found()),
+                }
+            }
+        })
+}
+
+// Drop a leading /<source name> segment so prefixed paths map onto the shared route table
+pub(crate) fn strip_source_prefix<'a>(path: &'a str, source_names: &[String]) -> &'a str {
+    for name in source_names {
+        if let Some(rest) = path.strip_prefix('/').and_then(|p| p.strip_prefix(name.as_str())) {
+            if rest.is_empty() || rest.starts_with('/') {
+                return rest;
+            }
+        }
+    }
+    path
+}
+
+// The whole server: every source's routes (the default one at the root, the others under
+// /<name>), the 405 fallback, CORS and the JSON error handler. states[i] serves config.sources[i].
+pub fn build_routes(
+    config: &Config,
+    states: &[AppState],
+) -> impl Filter<Extract = (impl warp::Reply,), Error = std::convert::Infallible> + Clone {
+    // CORS filter
+    let cors = warp::cors()
+        .allow_any_origin()
+        .allow_headers(vec!["content-type", "authorization", "x-api-key"])
+        .allow_methods(vec!["GET", "HEAD", "POST", "PATCH", "DELETE"])
+        .expose_headers(vec!["x-data-version", "x-request-id", "etag"]);
+
+    // Routes: the default source at the root, every other source under /<name>
+    let admin = AdminAuth::new(config);
+    let metrics = warp::path("metrics")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and_then(|| catch_panic(get_metrics()));
+    // API keys are server-wide, so their usage is only served at the root
+    let api_keys = ApiKeys::new(config);
+    let usage_keys = api_keys.clone();
+    let key_usage = warp::path!("admin" / "keys" / String / "usage")
+        .and(warp::get())
+        .and(require_admin(admin.clone()))
+        .and_then(move |id, _caller| catch_panic(get_key_usage(id, usage_keys.clone())));
+    let mut routes = metrics
+        .or(key_usage)
+        .unify()
+        .or(source_routes(states[0].clone(), admin.clone(), config.max_body_bytes))
+        .unify()
+        .boxed();
+    for state in &states[1..] {
+        let name = state.source.name.clone().unwrap_or_default();
+        let prefixed = warp::path(name).and(source_routes(state.clone(), admin.clone(), config.max_body_bytes));
+        routes = routes.or(prefixed).unify().boxed();
+    }
+    let source_names: Vec<String> = config.sources.iter().filter_map(|s| s.name.clone()).collect();
+    if let Some(dir) = &config.static_dir {
+        routes = routes.or(static_routes(dir.clone(), source_names.clone())).unify().boxed();
+    }
+    // After the frontend, so its own robots.txt or favicon.ico takes precedence
+    routes = routes.or(site_routes(config.robots_txt.clone())).unify().boxed();
+
+    // Keys are metered and admission happens before routing, so refused and shed requests never
+    // touch the database
+    let routes = query_limit(config.max_query_bytes)
+        .and(meter(api_keys))
+        .and(admit(ConcurrencyLimits::new(config)))
+        .and(routes.or(method_fallback(source_names)).unify())
+        .map(|admission: Admission, response: warp::reply::Response| admission.hold_until_sent(response))
+        .with(cors)
+        .recover(handle_rejection);
+    request_context(config.trusted_proxies.clone())
+        .and(routes)
+        .map(finish_response)
+}
+
+// An API key's limits and its requests over the last days
+pub(crate) async fn get_key_usage(id: String, keys: ApiKeys) -> Result<impl warp::Reply, warp::Rejection> {
+    let lookup = id.clone();
+    match tokio::task::spawn_blocking(move || keys.usage(&lookup)).await {
+        Ok(Ok(Some(usage))) => Ok(warp::reply::json(&usage)),
+        Ok(Ok(None)) => Err(ApiError::ApiKeyNotFound(id).into()),
+        Ok(Err(e)) => Err(ApiError::database("key usage", e).into()),
+        Err(_) => Err(ApiError::Panicked.into()),
+    }
+}
+
+// Query timings of all sources in the Prometheus text format, for scraping
+pub(crate) async fn get_metrics() -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::with_header(
+        render_metrics(),
+        warp::http::header::CONTENT_TYPE,
+        "text/plain; version=0.0.4; charset=utf-8",
+    ))
+}
+
+// Files of a frontend build, plus index.html for any other extension-less GET outside the API
+// so client-side routes survive a reload. Mounted after the API, which therefore wins.
+pub(crate) fn static_routes(dir: std::path::PathBuf, source_names: Vec<String>) -> warp::filters::BoxedFilter<(warp::reply::Response,)> {
+    use warp::Reply;
+
+    // Other methods fall through as 404 like any unknown path, rather than warp's 405 rejection
+    let get_or_head = || {
+        warp::method()
+            .and_then(|method: warp::http::Method| async move {
+                if method == warp::http::Method::GET || method == warp::http::Method::HEAD {
+                    Ok(())
+                } else {
+                    Err(warp::reject::not_found())
+                }
+            })
+            .untuple_one()
+    };
+    let files = get_or_head().and(warp::fs::dir(dir.clone()));
+    let index = get_or_head()
+        .and(warp::path::full())
+        .and_then(move |path: warp::path::FullPath| {
+            let path = strip_source_prefix(path.as_str(), &source_names).to_string();
+            async move {
+                let is_api = allowed_methods(&path).is_some() || path.starts_with("/images/");
+                let is_asset = path.rsplit('/').next().is_some_and(|name| name.contains('.'));
+                if is_api || is_asset {
+                    Err(warp::reject::not_found())
+                } else {
+                    Ok(())
+                }
+            }
+        })
+        .untuple_one()
+        .and(warp::fs::file(dir.join("index.html")));
+
+    files
+        .or(index)
+        .unify()
+        .map(Reply::into_response)
+        .boxed()
+}
+
+// Let an image path through only if it names a visible file inside `dir`: "." and ".." segments,
+// backslashes and NUL bytes are rejected (400) before touching the filesystem, dotfiles and paths
+// resolving outside the directory (through a symlink) are reported missing (404)
+pub(crate) fn image_path_guard(dir: std::path::PathBuf) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::path::peek()
+        .and(warp::path::full())
+        .and_then(move |rest: warp::path::Peek, full: warp::path::FullPath| {
+            let dir = dir.clone();
+            async move {
+                let mut path = dir.clone();
+                for segment in rest.segments() {
+                    let segment = percent_encoding::percent_decode_str(segment)
+                        .decode_utf8()
+                        .map_err(|_| ApiError::InvalidImagePath(full.as_str().to_string()))?;
+                    if segment == "." || segment == ".." || segment.contains(['/', '\\', '\0']) {
+                        return Err(ApiError::InvalidImagePath(full.as_str().to_string()).into());
+                    }
+                    if segment.starts_with('.') {
+                        return Err(warp::Rejection::from(ApiError::ImageNotFound(full.as_str().to_string())));
+                    }
+                    path.push(segment.as_ref());
+                }
+                // Missing files are left to the file route
+                if let (Ok(resolved), Ok(root)) = (tokio::fs::canonicalize(&path).await, tokio::fs::canonicalize(&dir).await) {
+                    if !resolved.starts_with(&root) {
+                        eprintln!("Image path {} resolves outside {}", full.as_str(), dir.display());
+                        return Err(ApiError::ImageNotFound(full.as_str().to_string()).into());
+                    }
+                }
+                Ok(())
+            }
+        })
+        .untuple_one()
+}
+
+// Image type from a file's first bytes; None for formats not recognized here
+pub(crate) fn sniff_image_type(head: &[u8]) -> Option<&'static str> {
+    match head {
+        [0xFF, 0xD8, 0xFF, ..] => Some("image/jpeg"),
+        [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => Some("image/png"),
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some("image/gif"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("image/webp"),
+        [_, _, _, _, b'f', b't', b'y', b'p', b'a', b'v', b'i', b's' | b'f', ..] => Some("image/avif"),
+        [b'B', b'M', ..] => Some("image/bmp"),
+        [0x00, 0x00, 0x01, 0x00, ..] => Some("image/x-icon"),
+        _ => None,
+    }
+}
+
+// Serve an image with the type its contents show, since synced files can have odd or missing
+// extensions; unrecognized files keep the type of their extension. nosniff stops browsers from
+// guessing anything else.
+pub(crate) async fn image_response(file: warp::fs::File) -> warp::reply::Response {
+    use tokio::io::AsyncReadExt;
+    use warp::Reply;
+
+    let mut head = [0u8; 16];
+    let mut read = 0;
+    if let Ok(mut opened) = tokio::fs::File::open(file.path()).await {
+        while read < head.len() {
+            match opened.read(&mut head[read..]).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => read += n,
+            }
+        }
+    }
+    let mut response = file.into_response();
+    let headers = response.headers_mut();
+    if let Some(content_type) = sniff_image_type(&head[..read]) {
+        headers.insert(warp::http::header::CONTENT_TYPE, warp::http::HeaderValue::from_static(content_type));
+    }
+    headers.insert(warp::http::header::X_CONTENT_TYPE_OPTIONS, warp::http::HeaderValue::from_static("nosniff"));
+    response
+}
+
+// Embedded favicon, so browsers asking for it don't fill the logs with 404s
+const FAVICON: &[u8] = include_bytes!("../assets/favicon.ico");
+
+// /robots.txt and /favicon.ico at the root, cacheable for a day
+pub(crate) fn site_routes(robots_txt: String) -> warp::filters::BoxedFilter<(warp::reply::Response,)> {
+    use warp::Reply;
+
+    let get_or_head = || warp::get().or(warp::head()).unify();
+    let robots = warp::path("robots.txt")
+        .and(warp::path::end())
+        .and(get_or_head())
+        .map(move || {
+            warp::reply::with_header(robots_txt.clone(), warp::http::header::CONTENT_TYPE, "text/plain; charset=utf-8")
+                .into_response()
+        });
+    let favicon = warp::path("favicon.ico")
+        .and(warp::path::end())
+        .and(get_or_head())
+        .map(|| warp::reply::with_header(FAVICON, warp::http::header::CONTENT_TYPE, "image/x-icon").into_response());
+    robots
+        .or(favicon)
+        .unify()
+        .map(|reply| warp::reply::with_header(reply, warp::http::header::CACHE_CONTROL, "public, max-age=86400").into_response())
+        .boxed()
+}
+
+// Per-request details echoed in error bodies and the X-Request-Id header
+#[derive(Debug, Clone)]
+pub(crate) struct RequestContext {
+    pub(crate) id: String,
+    pub(crate) method: String,
+    pub(crate) path: String,
+    // For the access log
+    pub(crate) query: Option<String>,
+    // The peer, or the client named by a trusted proxy
+    pub(crate) client: Option<std::net::IpAddr>,
+    pub(crate) started: std::time::Instant,
+}
+
+// Reuse a caller-supplied X-Request-Id when it is short and printable, otherwise mint one
+pub(crate) fn request_context(
+    proxies: TrustedProxies,
+) -> impl Filter<Extract = (RequestContext,), Error = std::convert::Infallible> + Clone {
+    let query = warp::query::raw().map(Some).or(warp::any().map(|| None)).unify();
+    warp::path::full()
+        .and(warp::method())
+        .and(query)
+        .and(warp::addr::remote())
+        .and(warp::header::headers_cloned())
+        .map(move |path: warp::path::FullPath,
+              method: warp::http::Method,
+              query: Option<String>,
+              remote: Option<std::net::SocketAddr>,
+              headers: warp::http::HeaderMap| {
+            let supplied = headers.get("x-request-id")
+                .and_then(|v| v.to_str().ok())
+                .filter(|id| {
+                    (1..=64).contains(&id.len())
+                        && id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+                })
+                .map(str::to_string);
+            RequestContext {
+                id: supplied.unwrap_or_else(next_request_id),
+                method: method.to_string(),
+                path: path.as_str().to_string(),
+                query,
+                client: proxies.client_ip(remote.map(|addr| addr.ip()), &headers),
+                started: std::time::Instant::now(),
+            }
+        })
+}
+
+// Unique within the process and unlikely to repeat across restarts: start time plus a counter
+pub(crate) fn next_request_id() -> String {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::OnceLock;
+
+    static STARTED: OnceLock<u64> = OnceLock::new();
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let started = *STARTED.get_or_init(|| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default()
+    });
+    format!("{:x}-{:06x}", started, COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+// Tag every response with its request id and complete error bodies with the path and id. Server
+// errors are reported with the request they happened in (panics are reported by their hook).
+pub(crate) fn finish_response(context: RequestContext, reply: impl warp::Reply) -> warp::reply::Response {
+    let mut response = reply.into_response();
+    if access_log_enabled() {
+        // Time to the response head; streamed bodies (exports) are still being sent
+        println!(
+            "{} {} {}{} {} {:.1}ms {}",
+            context.client.map(|ip| ip.to_string()).unwrap_or_else(|| "-".to_string()),
+            context.method,
+            context.path,
+            context.query.as_deref().map(|q| format!("?{}", q)).unwrap_or_default(),
+            response.status().as_u16(),
+            context.started.elapsed().as_secs_f64() * 1000.0,
+            context.id
+        );
+    }
+    if let Some(mut body) = response.extensions_mut().remove::<ErrorBody>() {
+        if let Some(detail) = &body.detail {
+            let request = ReportRequest {
+                id: &context.id,
+                method: &context.method,
+                path: &context.path,
+                client: context.client,
+            };
+            report("error", detail, &[("code", body.code)], Some(request));
+        }
+        body.path = Some(context.path);
+        body.request_id = Some(context.id.clone());
+        if let Ok(json) = serde_json::to_vec(&body) {
+            *response.body_mut() = warp::hyper::Body::from(json);
+        }
+    }
+    if let Ok(value) = warp::http::HeaderValue::from_str(&context.id) {
+        response.headers_mut().insert("x-request-id", value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{build_routes, camel_case_keys, list_cache_key, parse_date_param, parse_iso_week, DateParam};
+    use crate::config::{
+        Config, DataSource, DEFAULT_MAX_BODY_BYTES, DEFAULT_MAX_IN_FLIGHT, DEFAULT_MAX_IN_FLIGHT_PER_ROUTE,
+        DEFAULT_MAX_QUERY_BYTES, RESPONSE_CACHE_CAPACITY,
+    };
+    use crate::error::ApiError;
+    use crate::list::ListOptions;
+    use crate::AppState;
+
+    // A scratch directory with images/ (one image, one dotfile) and, beside it, a secret file
+    // images/escape links to
+    fn image_tree(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("trend-story-routes-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("images/20251101")).unwrap();
+        std::fs::create_dir_all(root.join("outside")).unwrap();
+        std::fs::write(root.join("images/20251101/story.png"), b"\x89PNG\r\n\x1a\n").unwrap();
+        std::fs::write(root.join("images/.hidden"), b"hidden").unwrap();
+        std::fs::write(root.join("outside/secret.txt"), b"secret").unwrap();
+        std::os::unix::fs::symlink(root.join("outside"), root.join("images/escape")).unwrap();
+        std::os::unix::fs::symlink(root.join("outside/secret.txt"), root.join("images/secret.png")).unwrap();
+        root
+    }
+
+    fn config(root: &std::path::Path) -> Config {
+        let mut source = DataSource::default_source();
+        source.repo_path = root.to_path_buf();
+        source.db_path = root.join("trends_data.db");
+        source.images_dir = root.join("images");
+        Config {
+            sources: vec![source],
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            max_in_flight_per_route: DEFAULT_MAX_IN_FLIGHT_PER_ROUTE,
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+            max_query_bytes: DEFAULT_MAX_QUERY_BYTES,
+            static_dir: None,
+            admin_token: None,
+            admin_tokens_file: None,
+            jwt: Default::default(),
+            api_keys_file: None,
+            api_key_required: false,
+            api_usage_path: root.join("usage.db"),
+            sentry_dsn: None,
+            log_file: None,
+            robots_txt: String::new(),
+            trusted_proxies: Default::default(),
+        }
+    }
+
+    async fn image_status(name: &str, path: &str) -> u16 {
+        let root = image_tree(name);
+        let config = config(&root);
+        let state = AppState::new(config.sources[0].clone());
+        state.set_ready(true);
+        let routes = build_routes(&config, &[state]);
+        let response = warp::test::request().path(path).reply(&routes).await;
+        let _ = std::fs::remove_dir_all(&root);
+        response.status().as_u16()
+    }
+
+    #[tokio::test]
+    async fn serves_an_image() {
+        assert_eq!(image_status("image", "/images/20251101/story.png").await, 200);
+    }
+
+    #[tokio::test]
+    async fn rejects_encoded_traversal() {
+        let status = image_status("encoded", "/images/..%2foutside%2fsecret.txt").await;
+        assert!(status == 400 || status == 404, "status {}", status);
+        let status = image_status("encoded-upper", "/images/20251101/..%2F..%2Foutside%2Fsecret.txt").await;
+        assert!(status == 400 || status == 404, "status {}", status);
+    }
+
+    #[tokio::test]
+    async fn rejects_literal_traversal() {
+        let status = image_status("literal", "/images/../outside/secret.txt").await;
+        assert!(status == 400 || status == 404, "status {}", status);
+        let status = image_status("literal-nested", "/images/20251101/../../outside/secret.txt").await;
+        assert!(status == 400 || status == 404, "status {}", status);
+    }
+
+    #[tokio::test]
+    async fn hides_dotfiles() {
+        assert_eq!(image_status("dotfile", "/images/.hidden").await, 404);
+    }
+
+    #[tokio::test]
+    async fn refuses_symlinks_out_of_the_images_dir() {
+        assert_eq!(image_status("symlink-dir", "/images/escape/secret.txt").await, 404);
+        assert_eq!(image_status("symlink-file", "/images/secret.png").await, 404);
+    }
+
+    #[tokio::test]
+    async fn health_shows_no_paths() {
+        let root = image_tree("health");
+        let config = config(&root);
+        let routes = build_routes(&config, &[AppState::new(config.sources[0].clone())]);
+        let health = warp::test::request().path("/health").reply(&routes).await;
+        let _ = std::fs::remove_dir_all(&root);
+
+        assert_eq!(health.status(), 503);
+        let body = String::from_utf8_lossy(health.body());
+        assert!(body.contains("\"status\":\"not_ready\""), "{}", body);
+        assert!(!body.contains(&*root.to_string_lossy()), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn version_answers_before_the_first_sync() {
+        let root = image_tree("version");
+        let config = config(&root);
+        let routes = build_routes(&config, &[AppState::new(config.sources[0].clone())]);
+        let version = warp::test::request().path("/version").reply(&routes).await;
+        let latest = warp::test::request().path("/latest").reply(&routes).await;
+        let _ = std::fs::remove_dir_all(&root);
+
+        assert_eq!(version.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(version.body()).unwrap();
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert!(body["data_commit"].is_null());
+        assert_eq!(latest.status(), 503);
+    }
+
+    #[test]
+    fn list_cache_key_ignores_parameters_that_choose_nothing() {
+        let params = |pairs: &[(&str, &str)]| -> std::collections::HashMap<String, String> {
+            pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+        };
+        let key = |pairs: &[(&str, &str)]| {
+            let options = ListOptions::from_params(&params(pairs)).unwrap();
+            list_cache_key("date", "2025-11-01", &options)
+        };
+
+        assert_eq!(key(&[("tag", "ai")]), key(&[("tag", " ai "), ("junk", "1"), ("fields", "id")]));
+        assert_eq!(key(&[]), key(&[("order", "asc")]));
+        assert_ne!(key(&[("tag", "ai")]), key(&[("keyword", "ai")]));
+        assert_ne!(key(&[]), key(&[("limit", "5")]));
+    }
+
+    #[test]
+    fn response_cache_is_bounded() {
+        let root = image_tree("cache");
+        let state = AppState::new(config(&root).sources[0].clone());
+        let _ = std::fs::remove_dir_all(&root);
+
+        for n in 0..RESPONSE_CACHE_CAPACITY + 10 {
+            state.store(&format!("stats:{}", n), serde_json::json!(n));
+        }
+        assert_eq!(state.cache.read().unwrap().len(), RESPONSE_CACHE_CAPACITY);
+        assert_eq!(state.cached(&format!("stats:{}", RESPONSE_CACHE_CAPACITY + 9)), Some(serde_json::json!(RESPONSE_CACHE_CAPACITY + 9)));
+    }
+
+    #[test]
+    fn parses_compact_and_iso_dates() {
+        assert_eq!(parse_date_param("20251101").unwrap(), DateParam::Day("2025-11-01".to_string()));
+        assert_eq!(parse_date_param(" 2025-11-01 ").unwrap(), DateParam::Day("2025-11-01".to_string()));
+        assert_eq!(parse_date_param("202511").unwrap(), DateParam::Month("2025-11".to_string()));
+        assert_eq!(parse_date_param("2025-11").unwrap(), DateParam::Month("2025-11".to_string()));
+    }
+
+    #[test]
+    fn accepts_leap_days_only_in_leap_years() {
+        assert_eq!(parse_date_param("20240229").unwrap(), DateParam::Day("2024-02-29".to_string()));
+        assert_eq!(parse_date_param("2000-02-29").unwrap(), DateParam::Day("2000-02-29".to_string()));
+        for day in ["20250229", "1900-02-29", "20240230"] {
+            assert!(matches!(parse_date_param(day), Err(ApiError::NonexistentDate(_))), "{}", day);
+        }
+    }
+
+    #[test]
+    fn tells_nonexistent_dates_from_malformed_ones() {
+        for date in ["202513", "2025-00", "20251301", "20250132", "20250100", "2025-04-31"] {
+            assert!(matches!(parse_date_param(date), Err(ApiError::NonexistentDate(_))), "{}", date);
+        }
+        for date in ["", "2025", "2025110", "202511011", "2025-1-01", "2025/11/01", "20251-101", "2025-11-0a", "+2025-11"] {
+            assert!(matches!(parse_date_param(date), Err(ApiError::InvalidDate(_))), "{}", date);
+        }
+    }
+
+    #[test]
+    fn parses_iso_weeks() {
+        let monday = |y, m, d| chrono::NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        assert_eq!(parse_iso_week("2025W44"), Some((2025, 44, monday(2025, 10, 27))));
+        assert_eq!(parse_iso_week("2025-w01"), Some((2025, 1, monday(2024, 12, 30))));
+        // 2020 and 2026 have 53 ISO weeks, 2021 doesn't
+        assert_eq!(parse_iso_week("2020W53"), Some((2020, 53, monday(2020, 12, 28))));
+        assert_eq!(parse_iso_week("2026-W53"), Some((2026, 53, monday(2026, 12, 28))));
+        for week in ["2021W53", "2025W00", "2025W54", "2025W4", "25W44", "2025-44", "2025--W44", "2025Wab"] {
+            assert_eq!(parse_iso_week(week), None, "{}", week);
+        }
+    }
+
+    #[test]
+    fn camel_cases_field_names_throughout() {
+        let mut value = serde_json::json!({
+            "total_records": 2,
+            "date_with_url": "https://example.com/date/20251101",
+            "news": [
+                {"serpapi_id": 1, "top_tags": [{"tag_name": "ai", "news_count": 3}], "image": {"file_name": "a.png"}},
+                [{"image_url_2x": null}],
+            ],
+        });
+        camel_case_keys(&mut value);
+        assert_eq!(value, serde_json::json!({
+            "totalRecords": 2,
+            "dateWithUrl": "https://example.com/date/20251101",
+            "news": [
+                {"serpapiId": 1, "topTags": [{"tagName": "ai", "newsCount": 3}], "image": {"fileName": "a.png"}},
+                [{"imageUrl2x": null}],
+            ],
+        }));
+    }
+
+    #[test]
+    fn keeps_camel_case_keys_data_keys_and_values() {
+        let mut value = serde_json::json!({
+            "nextCursor": "abc_def",
+            "date": "2025-11-01",
+            "counts": {"New_York": 1, "machine learning": 2, "ai": 3, "é_x": 4},
+        });
+        let expected = value.clone();
+        camel_case_keys(&mut value);
+        assert_eq!(value, expected);
+    }
+}
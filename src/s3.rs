@@ -0,0 +1,263 @@
+// Images from an S3-compatible bucket instead of the local images directory, so a deployment
+// doesn't need the image tree on disk: /images/<path> either redirects to a presigned URL of the
+// object <prefix><path> (AWS Signature Version 4, query string form, path-style addressing) or
+// fetches the object through curl and streams it itself. Buckets with public reads work without
+// keys, as plain redirects or fetches.
+
+use std::process::Stdio;
+use percent_encoding::{percent_decode_str, utf8_percent_encode};
+use tokio::io::AsyncReadExt;
+
+use crate::config::{S3_PRESIGN_SECONDS, S3_TIMEOUT_SECONDS};
+use crate::crypto::{hmac_sha256, sha256};
+use crate::error::ApiError;
+use crate::routes::sniff_image_type;
+use crate::urls::URL_UNRESERVED;
+
+// How /images/ hands out the objects
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageDelivery {
+    // 307 to a presigned URL, so image bytes never pass through the server
+    Redirect,
+    // Fetched and served like a local file, for buckets clients can't reach
+    Proxy,
+}
+
+impl ImageDelivery {
+    pub fn parse(raw: &str) -> Option<ImageDelivery> {
+        match raw.trim().to_lowercase().as_str() {
+            "redirect" => Some(ImageDelivery::Redirect),
+            "proxy" => Some(ImageDelivery::Proxy),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ImageBucket {
+    // Origin of the S3 API, e.g. https://s3.eu-west-1.amazonaws.com
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    // Prepended to image paths to form object keys, e.g. "images/"
+    pub prefix: String,
+    // Credentials signing the URLs; unsigned URLs without them
+    pub access_key: Option<String>,
+    pub secret_key: Option<String>,
+    pub delivery: ImageDelivery,
+}
+
+// Debug without the secret key, as sources are Debug
+impl std::fmt::Debug for ImageBucket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ImageBucket")
+            .field("endpoint", &self.endpoint)
+            .field("bucket", &self.bucket)
+            .field("region", &self.region)
+            .field("prefix", &self.prefix)
+            .field("access_key", &self.access_key)
+            .field("secret_key", &self.secret_key.as_ref().map(|_| "<redacted>"))
+            .field("delivery", &self.delivery)
+            .finish()
+    }
+}
+
+// Bytes of an object read before answering, enough for sniff_image_type to tell its type
+const SNIFF_BYTES: usize = 16;
+const PROXY_CHUNK_BYTES: usize = 64 * 1024;
+
+// Append what a read into `chunk` returned to `buffered`; true at the end of the output
+fn append_read(buffered: &mut Vec<u8>, chunk: &[u8], read: std::io::Result<usize>) -> bool {
+    match read {
+        Ok(0) | Err(_) => true,
+        Ok(n) => {
+            buffered.extend_from_slice(&chunk[..n]);
+            false
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn encode(raw: &str) -> String {
+    utf8_percent_encode(raw, URL_UNRESERVED).to_string()
+}
+
+impl ImageBucket {
+    fn host(&self) -> &str {
+        let rest = self.endpoint.split_once("://").map(|(_, rest)| rest).unwrap_or(&self.endpoint);
+        rest.split('/').next().unwrap_or_default()
+    }
+
+    // /<bucket>/<key>, each segment encoded as Signature Version 4 wants it
+    fn object_path(&self, image_path: &str) -> String {
+        let key = format!("{}{}", self.prefix, image_path);
+        let segments: Vec<String> = key.split('/').map(encode).collect();
+        format!("/{}/{}", encode(&self.bucket), segments.join("/"))
+    }
+
+    // URL of the object of an image path (decoded), valid for S3_PRESIGN_SECONDS when signed
+    pub(crate) fn object_url(&self, image_path: &str, now: chrono::DateTime<chrono::Utc>) -> String {
+        let path = self.object_path(image_path);
+        let (Some(access_key), Some(secret_key)) = (&self.access_key, &self.secret_key) else {
+            return format!("{}://{}{}", self.scheme(), self.host(), path);
+        };
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        // Already in the sorted order the canonical request needs
+        let query = format!(
+            "X-Amz-Algorithm=AWS4-HMAC-SHA256&X-Amz-Credential={}&X-Amz-Date={}&X-Amz-Expires={}&X-Amz-SignedHeaders=host",
+            encode(&format!("{}/{}", access_key, scope)),
+            amz_date,
+            S3_PRESIGN_SECONDS
+        );
+        let canonical_request = format!("GET\n{}\n{}\nhost:{}\n\nhost\nUNSIGNED-PAYLOAD", path, query, self.host());
+        let string_to_sign =
+            format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", amz_date, scope, hex(&sha256(canonical_request.as_bytes())));
+        let mut key = hmac_sha256(format!("AWS4{}", secret_key).as_bytes(), date.as_bytes());
+        for part in [self.region.as_str(), "s3", "aws4_request"] {
+            key = hmac_sha256(&key, part.as_bytes());
+        }
+        let signature = hex(&hmac_sha256(&key, string_to_sign.as_bytes()));
+        format!("{}://{}{}?{}&X-Amz-Signature={}", self.scheme(), self.host(), path, query, signature)
+    }
+
+    fn scheme(&self) -> &str {
+        self.endpoint.split_once("://").map(|(scheme, _)| scheme).unwrap_or("https")
+    }
+
+    // Answer /images/<tail> from the bucket: a redirect, or the object streamed from curl with
+    // its Content-Length and ETag. A missing object is a missing image; any other failure of the
+    // bucket (an error status, a refused signature, no answer in time) a 502.
+    pub(crate) async fn respond(&self, tail: &str, full_path: &str) -> Result<warp::reply::Response, warp::Rejection> {
+        let image_path = percent_decode_str(tail)
+            .decode_utf8()
+            .map_err(|_| ApiError::InvalidImagePath(full_path.to_string()))?;
+        let url = self.object_url(&image_path, chrono::Utc::now());
+        let mut response = warp::reply::Response::new(warp::hyper::Body::empty());
+        if self.delivery == ImageDelivery::Redirect {
+            *response.status_mut() = warp::http::StatusCode::TEMPORARY_REDIRECT;
+            if let Ok(location) = warp::http::HeaderValue::from_str(&url) {
+                response.headers_mut().insert(warp::http::header::LOCATION, location);
+            }
+            return Ok(response);
+        }
+
+        // The response head comes first on stdout (-D -), then the object
+        let child = tokio::process::Command::new("curl")
+            .args(["-sS", "-D", "-", "--max-time", &S3_TIMEOUT_SECONDS.to_string()])
+            .arg(&url)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(e) => {
+                eprintln!("Cannot fetch {} from bucket {}: {}", full_path, self.bucket, e);
+                return Err(ApiError::ImageUpstream(full_path.to_string()).into());
+            }
+        };
+        let Some(mut stdout) = child.stdout.take() else {
+            return Err(ApiError::ImageUpstream(full_path.to_string()).into());
+        };
+        let mut buffered = Vec::new();
+        let mut chunk = vec![0u8; PROXY_CHUNK_BYTES];
+        let mut finished = false;
+        let head_end = loop {
+            if let Some(end) = buffered.windows(4).position(|window| window == b"\r\n\r\n") {
+                break Some(end + 4);
+            }
+            let n = stdout.read(&mut chunk).await;
+            if append_read(&mut buffered, &chunk, n) {
+                break None;
+            }
+        };
+        let Some(head_end) = head_end else {
+            // No answer at all: unreachable endpoint, timeout
+            let stderr = child.wait_with_output().await.map(|output| output.stderr).unwrap_or_default();
+            eprintln!(
+                "Cannot fetch {} from bucket {}: {}",
+                full_path,
+                self.bucket,
+                String::from_utf8_lossy(&stderr).trim()
+            );
+            return Err(ApiError::ImageUpstream(full_path.to_string()).into());
+        };
+        let head = String::from_utf8_lossy(&buffered[..head_end]).into_owned();
+        let status = head.split_whitespace().nth(1).and_then(|code| code.parse::<u16>().ok()).unwrap_or_default();
+        if status != 200 {
+            eprintln!("Bucket {} answered {} for {}", self.bucket, status, full_path);
+            return Err(match status {
+                404 => ApiError::ImageNotFound(full_path.to_string()),
+                _ => ApiError::ImageUpstream(full_path.to_string()),
+            }
+            .into());
+        }
+        let header = |name: &str| {
+            head.lines().skip(1).find_map(|line| {
+                let (key, value) = line.split_once(':')?;
+                key.trim().eq_ignore_ascii_case(name).then(|| value.trim().to_string())
+            })
+        };
+
+        let mut body = buffered.split_off(head_end);
+        while body.len() < SNIFF_BYTES && !finished {
+            let n = stdout.read(&mut chunk).await;
+            finished = append_read(&mut body, &chunk, n);
+        }
+        let content_type = sniff_image_type(&body).unwrap_or("application/octet-stream");
+        let headers = response.headers_mut();
+        headers.insert(warp::http::header::CONTENT_TYPE, warp::http::HeaderValue::from_static(content_type));
+        headers.insert(warp::http::header::X_CONTENT_TYPE_OPTIONS, warp::http::HeaderValue::from_static("nosniff"));
+        for (name, value) in [
+            (warp::http::header::CONTENT_LENGTH, header("content-length")),
+            (warp::http::header::ETAG, header("etag")),
+        ] {
+            if let Some(value) = value.and_then(|value| warp::http::HeaderValue::from_str(&value).ok()) {
+                headers.insert(name, value);
+            }
+        }
+
+        // The rest is passed on as it arrives; a transfer failing halfway aborts the body, so the
+        // client sees it cut short rather than complete
+        let (mut sender, streamed) = warp::hyper::Body::channel();
+        let bucket = self.bucket.clone();
+        let path = full_path.to_string();
+        tokio::spawn(async move {
+            let mut failed = sender.send_data(body.into()).await.is_err();
+            while !finished && !failed {
+                match stdout.read(&mut chunk).await {
+                    Ok(0) => finished = true,
+                    Ok(n) => failed = sender.send_data(chunk[..n].to_vec().into()).await.is_err(),
+                    Err(_) => failed = true,
+                }
+            }
+            if failed {
+                let _ = child.kill().await;
+                return;
+            }
+            match child.wait_with_output().await {
+                Ok(output) if output.status.success() => {}
+                Ok(output) => {
+                    eprintln!(
+                        "Fetching {} from bucket {} failed halfway: {}",
+                        path,
+                        bucket,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    );
+                    sender.abort();
+                }
+                Err(e) => {
+                    eprintln!("Fetching {} from bucket {} failed halfway: {}", path, bucket, e);
+                    sender.abort();
+                }
+            }
+        });
+        *response.body_mut() = streamed;
+        Ok(response)
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use rusqlite::{params, Connection, Result as SqlResult};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{DataSource, SEARCH_DEFAULT_LIMIT, SEARCH_SNIPPET_TOKENS};
+use crate::db::{open_database, query_filtered_records, NewsRecord};
+use crate::list::ListOptions;
+
+// Full-text index of record texts and keywords, keyed by record id (rowid), read through the
+// views so local edits are searchable too. Diacritics are folded so "sengun" finds "Şengün".
+// The overlay holds one built with the day index; create it as `table` in `dest` from the records
+// `conn` serves.
+pub(crate) fn build_search_index(conn: &Connection, dest: &Connection, table: &str) -> SqlResult<()> {
+    dest.execute_batch(&format!(
+        "CREATE VIRTUAL TABLE {} USING fts5(news, keywords, tokenize = 'unicode61 remove_diacritics 2')",
+        table
+    ))?;
+    let mut rows = conn.prepare(
+        "SELECT main_news_data.id, main_news_data.news, serpapi_data.query \
+         FROM main_news_data LEFT JOIN serpapi_data ON main_news_data.serpapi_id = serpapi_data.id"
+    )?;
+    let mut rows = rows.query([])?;
+    let mut insert = dest.prepare(&format!("INSERT INTO {} (rowid, news, keywords) VALUES (?1, ?2, ?3)", table))?;
+    while let Some(row) = rows.next()? {
+        insert.execute(params![row.get::<_, i64>(0)?, row.get::<_, Option<String>>(1)?, row.get::<_, Option<String>>(2)?])?;
+    }
+    Ok(())
+}
+
+// FTS5 query for user input: every word must match, taken literally (quoted) so operators and
+// punctuation in the input can't produce a syntax error. None when the input has no words.
+pub(crate) fn match_expression(q: &str) -> Option<String> {
+    let terms: Vec<String> = q
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(|term| format!("\"{}\"", term))
+        .collect();
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" "))
+    }
+}
+
+// Matched terms of a record wrapped in the requested markers; a field is left out when the
+// search terms don't occur in it
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Highlights {
+    // A fragment of the text around the matches
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub news: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keywords: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchResult {
+    #[serde(flatten)]
+    pub record: NewsRecord,
+    pub highlights: Highlights,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchResponse {
+    pub query: String,
+    // Matches after filters, before ?limit=
+    pub total_records: usize,
+    pub records: Vec<SearchResult>,
+}
+
+// Markers placed around matched terms in highlights
+#[derive(Debug, Clone)]
+pub(crate) struct Markers {
+    pub(crate) start: String,
+    pub(crate) end: String,
+}
+
+// Records matching a match_expression, best match first unless `by_rank` is false (an explicit
+// ?sort=), narrowed by the list filters
+pub(crate) fn query_search(
+    source: &DataSource,
+    q: &str,
+    expression: &str,
+    markers: &Markers,
+    by_rank: bool,
+    options: &ListOptions,
+) -> SqlResult<SearchResponse> {
+    let conn = open_database(source)?;
+
+    // Without a current overlay, index the records for this connection, once as it is reused
+    let has_index: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM pragma_database_list WHERE name = 'overlay') \
+         OR EXISTS(SELECT 1 FROM temp.sqlite_master WHERE name = 'news_search')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_index {
+        build_search_index(&conn, &conn, "temp.news_search")?;
+    }
+
+    let mut stmt = conn.prepare_cached(&format!(
+        "SELECT rowid, snippet(news_search, 0, ?2, ?3, '…', {}), highlight(news_search, 1, ?2, ?3) \
+         FROM news_search WHERE news_search MATCH ?1 ORDER BY rank",
+        SEARCH_SNIPPET_TOKENS
+    ))?;
+    let matched = |text: Option<String>| text.filter(|text| text.contains(&markers.start));
+    let mut highlights: HashMap<i64, (usize, Highlights)> = HashMap::new();
+    let rows = stmt.query_map(params![expression, markers.start, markers.end], |row| {
+        Ok((row.get::<_, i64>(0)?, row.get::<_, Option<String>>(1)?, row.get::<_, Option<String>>(2)?))
+    })?;
+    for (rank, row) in rows.enumerate() {
+        let (id, news, keywords) = row?;
+        highlights.insert(id, (rank, Highlights { news: matched(news), keywords: matched(keywords) }));
+    }
+
+    let mut records = query_filtered_records(
+        &conn,
+        source,
+        "main_news_data.id IN (SELECT rowid FROM news_search WHERE news_search MATCH ?1)",
+        None,
+        expression,
+        // Ranked and truncated here, after all matches are known
+        &ListOptions { limit: None, ..options.clone() },
+    )?;
+    if by_rank {
+        records.sort_by_key(|record| highlights.get(&record.id).map(|(rank, _)| *rank).unwrap_or(usize::MAX));
+    }
+    let total_records = records.len();
+    records.truncate(options.limit.unwrap_or(SEARCH_DEFAULT_LIMIT));
+
+    let records = records
+        .into_iter()
+        .map(|record| SearchResult {
+            highlights: highlights.remove(&record.id).map(|(_, h)| h).unwrap_or_default(),
+            record,
+        })
+        .collect();
+    Ok(SearchResponse { query: q.to_string(), total_records, records })
+}
@@ -0,0 +1,208 @@
+// Polarity of news texts, from -1 (negative) to 1 (positive), scored from English word lists after
+// every sync and kept in a local side file (sentiment_path) by record id. Texts in other languages,
+// and records not scored yet, have none. Only built with the sentiment feature; without it
+// ?sentiment= answers 501.
+
+#[cfg(feature = "sentiment")]
+pub(crate) use analysis::{attach_sentiment, record_sentiment, spawn_sentiment_analysis};
+
+// Scores within this distance of 0 are neutral, neither positive nor negative
+const NEUTRAL_BAND: f64 = 0.05;
+
+// ?sentiment=
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Polarity {
+    Positive,
+    Negative,
+}
+
+impl Polarity {
+    pub(crate) fn from_param(raw: &str) -> Option<Polarity> {
+        match raw {
+            "positive" => Some(Polarity::Positive),
+            "negative" => Some(Polarity::Negative),
+            _ => None,
+        }
+    }
+
+    // SQL condition on main_news_data.id, against the scores attached by attach_sentiment
+    pub(crate) fn sql(self) -> String {
+        let condition = match self {
+            Polarity::Positive => format!("score >= {}", NEUTRAL_BAND),
+            Polarity::Negative => format!("score <= -{}", NEUTRAL_BAND),
+        };
+        format!(" AND main_news_data.id IN (SELECT record_id FROM sentiment_scores WHERE {})", condition)
+    }
+}
+
+#[cfg(feature = "sentiment")]
+mod analysis {
+    use std::collections::{HashMap, HashSet};
+    use std::sync::atomic::Ordering;
+    use rusqlite::{params, Connection, OptionalExtension, Result as SqlResult};
+
+    use crate::config::{DataSource, DB_BUSY_TIMEOUT_MS};
+    use crate::db::{database_uri, open_database};
+    use crate::lang::detect_language;
+    use crate::markup::strip_html;
+    use crate::AppState;
+
+    const POSITIVE_WORDS: &[&str] = &[
+        "good", "great", "best", "better", "win", "wins", "won", "winning", "victory", "success", "successful",
+        "celebrate", "celebrates", "celebrated", "celebration", "happy", "joy", "love", "loved", "beautiful",
+        "amazing", "excellent", "wonderful", "record", "breakthrough", "rescue", "rescued", "saved", "hope",
+        "hopeful", "praise", "praised", "award", "awarded", "honor", "honored", "hero", "champion", "champions",
+        "gain", "gains", "growth", "boost", "boosted", "rise", "rally", "recovery", "recovers", "improve",
+        "improved", "improvement", "safe", "peace", "agreement", "approved", "support", "thrilled", "excited",
+        "triumph", "strong", "top", "star", "favorite", "popular", "generous", "free", "healthy", "reunite",
+        "reunited", "wedding", "engaged", "birth", "welcome", "welcomes", "fans", "hit", "smash", "stunning",
+    ];
+
+    const NEGATIVE_WORDS: &[&str] = &[
+        "bad", "worse", "worst", "lose", "loses", "lost", "loss", "losing", "defeat", "defeated", "fail",
+        "failed", "failure", "death", "dead", "dies", "died", "die", "killed", "kill", "kills", "killing",
+        "murder", "shooting", "shot", "attack", "attacked", "war", "crash", "crashed", "accident", "injured",
+        "injury", "hurt", "fire", "storm", "flood", "disaster", "crisis", "fear", "fears", "threat", "threatens",
+        "arrest", "arrested", "charged", "guilty", "lawsuit", "sued", "scandal", "fraud", "controversy",
+        "criticism", "criticized", "backlash", "outrage", "angry", "sad", "tragic", "tragedy", "victim",
+        "victims", "missing", "fall", "falls", "drop", "plunge", "decline", "cut", "cuts", "layoffs", "banned",
+        "ban", "canceled", "cancelled", "delay", "delayed", "shutdown", "outage", "warning", "danger",
+        "dangerous", "violence", "protest", "conflict", "suspended", "recall", "sick", "illness", "cancer",
+    ];
+
+    // Words turning the sentiment of the next few words around ("not good"), besides the n't forms
+    const NEGATIONS: [&str; 6] = ["not", "no", "never", "without", "nobody", "nothing"];
+    const NEGATION_REACH: usize = 3;
+
+    // A text's score: the sum of its positive and negative words, negated ones counting the other
+    // way, squashed into -1..1 as VADER does. None for texts that aren't English.
+    pub(super) fn score_text(text: &str) -> Option<f64> {
+        if detect_language(text) != Some("en") {
+            return None;
+        }
+        let mut sum = 0i64;
+        let mut negated_for = 0usize;
+        let words = text
+            .split(|c: char| !(c.is_alphanumeric() || c == '\'' || c == '’'))
+            .filter(|word| !word.is_empty());
+        for word in words {
+            let word = word.to_lowercase();
+            let polarity = if POSITIVE_WORDS.contains(&word.as_str()) {
+                1
+            } else if NEGATIVE_WORDS.contains(&word.as_str()) {
+                -1
+            } else {
+                0
+            };
+            sum += if negated_for > 0 { -polarity } else { polarity };
+            negated_for = negated_for.saturating_sub(1);
+            if NEGATIONS.contains(&word.as_str()) || word.ends_with("n't") || word.ends_with("n’t") {
+                negated_for = NEGATION_REACH;
+            }
+        }
+        let sum = sum as f64;
+        let score = sum / (sum * sum + 15.0).sqrt();
+        Some((score * 1000.0).round() / 1000.0)
+    }
+
+    // source_sha1 identifies the text a score was computed from, so an edited record is scored again
+    const SENTIMENT_SCHEMA: &str = "\
+        CREATE TABLE IF NOT EXISTS sentiment_scores (\
+            record_id INTEGER PRIMARY KEY, source_sha1 TEXT NOT NULL, score REAL, analyzed_at TEXT NOT NULL);";
+
+    fn open_sentiment_database(source: &DataSource) -> SqlResult<Connection> {
+        let conn = Connection::open(&source.sentiment_path)?;
+        conn.busy_timeout(std::time::Duration::from_millis(DB_BUSY_TIMEOUT_MS))?;
+        conn.execute_batch(SENTIMENT_SCHEMA)?;
+        Ok(conn)
+    }
+
+    // Make sentiment_scores(record_id, score) available to the queries: the side file once the
+    // first analysis wrote it, an empty temp view until then
+    pub(crate) fn attach_sentiment(conn: &Connection, source: &DataSource) -> SqlResult<()> {
+        let attached = source.sentiment_path.exists()
+            && conn.execute("ATTACH DATABASE ?1 AS sentiment", [database_uri(&source.sentiment_path, false)]).is_ok();
+        if attached {
+            return Ok(());
+        }
+        conn.execute_batch(
+            "CREATE TEMP VIEW sentiment_scores AS \
+             SELECT NULL AS record_id, NULL AS source_sha1, NULL AS score, NULL AS analyzed_at WHERE 0"
+        )
+    }
+
+    pub(crate) fn record_sentiment(conn: &Connection, record_id: i64) -> SqlResult<Option<f64>> {
+        let mut stmt = conn.prepare_cached("SELECT score FROM sentiment_scores WHERE record_id = ?1")?;
+        Ok(stmt.query_row([record_id], |row| row.get(0)).optional()?.flatten())
+    }
+
+    fn text_sha1(text: &str) -> String {
+        use sha1::{Digest, Sha1};
+
+        Sha1::digest(text.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    // Once a sync is done, score the source's new and changed records and forget the deleted ones,
+    // on a background thread. A run still going when the next sync ends is left alone.
+    pub(crate) fn spawn_sentiment_analysis(state: &AppState) {
+        if state.analyzing_sentiment.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let state = state.clone();
+        std::thread::spawn(move || {
+            let started = std::time::Instant::now();
+            match analyze(&state.source) {
+                Ok(0) => {}
+                Ok(scored) => {
+                    println!("Scored the sentiment of {} records in {}ms", scored, started.elapsed().as_millis());
+                    // Responses computed meanwhile lack the new scores
+                    state.clear_cache();
+                }
+                Err(e) => eprintln!("Failed to score sentiment in {}: {}", state.source.sentiment_path.display(), e),
+            }
+            state.analyzing_sentiment.store(false, Ordering::Release);
+        });
+    }
+
+    // Returns how many records were scored or dropped
+    fn analyze(source: &DataSource) -> SqlResult<usize> {
+        let conn = open_database(source)?;
+        let mut stmt = conn.prepare("SELECT id, news FROM main_news_data")?;
+        let records = stmt
+            .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Option<String>>(1)?)))?
+            .collect::<SqlResult<Vec<_>>>()?;
+
+        let mut out = open_sentiment_database(source)?;
+        let known: HashMap<i64, String> = out
+            .prepare("SELECT record_id, source_sha1 FROM sentiment_scores")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<SqlResult<_>>()?;
+        let tx = out.transaction()?;
+        let mut changed = 0;
+        let mut present = HashSet::with_capacity(records.len());
+        {
+            let mut upsert = tx.prepare(
+                "INSERT OR REPLACE INTO sentiment_scores (record_id, source_sha1, score, analyzed_at) \
+                 VALUES (?1, ?2, ?3, ?4)"
+            )?;
+            let analyzed_at = chrono::Utc::now().to_rfc3339();
+            for (id, news) in &records {
+                present.insert(*id);
+                let text = news.as_deref().map(strip_html).unwrap_or_default();
+                let sha1 = text_sha1(&text);
+                if known.get(id) == Some(&sha1) {
+                    continue;
+                }
+                upsert.execute(params![id, sha1, score_text(&text), analyzed_at])?;
+                changed += 1;
+            }
+            let mut delete = tx.prepare("DELETE FROM sentiment_scores WHERE record_id = ?1")?;
+            for id in known.keys().filter(|id| !present.contains(id)) {
+                delete.execute([id])?;
+                changed += 1;
+            }
+        }
+        tx.commit()?;
+        Ok(changed)
+    }
+}
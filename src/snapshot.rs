@@ -0,0 +1,184 @@
+// Snapshot sync, for hosts where running git or keeping a whole checkout is unwanted: instead of
+// cloning the data repository, download its SQLite file from <snapshot_url>/trends_data.db and the
+// images listed by <snapshot_url>/images.json (a JSON array of paths under images/, optional) from
+// <snapshot_url>/images/<path>. Downloads go through curl like the other outbound calls, with
+// If-None-Match from the ETag of the last download, so an unchanged database costs one 304. Files
+// are downloaded next to their destination and renamed into place, never served half-written.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use percent_encoding::utf8_percent_encode;
+
+use crate::config::{DataSource, SNAPSHOT_TIMEOUT_SECONDS};
+use crate::urls::URL_UNRESERVED;
+
+// Outcome of one snapshot sync
+pub(crate) struct SnapshotFetch {
+    // The database was downloaded again (not answered 304)
+    pub(crate) changed: bool,
+    // Bytes downloaded, database and images
+    pub(crate) bytes: u64,
+}
+
+enum Download {
+    Fetched(u64),
+    NotModified,
+    NotFound,
+}
+
+// ETags of the last downloads are kept next to the files they describe
+fn etag_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".etag");
+    path.with_file_name(name)
+}
+
+fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".download");
+    path.with_file_name(name)
+}
+
+// GET `url` into `dest`, conditional on the ETag saved for it when `conditional`
+fn download(url: &str, dest: &Path, conditional: bool) -> Result<Download, String> {
+    if let Some(parent) = dest.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent).map_err(|e| format!("cannot create {}: {}", parent.display(), e))?;
+    }
+    let partial = partial_path(dest);
+    let etag = etag_path(dest);
+    let saved_etag = std::fs::read_to_string(&etag)
+        .ok()
+        .filter(|_| conditional)
+        .map(|saved| saved.trim().to_string())
+        .filter(|saved| !saved.is_empty());
+    let mut command = Command::new("curl");
+    command
+        .args(["-sS", "-L", "--max-time", &SNAPSHOT_TIMEOUT_SECONDS.to_string(), "-D", "-", "-o"])
+        .arg(&partial);
+    if let Some(saved) = &saved_etag {
+        command.arg("-H").arg(format!("If-None-Match: {}", saved));
+    }
+    let output = command.arg(url).output().map_err(|e| format!("cannot run curl: {}", e))?;
+    let discard = || {
+        let _ = std::fs::remove_file(&partial);
+    };
+    if !output.status.success() {
+        discard();
+        return Err(format!("curl {}: {}", output.status, String::from_utf8_lossy(&output.stderr).trim()));
+    }
+
+    // With redirects followed, the headers of every response are dumped; the last one counts
+    let headers = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = headers.lines().collect();
+    let Some(start) = lines.iter().rposition(|line| line.starts_with("HTTP/")) else {
+        discard();
+        return Err(format!("no HTTP response from {}", url));
+    };
+    let status = lines[start].split_whitespace().nth(1).and_then(|code| code.parse::<u16>().ok());
+    let new_etag = lines[start + 1..].iter().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim().eq_ignore_ascii_case("etag").then(|| value.trim().to_string())
+    });
+    match status {
+        Some(200) => {
+            let bytes = std::fs::metadata(&partial).map(|meta| meta.len()).unwrap_or_default();
+            std::fs::rename(&partial, dest).map_err(|e| format!("cannot replace {}: {}", dest.display(), e))?;
+            // Without an ETag the next download can't be conditional
+            let saved = match new_etag {
+                _ if !conditional => Ok(()),
+                Some(new_etag) => std::fs::write(&etag, new_etag),
+                None => std::fs::remove_file(&etag).or_else(|e| match e.kind() {
+                    std::io::ErrorKind::NotFound => Ok(()),
+                    _ => Err(e),
+                }),
+            };
+            if let Err(e) = saved {
+                eprintln!("Failed to save the ETag of {}: {}", dest.display(), e);
+            }
+            Ok(Download::Fetched(bytes))
+        }
+        Some(304) => {
+            discard();
+            Ok(Download::NotModified)
+        }
+        Some(404) => {
+            discard();
+            Ok(Download::NotFound)
+        }
+        _ => {
+            discard();
+            Err(format!("{} answered {}", url, lines[start].trim()))
+        }
+    }
+}
+
+// Manifest entry as a relative path under the images directory, None for anything escaping it
+fn manifest_path(entry: &str) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    for segment in entry.split('/') {
+        if segment.is_empty() || segment.starts_with('.') || segment.contains(['\\', '\0']) {
+            return None;
+        }
+        path.push(segment);
+    }
+    (!path.as_os_str().is_empty()).then_some(path)
+}
+
+fn image_url(base: &str, entry: &str) -> String {
+    let encoded: Vec<String> =
+        entry.split('/').map(|segment| utf8_percent_encode(segment, URL_UNRESERVED).to_string()).collect();
+    format!("{}/images/{}", base, encoded.join("/"))
+}
+
+// Download the listed images missing from the images directory. The manifest is kept in it, so
+// images whose download failed are retried at the next sync even when the manifest is unchanged.
+fn fetch_images(source: &DataSource, base: &str) -> Result<u64, String> {
+    // A dotfile, so /images/ doesn't serve it
+    let manifest_file = source.images_dir.join(".manifest.json");
+    match download(&format!("{}/images.json", base), &manifest_file, true)? {
+        Download::NotFound => return Ok(0),
+        Download::Fetched(_) | Download::NotModified => {}
+    }
+    let manifest = std::fs::read(&manifest_file).map_err(|e| format!("cannot read {}: {}", manifest_file.display(), e))?;
+    let entries: Vec<String> =
+        serde_json::from_slice(&manifest).map_err(|e| format!("images.json is not a list of paths: {}", e))?;
+    let mut bytes = 0;
+    let mut failed = 0;
+    for entry in &entries {
+        let Some(path) = manifest_path(entry) else {
+            eprintln!("Ignoring image '{}' of the snapshot manifest", entry);
+            continue;
+        };
+        let dest = source.images_dir.join(path);
+        if dest.exists() {
+            continue;
+        }
+        match download(&image_url(base, entry), &dest, false) {
+            Ok(Download::Fetched(fetched)) => bytes += fetched,
+            Ok(_) => failed += 1,
+            Err(e) => {
+                eprintln!("Failed to download image {}: {}", entry, e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        eprintln!("{} of {} snapshot images could not be downloaded", failed, entries.len());
+    }
+    Ok(bytes)
+}
+
+// Fetch the database and images of a source synced from a snapshot URL. A failed image download
+// doesn't fail the sync; the database is what the source serves.
+pub(crate) fn fetch_snapshot(source: &DataSource, base: &str) -> Result<SnapshotFetch, String> {
+    let (changed, mut bytes) = match download(&format!("{}/trends_data.db", base), &source.db_path, true)? {
+        Download::Fetched(bytes) => (true, bytes),
+        Download::NotModified => (false, 0),
+        Download::NotFound => return Err(format!("no database at {}/trends_data.db", base)),
+    };
+    match fetch_images(source, base) {
+        Ok(fetched) => bytes += fetched,
+        Err(e) => eprintln!("Failed to sync the images of {}: {}", base, e),
+    }
+    Ok(SnapshotFetch { changed, bytes })
+}
@@ -0,0 +1,112 @@
+use serde_json::Value;
+
+use crate::config::MAX_SUMMARY_LENGTH;
+
+// ?summary=: the news text cut to a number of characters, or to its first paragraph
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Summary {
+    Characters(usize),
+    Paragraph,
+}
+
+impl Summary {
+    pub(crate) fn from_param(raw: &str) -> Option<Summary> {
+        match raw {
+            "paragraph" => Some(Summary::Paragraph),
+            _ => raw
+                .parse::<usize>()
+                .ok()
+                .filter(|length| (1..=MAX_SUMMARY_LENGTH).contains(length))
+                .map(Summary::Characters),
+        }
+    }
+
+    fn apply(self, text: &str) -> String {
+        match self {
+            Summary::Characters(length) => {
+                let clusters = clusters(text);
+                if clusters.len() <= length {
+                    return text.to_string();
+                }
+                // The ellipsis counts towards the length
+                let cut = clusters[length - 1];
+                format!("{}…", text[..cut].trim_end())
+            }
+            Summary::Paragraph => {
+                let text = text.trim_start();
+                text.split('\n').next().unwrap_or_default().trim_end().to_string()
+            }
+        }
+    }
+}
+
+// Characters that belong to the character before them: combining marks, variation selectors,
+// emoji modifiers and tags, and whatever follows a zero width joiner
+fn extends(c: char) -> bool {
+    matches!(c as u32,
+        0x0300..=0x036F | 0x1AB0..=0x1AFF | 0x1DC0..=0x1DFF | 0x20D0..=0x20FF | 0xFE20..=0xFE2F
+        | 0xFE00..=0xFE0F | 0x200D | 0x1F3FB..=0x1F3FF | 0xE0020..=0xE007F | 0xE0100..=0xE01EF)
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    (0x1F1E6..=0x1F1FF).contains(&(c as u32))
+}
+
+// Byte offsets where the text's user-perceived characters start, approximating the Unicode
+// grapheme rules for the cases news texts have (accents, emoji sequences, flags)
+fn clusters(text: &str) -> Vec<usize> {
+    let mut starts = Vec::new();
+    let mut previous: Option<char> = None;
+    let mut pending_flag = false;
+    for (offset, c) in text.char_indices() {
+        let joined = previous == Some('\u{200D}');
+        let flag_half = is_regional_indicator(c) && pending_flag;
+        if previous.is_none() || !(extends(c) || joined || flag_half) {
+            starts.push(offset);
+        }
+        pending_flag = is_regional_indicator(c) && !flag_half;
+        previous = Some(c);
+    }
+    starts
+}
+
+// The text's length in user-perceived characters
+fn text_length(text: &str) -> usize {
+    clusters(text).len()
+}
+
+// Shorten the news of one record and of the duplicates nested in it, adding its full length as
+// news_length
+fn summarize_record(record: &mut Value, summary: Summary) {
+    let Value::Object(map) = record else {
+        return;
+    };
+    if let Some(Value::String(news)) = map.get_mut("news") {
+        let length = text_length(news);
+        *news = summary.apply(news);
+        map.insert("news_length".to_string(), Value::from(length));
+    } else if map.contains_key("news") {
+        map.insert("news_length".to_string(), Value::Null);
+    }
+    if let Some(Value::Array(duplicates)) = map.get_mut("duplicates") {
+        duplicates.iter_mut().for_each(|duplicate| summarize_record(duplicate, summary));
+    }
+}
+
+// Apply ?summary= to every "records" array of a response
+pub(crate) fn summarize_records(value: &mut Value, summary: Summary) {
+    match value {
+        Value::Object(map) => {
+            for (key, child) in map.iter_mut() {
+                match child {
+                    Value::Array(records) if key == "records" => {
+                        records.iter_mut().for_each(|record| summarize_record(record, summary));
+                    }
+                    _ => summarize_records(child, summary),
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(|item| summarize_records(item, summary)),
+        _ => {}
+    }
+}
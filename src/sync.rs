@@ -0,0 +1,383 @@
+use std::fs::{File, OpenOptions};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use serde::Serialize;
+
+use crate::config::{DataSource, SYNC_FAILURES_BEFORE_REPORT, SYNC_INTERVAL_MINUTES, SYNC_RETRY_SECONDS};
+use crate::db::{count_records, open_database, query_latest_news};
+#[cfg(feature = "embeddings")]
+use crate::embeddings::spawn_embedding;
+use crate::list::ListOptions;
+use crate::metrics::{observe_sync, observe_unverified_commit, time_query};
+use crate::report::report;
+use crate::snapshot::fetch_snapshot;
+#[cfg(feature = "sentiment")]
+use crate::sentiment::spawn_sentiment_analysis;
+use crate::thumbnails::spawn_image_processing;
+use crate::validate::validate_and_switch;
+use crate::AppState;
+
+// One sync attempt, as listed by GET /admin/sync/log
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct SyncRecord {
+    pub(crate) started_at: String,
+    pub(crate) duration_ms: u128,
+    pub(crate) action: &'static str,
+    pub(crate) commit_before: Option<String>,
+    pub(crate) commit_after: Option<String>,
+    pub(crate) rows_before: Option<i64>,
+    pub(crate) rows_after: Option<i64>,
+    pub(crate) rows_added: Option<i64>,
+    // Growth of the checkout's git object store, or the bytes a snapshot sync downloaded
+    pub(crate) bytes_fetched: Option<u64>,
+    pub(crate) ready: bool,
+    pub(crate) error: Option<String>,
+}
+
+impl SyncRecord {
+    // When the attempt finished
+    pub(crate) fn ended_at(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        let started = chrono::DateTime::parse_from_rfc3339(&self.started_at).ok()?;
+        let duration = chrono::Duration::milliseconds(i64::try_from(self.duration_ms).ok()?);
+        Some(started.with_timezone(&chrono::Utc) + duration)
+    }
+}
+
+// Current commit of a checkout, None if it isn't one (yet)
+fn head_commit(repo_path: &Path) -> Option<String> {
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(repo_path)
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// Size of a checkout's git objects (loose and packed), None if it isn't one (yet)
+fn object_bytes(repo_path: &Path) -> Option<u64> {
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(repo_path)
+        .args(["count-objects", "-v"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let kib: u64 = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.strip_prefix("size: ").or_else(|| line.strip_prefix("size-pack: ")))
+        .filter_map(|size| size.trim().parse::<u64>().ok())
+        .sum();
+    Some(kib * 1024)
+}
+
+// Image directories (images/yyyy/mm/dd) of the last `days` days, today (UTC) included
+fn recent_image_dirs(days: u32) -> Vec<String> {
+    let today = chrono::Utc::now().date_naive();
+    (0..days)
+        .filter_map(|back| today.checked_sub_days(chrono::Days::new(back.into())))
+        .map(|day| format!("images/{}", day.format("%Y/%m/%d")))
+        .collect()
+}
+
+// Run one git step of a sync; its error message if it fails
+fn run_git(step: &str, command: &mut Command) -> Option<String> {
+    match command.output() {
+        Ok(output) if output.status.success() => None,
+        Ok(output) => Some(format!(
+            "git {} failed ({}): {}",
+            step,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+        Err(e) => Some(format!("cannot run git {}: {}", step, e)),
+    }
+}
+
+// Point the checkout's origin at the configured repository if it was cloned from another one
+fn update_remote(source: &DataSource) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(&source.repo_path)
+        .args(["remote", "get-url", "origin"])
+        .output()
+        .ok()?;
+    let current = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if output.status.success() && current == source.repo_url {
+        return None;
+    }
+    println!("Switching the remote of {} from '{}' to {}", source.repo_path.display(), current, source.repo_url);
+    let mut command = Command::new("git");
+    command.arg("-C").arg(&source.repo_path);
+    if output.status.success() {
+        command.args(["remote", "set-url", "origin"]);
+    } else {
+        command.args(["remote", "add", "origin"]);
+    }
+    run_git("remote", command.arg(&source.repo_url))
+}
+
+// Clone the source's repository, or bring its checkout up to date. A shallow sync keeps only the
+// newest commits (a pull then fetches the configured branch and resets to it, as the checkout holds
+// no local work, which is also how a configured branch is followed); a sparse one clones without
+// blobs and checks out the root files (the database) and the image directories of the last days
+// only, moving that window forward before every pull. With a trust policy, commits are fetched
+// first and the checkout only moves to those it accepts.
+fn git_fetch(source: &DataSource) -> (&'static str, Option<String>) {
+    let repo_path = &source.repo_path;
+    let trust = source.trust.as_deref();
+    // Network steps (sparse checkouts and resets fetch missing blobs too) authenticate
+    let authenticate = |command: &mut Command| {
+        if let Some(credentials) = &source.git_credentials {
+            credentials.apply(command, &source.repo_url);
+        }
+    };
+    let git = || {
+        let mut command = Command::new("git");
+        command.arg("-C").arg(repo_path);
+        authenticate(&mut command);
+        command
+    };
+    let set_sparse = |days: u32| {
+        run_git("sparse-checkout", git().args(["sparse-checkout", "set", "--cone"]).args(recent_image_dirs(days)))
+    };
+    // Check the fetched commit, then move the checkout to it
+    let verify_and_reset = |rev: &str| {
+        if let Some(trust) = trust {
+            if let Err(reason) = trust.verify(repo_path, rev) {
+                eprintln!("Refusing to serve {} from {}: {}", repo_path.display(), source.repo_url, reason);
+                observe_unverified_commit(source.name.as_deref().unwrap_or("default"));
+                return Some(format!("unverified data: {}", reason));
+            }
+        }
+        run_git("reset", git().args(["reset", "--hard", rev]))
+    };
+
+    // If repo doesn't exist, clone; else, pull
+    if !repo_path.exists() {
+        let mut clone = Command::new("git");
+        authenticate(&mut clone);
+        clone.arg("clone");
+        if let Some(depth) = source.clone_depth {
+            clone.arg("--depth").arg(depth.to_string());
+        }
+        if let Some(branch) = &source.branch {
+            clone.arg("--branch").arg(branch);
+        }
+        if source.sparse_image_days.is_some() {
+            clone.args(["--filter=blob:none", "--sparse"]);
+        }
+        if trust.is_some() {
+            clone.arg("--no-checkout");
+        }
+        clone.arg(&source.repo_url).arg(repo_path);
+        let error = run_git("clone", &mut clone)
+            .or_else(|| source.sparse_image_days.and_then(set_sparse))
+            .or_else(|| trust.and_then(|_| verify_and_reset("HEAD")));
+        return ("clone", error);
+    }
+
+    if let Some(error) = update_remote(source).or_else(|| source.sparse_image_days.and_then(set_sparse)) {
+        return ("pull", Some(error));
+    }
+    let error = if source.clone_depth.is_some() || source.branch.is_some() || trust.is_some() {
+        let mut fetch = git();
+        fetch.arg("fetch");
+        if let Some(depth) = source.clone_depth {
+            fetch.arg("--depth").arg(depth.to_string());
+        }
+        fetch.arg("origin").arg(source.branch.as_deref().unwrap_or("HEAD"));
+        run_git("fetch", &mut fetch).or_else(|| verify_and_reset("FETCH_HEAD"))
+    } else {
+        run_git("pull", git().arg("pull"))
+    };
+    ("pull", error)
+}
+
+// Take in the data on disk after it changed: validate it (a database that fails isn't served, and
+// its problems are the error), drop cached responses, re-check the schema, rebuild the day index
+// and prepare the latest news. The source is ready once its database opens.
+pub(crate) fn refresh_data(state: &AppState, rows_served: Option<i64>, commit: Option<&str>) -> (bool, Option<String>) {
+    let mut error = None;
+    if state.source.db_path.exists() {
+        let problems = validate_and_switch(state, rows_served, commit);
+        if !problems.is_empty() {
+            error = Some(format!("validation failed: {}", problems.join("; ")));
+        }
+    }
+
+    // Data may have changed, drop computed responses and re-check the schema
+    state.clear_cache();
+    state.validate_schema();
+    state.refresh_overlay();
+
+    let ready = match open_database(&state.source) {
+        Ok(_) => true,
+        Err(e) => {
+            eprintln!("Source at {} is not ready: {}", state.source.db_path.display(), e);
+            error.get_or_insert_with(|| format!("cannot open database: {}", e));
+            false
+        }
+    };
+    state.set_ready(ready);
+    state.refresh_database_sha1();
+    // The hottest response is ready before the first request asks for it
+    if ready {
+        match time_query("latest", || query_latest_news(&state.source, &ListOptions::default())) {
+            Ok(response) => {
+                state.prepare_latest(&response);
+            }
+            Err(e) => eprintln!("Failed to prepare latest news of {}: {}", state.source.db_path.display(), e),
+        }
+        spawn_image_processing(state);
+        #[cfg(feature = "sentiment")]
+        spawn_sentiment_analysis(state);
+        #[cfg(feature = "embeddings")]
+        spawn_embedding(state);
+    }
+    (ready, error)
+}
+
+// Lock file next to the checkout, serializing its syncs across server processes sharing it
+fn sync_lock_path(source: &DataSource) -> PathBuf {
+    let mut path = source.repo_path.as_os_str().to_os_string();
+    path.push(".sync.lock");
+    PathBuf::from(path)
+}
+
+// Take the exclusive lock (flock) on the source's lock file, waiting while another process holds
+// it; it's released with the file. None when the lock can't be had, and the sync goes ahead
+// without it.
+fn lock_checkout(source: &DataSource) -> Option<File> {
+    let path = sync_lock_path(source);
+    let file = match OpenOptions::new().create(true).write(true).truncate(false).open(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Cannot open {}, syncing without the lock: {}", path.display(), e);
+            return None;
+        }
+    };
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        println!("Sync of {} waits for another process syncing it (holding {})", source.origin(), path.display());
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            eprintln!("Cannot lock {}, syncing without the lock: {}", path.display(), std::io::Error::last_os_error());
+            return None;
+        }
+    }
+    Some(file)
+}
+
+// Clone or pull a source's repository (or download its snapshot) once, then take in the new data
+// (see refresh_data). Blocks on git or curl, and first on a sync of the checkout by another process.
+pub fn sync_once(state: &AppState) -> bool {
+    let _lock = lock_checkout(&state.source);
+    let started = std::time::Instant::now();
+    let started_at = chrono::Utc::now().to_rfc3339();
+    let repo_path = &state.source.repo_path;
+    let snapshot_url = state.source.snapshot_url.as_deref();
+    // A snapshot directory isn't a checkout, and git would report the one around it
+    let commit_of = |path: &Path| if snapshot_url.is_some() { None } else { head_commit(path) };
+    let bytes_of = |path: &Path| if snapshot_url.is_some() { None } else { object_bytes(path) };
+    let commit_before = commit_of(repo_path);
+    let bytes_before = bytes_of(repo_path);
+    let rows_before = time_query("count_records", || count_records(&state.source)).ok();
+
+    let (action, mut error, downloaded) = match snapshot_url {
+        Some(url) => match fetch_snapshot(&state.source, url) {
+            Ok(fetch) => (if fetch.changed { "download" } else { "unchanged" }, None, Some(fetch.bytes)),
+            Err(e) => ("download", Some(format!("snapshot download failed: {}", e)), None),
+        },
+        None => {
+            let (action, error) = git_fetch(&state.source);
+            (action, error, None)
+        }
+    };
+    let origin = state.source.origin();
+    if let Some(message) = &error {
+        eprintln!("Sync of {}: {}", origin, message);
+    }
+
+    // A synced database that fails validation isn't served; its problems fail the sync
+    let commit_after = commit_of(repo_path);
+    let (ready, refresh_error) = refresh_data(state, rows_before, commit_after.as_deref());
+    error = error.or(refresh_error);
+
+    let rows_after = time_query("count_records", || count_records(&state.source)).ok();
+    let rows_added = rows_after.map(|after| after - rows_before.unwrap_or(0));
+    // A gc after the pull can shrink the store; count that as nothing fetched
+    let bytes_fetched =
+        downloaded.or_else(|| bytes_of(repo_path).map(|after| after.saturating_sub(bytes_before.unwrap_or(0))));
+    let source_label = state.source.name.as_deref().unwrap_or("default");
+    observe_sync(
+        source_label,
+        error.is_none(),
+        bytes_fetched.unwrap_or(0),
+        rows_added.unwrap_or(0).max(0) as u64,
+    );
+    state.record_sync(SyncRecord {
+        started_at,
+        duration_ms: started.elapsed().as_millis(),
+        action,
+        commit_before,
+        commit_after,
+        rows_before,
+        rows_after,
+        rows_added,
+        bytes_fetched,
+        ready,
+        error: error.clone(),
+    });
+    if state.consecutive_sync_failures() == SYNC_FAILURES_BEFORE_REPORT {
+        let message = format!(
+            "Sync of {} failed {} times in a row: {}",
+            origin,
+            SYNC_FAILURES_BEFORE_REPORT,
+            error.unwrap_or_default()
+        );
+        report("error", &message, &[("kind", "sync"), ("source", source_label)], None);
+    }
+    ready
+}
+
+// sync_once unless a sync of the source is already running in this process, returning its record;
+// None then, and the skipped run is logged
+pub(crate) fn sync_unless_running(state: &AppState) -> Option<SyncRecord> {
+    // Cleared on the way out, even if the sync panics
+    struct Running<'a>(&'a AtomicBool);
+    impl Drop for Running<'_> {
+        fn drop(&mut self) {
+            self.0.store(false, Ordering::Release);
+        }
+    }
+
+    if state.syncing.swap(true, Ordering::AcqRel) {
+        println!("Skipping a sync of {}: the previous one is still running", state.source.origin());
+        return None;
+    }
+    let _running = Running(&state.syncing);
+    sync_once(state);
+    state.sync_history().into_iter().next()
+}
+
+// Keep syncing a source in the background: every SYNC_INTERVAL_MINUTES once it is ready, every
+// SYNC_RETRY_SECONDS while it is not, skipping a turn while POST /admin/sync runs one. Run
+// sync_once first so the server starts with data.
+pub fn spawn_sync(state: AppState) {
+    tokio::spawn(async move {
+        use std::time::Duration;
+        loop {
+            let interval = if state.is_ready() {
+                Duration::from_secs(SYNC_INTERVAL_MINUTES * 60)
+            } else {
+                Duration::from_secs(SYNC_RETRY_SECONDS)
+            };
+            tokio::time::sleep(interval).await;
+            let sync_state = state.clone();
+            let _ = tokio::task::spawn_blocking(move || sync_unless_running(&sync_state)).await;
+        }
+    });
+}
@@ -0,0 +1,54 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+
+// Spellings of serpapi categories mapped onto one stable tag name, e.g. "deportes" and "Sport"
+// onto "Sports". Loaded from a JSON object of "alias": "Tag" pairs; aliases match case-insensitively
+// and unmapped tags are kept as they are.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TagMap {
+    // File the mapping was read from
+    path: Option<PathBuf>,
+    // As written in the file, for GET /tags/mapping
+    mappings: BTreeMap<String, String>,
+    #[serde(skip)]
+    by_alias: HashMap<String, String>,
+}
+
+impl TagMap {
+    pub fn load(path: &Path) -> Result<TagMap, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let mappings: BTreeMap<String, String> =
+            serde_json::from_str(&text).map_err(|e| e.to_string())?;
+        let mut by_alias = HashMap::new();
+        for (alias, tag) in &mappings {
+            let tag = tag.trim();
+            if tag.is_empty() || tag.contains('|') {
+                return Err(format!("invalid tag '{}' for '{}'", tag, alias));
+            }
+            by_alias.insert(alias.trim().to_lowercase(), tag.to_string());
+        }
+        Ok(TagMap { path: Some(path.to_path_buf()), mappings, by_alias })
+    }
+
+    // Stable name of a tag as found in the data or given by a client
+    pub(crate) fn normalize(&self, tag: &str) -> String {
+        let tag = tag.trim();
+        self.by_alias.get(&tag.to_lowercase()).cloned().unwrap_or_else(|| tag.to_string())
+    }
+
+    // Every spelling in the data that normalizes to the same tag as `tag`, including its own
+    pub(crate) fn spellings(&self, tag: &str) -> Vec<String> {
+        let normalized = self.normalize(tag);
+        let mut spellings = vec![tag.trim().to_string()];
+        if !normalized.eq_ignore_ascii_case(tag.trim()) {
+            spellings.push(normalized.clone());
+        }
+        for (alias, mapped) in &self.mappings {
+            if mapped.trim().eq_ignore_ascii_case(&normalized) && !spellings.iter().any(|s| s.eq_ignore_ascii_case(alias.trim())) {
+                spellings.push(alias.trim().to_string());
+            }
+        }
+        spellings
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use chrono::NaiveDate;
+use rusqlite::{Connection, Result as SqlResult};
+use serde::{Deserialize, Serialize};
+
+use crate::config::DataSource;
+use crate::db::{open_database, query_news_records, DayRecords};
+
+// A story followed across days: the records whose keywords (trimmed and lowercased, as
+// /analytics/keywords groups them) appear on consecutive days, identified by the id of its first
+// record. Keywords seen on a single day, however often, make no thread.
+#[derive(Debug)]
+struct Thread {
+    keywords: String,
+    // In day order, then by id
+    records: Vec<i64>,
+}
+
+#[derive(Debug, Default)]
+struct ThreadIndex {
+    thread_of: HashMap<i64, i64>,
+    threads: HashMap<i64, Thread>,
+}
+
+// Per-source threads of the records served, computed on first use from the whole dataset and
+// cleared with the response cache
+#[derive(Debug, Default)]
+pub(crate) struct Threads {
+    index: RwLock<Option<Arc<ThreadIndex>>>,
+}
+
+impl Threads {
+    fn index(&self, conn: &Connection) -> SqlResult<Arc<ThreadIndex>> {
+        if let Some(index) = self.index.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            return Ok(index.clone());
+        }
+        let index = Arc::new(build_index(conn)?);
+        *self.index.write().unwrap_or_else(|e| e.into_inner()) = Some(index.clone());
+        Ok(index)
+    }
+
+    // The thread a record belongs to, if any
+    pub(crate) fn thread_of(&self, conn: &Connection, record_id: i64) -> SqlResult<Option<i64>> {
+        Ok(self.index(conn)?.thread_of.get(&record_id).copied())
+    }
+
+    pub(crate) fn clear(&self) {
+        *self.index.write().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+}
+
+fn build_index(conn: &Connection) -> SqlResult<ThreadIndex> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT news_days.id, news_days.day, lower(trim(serpapi_data.query)) AS keyword \
+         FROM news_days \
+         JOIN main_news_data ON main_news_data.id = news_days.id \
+         JOIN serpapi_data ON main_news_data.serpapi_id = serpapi_data.id \
+         WHERE trim(serpapi_data.query) != '' \
+         ORDER BY keyword, news_days.day, news_days.id"
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+    })?;
+
+    let mut index = ThreadIndex::default();
+    // Records of the current keyword on consecutive days so far
+    let mut keywords = String::new();
+    let mut run: Vec<(i64, NaiveDate)> = Vec::new();
+    let close = |index: &mut ThreadIndex, keywords: &str, run: &[(i64, NaiveDate)]| {
+        let (Some((id, first)), Some((_, last))) = (run.first(), run.last()) else {
+            return;
+        };
+        if first == last {
+            return;
+        }
+        for (record_id, _) in run {
+            index.thread_of.insert(*record_id, *id);
+        }
+        let records = run.iter().map(|(record_id, _)| *record_id).collect();
+        index.threads.insert(*id, Thread { keywords: keywords.to_string(), records });
+    };
+    for row_result in rows {
+        let (id, day, keyword) = row_result?;
+        let Ok(day) = NaiveDate::parse_from_str(&day, "%Y-%m-%d") else {
+            continue;
+        };
+        let continues = keyword == keywords && run.last().is_some_and(|(_, last)| (day - *last).num_days() <= 1);
+        if !continues {
+            close(&mut index, &keywords, &run);
+            run.clear();
+            keywords = keyword;
+        }
+        run.push((id, day));
+    }
+    close(&mut index, &keywords, &run);
+    Ok(index)
+}
+
+// A thread's records per day (yyyymmdd), oldest first
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ThreadResponse {
+    pub thread_id: i64,
+    pub keywords: String,
+    pub first_seen: String,
+    pub last_seen: String,
+    pub total_records: usize,
+    pub days: Vec<DayRecords>,
+}
+
+// None when no thread has that id
+pub(crate) fn query_thread(source: &DataSource, thread_id: i64) -> SqlResult<Option<ThreadResponse>> {
+    let conn = open_database(source)?;
+    let index = source.threads.index(&conn)?;
+    let Some(thread) = index.threads.get(&thread_id) else {
+        return Ok(None);
+    };
+
+    // Ids come from the index, never from the request
+    let ids: Vec<String> = thread.records.iter().map(|id| id.to_string()).collect();
+    let records = query_news_records(
+        &conn,
+        source,
+        &format!("WHERE main_news_data.id IN ({}) ORDER BY main_news_data.date ASC, main_news_data.id ASC", ids.join(",")),
+        [],
+    )?;
+    let mut days: Vec<DayRecords> = Vec::new();
+    for record in records {
+        let day = record.date.as_deref().and_then(|date| date.get(..10)).unwrap_or_default().replace('-', "");
+        match days.last_mut() {
+            Some(last) if last.date == day => {
+                last.count += 1;
+                last.records.push(record);
+            }
+            _ => days.push(DayRecords { date: day, count: 1, records: vec![record] }),
+        }
+    }
+
+    Ok(Some(ThreadResponse {
+        thread_id,
+        keywords: thread.keywords.clone(),
+        first_seen: days.first().map(|day| day.date.clone()).unwrap_or_default(),
+        last_seen: days.last().map(|day| day.date.clone()).unwrap_or_default(),
+        total_records: days.iter().map(|day| day.count).sum(),
+        days,
+    }))
+}
@@ -0,0 +1,186 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::config::{DataSource, THUMBNAIL_WORKERS};
+use crate::placeholders::update_placeholders;
+use crate::routes::sniff_image_type;
+use crate::AppState;
+
+// Thumbnail widths from "320,640"; each must be a positive number of pixels
+pub(crate) fn parse_widths(raw: &str) -> Result<Vec<u32>, String> {
+    let mut widths = Vec::new();
+    for entry in raw.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        let width = entry
+            .parse::<u32>()
+            .ok()
+            .filter(|width| (1..=8192).contains(width))
+            .ok_or_else(|| format!("'{}' is not a width in pixels", entry))?;
+        if !widths.contains(&width) {
+            widths.push(width);
+        }
+    }
+    widths.sort_unstable();
+    Ok(widths)
+}
+
+// One image to resize: the original and the WebP file written for one width
+struct Job {
+    original: PathBuf,
+    width: u32,
+    target: PathBuf,
+}
+
+// Once a sync is done, compute placeholders of the source's new and changed images and resize
+// them to the configured widths, on background threads. A run still going when the next sync
+// ends is left alone.
+pub(crate) fn spawn_image_processing(state: &AppState) {
+    if state.processing_images.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    let state = state.clone();
+    std::thread::spawn(move || {
+        process_images(&state);
+        state.processing_images.store(false, Ordering::Release);
+    });
+}
+
+// Without ImageMagick there is nothing to do, which is only worth saying once
+static IMAGEMAGICK_MISSING: AtomicBool = AtomicBool::new(false);
+
+fn process_images(state: &AppState) {
+    let source = &state.source;
+    let Some(convert) = imagemagick() else {
+        if !IMAGEMAGICK_MISSING.swap(true, Ordering::Relaxed) {
+            eprintln!("ImageMagick (magick or convert) not found: no image placeholders or thumbnails are built");
+        }
+        return;
+    };
+    let mut originals = Vec::new();
+    collect_images(&source.images_dir, Path::new(""), &mut originals);
+    if update_placeholders(&source.placeholders, &source.placeholders_path, convert, &source.images_dir, &originals) {
+        // Responses computed meanwhile lack the new hashes
+        state.clear_cache();
+    }
+    generate_thumbnails(source, convert, &originals);
+}
+
+// Run `work` on each job with THUMBNAIL_WORKERS threads, returning once all are done
+pub(crate) fn run_workers<J: Send>(jobs: Vec<J>, work: impl Fn(J) + Sync) {
+    let workers = THUMBNAIL_WORKERS.min(jobs.len());
+    let queue = Mutex::new(jobs);
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let Some(job) = queue.lock().unwrap_or_else(|e| e.into_inner()).pop() else {
+                    break;
+                };
+                work(job);
+            });
+        }
+    });
+}
+
+fn generate_thumbnails(source: &DataSource, convert: &str, originals: &[(PathBuf, SystemTime)]) {
+    let started = std::time::Instant::now();
+    let jobs: Vec<Job> = originals
+        .iter()
+        .flat_map(|(relative, modified)| {
+            source.thumbnail_widths.iter().filter_map(move |width| {
+                let target = source.thumbnails_dir.join(width.to_string()).join(relative);
+                let current = std::fs::metadata(&target).and_then(|m| m.modified()).is_ok_and(|built| built >= *modified);
+                (!current).then(|| Job { original: source.images_dir.join(relative), width: *width, target })
+            })
+        })
+        .collect();
+    if jobs.is_empty() {
+        return;
+    }
+
+    let total = jobs.len();
+    let failed = AtomicUsize::new(0);
+    run_workers(jobs, |job| {
+        if let Err(e) = resize(convert, &job) {
+            eprintln!("Failed to build {}px thumbnail of {}: {}", job.width, job.original.display(), e);
+            failed.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+    let failed = failed.into_inner();
+    println!(
+        "Built {} thumbnails in {} in {}ms ({} failed)",
+        total - failed,
+        source.thumbnails_dir.display(),
+        started.elapsed().as_millis(),
+        failed
+    );
+}
+
+// The ImageMagick command available: magick (7) or convert (6); None without ImageMagick
+fn imagemagick() -> Option<&'static str> {
+    ["magick", "convert"].into_iter().find(|command| {
+        Command::new(command)
+            .arg("-version")
+            .output()
+            .is_ok_and(|output| output.status.success())
+    })
+}
+
+// Images under dir (recursively, skipping dotfiles) with their modification times, relative to
+// the images directory. Files are recognized by their first bytes, like /images serves them.
+fn collect_images(dir: &Path, relative: &Path, out: &mut Vec<(PathBuf, SystemTime)>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let name = entry.file_name();
+        if name.to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if metadata.is_dir() {
+            collect_images(&path, &relative.join(&name), out);
+        } else if metadata.is_file() && is_image(&path) {
+            if let Ok(modified) = metadata.modified() {
+                out.push((relative.join(&name), modified));
+            }
+        }
+    }
+}
+
+fn is_image(path: &Path) -> bool {
+    let mut head = [0u8; 16];
+    let read = std::fs::File::open(path).and_then(|mut file| file.read(&mut head)).unwrap_or(0);
+    sniff_image_type(&head[..read]).is_some()
+}
+
+// Write a WebP no wider than the job's width (smaller images keep their size) under a dotfile
+// name, then move it into place so /thumbnails never serves a partial file
+fn resize(convert: &str, job: &Job) -> std::io::Result<()> {
+    let dir = job.target.parent().unwrap_or(Path::new("."));
+    std::fs::create_dir_all(dir)?;
+    let name = job.target.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let partial = dir.join(format!(".{}.partial", name));
+    let output = Command::new(convert)
+        .arg(format!("{}[0]", job.original.display()))
+        .args(["-auto-orient", "-thumbnail"])
+        .arg(format!("{}x>", job.width))
+        .args(["-strip", "-quality", "80"])
+        .arg(format!("webp:{}", partial.display()))
+        .output()?;
+    if !output.status.success() {
+        let _ = std::fs::remove_file(&partial);
+        return Err(std::io::Error::other(format!(
+            "{} failed ({}): {}",
+            convert,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    std::fs::rename(&partial, &job.target)
+}
@@ -0,0 +1,315 @@
+// Machine translation of news texts and keywords for ?lang=, through a provider speaking the
+// LibreTranslate API: POST {"q", "source", "target", "format", "api_key"} to the configured URL,
+// answered with {"translatedText"}. Only the configured languages are offered. Requests never
+// wait for the provider: they read the translations kept in a local side file (translations_path)
+// by record id and language, serve records without one in their own text, and queue them for a
+// single background worker, which calls the provider through curl like the error reports, so
+// each record is translated once per language and text. Only built with the translation feature;
+// without it a configured provider is ignored and ?lang= answers 501.
+
+pub struct Translator {
+    pub url: String,
+    pub api_key: Option<String>,
+    // ?lang= values offered (TREND_STORY_TRANSLATE_LANGUAGES), exactly as requested
+    pub languages: Vec<String>,
+    #[cfg(feature = "translation")]
+    worker: provider::Worker,
+}
+
+// Debug without the API key, as sources are Debug
+impl std::fmt::Debug for Translator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Translator")
+            .field("url", &self.url)
+            .field("api_key", &self.api_key.as_ref().map(|_| "<redacted>"))
+            .field("languages", &self.languages)
+            .finish()
+    }
+}
+
+impl Translator {
+    pub fn new(url: String, api_key: Option<String>, languages: Vec<String>) -> Translator {
+        Translator {
+            url,
+            api_key,
+            languages,
+            #[cfg(feature = "translation")]
+            worker: provider::Worker::default(),
+        }
+    }
+
+    pub(crate) fn offers(&self, lang: &str) -> bool {
+        self.languages.iter().any(|offered| offered == lang)
+    }
+}
+
+// Comma-separated ?lang= values, each as parse_language accepts it
+pub(crate) fn parse_languages(raw: &str) -> Result<Vec<String>, String> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|lang| !lang.is_empty())
+        .map(|lang| parse_language(lang).ok_or_else(|| format!("'{}' is not a language code", lang)))
+        .collect()
+}
+
+// ?lang= value: a two-letter language code, optionally with a region ("pt-BR")
+pub(crate) fn parse_language(raw: &str) -> Option<String> {
+    let (language, region) = match raw.split_once('-') {
+        Some((language, region)) => (language, Some(region)),
+        None => (raw, None),
+    };
+    let valid = language.len() == 2
+        && language.bytes().all(|b| b.is_ascii_lowercase())
+        && region.is_none_or(|region| region.len() == 2 && region.bytes().all(|b| b.is_ascii_uppercase()));
+    valid.then(|| raw.to_string())
+}
+
+#[cfg(feature = "translation")]
+pub(crate) use provider::translate_records;
+
+#[cfg(feature = "translation")]
+mod provider {
+    use std::collections::HashSet;
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+    use std::process::{Command, Stdio};
+    use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+    use std::sync::atomic::Ordering;
+    use std::sync::{Arc, Mutex, OnceLock};
+    use rusqlite::{params, Connection, OptionalExtension, Result as SqlResult};
+    use serde_json::json;
+
+    use super::Translator;
+    use crate::config::{DataSource, DB_BUSY_TIMEOUT_MS, TRANSLATE_QUEUE_CAPACITY, TRANSLATE_TIMEOUT_SECONDS};
+    use crate::db::NewsRecord;
+
+    // source_sha1 identifies the text and keywords a translation was made from, so an edited
+    // record is translated again
+    const TRANSLATIONS_SCHEMA: &str = "\
+        CREATE TABLE IF NOT EXISTS translations (\
+            record_id INTEGER NOT NULL, lang TEXT NOT NULL, source_sha1 TEXT NOT NULL, \
+            news TEXT, keywords TEXT, translated_at TEXT NOT NULL, \
+            PRIMARY KEY (record_id, lang));";
+
+    fn open_translations_database(path: &Path) -> SqlResult<Connection> {
+        let conn = Connection::open(path)?;
+        conn.busy_timeout(std::time::Duration::from_millis(DB_BUSY_TIMEOUT_MS))?;
+        conn.execute_batch(TRANSLATIONS_SCHEMA)?;
+        Ok(conn)
+    }
+
+    fn source_sha1(record: &NewsRecord) -> String {
+        use sha1::{Digest, Sha1};
+
+        let mut hasher = Sha1::new();
+        hasher.update(record.news.as_deref().unwrap_or_default());
+        hasher.update([0]);
+        hasher.update(record.keywords.as_deref().unwrap_or_default());
+        hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    // A record waiting for its translation into `target`
+    pub(crate) struct Job {
+        translations_path: PathBuf,
+        record_id: i64,
+        target: String,
+        from: Option<String>,
+        sha1: String,
+        news: Option<String>,
+        keywords: Option<String>,
+    }
+
+    impl Job {
+        fn key(&self) -> (PathBuf, i64, String) {
+            (self.translations_path.clone(), self.record_id, self.target.clone())
+        }
+    }
+
+    // The background worker's queue, started with the first job, and the records queued or being
+    // translated, so a record requested again meanwhile isn't queued twice
+    #[derive(Default)]
+    pub(crate) struct Worker {
+        queue: OnceLock<SyncSender<Job>>,
+        pending: Arc<Mutex<HashSet<(PathBuf, i64, String)>>>,
+    }
+
+    impl Translator {
+        fn translate(&self, text: &str, from: Option<&str>, to: &str) -> Result<String, String> {
+            let mut body = json!({ "q": text, "source": from.unwrap_or("auto"), "target": to, "format": "text" });
+            if let Some(key) = &self.api_key {
+                body["api_key"] = json!(key);
+            }
+            let body = serde_json::to_vec(&body).unwrap_or_default();
+            let child = Command::new("curl")
+                .args(["-sS", "--fail", "--max-time", &TRANSLATE_TIMEOUT_SECONDS.to_string(), "-X", "POST"])
+                .args(["-H", "Content-Type: application/json", "--data-binary", "@-"])
+                .arg(&self.url)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn();
+            let output = child
+                .and_then(|mut child| {
+                    if let Some(mut stdin) = child.stdin.take() {
+                        stdin.write_all(&body)?;
+                    }
+                    child.wait_with_output()
+                })
+                .map_err(|e| e.to_string())?;
+            if !output.status.success() {
+                return Err(format!("curl {}: {}", output.status, String::from_utf8_lossy(&output.stderr).trim()));
+            }
+            let reply: serde_json::Value = serde_json::from_slice(&output.stdout).map_err(|e| e.to_string())?;
+            reply["translatedText"]
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| "no translatedText in the reply".to_string())
+        }
+
+        // Queue a record for the worker; false when the queue is full, the record then waiting
+        // for a later request
+        fn enqueue(self: &Arc<Self>, job: Job) -> bool {
+            let Ok(mut pending) = self.worker.pending.lock() else {
+                return false;
+            };
+            if pending.contains(&job.key()) {
+                return true;
+            }
+            let queue = self.worker.queue.get_or_init(|| {
+                let (queue, jobs) = sync_channel(TRANSLATE_QUEUE_CAPACITY);
+                let translator = Arc::clone(self);
+                std::thread::spawn(move || translator.work(jobs));
+                queue
+            });
+            let key = job.key();
+            let queued = queue.try_send(job).is_ok();
+            if queued {
+                pending.insert(key);
+            }
+            queued
+        }
+
+        // Translate queued records one at a time. After a provider failure the jobs already queued
+        // are dropped rather than each waiting out the timeout; requests queue them again.
+        fn work(&self, jobs: Receiver<Job>) {
+            while let Ok(job) = jobs.recv() {
+                let failed = self.run(&job).is_err();
+                let mut done = vec![job];
+                if failed {
+                    done.extend(jobs.try_iter());
+                }
+                if let Ok(mut pending) = self.worker.pending.lock() {
+                    for job in &done {
+                        pending.remove(&job.key());
+                    }
+                }
+            }
+        }
+
+        fn run(&self, job: &Job) -> Result<(), ()> {
+            let translate = |text: &Option<String>| {
+                text.as_deref().map(|text| self.translate(text, job.from.as_deref(), &job.target)).transpose()
+            };
+            let (news, keywords) = match translate(&job.news).and_then(|news| Ok((news, translate(&job.keywords)?))) {
+                Ok(translated) => translated,
+                Err(e) => {
+                    eprintln!("Failed to translate record {} into {}: {}", job.record_id, job.target, e);
+                    return Err(());
+                }
+            };
+            let stored = open_translations_database(&job.translations_path).and_then(|conn| {
+                conn.execute(
+                    "INSERT OR REPLACE INTO translations (record_id, lang, source_sha1, news, keywords, translated_at) \
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![job.record_id, job.target, job.sha1, news, keywords, chrono::Utc::now().to_rfc3339()],
+                )
+            });
+            if let Err(e) = stored {
+                eprintln!("Failed to store translation of record {}: {}", job.record_id, e);
+            }
+            Ok(())
+        }
+    }
+
+    // Replace the news and keywords of records in another language with their stored translation
+    // into `target`. Records without one keep their text and lang and are queued for the worker,
+    // and the source is marked as having translations pending, so the response isn't cached.
+    pub(crate) fn translate_records(source: &DataSource, records: &mut [NewsRecord], target: &str) {
+        let Some(translator) = &source.translator else {
+            return;
+        };
+        let conn = match open_translations_database(&source.translations_path) {
+            Ok(conn) => conn,
+            Err(e) => {
+                eprintln!("Failed to open translations in {}: {}", source.translations_path.display(), e);
+                return;
+            }
+        };
+        let language = target.split('-').next().unwrap_or(target);
+        let mut queue_full = false;
+        for record in records.iter_mut() {
+            if record.lang.as_deref() == Some(language) {
+                continue;
+            }
+            let sha1 = source_sha1(record);
+            let stored: Option<(Option<String>, Option<String>)> = conn
+                .query_row(
+                    "SELECT news, keywords FROM translations WHERE record_id = ?1 AND lang = ?2 AND source_sha1 = ?3",
+                    params![record.id, target, sha1],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()
+                .unwrap_or_else(|e| {
+                    eprintln!("Failed to read translation of record {}: {}", record.id, e);
+                    None
+                });
+            let Some((news, keywords)) = stored else {
+                source.translations_pending.store(true, Ordering::Relaxed);
+                if !queue_full {
+                    queue_full = !translator.enqueue(Job {
+                        translations_path: source.translations_path.clone(),
+                        record_id: record.id,
+                        target: target.to_string(),
+                        from: record.lang.clone(),
+                        sha1,
+                        news: record.news.clone(),
+                        keywords: record.keywords.clone(),
+                    });
+                }
+                continue;
+            };
+            record.news = news;
+            record.keywords = keywords;
+            record.lang = Some(language.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_languages, Translator};
+
+    #[test]
+    fn offers_only_the_configured_languages() {
+        let languages = parse_languages("en, pt-BR,,es").unwrap();
+        assert_eq!(languages, ["en", "pt-BR", "es"]);
+        let translator = Translator::new("https://translate.example/translate".to_string(), None, languages);
+        assert!(translator.offers("pt-BR"));
+        assert!(!translator.offers("pt"));
+        assert!(!translator.offers("fr"));
+        assert!(parse_languages("en,english").is_err());
+        assert!(parse_languages("EN").is_err());
+    }
+
+    #[test]
+    fn debug_hides_the_api_key() {
+        let translator = Translator::new(
+            "https://translate.example/translate".to_string(),
+            Some("k3y".to_string()),
+            vec!["en".to_string()],
+        );
+        let debug = format!("{:?}", translator);
+        assert!(debug.contains("translate.example"), "{}", debug);
+        assert!(!debug.contains("k3y"), "{}", debug);
+    }
+}
@@ -0,0 +1,101 @@
+use std::net::IpAddr;
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC};
+
+use crate::config::{DOMAIN, DOMAIN_API};
+use crate::proxy::TrustedProxies;
+
+// Origins that links in responses are built from: the API's (image URLs) and the frontend's
+// (date_with_url), each without a trailing slash
+#[derive(Debug, Clone)]
+pub struct BaseUrls {
+    pub api: String,
+    pub site: String,
+    // Build both from the host each request was sent to instead, believing X-Forwarded-Host and
+    // X-Forwarded-Proto from these proxies; api and site remain for requests without a Host
+    pub from_host: Option<TrustedProxies>,
+    // Emit links as paths (/images/...) without an origin, unless a request asks for ?urls=absolute
+    pub relative: bool,
+}
+
+impl Default for BaseUrls {
+    fn default() -> BaseUrls {
+        BaseUrls { api: DOMAIN_API.to_string(), site: DOMAIN.to_string(), from_host: None, relative: false }
+    }
+}
+
+// Characters left as they are in a path segment or query value of outgoing URLs (RFC 3986
+// unreserved); everything else is percent-encoded
+pub(crate) const URL_UNRESERVED: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.').remove(b'~');
+
+// "https://example.com" or "http://localhost:3003/api", trailing slashes dropped
+pub(crate) fn parse_base_url(raw: &str) -> Result<String, String> {
+    let url = raw.trim().trim_end_matches('/');
+    let host = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .ok_or_else(|| format!("'{}' is not an http(s) URL", raw.trim()))?;
+    let host = host.split('/').next().unwrap_or_default();
+    if !valid_host(host) {
+        return Err(format!("'{}' has no valid host", raw.trim()));
+    }
+    Ok(url.to_string())
+}
+
+// Host names, IPv4 and bracketed IPv6 addresses with an optional port; nothing that could break
+// out of a URL
+fn valid_host(host: &str) -> bool {
+    !host.is_empty()
+        && host.len() <= 255
+        && host.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':' | '[' | ']'))
+}
+
+// One spelling per origin, as links and cached responses are kept per origin: lower case, without
+// the scheme's default port or a trailing dot
+fn normalise_host(scheme: &str, host: &str) -> String {
+    let host = host.to_ascii_lowercase();
+    let default_port = if scheme == "https" { ":443" } else { ":80" };
+    let host = host.strip_suffix(default_port).unwrap_or(&host);
+    match host.rsplit_once(':') {
+        Some((name, port)) if !port.ends_with(']') => format!("{}:{}", name.trim_end_matches('.'), port),
+        _ => host.trim_end_matches('.').to_string(),
+    }
+}
+
+impl BaseUrls {
+    // What image links start with: the API origin, or nothing for relative links
+    pub(crate) fn api_origin(&self) -> &str {
+        if self.relative { "" } else { &self.api }
+    }
+
+    // What frontend links start with: the site origin, or nothing for relative links
+    pub(crate) fn site_origin(&self) -> &str {
+        if self.relative { "" } else { &self.site }
+    }
+
+    // The origin a request was sent to, when links follow the request's host: the first
+    // X-Forwarded-Host (and -Proto) if a trusted proxy forwarded it, else the Host header over
+    // plain http. None to keep the configured URLs.
+    pub(crate) fn for_request(
+        &self,
+        peer: Option<IpAddr>,
+        host: Option<&str>,
+        forwarded_host: Option<&str>,
+        forwarded_proto: Option<&str>,
+    ) -> Option<BaseUrls> {
+        let proxies = self.from_host.as_ref()?;
+        let first = |value: &str| value.split(',').next().unwrap_or_default().trim().to_string();
+        let forwarded = peer.is_some_and(|peer| proxies.trusts(peer)).then_some(forwarded_host).flatten();
+        let (scheme, host) = match forwarded {
+            Some(forwarded_host) => {
+                let scheme = forwarded_proto.map(first).filter(|p| p == "https" || p == "http");
+                (scheme.unwrap_or_else(|| "https".to_string()), first(forwarded_host))
+            }
+            None => ("http".to_string(), host?.trim().to_string()),
+        };
+        if !valid_host(&host) {
+            return None;
+        }
+        let origin = format!("{}://{}", scheme, normalise_host(&scheme, &host));
+        Some(BaseUrls { api: origin.clone(), site: origin, ..self.clone() })
+    }
+}
@@ -0,0 +1,148 @@
+// Checks of a synced database before it is served: it opens, it didn't lose more than
+// SYNC_MAX_ROW_DROP_PERCENT of the records served so far, and the image files of its newest day
+// are mostly there. A database failing them is kept out of service: requests read the copy of
+// the last one that passed (known_good_path) until a sync brings one that passes again. With the
+// atomic swap, a database that passes is published as the served version instead, and one that
+// fails leaves the served version as it is.
+
+use std::sync::Arc;
+use serde::Serialize;
+
+use crate::config::{DataSource, SYNC_MAX_MISSING_IMAGES_PERCENT, SYNC_MAX_ROW_DROP_PERCENT};
+use crate::db::{count_records, db_fingerprint, query_date_range, query_day_images};
+use crate::versions::{has_current_version, publish_version};
+use crate::AppState;
+
+// Outcome of the last validation, for /health
+#[derive(Debug, Clone, Default, Serialize)]
+pub(crate) struct ValidationStatus {
+    pub(crate) ok: bool,
+    pub(crate) checked_at: Option<String>,
+    pub(crate) problems: Vec<String>,
+    // Requests are served from the known-good copy (with the atomic swap, the previous version)
+    // rather than the synced file
+    pub(crate) serving_known_good: bool,
+}
+
+// The source reading the synced file itself, with connections of its own
+fn synced_view(source: &DataSource) -> DataSource {
+    let mut synced = source.clone();
+    synced.serving_override = Arc::default();
+    synced.atomic_swap = false;
+    synced.connections = Arc::default();
+    synced
+}
+
+// Problems of the synced database; none when it can be served. `rows_served` is the record count
+// of the database served before the sync.
+fn validate_synced(source: &DataSource, rows_served: Option<i64>) -> Vec<String> {
+    let synced = synced_view(source);
+    let rows = match count_records(&synced) {
+        Ok(rows) => rows,
+        Err(e) => return vec![format!("cannot read {}: {}", source.db_path.display(), e)],
+    };
+    let mut problems = Vec::new();
+    if let Some(served) = rows_served.filter(|served| *served > 0) {
+        if rows < served * (100 - SYNC_MAX_ROW_DROP_PERCENT) / 100 {
+            problems.push(format!("records dropped from {} to {}", served, rows));
+        }
+    }
+
+    // Images come from the bucket, or only recent ones are checked out, when configured so
+    if source.image_bucket.is_none() {
+        let last_day = query_date_range(&synced).ok().and_then(|range| range.last);
+        let day = last_day
+            .filter(|day| day.len() == 8)
+            .map(|day| format!("{}-{}-{}", &day[..4], &day[4..6], &day[6..]));
+        if let Some(Ok(Some(images))) = day.as_deref().map(|day| query_day_images(&synced, day)) {
+            let missing = images.images.iter().filter(|image| !image.exists).count();
+            if missing * 100 > images.images.len() * SYNC_MAX_MISSING_IMAGES_PERCENT {
+                problems.push(format!("{} of {} images of {} are missing", missing, images.images.len(), images.date));
+            }
+        }
+    }
+    problems
+}
+
+// Replace the known-good copy with the synced file, unless it already is a copy of it. Copied
+// beside it and renamed, so the copy is never half-written.
+fn keep_known_good(state: &AppState) {
+    let source = &state.source;
+    let fingerprint = db_fingerprint(&source.db_path);
+    let mut copied_from = state.known_good_fingerprint.lock().unwrap_or_else(|e| e.into_inner());
+    if fingerprint.is_some() && *copied_from == fingerprint && source.known_good_path.is_file() {
+        return;
+    }
+    let partial = source.known_good_path.with_extension("db.tmp");
+    let copied = std::fs::copy(&source.db_path, &partial).and_then(|_| std::fs::rename(&partial, &source.known_good_path));
+    match copied {
+        Ok(()) => *copied_from = fingerprint,
+        Err(e) => {
+            let _ = std::fs::remove_file(&partial);
+            eprintln!("Failed to copy {} to {}: {}", source.db_path.display(), source.known_good_path.display(), e);
+        }
+    }
+}
+
+// Validate the synced database and pick the file requests read: the synced one when it passes
+// (refreshing the known-good copy), otherwise the known-good copy if there is one yet. Returns the
+// problems found, which are kept for /health. `commit` names the version published by the atomic
+// swap.
+pub(crate) fn validate_and_switch(state: &AppState, rows_served: Option<i64>, commit: Option<&str>) -> Vec<String> {
+    let source = &state.source;
+    let mut problems = validate_synced(source, rows_served);
+    if source.atomic_swap {
+        if problems.is_empty() {
+            match publish_version(source, commit) {
+                Ok(true) => println!("Switched {} to the synced data", source.versions_dir.display()),
+                Ok(false) => {}
+                Err(e) => problems.push(e),
+            }
+        }
+        let fall_back = !problems.is_empty() && has_current_version(source);
+        if !problems.is_empty() {
+            eprintln!(
+                "Synced database {} not published ({}); {}",
+                source.db_path.display(),
+                problems.join("; "),
+                if fall_back { "serving the previous version" } else { "there is no version to serve" }
+            );
+        }
+        record_validation(state, &problems, fall_back);
+        return problems;
+    }
+
+    let fall_back = !problems.is_empty() && source.known_good_path.is_file();
+    if problems.is_empty() {
+        keep_known_good(state);
+    } else if fall_back {
+        eprintln!(
+            "Synced database {} failed validation ({}); serving {}",
+            source.db_path.display(),
+            problems.join("; "),
+            source.known_good_path.display()
+        );
+    } else {
+        eprintln!(
+            "Synced database {} failed validation ({}), and there is no known-good copy to serve instead",
+            source.db_path.display(),
+            problems.join("; ")
+        );
+    }
+    if let Ok(mut serving) = source.serving_override.write() {
+        *serving = fall_back.then(|| source.known_good_path.clone());
+    }
+    record_validation(state, &problems, fall_back);
+    problems
+}
+
+fn record_validation(state: &AppState, problems: &[String], serving_known_good: bool) {
+    if let Ok(mut status) = state.validation.write() {
+        *status = ValidationStatus {
+            ok: problems.is_empty(),
+            checked_at: Some(chrono::Utc::now().to_rfc3339()),
+            problems: problems.to_vec(),
+            serving_known_good,
+        };
+    }
+}
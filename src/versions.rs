@@ -0,0 +1,102 @@
+// Atomic dataset swap: with it enabled, requests never read the checkout, which git rewrites in
+// place while it pulls. Each synced version that passes validation is published as a directory of
+// its own under versions_dir, with a copy of the database and the image tree (hard links, so
+// images take no extra space), and versions_dir/current, a symlink, is then pointed at it with a
+// rename, which replaces it in one step. Requests open the database and images through
+// `current`; connections opened on an earlier version keep reading it until they are retired.
+
+use std::path::Path;
+
+use crate::config::{DataSource, DATA_VERSIONS_KEPT};
+use crate::db::db_fingerprint;
+
+// Name of the symlink to the served version inside versions_dir
+pub(crate) const CURRENT_VERSION: &str = "current";
+
+// Link (or, across file systems, copy) every file under `from` into `to`
+fn link_tree(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            link_tree(&entry.path(), &target)?;
+        } else if std::fs::hard_link(entry.path(), &target).is_err() {
+            std::fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+// Version directory name: the commit checked out, or the database file's fingerprint
+fn version_name(source: &DataSource, commit: Option<&str>) -> Option<String> {
+    let name = match commit {
+        Some(commit) => commit.to_string(),
+        None => db_fingerprint(&source.db_path)?,
+    };
+    Some(name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '-' }).collect())
+}
+
+// The version `current` points at, if any
+fn current_version(source: &DataSource) -> Option<String> {
+    let target = std::fs::read_link(source.versions_dir.join(CURRENT_VERSION)).ok()?;
+    Some(target.file_name()?.to_string_lossy().into_owned())
+}
+
+pub(crate) fn has_current_version(source: &DataSource) -> bool {
+    source.versions_dir.join(CURRENT_VERSION).join("trends_data.db").is_file()
+}
+
+// Publish the synced database and images as a version and make it the served one; false when it
+// already is
+pub(crate) fn publish_version(source: &DataSource, commit: Option<&str>) -> Result<bool, String> {
+    let name = version_name(source, commit).ok_or_else(|| format!("cannot read {}", source.db_path.display()))?;
+    if current_version(source).as_deref() == Some(name.as_str()) {
+        return Ok(false);
+    }
+    let dir = source.versions_dir.join(&name);
+    if !dir.is_dir() {
+        let partial = source.versions_dir.join(format!("{}.partial", name));
+        let _ = std::fs::remove_dir_all(&partial);
+        let built = std::fs::create_dir_all(&partial)
+            .and_then(|_| std::fs::copy(&source.db_path, partial.join("trends_data.db")))
+            .and_then(|_| match source.images_dir.is_dir() {
+                true => link_tree(&source.images_dir, &partial.join("images")),
+                false => Ok(()),
+            })
+            .and_then(|_| std::fs::rename(&partial, &dir));
+        if let Err(e) = built {
+            let _ = std::fs::remove_dir_all(&partial);
+            return Err(format!("cannot build version {}: {}", dir.display(), e));
+        }
+    }
+
+    // A relative link, so the versions directory can move
+    let link = source.versions_dir.join(format!("{}.link", CURRENT_VERSION));
+    let _ = std::fs::remove_file(&link);
+    std::os::unix::fs::symlink(&name, &link)
+        .and_then(|_| std::fs::rename(&link, source.versions_dir.join(CURRENT_VERSION)))
+        .map_err(|e| format!("cannot switch {} to {}: {}", source.versions_dir.display(), name, e))?;
+    prune_versions(source, &name);
+    Ok(true)
+}
+
+// Remove all but the DATA_VERSIONS_KEPT newest versions, never the served one
+fn prune_versions(source: &DataSource, current: &str) {
+    let Ok(entries) = std::fs::read_dir(&source.versions_dir) else {
+        return;
+    };
+    let mut versions: Vec<(std::time::SystemTime, std::path::PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()))
+        .filter(|entry| entry.file_name() != current)
+        .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+        .collect();
+    versions.sort();
+    let stale = versions.len().saturating_sub(DATA_VERSIONS_KEPT.saturating_sub(1));
+    for (_, path) in versions.into_iter().take(stale) {
+        if let Err(e) = std::fs::remove_dir_all(&path) {
+            eprintln!("Failed to remove old data version {}: {}", path.display(), e);
+        }
+    }
+}
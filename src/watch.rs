@@ -0,0 +1,99 @@
+// Reload on file changes: with TREND_STORY_WATCH_FILES, the database file and the images directory
+// of every source are watched (inotify and the like, through notify), and a change to them, e.g. an
+// operator copying in a fixed database, is taken in like a sync's once the files have been quiet
+// for WATCH_DEBOUNCE_MILLIS, without waiting for the sync timer. Changes made while a sync runs,
+// or that a sync finished shortly before, are left to it: the reload claims the source's sync
+// flag, so the two never run at once.
+
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::time::Duration;
+use notify::{EventKind, RecursiveMode, Watcher};
+
+use crate::config::WATCH_DEBOUNCE_MILLIS;
+use crate::db::count_records;
+use crate::sync::refresh_data;
+use crate::AppState;
+
+// Whether an event touches the database (its journal and WAL files included) or an image
+fn is_data_change(state: &AppState, event: &notify::Event) -> bool {
+    if matches!(event.kind, EventKind::Access(_)) {
+        return false;
+    }
+    let db_name = state.source.db_path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    event.paths.iter().any(|path| {
+        path.starts_with(&state.source.images_dir)
+            || path.file_name().is_some_and(|name| !db_name.is_empty() && name.to_string_lossy().starts_with(&db_name))
+    })
+}
+
+// The source's sync flag while a reload holds it, cleared on the way out even if the reload panics
+struct SyncClaim<'a>(&'a AtomicBool);
+
+impl Drop for SyncClaim<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+// Watch a source's files from a thread of its own, when configured to
+pub fn spawn_watcher(state: AppState) {
+    if !state.source.watch_files {
+        return;
+    }
+    let (sender, events) = mpsc::channel();
+    let mut watcher = match notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let _ = sender.send(event);
+    }) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            eprintln!("Cannot watch the files of {}: {}", state.source.origin(), e);
+            return;
+        }
+    };
+    // The directory rather than the file, which git and most tools replace rather than rewrite
+    let db_dir = match state.source.db_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => Path::new(".").to_path_buf(),
+    };
+    for (path, mode) in [(&db_dir, RecursiveMode::NonRecursive), (&state.source.images_dir, RecursiveMode::Recursive)] {
+        if let Err(e) = watcher.watch(path, mode) {
+            eprintln!("Cannot watch {}: {}", path.display(), e);
+        }
+    }
+
+    std::thread::spawn(move || {
+        // Dropping the watcher would end the events
+        let _watcher = watcher;
+        while let Ok(event) = events.recv() {
+            match event {
+                Ok(event) if is_data_change(&state, &event) => {}
+                Ok(_) => continue,
+                Err(e) => {
+                    eprintln!("Watching the files of {} failed: {}", state.source.origin(), e);
+                    continue;
+                }
+            }
+            // Wait for the burst of changes to end
+            let debounce = Duration::from_millis(WATCH_DEBOUNCE_MILLIS);
+            let burst_started = chrono::Utc::now();
+            while events.recv_timeout(debounce).is_ok() {}
+            if state.syncing.compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire).is_err() {
+                continue;
+            }
+            let _claim = SyncClaim(&state.syncing);
+            // Changes a sync made, reported once it had finished, were already taken in by it
+            let window_start = burst_started - chrono::Duration::milliseconds(WATCH_DEBOUNCE_MILLIS as i64);
+            let history = state.sync_history();
+            if history.first().and_then(|record| record.ended_at()).is_some_and(|ended| ended >= window_start) {
+                continue;
+            }
+            println!("Files of {} changed, reloading", state.source.db_path.display());
+            let rows_served = count_records(&state.source).ok();
+            if let (_, Some(e)) = refresh_data(&state, rows_served, None) {
+                eprintln!("Reload of {} after a file change: {}", state.source.db_path.display(), e);
+            }
+        }
+    });
+}